@@ -357,6 +357,8 @@ fn to_sql_inner(
                 }
                 _ => unreachable!(),
             };
+            let prefilter =
+                similar_hash_prefilter(&left, PairsWrapper(inner.0.clone()), &curobj, context)?;
             res += &to_sql_inner(left, rec, query_type, context)?;
             if let Some(pair) = inner.next() {
                 if pair.as_rule() == Rule::in_statement_selector {
@@ -444,6 +446,9 @@ fn to_sql_inner(
                     }
                 }
             }
+            if let Some(prefilter) = prefilter {
+                res = format!("({prefilter} AND {res})");
+            }
         }
         Rule::node => {
             res += "(";
@@ -1108,6 +1113,14 @@ fn to_sql_inner(
             }
             res += &format!(" FROM objects AS {nextobj} WHERE {curobj}.id={nextobj}.id)");
         }
+        Rule::similar_hash_fn => {
+            let (algorithm, reference) = similar_hash_args(pair, context)?;
+            let function = match algorithm {
+                "tlsh" => "tlsh_distance",
+                _ => "ssdeep_compare",
+            };
+            res += &format!("{function}({curobj}.\"hashes\"->>'{algorithm}', {reference})");
+        }
         Rule::get_symbols_fn => {
             res += &format!(r#"jsonb_array_elements_text({curobj}."result"->'ok'->'symbols')"#);
         }
@@ -1289,6 +1302,122 @@ fn get_variable<'a>(
     })
 }
 
+/// Returns the algorithm and the escaped reference hash of a `similar_hash` function
+fn similar_hash_args(
+    pair: PairWrapper,
+    context: &ToSqlContext,
+) -> Result<(&'static str, String), Box<pest::error::Error<Rule>>> {
+    let mut inner = pair.into_inner();
+    //safe
+    let algorithm_pair = inner.next().unwrap();
+    let algorithm = match rules::unescape_string(algorithm_pair.0.clone())?.as_str() {
+        "tlsh" => "tlsh",
+        "ssdeep" => "ssdeep",
+        _ => {
+            return Err(new_pest_error(
+                "Supported hash algorithm (\"tlsh\" or \"ssdeep\")",
+                algorithm_pair.as_span(),
+            )
+            .into())
+        }
+    };
+    //safe
+    let reference_pair = inner.next().unwrap();
+    let reference = if is_variable_rule(reference_pair.as_rule()) {
+        match get_variable(&context.variables, &reference_pair.0)? {
+            VariableValue::String(v) => escape_string_as_constant_string(v),
+            _ => return Err(incompatible_variable(reference_pair.as_span())),
+        }
+    } else {
+        escape_pair_as_constant_string(reference_pair.0)?
+    };
+    Ok((algorithm, reference))
+}
+
+/// Returns an index friendly condition for a `similar_hash` comparison
+///
+/// Only hashes of a close enough length (TLSH) or of a compatible block size
+/// (ssdeep) can satisfy the comparison; `None` is returned when the comparison
+/// cannot be pre-filtered
+fn similar_hash_prefilter(
+    left: &PairWrapper,
+    mut rest: PairsWrapper,
+    curobj: &str,
+    context: &ToSqlContext,
+) -> Result<Option<String>, Box<pest::error::Error<Rule>>> {
+    if left.as_rule() != Rule::functions_number {
+        return Ok(None);
+    }
+    let Some(function) = PairWrapper(left.0.clone())
+        .into_inner()
+        .next()
+        .filter(|p| p.as_rule() == Rule::similar_hash_fn)
+    else {
+        return Ok(None);
+    };
+    let (Some(op), Some(bound)) = (rest.next(), rest.next()) else {
+        return Ok(None);
+    };
+    if op.as_rule() != Rule::op {
+        return Ok(None);
+    }
+    let bound = if bound.as_rule() == Rule::number {
+        bound.as_str().parse::<f64>().ok()
+    } else if let Some(VariableValue::Number(v)) = context.variables.get(bound.as_str()) {
+        v.parse::<f64>().ok()
+    } else {
+        None
+    };
+    let Some(bound) = bound.filter(|b| b.is_finite()) else {
+        return Ok(None);
+    };
+    let (algorithm, reference) = similar_hash_args(function, context)?;
+    let hash = format!("{curobj}.\"hashes\"->>'{algorithm}'");
+    if algorithm == "tlsh" {
+        // The length difference alone adds 0, 1 or 12 per unit to the distance
+        let max_distance = match op.as_str() {
+            "<" => bound.ceil() - 1.0,
+            "<=" | "=" | "==" => bound.floor(),
+            _ => return Ok(None),
+        };
+        if max_distance < 0.0 {
+            return Ok(None);
+        }
+        let max_distance = max_distance.min(4096.0) as u32;
+        let max_ldiff = if max_distance >= 24 {
+            max_distance / 12
+        } else {
+            max_distance.min(1)
+        };
+        if max_ldiff >= 128 {
+            return Ok(None);
+        }
+        // Note: the length difference wraps around
+        let lvalue = format!("tlsh_lvalue({hash})");
+        let reference = format!("tlsh_lvalue({reference})");
+        Ok(Some(format!(
+            "({lvalue} BETWEEN {reference} - {max_ldiff} AND {reference} + {max_ldiff} OR {lvalue} >= {reference} + {} OR {lvalue} <= {reference} - {})",
+            256 - max_ldiff,
+            256 - max_ldiff
+        )))
+    } else {
+        // Only block sizes equal or at a factor 2 produce non zero scores
+        let nonzero = match op.as_str() {
+            ">" => bound >= 0.0,
+            ">=" | "=" | "==" => bound > 0.0,
+            _ => false,
+        };
+        if !nonzero {
+            return Ok(None);
+        }
+        let blocksize = format!("ssdeep_blocksize({hash})");
+        let reference = format!("ssdeep_blocksize({reference})");
+        Ok(Some(format!(
+            "{blocksize} IN ({reference}, {reference} * 2, {reference} / 2)"
+        )))
+    }
+}
+
 fn find_selector_table_name(
     variables: &mut HashMap<String, VariableValue>,
     pair: &Pair<'_, Rule>,
@@ -201,6 +201,7 @@ impl RuleVersion {
             | Rule::jsonpath_object_match_equals
             | Rule::jsonpath_object_match_compares
             | Rule::count_conditions_fn
+            | Rule::similar_hash_fn
             | Rule::in_operator
             | Rule::in_statement_number
             | Rule::in_statement_string
@@ -483,3 +483,30 @@ fn test_global_query_settings() {
     );
     assert_eq!(settings.max_neighbors, Some(25));
 }
+
+#[test]
+fn test_similar_hash() {
+    assert_eq!(
+        parse_to_sql(r#"@similar_hash("ssdeep", "3:abc:def") > 0"#).unwrap(),
+        r#"FROM objects AS "objects_0" WHERE ((ssdeep_blocksize("objects_0"."hashes"->>'ssdeep') IN (ssdeep_blocksize('3:abc:def'), ssdeep_blocksize('3:abc:def') * 2, ssdeep_blocksize('3:abc:def') / 2) AND ssdeep_compare("objects_0"."hashes"->>'ssdeep', '3:abc:def')>0))"#
+    );
+    assert_eq!(
+        parse_to_sql(r#"@similar_hash("tlsh", "T1AB") < 50"#).unwrap(),
+        r#"FROM objects AS "objects_0" WHERE (((tlsh_lvalue("objects_0"."hashes"->>'tlsh') BETWEEN tlsh_lvalue('T1AB') - 4 AND tlsh_lvalue('T1AB') + 4 OR tlsh_lvalue("objects_0"."hashes"->>'tlsh') >= tlsh_lvalue('T1AB') + 252 OR tlsh_lvalue("objects_0"."hashes"->>'tlsh') <= tlsh_lvalue('T1AB') - 252) AND tlsh_distance("objects_0"."hashes"->>'tlsh', 'T1AB')<50))"#
+    );
+    assert_eq!(
+        parse_to_sql(r#"${h}="T1AB"; ${d}=50; @similar_hash("tlsh", ${h}) < ${d}"#).unwrap(),
+        parse_to_sql(r#"@similar_hash("tlsh", "T1AB") < 50"#).unwrap()
+    );
+    // Comparisons that cannot be pre-filtered
+    assert_eq!(
+        parse_to_sql(r#"@similar_hash("tlsh", "T1AB") > 50"#).unwrap(),
+        r#"FROM objects AS "objects_0" WHERE (tlsh_distance("objects_0"."hashes"->>'tlsh', 'T1AB')>50)"#
+    );
+    assert_eq!(
+        parse_to_sql(r#"@similar_hash("ssdeep", "3:abc:def") < 50"#).unwrap(),
+        r#"FROM objects AS "objects_0" WHERE (ssdeep_compare("objects_0"."hashes"->>'ssdeep', '3:abc:def')<50)"#
+    );
+    assert!(parse_to_sql(r#"@similar_hash("md5", "abc") < 50"#).is_err());
+    assert!(parse_to_sql(r#"${h}=1; @similar_hash("tlsh", ${h}) < 50"#).is_err());
+}
@@ -1,5 +1,6 @@
+-- Note: same layout as the grapher migrations (objects partitioned on t)
 CREATE TABLE objects (
-    id bigserial NOT NULL PRIMARY KEY,
+    id bigserial NOT NULL,
     org text NOT NULL,
     work_id text NOT NULL,
     is_entry boolean NOT NULL,
@@ -12,16 +13,20 @@ CREATE TABLE objects (
     t timestamp with time zone NOT NULL,
     result jsonb NOT NULL,
     entropy double precision NULL
-);
+) PARTITION BY RANGE (t);
 CREATE INDEX o_work_id_idx ON objects USING hash (work_id);
 CREATE INDEX o_object_id_idx ON objects USING hash (object_id);
 CREATE INDEX o_object_type_idx ON objects USING hash (object_type);
 CREATE INDEX o_t_idx ON objects USING btree (t);
 CREATE INDEX o_result_idx ON objects USING GIN (result);
+CREATE TABLE objects_p2000 PARTITION OF objects FOR VALUES FROM ('2000-01-01') TO ('2025-01-01');
+CREATE INDEX ON objects_p2000 USING btree (id);
+CREATE TABLE objects_default PARTITION OF objects DEFAULT;
+CREATE INDEX ON objects_default USING btree (id);
 
 CREATE TABLE rels (
-    parent bigint NULL REFERENCES objects(id) ON DELETE CASCADE ON UPDATE CASCADE,
-    child bigint NOT NULL REFERENCES objects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    parent bigint NULL,
+    child bigint NOT NULL,
     props jsonb NOT NULL
 );
 CREATE INDEX r_parent_idx ON rels USING hash (parent);
@@ -0,0 +1,866 @@
+//! Contextal platform API client
+//!
+//! This crate provides a typed, async interface to the routes exposed by the
+//! work-manager endpoint (see the `/api/v1/openapi.json` document for the
+//! full description of the API)
+
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use shared::api::{CountResult, SearchErrorBody};
+pub use shared::{
+    amqp::{BackendAnnouncement, CacheInvalidation, JobResult},
+    api::{
+        BacktestResult, Bundle, GraphsPage, ImportReport, LegalHold, Manifest, ManifestEntry,
+        OnConflict, OrgUsage, Renamed, ReprocessAction, ReprocessJob, ReprocessRequest,
+        ScenarioDetails, ScenarioStats, ScenarioVersion, SearchPage, StatsPeriod, SubmitResult,
+        WorkState, WorkStatus,
+    },
+    object::{Metadata, Priority},
+    scene::{Scenario, ScenarioMode, WorkActions},
+};
+use std::collections::HashMap;
+
+/// The client error
+#[derive(Debug)]
+pub enum Error {
+    /// Transport or protocol failure
+    Http(reqwest::Error),
+    /// The endpoint returned an unexpected status
+    Status { status: u16, body: String },
+    /// The search query was rejected or failed
+    Search { kind: String, message: String },
+    /// The endpoint returned an unexpected payload
+    Decode(serde_json::Error),
+    /// The endpoint base URL or a path parameter is invalid
+    InvalidUrl(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "HTTP error: {e}"),
+            Self::Status { status, body } => write!(f, "Unexpected status {status}: {body}"),
+            Self::Search { kind, message } => write!(f, "{kind}: {message}"),
+            Self::Decode(e) => write!(f, "Invalid response: {e}"),
+            Self::InvalidUrl(e) => write!(f, "Invalid URL: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e)
+    }
+}
+
+/// The optional parameters of a work submission
+#[derive(Debug, Default, Clone)]
+pub struct SubmitOptions {
+    /// Org
+    pub org: Option<String>,
+    /// Relation metadata
+    pub relation_metadata: Option<Metadata>,
+    /// The number of seconds allowed to fully complete the work request
+    pub ttl: Option<u64>,
+    /// The maximum recursion level the work can reach
+    pub maxrec: Option<u32>,
+    /// The work priority
+    pub priority: Option<Priority>,
+}
+
+/// The outcome of a work deletion request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeleteOutcome {
+    Deleted,
+    OnHold,
+    NotFound,
+}
+
+/// The author and reason of a scenario change
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Change<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct SearchParams<'a> {
+    q: &'a str,
+    getobjects: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maxitems: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paginate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct WorkIds<'a> {
+    work_ids: &'a [String],
+}
+
+/// The platform API client
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: reqwest::Url,
+    http: reqwest::Client,
+    admin_token: Option<String>,
+}
+
+impl Client {
+    /// Creates a new client for the endpoint at `base_url` (e.g. `http://127.0.0.1:8080`)
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Creates a new client using a preconfigured [`reqwest::Client`]
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self, Error> {
+        let base_url = reqwest::Url::parse(base_url)
+            .map_err(|e| Error::InvalidUrl(format!("{base_url}: {e}")))?;
+        if base_url.cannot_be_a_base() {
+            return Err(Error::InvalidUrl(format!("{base_url}: not a base URL")));
+        }
+        Ok(Self {
+            base_url,
+            http,
+            admin_token: None,
+        })
+    }
+
+    /// Sets the bearer token used to access the admin routes
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    fn admin(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.admin_token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    /// Returns the endpoint URL of the route made of the (percent-encoded) `segments`
+    ///
+    /// Dot segments cannot be represented in a URL path and are rejected
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url, Error> {
+        if let Some(segment) = segments.iter().find(|s| **s == "." || **s == "..") {
+            return Err(Error::InvalidUrl(format!(
+                "invalid path parameter {segment:?}"
+            )));
+        }
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL checked on creation")
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// Returns the response if successful, the failure details otherwise
+    async fn check(res: reqwest::Response) -> Result<reqwest::Response, Error> {
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res);
+        }
+        let body = res.text().await.unwrap_or_default();
+        if status == reqwest::StatusCode::BAD_REQUEST {
+            if let Ok(e) = serde_json::from_str::<SearchErrorBody>(&body) {
+                return Err(Error::Search {
+                    kind: e.kind,
+                    message: e.message,
+                });
+            }
+        }
+        Err(Error::Status {
+            status: status.as_u16(),
+            body,
+        })
+    }
+
+    async fn json<T: serde::de::DeserializeOwned>(res: reqwest::Response) -> Result<T, Error> {
+        let body = Self::check(res).await?.bytes().await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Retrieves the prometheus metrics
+    pub async fn metrics(&self) -> Result<String, Error> {
+        let res = self.http.get(self.url(&["metrics"])?).send().await?;
+        Ok(Self::check(res).await?.text().await?)
+    }
+
+    /// Retrieves the OpenAPI document
+    pub async fn openapi(&self) -> Result<serde_json::Value, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "openapi.json"])?)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Submits an object for processing
+    pub async fn submit(
+        &self,
+        object_data: impl Into<reqwest::Body>,
+        options: &SubmitOptions,
+    ) -> Result<SubmitResult, Error> {
+        let mut form = reqwest::multipart::Form::new();
+        if let Some(org) = &options.org {
+            form = form.text("org", org.clone());
+        }
+        if let Some(metadata) = &options.relation_metadata {
+            form = form.part(
+                "relation_metadata",
+                reqwest::multipart::Part::text(serde_json::to_string(metadata)?)
+                    .mime_str("application/json")?,
+            );
+        }
+        if let Some(ttl) = options.ttl {
+            form = form.text("ttl", ttl.to_string());
+        }
+        if let Some(maxrec) = options.maxrec {
+            form = form.text("maxrec", maxrec.to_string());
+        }
+        if let Some(priority) = options.priority {
+            let priority = match priority {
+                Priority::Interactive => "interactive",
+                Priority::Bulk => "bulk",
+            };
+            form = form.text("priority", priority);
+        }
+        form = form.part(
+            "object_data",
+            reqwest::multipart::Part::stream(object_data).file_name("object_data"),
+        );
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "submit"])?)
+            .multipart(form)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves the graph of a work (`None` if not found or not yet complete)
+    pub async fn get_work_graph(&self, work_id: &str) -> Result<Option<JobResult>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "get_work_graph", work_id])?)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Retrieves the processing status of a work (`None` if not found)
+    pub async fn get_work_status(&self, work_id: &str) -> Result<Option<WorkStatus>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "work", work_id, "status"])?)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Deletes a work and the objects no longer referenced by other works (admin)
+    pub async fn delete_work(&self, work_id: &str) -> Result<DeleteOutcome, Error> {
+        let req = self.http.delete(self.url(&["api", "v1", "work", work_id])?);
+        let res = self.admin(req).send().await?;
+        match res.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(DeleteOutcome::NotFound),
+            reqwest::StatusCode::CONFLICT => Ok(DeleteOutcome::OnHold),
+            _ => Self::check(res).await.map(|_| DeleteOutcome::Deleted),
+        }
+    }
+
+    /// Places a work on legal hold (admin)
+    ///
+    /// Returns `false` if the work does not exist
+    pub async fn set_legal_hold(&self, work_id: &str, reason: Option<&str>) -> Result<bool, Error> {
+        let req = self
+            .http
+            .put(self.url(&["api", "v1", "work", work_id, "hold"])?)
+            .json(&serde_json::json!({ "reason": reason }));
+        let res = self.admin(req).send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(res).await.map(|_| true)
+    }
+
+    /// Lifts the legal hold of a work (admin)
+    ///
+    /// Returns `false` if the work was not on hold
+    pub async fn clear_legal_hold(&self, work_id: &str) -> Result<bool, Error> {
+        let req = self
+            .http
+            .delete(self.url(&["api", "v1", "work", work_id, "hold"])?);
+        let res = self.admin(req).send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(res).await.map(|_| true)
+    }
+
+    /// Retrieves the legal hold of a work, `None` if not on hold (admin)
+    pub async fn get_legal_hold(&self, work_id: &str) -> Result<Option<LegalHold>, Error> {
+        let req = self
+            .http
+            .get(self.url(&["api", "v1", "work", work_id, "hold"])?);
+        let res = self.admin(req).send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Retrieves the graphs of multiple works
+    pub async fn get_works_graphs(
+        &self,
+        work_ids: &[String],
+    ) -> Result<HashMap<String, Option<JobResult>>, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "get_works_graphs"])?)
+            .json(&WorkIds { work_ids })
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves a page of graphs of multiple works
+    pub async fn get_works_graphs_page(
+        &self,
+        work_ids: &[String],
+        cursor: Option<&str>,
+    ) -> Result<GraphsPage, Error> {
+        let mut req = self
+            .http
+            .post(self.url(&["api", "v1", "get_works_graphs"])?)
+            .query(&[("paginate", "true")]);
+        if let Some(cursor) = cursor {
+            req = req.query(&[("cursor", cursor)]);
+        }
+        let res = req.json(&WorkIds { work_ids }).send().await?;
+        Self::json(res).await
+    }
+
+    /// Downloads an object (`None` if not found)
+    pub async fn get_object(&self, object_id: &str) -> Result<Option<Vec<u8>>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "get_object", object_id])?)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(Self::check(res).await?.bytes().await?.to_vec()))
+    }
+
+    /// Searches for works (or objects) matching the query
+    pub async fn search(
+        &self,
+        q: &str,
+        getobjects: bool,
+        maxitems: Option<u32>,
+    ) -> Result<Vec<String>, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "search"])?)
+            .json(&SearchParams {
+                q,
+                getobjects,
+                maxitems,
+                paginate: None,
+                cursor: None,
+            })
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves a page of works (or objects) matching the query
+    pub async fn search_page(
+        &self,
+        q: &str,
+        getobjects: bool,
+        maxitems: Option<u32>,
+        cursor: Option<&str>,
+    ) -> Result<SearchPage, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "search"])?)
+            .json(&SearchParams {
+                q,
+                getobjects,
+                maxitems,
+                paginate: Some(true),
+                cursor,
+            })
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Streams all the works (or objects) matching the query
+    pub async fn search_export(
+        &self,
+        q: &str,
+        getobjects: bool,
+    ) -> Result<impl Stream<Item = Result<String, Error>>, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "search", "export"])?)
+            .json(&SearchParams {
+                q,
+                getobjects,
+                maxitems: None,
+                paginate: None,
+                cursor: None,
+            })
+            .send()
+            .await?;
+        let key = if getobjects { "object_id" } else { "work_id" };
+        let chunks = Self::check(res).await?.bytes_stream().map_err(Error::from);
+        let lines = futures::stream::unfold(
+            (Box::pin(chunks), Vec::<u8>::new(), false),
+            |(mut chunks, mut buf, mut eof)| async move {
+                loop {
+                    if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = buf.drain(..=pos).collect();
+                        return Some((Ok(line), (chunks, buf, eof)));
+                    }
+                    if eof {
+                        return (!buf.is_empty())
+                            .then(|| (Ok(std::mem::take(&mut buf)), (chunks, Vec::new(), true)));
+                    }
+                    match chunks.next().await {
+                        Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                        Some(Err(e)) => return Some((Err(e), (chunks, Vec::new(), true))),
+                        None => eof = true,
+                    }
+                }
+            },
+        );
+        Ok(lines.map(move |line| {
+            let line = line?;
+            let mut item: HashMap<String, serde_json::Value> = serde_json::from_slice(&line)?;
+            if let Some(e) = item.remove("error") {
+                let e: SearchErrorBody = serde_json::from_value(e)?;
+                return Err(Error::Search {
+                    kind: e.kind,
+                    message: e.message,
+                });
+            }
+            match item.remove(key) {
+                Some(serde_json::Value::String(id)) => Ok(id),
+                _ => Err(Error::Status {
+                    status: 200,
+                    body: String::from_utf8_lossy(&line).into_owned(),
+                }),
+            }
+        }))
+    }
+
+    /// Counts the works (or objects) matching the query
+    pub async fn count(&self, q: &str, getobjects: bool) -> Result<i64, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "count"])?)
+            .json(&SearchParams {
+                q,
+                getobjects,
+                maxitems: None,
+                paginate: None,
+                cursor: None,
+            })
+            .send()
+            .await?;
+        Self::json::<CountResult>(res).await.map(|c| c.count)
+    }
+
+    /// Adds a scenario, optionally replacing the scenario with id `replace_id`
+    pub async fn add_scenario(
+        &self,
+        scenario: &Scenario,
+        replace_id: Option<i64>,
+        change: &Change<'_>,
+    ) -> Result<ScenarioDetails, Error> {
+        let mut req = self.http.post(self.url(&["api", "v1", "scenarios"])?);
+        if let Some(replace_id) = replace_id {
+            req = req.query(&[("replace_id", replace_id)]);
+        }
+        let res = req.query(change).json(scenario).send().await?;
+        Self::json(res).await
+    }
+
+    /// Deletes a scenario (returns `false` if not found)
+    pub async fn del_scenario(&self, id: i64, change: &Change<'_>) -> Result<bool, Error> {
+        let res = self
+            .http
+            .delete(self.url(&["api", "v1", "scenarios", &id.to_string()])?)
+            .query(change)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(res).await.map(|_| true)
+    }
+
+    /// Retrieves a scenario (`None` if not found)
+    pub async fn get_scenario(&self, id: i64) -> Result<Option<Scenario>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "scenarios", &id.to_string()])?)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Lists all the scenarios
+    pub async fn list_scenarios(&self) -> Result<Vec<ScenarioDetails>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "scenarios"])?)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves the hit statistics of a scenario over the last `hours` and
+    /// `days` (`None` if the scenario does not exist)
+    pub async fn get_scenario_stats(
+        &self,
+        id: i64,
+        hours: Option<u32>,
+        days: Option<u32>,
+    ) -> Result<Option<ScenarioStats>, Error> {
+        let mut req =
+            self.http
+                .get(self.url(&["api", "v1", "scenarios", &id.to_string(), "stats"])?);
+        if let Some(hours) = hours {
+            req = req.query(&[("hours", hours)]);
+        }
+        if let Some(days) = days {
+            req = req.query(&[("days", days)]);
+        }
+        let res = req.send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Lists the recorded versions of a scenario, most recent first
+    pub async fn list_scenario_versions(&self, name: &str) -> Result<Vec<ScenarioVersion>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "scenarios", "history", name])?)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves a recorded version of a scenario (`None` if not found)
+    pub async fn get_scenario_version(
+        &self,
+        name: &str,
+        version: i32,
+    ) -> Result<Option<ScenarioVersion>, Error> {
+        let res = self
+            .http
+            .get(self.url(&[
+                "api",
+                "v1",
+                "scenarios",
+                "history",
+                name,
+                &version.to_string(),
+            ])?)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Restores a recorded version of a scenario and requests that all
+    /// directors reload their scenarios
+    pub async fn rollback_scenario(
+        &self,
+        name: &str,
+        version: i32,
+        change: &Change<'_>,
+    ) -> Result<ScenarioDetails, Error> {
+        let res = self
+            .http
+            .post(self.url(&[
+                "api",
+                "v1",
+                "scenarios",
+                "history",
+                name,
+                &version.to_string(),
+                "rollback",
+            ])?)
+            .query(change)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Evaluates an unsaved scenario against the works submitted between
+    /// `start` and `end` (Unix times, `end` defaults to now)
+    pub async fn backtest_scenario(
+        &self,
+        scenario: &Scenario,
+        start: f64,
+        end: Option<f64>,
+    ) -> Result<BacktestResult, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "scenarios", "backtest"])?)
+            .json(&serde_json::json!({
+                "scenario": scenario,
+                "start": start,
+                "end": end,
+            }))
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Exports the given scenarios (or all of them) as a bundle
+    pub async fn export_scenarios(&self, ids: Option<&[i64]>) -> Result<Bundle, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "scenarios", "export"])?)
+            .json(&serde_json::json!({ "ids": ids }))
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Imports a scenario bundle and requests that all directors reload their
+    /// scenarios
+    pub async fn import_scenarios(
+        &self,
+        bundle: &Bundle,
+        on_conflict: OnConflict,
+        change: &Change<'_>,
+    ) -> Result<ImportReport, Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "scenarios", "import"])?)
+            .query(&[("on_conflict", on_conflict)])
+            .query(change)
+            .json(bundle)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Retrieves the most recent actions of a work
+    pub async fn get_work_actions(
+        &self,
+        work_id: &str,
+        maxitems: Option<u32>,
+    ) -> Result<Vec<WorkActions>, Error> {
+        let mut req = self.http.get(self.url(&["api", "v1", "actions", work_id])?);
+        if let Some(maxitems) = maxitems {
+            req = req.query(&[("maxitems", maxitems)]);
+        }
+        Self::json(req.send().await?).await
+    }
+
+    /// Requests that all directors reload their scenarios
+    pub async fn reload_scenarios(&self) -> Result<(), Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "scenarios", "reload"])?)
+            .send()
+            .await?;
+        Self::check(res).await.map(|_| ())
+    }
+
+    /// Requests that scenarios are (re-)applied to the given works
+    pub async fn apply_scenarios(&self, work_ids: &[String]) -> Result<(), Error> {
+        let res = self
+            .http
+            .post(self.url(&["api", "v1", "scenarios", "apply"])?)
+            .json(&WorkIds { work_ids })
+            .send()
+            .await?;
+        Self::check(res).await.map(|_| ())
+    }
+
+    /// Retrieves the current day submission usage of all orgs (admin)
+    pub async fn get_usage(&self) -> Result<Vec<OrgUsage>, Error> {
+        let req = self.http.get(self.url(&["api", "v1", "admin", "usage"])?);
+        Self::json(self.admin(req).send().await?).await
+    }
+
+    /// Resets the submission usage and rate limit of an org (admin)
+    ///
+    /// Returns `false` if no usage was recorded for the org
+    pub async fn reset_usage(&self, org: &str) -> Result<bool, Error> {
+        let req = self
+            .http
+            .delete(self.url(&["api", "v1", "admin", "usage", org])?);
+        let res = self.admin(req).send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(res).await.map(|_| true)
+    }
+
+    /// Requests that all the workers drop their matching cached backend results (admin)
+    pub async fn invalidate_cache(&self, invalidation: &CacheInvalidation) -> Result<(), Error> {
+        let req = self
+            .http
+            .post(self.url(&["api", "v1", "admin", "cache", "invalidate"])?)
+            .json(invalidation);
+        Self::check(self.admin(req).send().await?).await.map(|_| ())
+    }
+
+    /// Lists the backends announced by the workers, sorted by worker type
+    pub async fn list_backends(&self) -> Result<Vec<BackendAnnouncement>, Error> {
+        let res = self
+            .http
+            .get(self.url(&["api", "v1", "backends"])?)
+            .send()
+            .await?;
+        Self::json(res).await
+    }
+
+    /// Starts reprocessing the historical works affected by a backend upgrade (admin)
+    pub async fn create_reprocess_job(
+        &self,
+        request: &ReprocessRequest,
+    ) -> Result<ReprocessJob, Error> {
+        let req = self
+            .http
+            .post(self.url(&["api", "v1", "admin", "reprocess"])?)
+            .json(request);
+        Self::json(self.admin(req).send().await?).await
+    }
+
+    /// Lists the reprocess jobs, most recent first (admin)
+    pub async fn list_reprocess_jobs(&self) -> Result<Vec<ReprocessJob>, Error> {
+        let req = self
+            .http
+            .get(self.url(&["api", "v1", "admin", "reprocess"])?);
+        Self::json(self.admin(req).send().await?).await
+    }
+
+    /// Retrieves a reprocess job and its progress (admin)
+    pub async fn get_reprocess_job(&self, id: i64) -> Result<Option<ReprocessJob>, Error> {
+        let req = self
+            .http
+            .get(self.url(&["api", "v1", "admin", "reprocess", &id.to_string()])?);
+        let res = self.admin(req).send().await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Self::json(res).await.map(Some)
+    }
+
+    /// Pauses, resumes or cancels a reprocess job (admin)
+    pub async fn update_reprocess_job(
+        &self,
+        id: i64,
+        action: ReprocessAction,
+    ) -> Result<ReprocessJob, Error> {
+        let req = self.http.post(self.url(&[
+            "api",
+            "v1",
+            "admin",
+            "reprocess",
+            &id.to_string(),
+            action.as_str(),
+        ])?);
+        Self::json(self.admin(req).send().await?).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let client = Client::new("http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            client
+                .url(&["api", "v1", "work", "a/b?c#d e", "hold"])
+                .unwrap()
+                .as_str(),
+            "http://127.0.0.1:8080/api/v1/work/a%2Fb%3Fc%23d%20e/hold"
+        );
+        assert!(matches!(
+            client.url(&["api", "v1", "scenarios", "history", "..", "1"]),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert_eq!(
+            client
+                .url(&["api", "v1", "get_object", "..."])
+                .unwrap()
+                .as_str(),
+            "http://127.0.0.1:8080/api/v1/get_object/..."
+        );
+
+        let client = Client::new("https://example.com/prefix/").unwrap();
+        assert_eq!(
+            client
+                .url(&["api", "v1", "admin", "usage", "my org"])
+                .unwrap()
+                .as_str(),
+            "https://example.com/prefix/api/v1/admin/usage/my%20org"
+        );
+        let client = Client::new("https://example.com/prefix").unwrap();
+        assert_eq!(
+            client.url(&["metrics"]).unwrap().as_str(),
+            "https://example.com/prefix/metrics"
+        );
+
+        assert!(matches!(
+            Client::new("127.0.0.1:8080"),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            Client::new("mailto:admin@example.com"),
+            Err(Error::InvalidUrl(_))
+        ));
+    }
+}
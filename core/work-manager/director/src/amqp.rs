@@ -21,6 +21,10 @@ pub struct Broker {
     director_channel: Channel,
     director_ctag: String,
     director_receiver: UnboundedReceiver<ConsumerMessage>,
+    actions_channel: Channel,
+    actions_ctag: String,
+    actions_receiver: UnboundedReceiver<ConsumerMessage>,
+    executors: crate::executor::Executors,
     graphdb: crate::graph::GraphDB,
     reload_requested: bool,
 }
@@ -28,9 +32,10 @@ pub struct Broker {
 impl Broker {
     /// Creates a new broker interface
     pub async fn new(
-        broker_cfg: &BrokerConfig,
+        config: &crate::config::Config,
         graphdb: crate::graph::GraphDB,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let broker_cfg: &BrokerConfig = &config.broker;
         // Connect to broker
         let connection = shared::amqp::connect(broker_cfg).await?;
 
@@ -123,6 +128,51 @@ impl Broker {
                 return Err(e);
             }
         };
+
+        // Create the executors and subscribe to the actions queue
+        let (actions_channel, actions_ctag, actions_receiver, executors) = match async {
+            let channel = shared::amqp::open_channel(&connection).await?;
+            match async {
+                let executors = crate::executor::Executors::new(
+                    &config.executors,
+                    config.get_max_concurrent_actions(),
+                    &channel,
+                )
+                .await?;
+                let args = BasicConsumeArguments::default()
+                    .queue(crate::executor::ACTIONS_QUEUE_NAME.to_owned())
+                    .auto_ack(false)
+                    .finish();
+                let (ctag, receiver) = channel.basic_consume_rx(args).await?;
+                debug!(
+                    "Subscribed to the actions queue \"{}\" with ctag {}",
+                    crate::executor::ACTIONS_QUEUE_NAME,
+                    ctag
+                );
+                Ok::<_, Box<dyn std::error::Error>>((ctag, receiver, executors))
+            }
+            .await
+            {
+                Ok((ctag, receiver, executors)) => Ok((channel, ctag, receiver, executors)),
+                Err(e) => {
+                    error!("Failed to setup action executors: {}", e);
+                    shared::amqp::close_channel(channel).await;
+                    Err(e)
+                }
+            }
+        }
+        .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                shared::amqp::unsubscribe(&director_channel, &director_ctag).await;
+                shared::amqp::close_channel(director_channel).await;
+                shared::amqp::unsubscribe(&reload_channel, &reload_ctag).await;
+                shared::amqp::close_channel(reload_channel).await;
+                shared::amqp::close_connection(connection).await;
+                return Err(e);
+            }
+        };
         Ok(Self {
             connection,
             reload_channel,
@@ -131,6 +181,10 @@ impl Broker {
             director_channel,
             director_ctag,
             director_receiver,
+            actions_channel,
+            actions_ctag,
+            actions_receiver,
+            executors,
             graphdb,
             reload_requested: false,
         })
@@ -138,6 +192,8 @@ impl Broker {
 
     /// Cleanly unsubscribes, closes the channel and disconnects
     pub async fn close(self) {
+        shared::amqp::unsubscribe(&self.actions_channel, &self.actions_ctag).await;
+        shared::amqp::close_channel(self.actions_channel).await;
         shared::amqp::unsubscribe(&self.director_channel, &self.director_ctag).await;
         shared::amqp::close_channel(self.director_channel).await;
         shared::amqp::unsubscribe(&self.reload_channel, &self.reload_ctag).await;
@@ -161,6 +217,8 @@ impl Broker {
         }
         let mut rng = rand::rng();
         let mut interval = make_interval(&mut rng).await;
+        let mut stats_interval = tokio::time::interval(self.graphdb.stats_flush_interval());
+        stats_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         loop {
             tokio::select!(
                 msg = self.director_receiver.recv() => {
@@ -170,6 +228,13 @@ impl Broker {
                         break;
                     }
                 }
+                msg = self.actions_receiver.recv() => {
+                    if let Some(msg) = msg {
+                        self.executors.execute(msg).await?;
+                    } else {
+                        break;
+                    }
+                }
                 msg = self.reload_receiver.recv() => {
                     if let Some(msg) = msg {
                         self.schedule_reload(msg).await?;
@@ -184,6 +249,9 @@ impl Broker {
                         interval = make_interval(&mut rng).await;
                     }
                 }
+                _ = stats_interval.tick() => {
+                    self.graphdb.flush_stats().await;
+                }
             );
         }
         Err("Broker lost".into())
@@ -224,8 +292,17 @@ impl Broker {
             }
         };
         debug!("Processing request received: {:#?}", request);
-        if let Err(e) = self.graphdb.apply_scenarios(&request.work_id).await {
-            error!("Graph database error, exiting: {}", e);
+        let work_actions = match self.graphdb.apply_scenarios(&request.work_id).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Graph database error, exiting: {}", e);
+                self.reject_tag(delivery_tag).await.ok();
+                return Err(e);
+            }
+        };
+        // Note: the request is only acked once the actions are queued
+        if let Err(e) = self.executors.enqueue(&work_actions).await {
+            error!("Broker error, exiting: {}", e);
             self.reject_tag(delivery_tag).await.ok();
             return Err(e);
         }
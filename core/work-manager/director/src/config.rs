@@ -5,6 +5,7 @@ use figment::{
 };
 use serde::Deserialize;
 use shared::config::{BrokerConfig, DBConfig};
+use std::collections::HashMap;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
@@ -17,6 +18,13 @@ pub struct Config {
     pub read_db: DBConfig,
     /// Read/Write DB configuration
     pub write_db: DBConfig,
+    /// Action executors, by name
+    #[serde(default)]
+    pub executors: HashMap<String, ExecutorConfig>,
+    max_concurrent_actions: Option<usize>,
+    scenario_batch_size: Option<usize>,
+    stats_flush_interval_secs: Option<u64>,
+    stats_retention_days: Option<u32>,
 }
 
 impl Config {
@@ -32,3 +40,99 @@ impl Config {
             })
     }
 }
+
+impl Config {
+    /// Maximum number of concurrently executing actions (default 64)
+    pub fn get_max_concurrent_actions(&self) -> usize {
+        self.max_concurrent_actions.unwrap_or(64).max(1)
+    }
+
+    /// Maximum number of scenarios evaluated in a single query (default 100)
+    pub fn get_scenario_batch_size(&self) -> usize {
+        self.scenario_batch_size.unwrap_or(100).max(1)
+    }
+
+    /// Interval between scenario statistics flushes (default 60 seconds)
+    pub fn get_stats_flush_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.stats_flush_interval_secs.unwrap_or(60).max(1))
+    }
+
+    /// Number of days scenario statistics are retained (default 30)
+    pub fn get_stats_retention_days(&self) -> u32 {
+        self.stats_retention_days.unwrap_or(30).max(1)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// The format of syslog messages
+pub enum SyslogFormat {
+    /// ArcSight Common Event Format
+    #[default]
+    Cef,
+    /// JSON event
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+/// The executor types
+pub enum ExecutorKind {
+    /// POSTs the JSON event to an HTTP endpoint
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        timeout_ms: Option<u64>,
+    },
+    /// Publishes the JSON event to an AMQP exchange
+    Amqp {
+        exchange: String,
+        /// The type of the exchange, declared at startup (default topic)
+        exchange_type: Option<String>,
+        #[serde(default)]
+        routing_key: String,
+    },
+    /// Sends the event to a syslog server
+    Syslog {
+        /// The server address (`host:port`)
+        address: String,
+        /// Use TCP instead of UDP
+        #[serde(default)]
+        tcp: bool,
+        #[serde(default)]
+        format: SyslogFormat,
+        /// The syslog facility (default 16, i.e. local0)
+        facility: Option<u8>,
+    },
+    /// Appends the JSON event as a line to a file
+    File { path: String },
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// The retry policy of an executor
+pub struct RetryPolicy {
+    /// The maximum number of attempts (default 3)
+    attempts: Option<u32>,
+    /// The delay before the first retry, doubled on each retry (default 1000)
+    backoff_ms: Option<u64>,
+}
+
+impl RetryPolicy {
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts.unwrap_or(3).max(1)
+    }
+
+    pub fn get_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.backoff_ms.unwrap_or(1000))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Action executor configuration
+pub struct ExecutorConfig {
+    #[serde(flatten)]
+    pub kind: ExecutorKind,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
@@ -0,0 +1,601 @@
+//! Action executors
+//!
+//! This module dispatches the actions triggered by scenarios to the configured
+//! executors (webhooks, AMQP exchanges, syslog servers and files)
+//!
+//! Before the director request is acknowledged, the triggered actions are
+//! queued to the durable actions queue, from which they are executed in the
+//! background; executions are acknowledged once complete, so that they are not
+//! lost if the director stops midway (i.e. they are executed at least once)
+//!
+//! Failed executions are retried according to each executor retry policy;
+//! executions which still fail are moved to the failed actions queue (with the
+//! error in the `error` header), from which they can be replayed by moving them
+//! back to the actions queue (e.g. via the RabbitMQ management shovel)
+
+use crate::config::{ExecutorConfig, ExecutorKind, RetryPolicy, SyslogFormat};
+use amqprs::{
+    channel::{
+        BasicAckArguments, BasicPublishArguments, BasicQosArguments, BasicRejectArguments, Channel,
+        ConsumerMessage, ExchangeDeclareArguments, QueueDeclareArguments,
+    },
+    BasicProperties, FieldTable,
+};
+use serde::{Deserialize, Serialize};
+use shared::scene::{Action, Severity, Verdict, WorkActions};
+use std::{collections::HashMap, sync::Arc};
+use tokio::io::AsyncWriteExt;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const EXECUTIONS_COUNT: &str = "director_action_executions_total";
+const RETRIES_COUNT: &str = "director_action_retries_total";
+const EXECUTION_TIME: &str = "director_action_execution_time_seconds";
+
+/// The message type of the events published by AMQP executors
+const ACTION_EVENT_TYPE: &str = "ActionEvent";
+/// The name of the queue holding the pending action executions
+pub const ACTIONS_QUEUE_NAME: &str = "CTX-Actions";
+/// The name of the queue holding the failed action executions
+const FAILED_ACTIONS_QUEUE_NAME: &str = "CTX-FailedActions";
+/// The message type of the queued action executions
+const EXECUTION_TYPE: &str = "action.execution";
+
+/// The event handed to executors
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActionEvent {
+    /// The work id
+    pub work_id: String,
+    /// The time the scenarios were applied
+    pub t: f64,
+    /// The final verdict of the work
+    pub final_verdict: Verdict,
+    /// The name of the scenario which triggered the action
+    pub scenario: String,
+    /// The action
+    pub action: Action,
+}
+
+/// A queued action execution
+#[derive(Debug, Serialize, Deserialize)]
+struct Execution {
+    /// The name of the executor
+    executor: String,
+    /// The event to hand to the executor
+    event: ActionEvent,
+}
+
+/// A configured executor
+struct Executor {
+    name: String,
+    /// The executor type, used as the metrics label
+    kind: &'static str,
+    target: Target,
+    retry: RetryPolicy,
+}
+
+/// The executor target
+enum Target {
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        headers: HashMap<String, String>,
+    },
+    Amqp {
+        channel: Channel,
+        exchange: String,
+        routing_key: String,
+    },
+    Syslog {
+        address: String,
+        tcp: bool,
+        format: SyslogFormat,
+        facility: u8,
+    },
+    File {
+        path: String,
+        // Note: serializes writes so that lines do not interleave
+        lock: tokio::sync::Mutex<()>,
+    },
+}
+
+impl Executor {
+    /// Executes the action once
+    async fn execute(&self, event: &ActionEvent) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.target {
+            Target::Webhook {
+                client,
+                url,
+                headers,
+            } => {
+                let mut req = client.post(url).json(event);
+                for (k, v) in headers {
+                    req = req.header(k, v);
+                }
+                req.send().await?.error_for_status()?;
+            }
+            Target::Amqp {
+                channel,
+                exchange,
+                routing_key,
+            } => {
+                let bprops = BasicProperties::default()
+                    .with_content_type(shared::MSG_CONTENT_TYPE)
+                    .with_message_type(ACTION_EVENT_TYPE)
+                    .with_persistence(true)
+                    .finish();
+                let args = BasicPublishArguments::new(exchange, routing_key);
+                channel
+                    .basic_publish(bprops, serde_json::to_vec(event)?, args)
+                    .await?;
+            }
+            Target::Syslog {
+                address,
+                tcp,
+                format,
+                facility,
+            } => {
+                let msg = match format {
+                    SyslogFormat::Cef => to_cef(event),
+                    SyslogFormat::Json => serde_json::to_string(event)?,
+                };
+                let pri = u16::from(*facility) * 8 + syslog_severity(event.action.severity);
+                // Note: the timestamp and hostname are left for the server to fill in
+                let line = format!("<{pri}>1 - - contextal-director - - - {msg}");
+                if *tcp {
+                    let mut stream = tokio::net::TcpStream::connect(address).await?;
+                    stream.write_all(format!("{line}\n").as_bytes()).await?;
+                    stream.shutdown().await?;
+                } else {
+                    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+                    socket.send_to(line.as_bytes(), address).await?;
+                }
+            }
+            Target::File { path, lock } => {
+                let mut line = serde_json::to_vec(event)?;
+                line.push(b'\n');
+                let _guard = lock.lock().await;
+                // Note: the file is reopened each time to play well with rotation
+                let mut f = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                f.write_all(&line).await?;
+                f.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the action, retrying on failure
+    ///
+    /// Returns the last error if all the attempts failed
+    async fn run(&self, event: &ActionEvent) -> Result<(), String> {
+        let attempts = self.retry.get_attempts();
+        let mut backoff = self.retry.get_backoff();
+        let mut attempt = 1;
+        loop {
+            let start = std::time::Instant::now();
+            // Note: the error is stringified as it is not Send
+            let res = self.execute(event).await.map_err(|e| e.to_string());
+            metrics::histogram!(EXECUTION_TIME, "kind" => self.kind)
+                .record(start.elapsed().as_secs_f64());
+            match res {
+                Ok(()) => {
+                    debug!(
+                        "Action {} for work {} executed by {}",
+                        event.action.name, event.work_id, self.name
+                    );
+                    metrics::counter!(
+                        EXECUTIONS_COUNT,
+                        "kind" => self.kind,
+                        "outcome" => "success",
+                    )
+                    .increment(1);
+                    return Ok(());
+                }
+                Err(e) if attempt < attempts => {
+                    warn!(
+                        "Executor {} failed (attempt {}/{}), retrying in {}ms: {}",
+                        self.name,
+                        attempt,
+                        attempts,
+                        backoff.as_millis(),
+                        e
+                    );
+                    metrics::counter!(RETRIES_COUNT, "kind" => self.kind).increment(1);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(std::time::Duration::from_secs(60));
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!(
+                        "Executor {} failed to execute action {} for work {}: {}",
+                        self.name, event.action.name, event.work_id, e
+                    );
+                    metrics::counter!(
+                        EXECUTIONS_COUNT,
+                        "kind" => self.kind,
+                        "outcome" => "failure",
+                    )
+                    .increment(1);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// The action executors
+pub struct Executors {
+    executors: HashMap<String, Arc<Executor>>,
+    channel: Channel,
+}
+
+/// Declares a durable queue for action executions
+async fn declare_queue(channel: &Channel, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    debug!("Declaring the action queue \"{name}\"...");
+    let mut args = FieldTable::new();
+    args.insert("x-queue-type".try_into().unwrap(), "quorum".into());
+    let qargs = QueueDeclareArguments::new(name)
+        .durable(true)
+        .arguments(args)
+        .finish();
+    let (_, message_count, consumer_count) = channel
+        .queue_declare(qargs)
+        .await
+        .map_err(|e| {
+            error!("Failed to declare the action queue \"{name}\": {e}");
+            e
+        })?
+        .unwrap();
+    debug!(
+        "Action queue \"{name}\" successfully declared ({message_count} messages, {consumer_count} consumers)"
+    );
+    Ok(())
+}
+
+impl Executors {
+    /// Creates the configured executors
+    ///
+    /// The `channel` is used to queue and consume the action executions and
+    /// by the AMQP executors (if any); the action queues and the AMQP executor
+    /// exchanges are declared here
+    ///
+    /// Note: the `max_concurrent` executions are enforced via the channel prefetch
+    pub async fn new(
+        config: &HashMap<String, ExecutorConfig>,
+        max_concurrent: usize,
+        channel: &Channel,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        metrics::describe_counter!(EXECUTIONS_COUNT, "Number of action executions");
+        metrics::describe_counter!(RETRIES_COUNT, "Number of action execution retries");
+        metrics::describe_histogram!(
+            EXECUTION_TIME,
+            metrics::Unit::Seconds,
+            "Action execution time"
+        );
+        let mut executors = HashMap::new();
+        for (name, cfg) in config {
+            let (kind, target) = match &cfg.kind {
+                ExecutorKind::Webhook {
+                    url,
+                    headers,
+                    timeout_ms,
+                } => (
+                    "webhook",
+                    Target::Webhook {
+                        client: reqwest::Client::builder()
+                            .timeout(std::time::Duration::from_millis(
+                                timeout_ms.unwrap_or(10000),
+                            ))
+                            .build()?,
+                        url: url.clone(),
+                        headers: headers.clone(),
+                    },
+                ),
+                ExecutorKind::Amqp {
+                    exchange,
+                    exchange_type,
+                    routing_key,
+                } => {
+                    let exchange_type = exchange_type.as_deref().unwrap_or("topic");
+                    debug!("Declaring the {exchange_type} exchange \"{exchange}\" for executor {name}...");
+                    channel
+                        .exchange_declare(
+                            ExchangeDeclareArguments::new(exchange, exchange_type)
+                                .durable(true)
+                                .finish(),
+                        )
+                        .await
+                        .map_err(|e| {
+                            error!("Failed to declare the exchange of executor {name}: {e}");
+                            e
+                        })?;
+                    (
+                        "amqp",
+                        Target::Amqp {
+                            channel: channel.clone(),
+                            exchange: exchange.clone(),
+                            routing_key: routing_key.clone(),
+                        },
+                    )
+                }
+                ExecutorKind::Syslog {
+                    address,
+                    tcp,
+                    format,
+                    facility,
+                } => {
+                    let facility = facility.unwrap_or(16);
+                    if facility > 23 {
+                        return Err(format!("Invalid syslog facility for executor {name}").into());
+                    }
+                    (
+                        "syslog",
+                        Target::Syslog {
+                            address: address.clone(),
+                            tcp: *tcp,
+                            format: *format,
+                            facility,
+                        },
+                    )
+                }
+                ExecutorKind::File { path } => (
+                    "file",
+                    Target::File {
+                        path: path.clone(),
+                        lock: tokio::sync::Mutex::new(()),
+                    },
+                ),
+            };
+            debug!("Executor {} configured", name);
+            executors.insert(
+                name.clone(),
+                Arc::new(Executor {
+                    name: name.clone(),
+                    kind,
+                    target,
+                    retry: cfg.retry.clone(),
+                }),
+            );
+        }
+        declare_queue(channel, ACTIONS_QUEUE_NAME).await?;
+        declare_queue(channel, FAILED_ACTIONS_QUEUE_NAME).await?;
+        let prefetch = u16::try_from(max_concurrent).unwrap_or(u16::MAX);
+        channel
+            .basic_qos(BasicQosArguments::new(0, prefetch, false))
+            .await
+            .map_err(|e| {
+                error!("Failed to set the action executions prefetch: {e}");
+                e
+            })?;
+        info!("Configured {} action executors", executors.len());
+        Ok(Self {
+            executors,
+            channel: channel.clone(),
+        })
+    }
+
+    /// Queues the triggered actions for execution
+    ///
+    /// Actions of monitored scenarios are not executed
+    ///
+    /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
+    pub async fn enqueue(&self, work: &WorkActions) -> Result<(), Box<dyn std::error::Error>> {
+        let t = work
+            .t
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        for wa in work.actions.iter() {
+            if wa.monitor {
+                continue;
+            }
+            for name in wa.action.executors.iter() {
+                let execution = Execution {
+                    executor: name.clone(),
+                    event: ActionEvent {
+                        work_id: work.work_id.clone(),
+                        t,
+                        final_verdict: work.verdict,
+                        scenario: wa.scenario.clone(),
+                        action: wa.action.clone(),
+                    },
+                };
+                let bprops = BasicProperties::default()
+                    .with_content_type(shared::MSG_CONTENT_TYPE)
+                    .with_message_type(EXECUTION_TYPE)
+                    .with_persistence(true)
+                    .finish();
+                let args = BasicPublishArguments::new("", ACTIONS_QUEUE_NAME);
+                self.channel
+                    .basic_publish(bprops, serde_json::to_vec(&execution)?, args)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to queue action execution: {e}");
+                        e
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the execution of a queued action in the background
+    ///
+    /// The message is acknowledged once the execution is complete; executions
+    /// which fail are moved to the failed actions queue
+    ///
+    /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
+    pub async fn execute(&self, msg: ConsumerMessage) -> Result<(), Box<dyn std::error::Error>> {
+        // Note: unwaps on msg are safe - see amqprs docs
+        let delivery_tag = msg.deliver.unwrap().delivery_tag();
+        let content = msg.content.unwrap();
+        let execution: Execution = match serde_json::from_slice(&content) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Action execution message has invalid payload: {}", e);
+                self.channel
+                    .basic_reject(BasicRejectArguments::new(delivery_tag, false))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let channel = self.channel.clone();
+        let Some(executor) = self.executors.get(&execution.executor) else {
+            warn!(
+                "Scenario {} references unknown executor {}",
+                execution.event.scenario, execution.executor
+            );
+            metrics::counter!(
+                EXECUTIONS_COUNT,
+                "kind" => "unknown",
+                "outcome" => "failure",
+            )
+            .increment(1);
+            let e = format!("Unknown executor {}", execution.executor);
+            return complete(&channel, delivery_tag, content, Err(e)).await;
+        };
+        let executor = executor.clone();
+        tokio::spawn(async move {
+            let res = executor.run(&execution.event).await;
+            // Note: the execution is retried on redelivery if this fails
+            complete(&channel, delivery_tag, content, res).await.ok();
+        });
+        Ok(())
+    }
+}
+
+/// Acknowledges a queued execution, moving it to the failed actions queue first
+/// if it failed
+async fn complete(
+    channel: &Channel,
+    delivery_tag: u64,
+    content: Vec<u8>,
+    res: Result<(), String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Err(e) = res {
+        let mut headers = FieldTable::new();
+        headers.insert("error".try_into().unwrap(), e.into());
+        let bprops = BasicProperties::default()
+            .with_content_type(shared::MSG_CONTENT_TYPE)
+            .with_message_type(EXECUTION_TYPE)
+            .with_persistence(true)
+            .with_headers(headers)
+            .finish();
+        let args = BasicPublishArguments::new("", FAILED_ACTIONS_QUEUE_NAME);
+        channel
+            .basic_publish(bprops, content, args)
+            .await
+            .map_err(|e| {
+                error!("Failed to record failed action execution: {e}");
+                e
+            })?;
+    }
+    channel
+        .basic_ack(BasicAckArguments::new(delivery_tag, false))
+        .await
+        .map_err(|e| {
+            error!("Failed to ack action execution with delivery tag {delivery_tag}: {e}");
+            e
+        })?;
+    Ok(())
+}
+
+/// Maps the action severity to the syslog severity
+fn syslog_severity(severity: Severity) -> u16 {
+    match severity {
+        Severity::Critical => 2,
+        Severity::High => 3,
+        Severity::Medium => 4,
+        Severity::Low => 5,
+        Severity::Info => 6,
+    }
+}
+
+/// Formats the event as a CEF record
+fn to_cef(event: &ActionEvent) -> String {
+    fn header(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('|', "\\|")
+    }
+    fn ext(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('=', "\\=")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    }
+    let severity = match event.action.severity {
+        Severity::Info => 1,
+        Severity::Low => 3,
+        Severity::Medium => 5,
+        Severity::High => 8,
+        Severity::Critical => 10,
+    };
+    format!(
+        "CEF:0|Contextal|Platform|{}|{}|{}|{}|act={} rt={} cs1Label=workId cs1={} cs2Label=finalVerdict cs2={} cs3Label=mitreTechniques cs3={}",
+        header(env!("CARGO_PKG_VERSION")),
+        header(&event.scenario),
+        header(&event.action.name),
+        severity,
+        event.action.verdict,
+        (event.t * 1000.0) as u64,
+        ext(&event.work_id),
+        event.final_verdict,
+        ext(&event.action.mitre_techniques.join(",")),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_execution_message() {
+        let execution = Execution {
+            executor: "siem".to_string(),
+            event: ActionEvent {
+                work_id: "w".to_string(),
+                t: 1700000000.5,
+                final_verdict: Verdict::Block,
+                scenario: "scenario".to_string(),
+                action: serde_json::from_value(serde_json::json!({
+                    "name": "quarantine",
+                    "verdict": "QUARANTINE",
+                    "executors": ["siem"],
+                }))
+                .unwrap(),
+            },
+        };
+        // Note: queued executions must survive the trip (and replays)
+        let json = serde_json::to_vec(&execution).unwrap();
+        let back: Execution = serde_json::from_slice(&json).unwrap();
+        assert_eq!(serde_json::to_vec(&back).unwrap(), json);
+        assert_eq!(back.executor, "siem");
+        assert_eq!(back.event.action.executors, ["siem"]);
+    }
+
+    #[test]
+    fn test_cef() {
+        let event = ActionEvent {
+            work_id: "w=1".to_string(),
+            t: 1700000000.5,
+            final_verdict: Verdict::Block,
+            scenario: "bad|scenario".to_string(),
+            action: serde_json::from_value(serde_json::json!({
+                "name": "quarantine",
+                "verdict": "QUARANTINE",
+                "severity": "high",
+                "mitre_techniques": ["T1566.001"],
+            }))
+            .unwrap(),
+        };
+        assert_eq!(
+            to_cef(&event),
+            format!(
+                "CEF:0|Contextal|Platform|{}|bad\\|scenario|quarantine|8|act=QUARANTINE rt=1700000000500 cs1Label=workId cs1=w\\=1 cs2Label=finalVerdict cs2=BLOCK cs3Label=mitreTechniques cs3=T1566.001",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
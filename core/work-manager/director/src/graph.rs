@@ -3,31 +3,65 @@
 //! This module interacts with the GraphDB
 
 use futures::{pin_mut, stream::TryStreamExt};
-use pgrules::Interval;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
 const SCENARIOS_COUNT: &str = "director_scenarios_total";
 const WORKS_COUNT: &str = "director_works_total";
 const PROCESSING_TIME: &str = "director_process_time_seconds";
+const BATCHED_COUNT: &str = "director_batched_scenarios_total";
+const BATCH_FALLBACK_COUNT: &str = "director_batch_fallbacks_total";
+
+/// A group of scenarios evaluated together
+struct Batch {
+    /// The combined statement (`None` if the scenarios failed to compile
+    /// together and must be evaluated one by one)
+    stmt: Option<tokio_postgres::Statement>,
+    /// The batch members, as indexes into the scenarios list
+    members: std::ops::Range<usize>,
+    /// The orgs whose works the batch members apply to (empty means all)
+    orgs: Vec<String>,
+}
+
+impl Batch {
+    /// Returns true if the batch members apply to works of the given org
+    fn applies_to(&self, org: Option<&str>) -> bool {
+        self.orgs.is_empty() || org.is_some_and(|org| self.orgs.iter().any(|o| o == org))
+    }
+}
+
+/// Splits the scenarios into batches of scenarios applying to the same orgs
+///
+/// The scenario orgs are expected to be sorted, so that equal orgs are adjacent
+fn batch_ranges(orgs: &[Vec<String>], batch_size: usize) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < orgs.len() {
+        let end = orgs[start..]
+            .iter()
+            .take(batch_size)
+            .position(|o| *o != orgs[start])
+            .map(|n| start + n)
+            .unwrap_or((start + batch_size).min(orgs.len()));
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
 
 /// The graph database connector
 pub struct GraphDB {
     read_client: tokio_postgres::Client,
     write_client: tokio_postgres::Client,
     scenarios: Vec<(i64, tokio_postgres::Statement)>,
-    get_before: tokio_postgres::Statement,
-    get_before_count: tokio_postgres::Statement,
-    get_after: tokio_postgres::Statement,
-    get_after_count: tokio_postgres::Statement,
+    batches: Vec<Batch>,
+    batch_size: usize,
+    neighbors: shared::global::Neighbors,
+    get_org: tokio_postgres::Statement,
     save_actions: tokio_postgres::Statement,
-}
-
-async fn portal_next(
-    txn: &mut tokio_postgres::Transaction<'_>,
-    portal: &tokio_postgres::Portal,
-) -> Result<Option<tokio_postgres::row::Row>, tokio_postgres::error::Error> {
-    txn.query_portal(portal, 1).await.map(|mut rows| rows.pop())
+    /// The names of the loaded scenarios, by id
+    names: std::collections::HashMap<i64, String>,
+    stats: crate::stats::Stats,
 }
 
 impl GraphDB {
@@ -41,6 +75,14 @@ impl GraphDB {
             metrics::Unit::Seconds,
             "Work processing time"
         );
+        metrics::describe_gauge!(
+            BATCHED_COUNT,
+            "Number of scenarios evaluated in combined queries"
+        );
+        metrics::describe_counter!(
+            BATCH_FALLBACK_COUNT,
+            "Number of combined queries which failed and were evaluated one by one"
+        );
 
         // Read side
         let read_config = &config.read_db;
@@ -78,66 +120,7 @@ impl GraphDB {
             );
             return Err("Wrong database version".into());
         }
-        let qtypes = &[
-            tokio_postgres::types::Type::TEXT,
-            tokio_postgres::types::Type::INTERVAL,
-        ];
-        let get_before = read_client
-            .prepare_typed(
-                "
-            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
-            SELECT work_id, ref.t - objects.t dt
-            FROM objects, ref
-            WHERE
-                objects.t >= ref.t - $2 AND
-                objects.t <= ref.t AND
-                work_id != $1 AND
-                is_entry
-            ORDER BY objects.t DESC",
-                qtypes,
-            )
-            .await?;
-        let get_before_count = read_client
-            .prepare_typed(
-                "
-            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
-            SELECT COUNT(*)
-            FROM objects, ref
-            WHERE
-                objects.t >= ref.t - $2 AND
-                objects.t <= ref.t AND
-                work_id != $1 AND
-                is_entry",
-                qtypes,
-            )
-            .await?;
-        let get_after = read_client
-            .prepare_typed(
-                "
-            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
-            SELECT work_id, objects.t - ref.t AS dt
-            FROM objects, ref
-            WHERE
-                objects.t > ref.t AND
-                objects.t <= ref.t + $2 AND
-                is_entry
-            ORDER BY objects.t ASC",
-                qtypes,
-            )
-            .await?;
-        let get_after_count = read_client
-            .prepare_typed(
-                "
-            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
-            SELECT COUNT(*)
-            FROM objects, ref
-            WHERE
-                objects.t > ref.t AND
-                objects.t <= ref.t + $2 AND
-                is_entry",
-                qtypes,
-            )
-            .await?;
+        let neighbors = shared::global::Neighbors::prepare(&read_client).await?;
         debug!(
             "Connected to GraphDB {} at {}:{} in read-only mode",
             read_config.dbname, read_config.host, read_config.port
@@ -184,18 +167,26 @@ impl GraphDB {
             );
             return Err("Wrong database version".into());
         }
+        let get_org = read_client
+            .prepare("SELECT org FROM objects WHERE is_entry AND work_id = $1 LIMIT 1")
+            .await?;
         let save_actions = write_client
-            .prepare("INSERT INTO results (work_id, actions) VALUES ($1, $2)")
+            .prepare("INSERT INTO results (work_id, actions, verdict) VALUES ($1, $2, $3)")
             .await?;
         let mut res = Self {
             read_client,
             write_client,
             scenarios: Vec::new(),
-            get_before,
-            get_before_count,
-            get_after,
-            get_after_count,
+            batches: Vec::new(),
+            batch_size: config.get_scenario_batch_size(),
+            neighbors,
+            get_org,
             save_actions,
+            names: std::collections::HashMap::new(),
+            stats: crate::stats::Stats::new(
+                config.get_stats_flush_interval(),
+                config.get_stats_retention_days(),
+            ),
         };
         res.load_scenarios().await?;
         Ok(res)
@@ -203,7 +194,10 @@ impl GraphDB {
 
     #[tracing::instrument(level=tracing::Level::ERROR, skip(self))]
     pub async fn load_scenarios(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.flush_stats().await;
         self.scenarios.clear();
+        self.batches.clear();
+        self.names.clear();
         let txn = self.read_client.transaction().await?;
         // A portal is employed here in order to avoid deadlocks with nested queries
         // This typically happens when the network buffer is filled with the outer query
@@ -213,6 +207,8 @@ impl GraphDB {
         let portal = txn.bind("SELECT id, def FROM scenarios", &[]).await?;
         let mut n_scenarios = 0usize;
         let mut n_actual_scenarios = 0usize;
+        let mut n_disabled = 0usize;
+        let mut loaded: Vec<(Vec<String>, i64, tokio_postgres::Statement, String)> = Vec::new();
         loop {
             let scn_it = txn.query_portal_raw(&portal, 1).await?;
             pin_mut!(scn_it);
@@ -231,6 +227,11 @@ impl GraphDB {
                     continue;
                 }
             };
+            if !scenario.enabled {
+                debug!("Scenario {} (id {}) is disabled", scenario.name, id);
+                n_disabled += 1;
+                continue;
+            }
             if !scenario.is_compatible() {
                 warn!(
                     "Scenario {} (id {}) skipped due to unsatisfied version requirements ({})",
@@ -250,14 +251,18 @@ impl GraphDB {
                 continue;
             }
             let query = format!(
-                "SELECT t, def FROM scenarios WHERE id = {} AND EXISTS (SELECT 1 {})",
+                "SELECT id, t, def FROM scenarios WHERE id = {} AND EXISTS (SELECT 1 {})",
                 id,
                 rule.unwrap().query
             );
             match txn.prepare(&query).await {
                 Ok(stmt) => {
                     debug!("Scenario {} (id {}): {}", scenario.name, id, query);
-                    self.scenarios.push((id, stmt));
+                    self.names.insert(id, scenario.name);
+                    let mut orgs = scenario.orgs;
+                    orgs.sort_unstable();
+                    orgs.dedup();
+                    loaded.push((orgs, id, stmt, query));
                     n_actual_scenarios += 1;
                 }
                 Err(e) => {
@@ -268,32 +273,144 @@ impl GraphDB {
                 }
             }
         }
+        // Combine the local queries which compiled individually into batches
+        // Note: scenarios are grouped by orgs, so that the batches which do
+        // not apply to the work org can be skipped entirely
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut orgs: Vec<Vec<String>> = Vec::with_capacity(loaded.len());
+        let mut queries: Vec<String> = Vec::with_capacity(loaded.len());
+        for (scn_orgs, id, stmt, query) in loaded {
+            self.scenarios.push((id, stmt));
+            orgs.push(scn_orgs);
+            queries.push(query);
+        }
+        let mut n_batched = 0usize;
+        for members in batch_ranges(&orgs, self.batch_size) {
+            let stmt = if members.len() > 1 {
+                let query = queries[members.clone()].join(" UNION ALL ");
+                match txn.prepare(&query).await {
+                    Ok(stmt) => {
+                        n_batched += members.len();
+                        Some(stmt)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Scenarios {}..{} failed to compile together, evaluating them one by one: {}",
+                            members.start, members.end, e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let orgs = orgs[members.start].clone();
+            self.batches.push(Batch {
+                stmt,
+                members,
+                orgs,
+            });
+        }
         txn.commit().await?;
         self.scenarios.shrink_to_fit();
         info!(
-            "Loaded {} scenarios out of {}",
-            n_actual_scenarios, n_scenarios
+            "Loaded {} scenarios out of {} ({} disabled)",
+            n_actual_scenarios, n_scenarios, n_disabled
         );
         metrics::gauge!(SCENARIOS_COUNT).set(n_actual_scenarios as f64);
+        metrics::gauge!(BATCHED_COUNT).set(n_batched as f64);
         Ok(())
     }
 
+    /// Writes the accumulated scenario statistics
+    ///
+    /// Note: besides being flushed while works are processed, the statistics
+    /// are expected to be flushed periodically, regardless of traffic
+    pub async fn flush_stats(&mut self) {
+        if let Err(e) = self.stats.flush(&mut self.write_client, &self.names).await {
+            warn!("Failed to flush scenario statistics: {}", e);
+        }
+    }
+
+    /// Returns the interval between scenario statistics flushes
+    pub fn stats_flush_interval(&self) -> std::time::Duration {
+        self.stats.flush_interval()
+    }
+
     #[tracing::instrument(level=tracing::Level::ERROR, skip(self))]
     pub async fn apply_scenarios(
         &mut self,
         work_id: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<shared::scene::WorkActions, Box<dyn std::error::Error + Send + Sync>> {
         let start = std::time::Instant::now();
 
-        let mut actions: Vec<shared::scene::WorkAction> = Vec::new();
-        for (id, stmt) in self.scenarios.iter() {
-            debug!("Testing scenario {} for local matches...", id);
-            let row = self.read_client.query_opt(stmt, &[&work_id]).await?;
-            if row.is_none() {
-                debug!("Scenario {}: no local match", id);
+        // Find the work org, if any scenario is restricted to some orgs
+        let work_org: Option<String> = if self.batches.iter().any(|b| !b.orgs.is_empty()) {
+            self.read_client
+                .query_opt(&self.get_org, &[&work_id])
+                .await?
+                .map(|row| row.try_get("org"))
+                .transpose()?
+        } else {
+            None
+        };
+
+        // Find the local matches
+        let mut local_matches: Vec<(usize, tokio_postgres::Row)> = Vec::new();
+        for batch in self.batches.iter() {
+            if !batch.applies_to(work_org.as_deref()) {
+                debug!("Scenarios {:?} do not apply to the work org", batch.members);
                 continue;
             }
-            let row = row.unwrap();
+            if let Some(stmt) = &batch.stmt {
+                debug!("Testing scenarios {:?} for local matches...", batch.members);
+                let batch_start = std::time::Instant::now();
+                match self.read_client.query(stmt, &[&work_id]).await {
+                    Ok(rows) => {
+                        // Note: the batch time is split evenly among its members
+                        let share =
+                            batch_start.elapsed().as_secs_f64() / batch.members.len() as f64;
+                        for (id, _) in self.scenarios[batch.members.clone()].iter() {
+                            self.stats.record_local(*id, share);
+                        }
+                        for row in rows {
+                            let id: i64 = row.try_get("id")?;
+                            let pos = self.scenarios[batch.members.clone()]
+                                .iter()
+                                .position(|(scn_id, _)| *scn_id == id)
+                                .ok_or("Unexpected scenario in batch results")?;
+                            local_matches.push((batch.members.start + pos, row));
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to evaluate scenarios {:?} together, evaluating them one by one: {}",
+                            batch.members, e
+                        );
+                        metrics::counter!(BATCH_FALLBACK_COUNT).increment(1);
+                    }
+                }
+            }
+            for pos in batch.members.clone() {
+                let (id, stmt) = &self.scenarios[pos];
+                debug!("Testing scenario {} for local matches...", id);
+                let scenario_start = std::time::Instant::now();
+                let row = self.read_client.query_opt(stmt, &[&work_id]).await?;
+                self.stats
+                    .record_local(*id, scenario_start.elapsed().as_secs_f64());
+                match row {
+                    Some(row) => local_matches.push((pos, row)),
+                    None => debug!("Scenario {}: no local match", id),
+                }
+            }
+        }
+        // Note: actions are reported in scenario order
+        local_matches.sort_unstable_by_key(|(pos, _)| *pos);
+
+        let mut actions: Vec<shared::scene::WorkAction> = Vec::new();
+        for (pos, row) in local_matches {
+            let id = self.scenarios[pos].0;
             debug!("Scenario {} has local match...", id);
             let ctime: std::time::SystemTime = row.try_get("t")?;
             let ctime = ctime
@@ -303,163 +420,100 @@ impl GraphDB {
             let scenario: shared::scene::Scenario = serde_json::from_value(json_scenario).unwrap();
             if let Some(context) = scenario.context {
                 debug!("Testing scenario {} for global matches...", id);
-                let global_rule = pgrules::parse_to_sql(
-                    &context.global_query,
-                    pgrules::QueryType::ScenarioGlobal,
-                );
-                if let Err(e) = global_rule {
-                    warn!(
-                        "Scenario {} skipped due to global query compilation error ({}): {}",
-                        scenario.name, context.global_query, e
-                    );
-                    continue;
-                }
-                let global_parsed = global_rule.unwrap();
-                let Some(global_settings) = global_parsed.global_query_settings else {
-                    warn!(
-                        "Scenario {} skipped due to missing global query settings",
-                        scenario.name,
-                    );
-                    continue;
+                let global_query = match shared::global::GlobalQuery::compile(&context.global_query)
+                {
+                    Ok(global_query) => global_query,
+                    Err(e) => {
+                        warn!(
+                            "Scenario {} skipped due to global query compilation error ({}): {}",
+                            scenario.name, context.global_query, e
+                        );
+                        continue;
+                    }
                 };
-                let time_window = global_settings.time_window;
-                let required_matches = global_settings.matches;
-                let has_with_clause = global_parsed.with_clause.is_some();
-                let global_query = format!(
-                    "{} SELECT EXISTS (SELECT 1 {})",
-                    global_parsed.with_clause.unwrap_or_default(),
-                    global_parsed.query
-                );
-                let mut txn = self
+                let txn = self
                     .read_client
                     .build_transaction()
                     .read_only(true)
                     .isolation_level(tokio_postgres::IsolationLevel::RepeatableRead)
                     .start()
                     .await?;
-                let global_stmt = txn.prepare(&global_query).await?;
-                let params: &[&(dyn tokio_postgres::types::ToSql + Sync)] =
-                    &[&work_id, &time_window];
-                let before_it = txn.bind(&self.get_before, params).await?;
-                let after_it = txn.bind(&self.get_after, params).await?;
-                let avail_before: i64 = txn.query_one(&self.get_before_count, params).await?.get(0);
-                let avail_after: i64 = txn.query_one(&self.get_after_count, params).await?.get(0);
-                let avail_neighbors: u32 = avail_before
-                    .saturating_add(avail_after)
-                    .try_into()
-                    .unwrap_or(u32::MAX);
-                let max_neighbors = global_settings.max_neighbors.unwrap_or(avail_neighbors);
-                let total_neigbors = max_neighbors.min(avail_neighbors);
-                let mut before = portal_next(&mut txn, &before_it).await?;
-                debug!("Before: {:?}", before);
-                let mut after = portal_next(&mut txn, &after_it).await?;
-                debug!("After: {:?}", after);
-                let mut nmatches = 0u32;
-                let target_matches = match &required_matches {
-                    pgrules::Matches::MoreThan(req) => *req,
-                    pgrules::Matches::MoreThanPercent(req) => {
-                        (f64::from(*req) / 100.0 * f64::from(total_neigbors)) as u32
-                    }
-                    pgrules::Matches::LessThan(req) => (*req).saturating_sub(1),
-                    pgrules::Matches::LessThanPercent(req) => {
-                        ((f64::from(*req) / 100.0 * f64::from(total_neigbors)) as u32)
-                            .saturating_sub(1)
-                    }
-                    pgrules::Matches::None => 0,
-                };
-                let mut attempts = 0u32;
-                for _ in 0..max_neighbors {
-                    attempts += 1;
-                    if nmatches > target_matches {
-                        break;
-                    }
-                    let neighbour_work_id = if let Some(before_row) = &before {
-                        if let Some(after_row) = &after {
-                            let before_t: Interval = before_row.try_get("dt")?;
-                            let after_t: Interval = after_row.try_get("dt")?;
-                            if before_t <= after_t {
-                                let work_id: String = before_row.try_get("work_id")?;
-                                before = portal_next(&mut txn, &before_it).await?;
-                                work_id
-                            } else {
-                                let work_id: String = after_row.try_get("work_id")?;
-                                after = portal_next(&mut txn, &after_it).await?;
-                                work_id
-                            }
-                        } else {
-                            let work_id: String = before_row.try_get("work_id")?;
-                            before = portal_next(&mut txn, &before_it).await?;
-                            work_id
-                        }
-                    } else if let Some(after_row) = &after {
-                        let work_id: String = after_row.try_get("work_id")?;
-                        after = portal_next(&mut txn, &after_it).await?;
-                        work_id
-                    } else {
-                        // not reached
-                        break;
-                    };
-                    let row = if has_with_clause {
-                        txn.query_one(&global_stmt, &[&neighbour_work_id, &work_id])
-                            .await?
-                    } else {
-                        txn.query_one(&global_stmt, &[&neighbour_work_id]).await?
-                    };
-
-                    let global_match: bool = row.try_get(0)?;
-                    if global_match {
-                        nmatches += 1;
-                    }
-                    debug!(
-                        "Scenario {}: {}global match on {} ({} matches so far) - lookup {}/{}",
-                        id,
-                        if global_match { "" } else { "no " },
-                        neighbour_work_id,
-                        nmatches,
-                        attempts,
-                        max_neighbors
-                    );
-                }
-                let matched = match required_matches {
-                    pgrules::Matches::MoreThan(_) | pgrules::Matches::MoreThanPercent(_) => {
-                        nmatches > target_matches
-                    }
-                    pgrules::Matches::LessThan(_) | pgrules::Matches::LessThanPercent(_) => {
-                        nmatches <= target_matches
-                    }
-                    pgrules::Matches::None => nmatches == 0,
-                };
-                debug!(
-                    "Scenario {}: condition ({}) {}met (matches: {}, target: {}, max_neighbors: {}, attempts: {})",
-                    id,
-                    required_matches,
-                    if matched { "" } else { "not "},
-                    nmatches,
-                    target_matches,
-                    max_neighbors,
-                    attempts,
-                );
+                let global_start = std::time::Instant::now();
+                let matched = global_query
+                    .matches(&txn, &self.neighbors, work_id, &id.to_string())
+                    .await?;
+                self.stats
+                    .record_global(id, global_start.elapsed().as_secs_f64());
                 if !matched {
                     continue;
                 }
             }
-            debug!("Action {} triggered for scenario {}", scenario.action, id);
+            debug!(
+                "Action {} ({}) triggered for scenario {}",
+                scenario.action.name, scenario.action.verdict, id
+            );
+            self.stats.record_match(id);
             actions.push(shared::scene::WorkAction {
                 scenario: scenario.name,
                 ctime,
                 action: scenario.action,
+                monitor: scenario.mode == shared::scene::ScenarioMode::Monitor,
             })
         }
 
+        let verdict = shared::scene::final_verdict(&actions);
+        debug!("Final verdict for {}: {}", work_id, verdict);
         self.write_client
             .execute(
                 &self.save_actions,
-                &[&work_id, &serde_json::to_value(actions).unwrap()],
+                &[
+                    &work_id,
+                    &serde_json::to_value(&actions).unwrap(),
+                    &verdict.to_string(),
+                ],
             )
             .await?;
 
         metrics::histogram!(PROCESSING_TIME).record(start.elapsed().as_secs_f64());
         metrics::counter!(WORKS_COUNT).increment(1);
-        Ok(())
+        if self.stats.is_due() {
+            self.flush_stats().await;
+        }
+        Ok(shared::scene::WorkActions {
+            work_id: work_id.to_string(),
+            t: std::time::SystemTime::now(),
+            verdict,
+            actions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batch_ranges() {
+        let orgs = |v: &[&str]| v.iter().map(|o| o.to_string()).collect::<Vec<String>>();
+        let scenarios = [
+            orgs(&[]),
+            orgs(&[]),
+            orgs(&[]),
+            orgs(&["a"]),
+            orgs(&["a", "b"]),
+            orgs(&["a", "b"]),
+            orgs(&["b"]),
+        ];
+        assert_eq!(batch_ranges(&scenarios, 2), [0..2, 2..3, 3..4, 4..6, 6..7]);
+        assert_eq!(batch_ranges(&scenarios, 10), [0..3, 3..4, 4..6, 6..7]);
+        assert!(batch_ranges(&scenarios[..0], 10).is_empty());
+        let batch = Batch {
+            stmt: None,
+            members: 0..1,
+            orgs: orgs(&["a", "b"]),
+        };
+        assert!(batch.applies_to(Some("b")));
+        assert!(!batch.applies_to(Some("c")));
+        assert!(!batch.applies_to(None));
     }
 }
@@ -1,6 +1,8 @@
 mod amqp;
 mod config;
+mod executor;
 mod graph;
+mod stats;
 
 use metrics_exporter_prometheus::PrometheusBuilder;
 use tokio::signal::unix;
@@ -51,7 +53,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let graphdb = graph::GraphDB::new(&config).await?;
-    let mut broker = amqp::Broker::new(&config.broker, graphdb).await?;
+    let mut broker = amqp::Broker::new(&config, graphdb).await?;
 
     info!("Director started");
     let ret = tokio::select!(
@@ -0,0 +1,238 @@
+//! Scenario statistics
+//!
+//! Hit counts and evaluation times are accumulated in memory in hourly buckets
+//! and periodically flushed to the `scenario_stats` table and to the metrics
+//!
+//! Statistics are keyed by scenario id, which is preserved across scenario edits
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const SCENARIO_EVALUATIONS: &str = "director_scenario_evaluations_total";
+const SCENARIO_MATCHES: &str = "director_scenario_matches_total";
+const SCENARIO_EVAL_TIME: &str = "director_scenario_evaluation_microseconds_total";
+const SCENARIO_LAST_HIT: &str = "director_scenario_last_hit_timestamp_seconds";
+
+/// The statistics of a scenario within an hour
+#[derive(Default)]
+struct Bucket {
+    evaluations: u64,
+    matches: u64,
+    local_time: f64,
+    global_evaluations: u64,
+    global_time: f64,
+    last_hit: Option<SystemTime>,
+}
+
+/// The scenario statistics accumulator
+pub struct Stats {
+    buckets: HashMap<(i64, SystemTime), Bucket>,
+    last_flush: Instant,
+    flush_interval: Duration,
+    retention_days: u32,
+}
+
+/// Returns the start of the hour of the given time
+fn hour_of(t: SystemTime) -> SystemTime {
+    let secs = t
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs - secs % 3600)
+}
+
+impl Stats {
+    pub fn new(flush_interval: Duration, retention_days: u32) -> Self {
+        metrics::describe_counter!(SCENARIO_EVALUATIONS, "Number of evaluations per scenario");
+        metrics::describe_counter!(SCENARIO_MATCHES, "Number of matches per scenario");
+        metrics::describe_counter!(
+            SCENARIO_EVAL_TIME,
+            metrics::Unit::Microseconds,
+            "Time spent evaluating each scenario (local and global)"
+        );
+        metrics::describe_gauge!(
+            SCENARIO_LAST_HIT,
+            metrics::Unit::Seconds,
+            "Time of the last match of each scenario"
+        );
+        Self {
+            buckets: HashMap::new(),
+            last_flush: Instant::now(),
+            flush_interval,
+            retention_days,
+        }
+    }
+
+    fn bucket(&mut self, id: i64) -> &mut Bucket {
+        self.buckets
+            .entry((id, hour_of(SystemTime::now())))
+            .or_default()
+    }
+
+    /// Accounts a local query evaluation
+    pub fn record_local(&mut self, id: i64, time: f64) {
+        let bucket = self.bucket(id);
+        bucket.evaluations += 1;
+        bucket.local_time += time;
+    }
+
+    /// Accounts a global query evaluation
+    pub fn record_global(&mut self, id: i64, time: f64) {
+        let bucket = self.bucket(id);
+        bucket.global_evaluations += 1;
+        bucket.global_time += time;
+    }
+
+    /// Accounts a match
+    pub fn record_match(&mut self, id: i64) {
+        let bucket = self.bucket(id);
+        bucket.matches += 1;
+        bucket.last_hit = Some(SystemTime::now());
+    }
+
+    /// Returns the interval between flushes
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    /// Returns true if the statistics are due for flushing
+    pub fn is_due(&self) -> bool {
+        self.last_flush.elapsed() >= self.flush_interval
+    }
+
+    /// Writes the accumulated statistics to the database and to the metrics
+    ///
+    /// The `names` map the scenario ids to their names (for metric labels)
+    ///
+    /// Note: on failure the statistics are retained and written with the next flush
+    pub async fn flush(
+        &mut self,
+        client: &mut tokio_postgres::Client,
+        names: &HashMap<i64, String>,
+    ) -> Result<(), tokio_postgres::Error> {
+        self.last_flush = Instant::now();
+        if self.buckets.is_empty() {
+            return Ok(());
+        }
+        let txn = client.transaction().await?;
+        let stmt = txn
+            .prepare(
+                "INSERT INTO scenario_stats
+                   (scenario_id, hour, evaluations, matches, local_time, global_evaluations, global_time, last_hit)
+                 SELECT $1, $2, $3, $4, $5, $6, $7, $8
+                 WHERE EXISTS (SELECT 1 FROM scenarios WHERE id = $1)
+                 ON CONFLICT (scenario_id, hour) DO UPDATE SET
+                   evaluations = scenario_stats.evaluations + EXCLUDED.evaluations,
+                   matches = scenario_stats.matches + EXCLUDED.matches,
+                   local_time = scenario_stats.local_time + EXCLUDED.local_time,
+                   global_evaluations = scenario_stats.global_evaluations + EXCLUDED.global_evaluations,
+                   global_time = scenario_stats.global_time + EXCLUDED.global_time,
+                   last_hit = GREATEST(scenario_stats.last_hit, EXCLUDED.last_hit)",
+            )
+            .await?;
+        for ((id, hour), bucket) in self.buckets.iter() {
+            txn.execute(
+                &stmt,
+                &[
+                    id,
+                    hour,
+                    &(bucket.evaluations as i64),
+                    &(bucket.matches as i64),
+                    &bucket.local_time,
+                    &(bucket.global_evaluations as i64),
+                    &bucket.global_time,
+                    &bucket.last_hit,
+                ],
+            )
+            .await?;
+        }
+        txn.execute(
+            "DELETE FROM scenario_stats WHERE hour < now() - make_interval(days => $1)",
+            &[&(self.retention_days as i32)],
+        )
+        .await?;
+        txn.commit().await?;
+        self.update_metrics(names);
+        debug!("Flushed statistics for {} scenarios", self.buckets.len());
+        self.buckets.clear();
+        Ok(())
+    }
+
+    /// Adds the accumulated statistics to the metrics
+    fn update_metrics(&self, names: &HashMap<i64, String>) {
+        for ((id, _), bucket) in self.buckets.iter() {
+            let Some(name) = names.get(id) else {
+                continue;
+            };
+            metrics::counter!(SCENARIO_EVALUATIONS, "scenario" => name.clone())
+                .increment(bucket.evaluations);
+            metrics::counter!(SCENARIO_MATCHES, "scenario" => name.clone())
+                .increment(bucket.matches);
+            metrics::counter!(SCENARIO_EVAL_TIME, "scenario" => name.clone(), "phase" => "local")
+                .increment((bucket.local_time * 1e6) as u64);
+            metrics::counter!(SCENARIO_EVAL_TIME, "scenario" => name.clone(), "phase" => "global")
+                .increment((bucket.global_time * 1e6) as u64);
+            if let Some(last_hit) = bucket.last_hit {
+                let t = last_hit
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or_default();
+                metrics::gauge!(SCENARIO_LAST_HIT, "scenario" => name.clone()).set(t);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(7200 + 1234);
+        assert_eq!(
+            hour_of(t),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(7200)
+        );
+        let mut stats = Stats::new(Duration::from_secs(60), 30);
+        stats.record_local(1, 0.5);
+        stats.record_local(1, 0.25);
+        stats.record_global(1, 1.0);
+        stats.record_match(1);
+        stats.record_local(2, 0.1);
+        let of = |id: i64| stats.buckets.iter().filter(move |((i, _), _)| *i == id);
+        assert_eq!(of(1).map(|(_, b)| b.evaluations).sum::<u64>(), 2);
+        assert_eq!(of(1).map(|(_, b)| b.local_time).sum::<f64>(), 0.75);
+        assert_eq!(of(1).map(|(_, b)| b.global_evaluations).sum::<u64>(), 1);
+        assert_eq!(of(1).map(|(_, b)| b.matches).sum::<u64>(), 1);
+        assert!(of(1).any(|(_, b)| b.last_hit.is_some()));
+        assert_eq!(of(2).map(|(_, b)| b.evaluations).sum::<u64>(), 1);
+        assert!(!stats.is_due());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a database (see DIRECTOR_TEST_DB)"]
+    async fn test_flush_failure_retains_stats() {
+        let url = std::env::var("DIRECTOR_TEST_DB")
+            .expect("DIRECTOR_TEST_DB should be set to the connection string of a test database");
+        let (mut client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let mut stats = Stats::new(Duration::from_secs(60), 30);
+        // Note: no such scenario, nothing is written
+        stats.record_local(-1, 0.5);
+        let names = HashMap::from([(-1, "test".to_string())]);
+        client
+            .batch_execute("SET search_path TO no_such_schema")
+            .await
+            .unwrap();
+        assert!(stats.flush(&mut client, &names).await.is_err());
+        assert_eq!(stats.buckets.len(), 1);
+        client.batch_execute("RESET search_path").await.unwrap();
+        stats.flush(&mut client, &names).await.unwrap();
+        assert!(stats.buckets.is_empty());
+    }
+}
@@ -67,6 +67,13 @@ impl Broker {
             consumer_count
         );
 
+        // Create the cache invalidation exchange (the workers may not be running yet)
+        if let Err(e) = shared::amqp::declare_cache_invalidation_exchange(&channel).await {
+            shared::amqp::close_channel(channel).await;
+            shared::amqp::close_connection(connection).await;
+            return Err(e);
+        }
+
         Ok(Self {
             connection,
             channel,
@@ -85,6 +92,7 @@ impl Broker {
         object: object::Info,
         ttl: Duration,
         max_recursion: u32,
+        priority: object::Priority,
         relation_metadata: object::Metadata,
     ) -> Result<String, Box<dyn std::error::Error>> {
         let symbols: Vec<String> = Vec::new();
@@ -93,6 +101,8 @@ impl Broker {
             symbols: &symbols,
             relation_metadata: &relation_metadata,
             max_recursion,
+            work_id: None,
+            priority,
         };
         shared::amqp::publish_job_request(&self.channel, req_object, ttl, None, None).await
     }
@@ -121,6 +131,28 @@ impl Broker {
         Ok(())
     }
 
+    /// Issue a backend result cache invalidation request
+    pub async fn request_cache_invalidation(
+        &self,
+        invalidation: &shared::amqp::CacheInvalidation,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        shared::amqp::publish_cache_invalidation(invalidation, &self.channel)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to publish cache invalidation request to {}: {}",
+                    shared::CACHE_INVALIDATE_EXCHANGE_NAME,
+                    e
+                );
+                e
+            })?;
+        debug!(
+            "Posted cache invalidation request to {}",
+            shared::CACHE_INVALIDATE_EXCHANGE_NAME
+        );
+        Ok(())
+    }
+
     /// Issue apply scenario requests
     pub async fn request_apply_scenarios(
         &self,
@@ -0,0 +1,183 @@
+//! Scenario bundles
+//!
+//! A bundle is a set of scenarios along with a manifest listing, for each
+//! scenario, its version requirements and checksum. When a signing key is
+//! configured the manifest is signed (HMAC-SHA256) on export and the signature
+//! is verified on import; without a key, imports are refused unless unsigned
+//! bundles are explicitly allowed
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+pub use shared::api::{Bundle, Manifest, ManifestEntry};
+use shared::scene::Scenario;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The bundle format version
+const BUNDLE_FORMAT: u32 = 1;
+
+/// Returns the canonical representation of a value
+///
+/// Note: object keys are sorted
+fn canonical<T: Serialize>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+    serde_json::to_vec(&serde_json::to_value(value)?)
+}
+
+/// Returns the hex encoded checksum of a scenario
+fn checksum(scenario: &Scenario) -> Result<String, serde_json::Error> {
+    Ok(hex::encode(Sha256::digest(canonical(scenario)?)))
+}
+
+/// The bundle signer
+pub struct BundleKey {
+    key: Option<Vec<u8>>,
+    allow_unsigned: bool,
+}
+
+impl BundleKey {
+    /// Creates a new signer
+    ///
+    /// Without a `key` bundles are not signed and, unless `allow_unsigned` is
+    /// set, cannot be imported
+    pub fn new(key: Option<&str>, allow_unsigned: bool) -> Self {
+        if key.is_none() {
+            if allow_unsigned {
+                warn!("No bundle key configured: bundle imports are NOT verified");
+            } else {
+                warn!("No bundle key configured: bundle imports are disabled");
+            }
+        }
+        Self {
+            key: key.map(|k| k.as_bytes().to_vec()),
+            allow_unsigned,
+        }
+    }
+
+    /// Returns true if bundles can be imported
+    pub fn can_import(&self) -> bool {
+        self.key.is_some() || self.allow_unsigned
+    }
+
+    fn mac(&self, manifest: &Manifest) -> Result<Option<Hmac<Sha256>>, serde_json::Error> {
+        let Some(key) = &self.key else {
+            return Ok(None);
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key size");
+        mac.update(&canonical(manifest)?);
+        Ok(Some(mac))
+    }
+
+    /// Creates a (signed) bundle out of the given scenarios
+    pub fn bundle(&self, scenarios: Vec<Scenario>) -> Result<Bundle, serde_json::Error> {
+        let entries = scenarios
+            .iter()
+            .map(|scenario| {
+                Ok(ManifestEntry {
+                    name: scenario.name.clone(),
+                    compatible_with: scenario.compatibility(),
+                    sha256: checksum(scenario)?,
+                })
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let manifest = Manifest {
+            format: BUNDLE_FORMAT,
+            platform_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: std::time::UNIX_EPOCH
+                .elapsed()
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default(),
+            scenarios: entries,
+        };
+        let signature = self
+            .mac(&manifest)?
+            .map(|mac| hex::encode(mac.finalize().into_bytes()));
+        Ok(Bundle {
+            manifest,
+            scenarios,
+            signature,
+        })
+    }
+
+    /// Checks the bundle signature and integrity
+    pub fn verify(&self, bundle: &Bundle) -> Result<(), String> {
+        if !self.can_import() {
+            return Err("Bundle import is disabled (no bundle key configured)".to_string());
+        }
+        if bundle.manifest.format != BUNDLE_FORMAT {
+            return Err(format!(
+                "Unsupported bundle format {}",
+                bundle.manifest.format
+            ));
+        }
+        let mac = self
+            .mac(&bundle.manifest)
+            .map_err(|_| "Invalid manifest".to_string())?;
+        if let Some(mac) = mac {
+            let signature = bundle
+                .signature
+                .as_deref()
+                .and_then(|s| hex::decode(s).ok())
+                .ok_or("The bundle is not signed")?;
+            mac.verify_slice(&signature)
+                .map_err(|_| "Invalid bundle signature")?;
+        } else {
+            warn!("Importing an unverified bundle (no bundle key configured)");
+        }
+        if bundle.manifest.scenarios.len() != bundle.scenarios.len() {
+            return Err("The manifest does not match the scenarios".to_string());
+        }
+        for (entry, scenario) in bundle.manifest.scenarios.iter().zip(&bundle.scenarios) {
+            let sum = checksum(scenario).map_err(|_| "Invalid scenario".to_string())?;
+            if entry.name != scenario.name || entry.sha256 != sum {
+                return Err(format!("Checksum mismatch for scenario {}", entry.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scenario(name: &str) -> Scenario {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "compatible_with": ">=1.3.0",
+            "creator": "me",
+            "description": "",
+            "local_query": "true",
+            "context": null,
+            "action": "ALERT",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bundle() {
+        let key = BundleKey::new(Some("secret"), false);
+        let mut bundle = key.bundle(vec![scenario("a"), scenario("b")]).unwrap();
+        assert_eq!(bundle.manifest.scenarios.len(), 2);
+        assert!(bundle.signature.is_some());
+        assert!(key.verify(&bundle).is_ok());
+        assert!(BundleKey::new(Some("other"), false)
+            .verify(&bundle)
+            .is_err());
+        assert!(BundleKey::new(None, false).verify(&bundle).is_err());
+        assert!(BundleKey::new(None, true).verify(&bundle).is_ok());
+
+        bundle.scenarios[1].description = "tampered".to_string();
+        assert!(key.verify(&bundle).is_err());
+        bundle.scenarios[1].description = String::new();
+        bundle.manifest.created += 1.0;
+        assert!(key.verify(&bundle).is_err());
+
+        let unsigned = BundleKey::new(None, false)
+            .bundle(vec![scenario("a")])
+            .unwrap();
+        assert!(unsigned.signature.is_none());
+        assert!(key.verify(&unsigned).is_err());
+        assert!(!BundleKey::new(None, false).can_import());
+    }
+}
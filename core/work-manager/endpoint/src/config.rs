@@ -4,7 +4,8 @@ use figment::{
     Figment,
 };
 use serde::Deserialize;
-use shared::config::{BrokerConfig, ClamdServiceConfig, DBConfig};
+use shared::config::{BrokerConfig, DBConfig, TypedetConfig};
+use std::collections::HashMap;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
@@ -15,8 +16,9 @@ pub struct Config {
     pub port: u16,
     /// Message broker configuration
     pub broker: BrokerConfig,
-    /// Object type detection server
-    pub typedet: ClamdServiceConfig,
+    /// Object type detection
+    #[serde(default)]
+    pub typedet: TypedetConfig,
     /// The path to the objects store
     pub objects_path: String,
     /// Read only DB configuration
@@ -29,6 +31,60 @@ pub struct Config {
     max_action_results: Option<u32>,
     search_timeout_ms: Option<u32>,
     enable_reprocess: Option<bool>,
+    /// The key used to sign and verify scenario bundles
+    ///
+    /// When unset, exported bundles are not signed and bundle imports are
+    /// refused (unless `allow_unsigned_bundles` is set)
+    pub bundle_key: Option<String>,
+    allow_unsigned_bundles: Option<bool>,
+    /// Submission rate limits and quotas
+    #[serde(default)]
+    pub quotas: QuotasConfig,
+    /// Work retention
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// Reprocessing of historical works
+    #[serde(default)]
+    pub reprocess: ReprocessConfig,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+/// Submission limits (unset means unlimited)
+pub struct QuotaLimits {
+    /// Sustained submission rate (requests per second)
+    pub rate: Option<f64>,
+    /// Maximum number of submissions accepted in a burst (default 1)
+    pub burst: Option<u32>,
+    /// Maximum number of bytes submitted per (UTC) day
+    pub daily_bytes: Option<u64>,
+    /// Maximum number of objects submitted per (UTC) day
+    pub daily_objects: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Returns the limits with the unset values taken from `defaults`
+    pub fn or(&self, defaults: &Self) -> Self {
+        Self {
+            rate: self.rate.or(defaults.rate),
+            burst: self.burst.or(defaults.burst),
+            daily_bytes: self.daily_bytes.or(defaults.daily_bytes),
+            daily_objects: self.daily_objects.or(defaults.daily_objects),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+/// Submission rate limits and quotas configuration
+pub struct QuotasConfig {
+    /// The limits applied to all orgs
+    #[serde(default)]
+    pub default: QuotaLimits,
+    /// Per org overrides (only these orgs are labelled individually in the metrics)
+    #[serde(default)]
+    pub orgs: HashMap<String, QuotaLimits>,
+    /// The bearer token required to access the admin routes (unset means the
+    /// admin routes are disabled)
+    pub admin_token: Option<String>,
 }
 
 impl Config {
@@ -69,8 +125,68 @@ impl Config {
         self.enable_reprocess.unwrap_or(false)
     }
 
+    /// Whether unsigned bundles can be imported when no `bundle_key` is set,
+    /// with only their integrity checked (default false)
+    pub fn are_unsigned_bundles_allowed(&self) -> bool {
+        self.allow_unsigned_bundles.unwrap_or(false)
+    }
+
     /// Max action results (default 100)
     pub fn get_max_action_results(&self) -> u32 {
         self.max_action_results.unwrap_or(100)
     }
 }
+
+#[derive(Deserialize, Debug, Default, Clone)]
+/// Work retention configuration
+pub struct RetentionConfig {
+    /// The number of days works are retained for (unset or 0 means forever)
+    pub days: Option<u32>,
+    /// Per org overrides (0 means forever)
+    #[serde(default)]
+    pub orgs: HashMap<String, u32>,
+    purge_interval_secs: Option<u64>,
+    purge_batch_size: Option<u32>,
+}
+
+impl RetentionConfig {
+    /// Returns true if works may be purged
+    pub fn is_enabled(&self) -> bool {
+        self.days.unwrap_or(0) > 0 || self.orgs.values().any(|d| *d > 0)
+    }
+
+    /// Interval between purge runs (default 1 hour)
+    pub fn get_purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.purge_interval_secs.unwrap_or(3600).max(1))
+    }
+
+    /// Maximum number of works purged in a single transaction (default 100)
+    pub fn get_purge_batch_size(&self) -> u32 {
+        self.purge_batch_size.unwrap_or(100).max(1)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+/// Reprocessing configuration
+pub struct ReprocessConfig {
+    interval_secs: Option<u64>,
+    batch_size: Option<u32>,
+    max_in_flight: Option<u32>,
+}
+
+impl ReprocessConfig {
+    /// Interval between reprocess batches (default 10 seconds)
+    pub fn get_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_secs.unwrap_or(10).max(1))
+    }
+
+    /// Maximum number of works re-enqueued in a single batch (default 10)
+    pub fn get_batch_size(&self) -> u32 {
+        self.batch_size.unwrap_or(10).max(1)
+    }
+
+    /// Maximum number of reprocessed works being processed at any time (default 50)
+    pub fn get_max_in_flight(&self) -> u32 {
+        self.max_in_flight.unwrap_or(50)
+    }
+}
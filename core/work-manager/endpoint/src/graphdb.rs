@@ -2,7 +2,13 @@
 //!
 //! This module interacts with the GraphDB
 
+mod backtest;
 mod clam;
+mod cursor;
+mod history;
+mod reprocess;
+mod stats;
+mod transfer;
 
 use shared::{
     amqp::{JobResult, JobResultKind},
@@ -11,18 +17,24 @@ use shared::{
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
+pub use cursor::Cursor;
+pub use history::Change;
+pub use reprocess::{ReprocessCandidate, ReprocessUpdate};
+pub use shared::api::{
+    BacktestResult, CountResult, ImportReport, LegalHold, OnConflict, ReprocessAction,
+    ReprocessJob, ScenarioDetails, ScenarioStats, ScenarioVersion, SearchPage, WorkState,
+    WorkStatus,
+};
+
 pub enum SearchError {
     Rule(String),
     Query(String),
+    InvalidCursor,
     Timeout,
     Internal,
 }
 
-#[derive(serde::Serialize)]
-pub struct CountResult {
-    count: i64,
-}
-
+#[derive(Debug)]
 pub enum ScenaryError {
     Invalid(&'static str),
     Signature(String),
@@ -32,15 +44,69 @@ pub enum ScenaryError {
     Internal,
 }
 
-#[derive(serde::Serialize)]
-pub struct ScenarioDetails {
-    id: i64,
-    name: String,
-    creator: String,
-    description: String,
-    #[serde(serialize_with = "shared::time_to_f64")]
-    t: std::time::SystemTime,
-    action: String,
+/// The submission usage of an org on the current day
+pub struct OrgUsage {
+    pub org: String,
+    pub objects: i64,
+    pub bytes: i64,
+}
+
+/// The outcome of a work deletion request
+pub enum DeleteOutcome {
+    /// The work was deleted; the object ids no longer referenced are returned
+    Deleted(Vec<String>),
+    /// The work is on legal hold
+    OnHold,
+    /// The work is still being processed
+    InProgress,
+    /// No such work
+    NotFound,
+}
+
+/// Used to serialize purge runs across endpoint instances
+const PURGE_LOCK_ID: i64 = 0x6374_7870_7572_6765;
+
+/// The advisory lock class (the first key) of the per work locks
+///
+/// The lock, keyed by the hash of the work id, serializes the deletion, the
+/// purging and the legal holds of a work
+const WORK_LOCK_CLASS: i32 = 0x6374_7877;
+
+/// Removes the given works from the database
+///
+/// Returns the ids of the objects which are no longer referenced by any work
+async fn remove_works(
+    txn: &deadpool_postgres::Transaction<'_>,
+    work_ids: &[String],
+) -> Result<Vec<String>, tokio_postgres::Error> {
+    let deleted = txn
+        .query(
+            "DELETE FROM objects WHERE work_id = ANY($1) RETURNING id, object_id",
+            &[&work_ids],
+        )
+        .await?;
+    let ids: Vec<i64> = deleted.iter().map(|row| row.get(0)).collect();
+    let object_ids: Vec<String> = deleted.iter().map(|row| row.get(1)).collect();
+    // Note: objects are partitioned and cannot be referenced by foreign keys
+    txn.execute("DELETE FROM rels WHERE child = ANY($1)", &[&ids])
+        .await?;
+    txn.execute("DELETE FROM results WHERE work_id = ANY($1)", &[&work_ids])
+        .await?;
+    txn.execute(
+        "DELETE FROM work_status WHERE work_id = ANY($1)",
+        &[&work_ids],
+    )
+    .await?;
+    Ok(txn
+        .query(
+            "SELECT DISTINCT d.object_id FROM unnest($1::text[]) AS d(object_id)
+             WHERE NOT EXISTS (SELECT 1 FROM objects WHERE objects.object_id = d.object_id)",
+            &[&object_ids],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect())
 }
 
 /// The graph database connector
@@ -225,6 +291,590 @@ impl GraphDB {
         Ok(entry)
     }
 
+    pub async fn add_work_status(
+        &self,
+        work_id: &str,
+        ttl: std::time::Duration,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        // Note: progress updates may have been accounted already
+        let stmt = client
+            .prepare_cached(
+                "INSERT INTO work_status (work_id, expires_at)
+                 VALUES ($1, current_timestamp + make_interval(secs => $2))
+                 ON CONFLICT (work_id) DO UPDATE SET expires_at = EXCLUDED.expires_at",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare add_work_status statement: {}", e);
+                e
+            })?;
+        client
+            .execute(&stmt, &[&work_id, &ttl.as_secs_f64()])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute add_work_status statement: {}", e);
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Accounts a submission against the daily usage of an org
+    ///
+    /// Returns false (and accounts nothing) if the submission exceeds the
+    /// daily limits
+    pub async fn reserve_usage(
+        &self,
+        org: &str,
+        size: u64,
+        max_bytes: Option<u64>,
+        max_objects: Option<u64>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        // Note: the check and the update are atomic, so concurrent submissions
+        // cannot overrun the quota
+        let stmt = client
+            .prepare_cached(
+                "INSERT INTO org_usage (org, day, objects, bytes)
+                 SELECT $1, (current_timestamp AT TIME ZONE 'UTC')::date, 1, $2
+                 WHERE ($3::bigint IS NULL OR $2 <= $3)
+                   AND ($4::bigint IS NULL OR 1 <= $4)
+                 ON CONFLICT (org, day) DO UPDATE SET
+                   objects = org_usage.objects + 1,
+                   bytes = org_usage.bytes + $2
+                 WHERE ($3::bigint IS NULL OR org_usage.bytes + $2 <= $3)
+                   AND ($4::bigint IS NULL OR org_usage.objects + 1 <= $4)",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare reserve_usage statement: {}", e);
+                e
+            })?;
+        let to_i64 = |v: u64| i64::try_from(v).unwrap_or(i64::MAX);
+        let updated = client
+            .execute(
+                &stmt,
+                &[
+                    &org,
+                    &to_i64(size),
+                    &max_bytes.map(to_i64),
+                    &max_objects.map(to_i64),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to execute reserve_usage statement: {}", e);
+                e
+            })?;
+        Ok(updated > 0)
+    }
+
+    /// Gives back a submission previously accounted via [`reserve_usage`](Self::reserve_usage)
+    pub async fn release_usage(
+        &self,
+        org: &str,
+        size: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "UPDATE org_usage SET
+                   objects = GREATEST(objects - 1, 0),
+                   bytes = GREATEST(bytes - $2, 0)
+                 WHERE org = $1 AND day = (current_timestamp AT TIME ZONE 'UTC')::date",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare release_usage statement: {}", e);
+                e
+            })?;
+        client
+            .execute(&stmt, &[&org, &i64::try_from(size).unwrap_or(i64::MAX)])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute release_usage statement: {}", e);
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Retrieves the submission usage of all orgs on the current day
+    pub async fn get_usage(&self) -> Result<Vec<OrgUsage>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT org, objects, bytes FROM org_usage
+                 WHERE day = (current_timestamp AT TIME ZONE 'UTC')::date
+                 ORDER BY org",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_usage statement: {}", e);
+                e
+            })?;
+        let rows = client.query(&stmt, &[]).await.map_err(|e| {
+            error!("Failed to execute get_usage statement: {}", e);
+            e
+        })?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(OrgUsage {
+                    org: row.try_get("org")?,
+                    objects: row.try_get("objects")?,
+                    bytes: row.try_get("bytes")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Clears the submission usage of an org on the current day
+    pub async fn reset_usage(&self, org: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "DELETE FROM org_usage
+                 WHERE org = $1 AND day = (current_timestamp AT TIME ZONE 'UTC')::date",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare reset_usage statement: {}", e);
+                e
+            })?;
+        let deleted = client.execute(&stmt, &[&org]).await.map_err(|e| {
+            error!("Failed to execute reset_usage statement: {}", e);
+            e
+        })?;
+        Ok(deleted > 0)
+    }
+
+    /// Purges a batch of works which exceeded their retention period
+    ///
+    /// Returns `None` if another purge is in progress, otherwise the number
+    /// of works purged and the ids of the objects no longer referenced
+    pub async fn purge_stale_works(
+        &self,
+        retention: &crate::config::RetentionConfig,
+    ) -> Result<Option<(usize, Vec<String>)>, Box<dyn std::error::Error>> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start purge transaction: {e}");
+            e
+        })?;
+        let locked: bool = txn
+            .query_one("SELECT pg_try_advisory_xact_lock($1)", &[&PURGE_LOCK_ID])
+            .await
+            .map_err(|e| {
+                error!("Failed to acquire purge lock: {e}");
+                e
+            })?
+            .get(0);
+        if !locked {
+            return Ok(None);
+        }
+        let (orgs, days): (Vec<&str>, Vec<i32>) = retention
+            .orgs
+            .iter()
+            .map(|(org, days)| (org.as_str(), i32::try_from(*days).unwrap_or(i32::MAX)))
+            .unzip();
+        let default_days = i32::try_from(retention.days.unwrap_or(0)).unwrap_or(i32::MAX);
+        let batch_size = i64::from(retention.get_purge_batch_size());
+        let work_ids: Vec<String> = txn
+            .query(
+                "SELECT o.work_id FROM objects AS o
+                 LEFT JOIN unnest($1::text[], $2::int[]) AS r(org, days) ON r.org = o.org
+                 WHERE o.is_entry
+                   AND COALESCE(r.days, $3) > 0
+                   AND o.t < current_timestamp - make_interval(days => COALESCE(r.days, $3))
+                   AND NOT EXISTS (SELECT 1 FROM legal_holds AS h WHERE h.work_id = o.work_id)
+                 LIMIT $4",
+                &[&orgs, &days, &default_days, &batch_size],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to select stale works: {e}");
+                e
+            })?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+        if work_ids.is_empty() {
+            return Ok(Some((0, Vec::new())));
+        }
+        // Note: holds placed after the selection are only visible to the
+        // statements run once the works are locked
+        txn.execute(
+            "SELECT pg_advisory_xact_lock($1, hashtext(w.work_id))
+             FROM unnest($2::text[]) AS w(work_id)
+             ORDER BY w.work_id",
+            &[&WORK_LOCK_CLASS, &work_ids],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to lock stale works: {e}");
+            e
+        })?;
+        let held: Vec<String> = txn
+            .query(
+                "SELECT work_id FROM legal_holds WHERE work_id = ANY($1)",
+                &[&work_ids],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to check legal holds: {e}");
+                e
+            })?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+        let work_ids: Vec<String> = work_ids
+            .into_iter()
+            .filter(|work_id| !held.contains(work_id))
+            .collect();
+        let unreferenced = remove_works(&txn, &work_ids).await.map_err(|e| {
+            error!("Failed to purge stale works: {e}");
+            e
+        })?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit purge transaction: {e}");
+            e
+        })?;
+        Ok(Some((work_ids.len(), unreferenced)))
+    }
+
+    /// Records the object files to be removed by a later purge run
+    pub async fn defer_object_removals(
+        &self,
+        object_ids: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if object_ids.is_empty() {
+            return Ok(());
+        }
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "INSERT INTO deferred_object_removals (object_id)
+                 SELECT unnest($1::text[])
+                 ON CONFLICT (object_id) DO UPDATE SET t = current_timestamp",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare defer_object_removals statement: {}", e);
+                e
+            })?;
+        client.execute(&stmt, &[&object_ids]).await.map_err(|e| {
+            error!("Failed to execute defer_object_removals statement: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// Takes the deferred object removals older than `min_age`
+    ///
+    /// Returns the ids of the objects which are still unreferenced; the
+    /// objects referenced again are dropped from the list
+    pub async fn take_object_removals(
+        &self,
+        min_age: std::time::Duration,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "DELETE FROM deferred_object_removals AS d
+                 WHERE d.t < current_timestamp - make_interval(secs => $1)
+                 RETURNING d.object_id,
+                   NOT EXISTS (SELECT 1 FROM objects WHERE objects.object_id = d.object_id)",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare take_object_removals statement: {}", e);
+                e
+            })?;
+        Ok(client
+            .query(&stmt, &[&min_age.as_secs_f64()])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute take_object_removals statement: {}", e);
+                e
+            })?
+            .into_iter()
+            .filter(|row| row.get::<_, bool>(1))
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    /// Deletes a work unless it is on legal hold or still being processed
+    pub async fn delete_work(
+        &self,
+        work_id: &str,
+    ) -> Result<DeleteOutcome, Box<dyn std::error::Error>> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start delete_work transaction: {e}");
+            e
+        })?;
+        txn.execute(
+            "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+            &[&WORK_LOCK_CLASS, &work_id],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to acquire work lock: {e}");
+            e
+        })?;
+        let on_hold = txn
+            .query_opt(
+                "SELECT 1 FROM legal_holds WHERE work_id = $1 FOR SHARE",
+                &[&work_id],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to check legal hold: {e}");
+                e
+            })?
+            .is_some();
+        if on_hold {
+            return Ok(DeleteOutcome::OnHold);
+        }
+        // Note: the results of a work still in flight would be stored after
+        // the deletion, resurrecting the work
+        let in_progress = txn
+            .query_opt(
+                "SELECT 1 FROM work_status
+                 WHERE work_id = $1
+                   AND state <> 'completed'
+                   AND COALESCE(expires_at >= current_timestamp, true)",
+                &[&work_id],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to check work status: {e}");
+                e
+            })?
+            .is_some();
+        if in_progress {
+            return Ok(DeleteOutcome::InProgress);
+        }
+        let exists = txn
+            .query_opt(
+                "SELECT 1 FROM objects WHERE work_id = $1
+                 UNION ALL
+                 SELECT 1 FROM work_status WHERE work_id = $1
+                 LIMIT 1",
+                &[&work_id],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to check work existence: {e}");
+                e
+            })?
+            .is_some();
+        if !exists {
+            return Ok(DeleteOutcome::NotFound);
+        }
+        let unreferenced = remove_works(&txn, &[work_id.to_string()])
+            .await
+            .map_err(|e| {
+                error!("Failed to delete work: {e}");
+                e
+            })?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit delete_work transaction: {e}");
+            e
+        })?;
+        Ok(DeleteOutcome::Deleted(unreferenced))
+    }
+
+    /// Places a work on legal hold, returns false if the work does not exist
+    pub async fn set_legal_hold(
+        &self,
+        work_id: &str,
+        reason: Option<&str>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start set_legal_hold transaction: {e}");
+            e
+        })?;
+        // Note: the lock waits for any deletion or purge of the work in progress
+        txn.execute(
+            "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+            &[&WORK_LOCK_CLASS, &work_id],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to acquire work lock: {e}");
+            e
+        })?;
+        let stmt = txn
+            .prepare_cached(
+                "INSERT INTO legal_holds (work_id, reason)
+                 SELECT $1, $2 WHERE EXISTS (
+                   SELECT 1 FROM objects WHERE work_id = $1
+                   UNION ALL
+                   SELECT 1 FROM work_status WHERE work_id = $1
+                 )
+                 ON CONFLICT (work_id) DO UPDATE SET reason = EXCLUDED.reason",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare set_legal_hold statement: {}", e);
+                e
+            })?;
+        let updated = txn
+            .execute(&stmt, &[&work_id, &reason])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute set_legal_hold statement: {}", e);
+                e
+            })?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit set_legal_hold transaction: {e}");
+            e
+        })?;
+        Ok(updated > 0)
+    }
+
+    /// Lifts the legal hold of a work, returns false if the work was not on hold
+    pub async fn clear_legal_hold(
+        &self,
+        work_id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached("DELETE FROM legal_holds WHERE work_id = $1")
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare clear_legal_hold statement: {}", e);
+                e
+            })?;
+        let deleted = client.execute(&stmt, &[&work_id]).await.map_err(|e| {
+            error!("Failed to execute clear_legal_hold statement: {}", e);
+            e
+        })?;
+        Ok(deleted > 0)
+    }
+
+    /// Retrieves the legal hold of a work
+    pub async fn get_legal_hold(
+        &self,
+        work_id: &str,
+    ) -> Result<Option<LegalHold>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached("SELECT t, reason FROM legal_holds WHERE work_id = $1")
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_legal_hold statement: {}", e);
+                e
+            })?;
+        let row = client.query_opt(&stmt, &[&work_id]).await.map_err(|e| {
+            error!("Failed to execute get_legal_hold statement: {}", e);
+            e
+        })?;
+        row.map(|row| {
+            Ok(LegalHold {
+                work_id: work_id.to_string(),
+                t: row.try_get("t")?,
+                reason: row.try_get("reason")?,
+            })
+        })
+        .transpose()
+    }
+
+    pub async fn get_work_status(
+        &self,
+        work_id: &str,
+    ) -> Result<Option<WorkStatus>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT
+                    state,
+                    objects_queued,
+                    objects_done,
+                    t_queued,
+                    t_updated,
+                    expires_at,
+                    COALESCE(expires_at < current_timestamp, false) AS is_expired
+                  FROM work_status
+                  WHERE work_id = $1",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_work_status statement: {}", e);
+                e
+            })?;
+        let row = match client.query_opt(&stmt, &[&work_id]).await.map_err(|e| {
+            error!("Failed to execute get_work_status statement: {}", e);
+            e
+        })? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let state: &str = row.try_get("state")?;
+        let is_expired: bool = row.try_get("is_expired")?;
+        let state = match state {
+            "completed" => WorkState::Completed,
+            _ if is_expired => WorkState::TimedOut,
+            "in_progress" => WorkState::InProgress,
+            _ => WorkState::Queued,
+        };
+        let objects_queued: i64 = row.try_get("objects_queued")?;
+        let objects_done: i64 = row.try_get("objects_done")?;
+        Ok(Some(WorkStatus {
+            work_id: work_id.to_string(),
+            objects_pending: if state == WorkState::Completed {
+                0
+            } else {
+                (objects_queued - objects_done).max(0)
+            },
+            state,
+            objects_done,
+            t_queued: row.try_get("t_queued")?,
+            t_updated: row.try_get("t_updated")?,
+            expires_at: row.try_get("expires_at")?,
+        }))
+    }
+
     pub async fn search(
         &self,
         q: &str,
@@ -243,6 +893,79 @@ impl GraphDB {
             parsed.query,
             max_items
         );
+        let rows = self.search_query(&query, &[]).await?;
+        let mut items: Vec<String> = Vec::with_capacity(rows.len());
+        for row in rows {
+            items.push(row.try_get(0).map_err(|_| SearchError::Internal)?);
+        }
+        Ok(items)
+    }
+
+    /// Returns a page of search results starting after the provided cursor
+    ///
+    /// Works are returned in work id order, objects in insertion order
+    pub async fn search_page(
+        &self,
+        q: &str,
+        getobjects: bool,
+        cursor: Option<&str>,
+        max_items: u32,
+    ) -> Result<SearchPage, SearchError> {
+        let parsed = pgrules::parse_to_sql(q, pgrules::QueryType::Search)
+            .map_err(|e| SearchError::Rule(e.to_string()))?;
+        let cursor = cursor
+            .map(|c| Cursor::decode(c).ok_or(SearchError::InvalidCursor))
+            .transpose()?;
+        let mut items: Vec<String> = Vec::new();
+        let mut last: Option<Cursor> = None;
+        if getobjects {
+            let after = match cursor {
+                None => 0i64,
+                Some(Cursor::Object(id)) => id,
+                Some(_) => return Err(SearchError::InvalidCursor),
+            };
+            let query = format!(
+                "SELECT id, object_id FROM (SELECT \"objects_0\".id, \"objects_0\".object_id {})
+                   AS page WHERE id > $1 ORDER BY id LIMIT {}",
+                parsed.query, max_items
+            );
+            for row in self.search_query(&query, &[&after]).await? {
+                items.push(row.try_get(1).map_err(|_| SearchError::Internal)?);
+                last = Some(Cursor::Object(
+                    row.try_get(0).map_err(|_| SearchError::Internal)?,
+                ));
+            }
+        } else {
+            let after = match cursor {
+                None => String::new(),
+                Some(Cursor::Work(work_id)) => work_id,
+                Some(_) => return Err(SearchError::InvalidCursor),
+            };
+            let query = format!(
+                "SELECT work_id FROM (SELECT DISTINCT \"objects_0\".work_id {})
+                   AS page WHERE work_id > $1 ORDER BY work_id LIMIT {}",
+                parsed.query, max_items
+            );
+            for row in self.search_query(&query, &[&after]).await? {
+                let work_id: String = row.try_get(0).map_err(|_| SearchError::Internal)?;
+                last = Some(Cursor::Work(work_id.clone()));
+                items.push(work_id);
+            }
+        }
+        let next_cursor = if items.len() == max_items as usize {
+            last.map(|c| c.encode())
+        } else {
+            None
+        };
+        Ok(SearchPage { items, next_cursor })
+    }
+
+    /// Runs a search query with the configured time limit
+    async fn search_query(
+        &self,
+        query: &str,
+        params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+    ) -> Result<Vec<tokio_postgres::Row>, SearchError> {
         let mut client = self.read_pool.get().await.map_err(|e| {
             error!("Failed to get read-only connection from pool: {e}");
             SearchError::Internal
@@ -260,7 +983,7 @@ impl GraphDB {
             error!("Failed to set transaction timeout: {e}");
             SearchError::Internal
         })?;
-        let rows = txn.query(&query, &[]).await.map_err(|e| {
+        let rows = txn.query(query, params).await.map_err(|e| {
             match e.code() {
                 Some(sqst)
                     if *sqst
@@ -281,11 +1004,7 @@ impl GraphDB {
         if let Err(e) = txn.commit().await {
             warn!("Failed to commit search transaction: {e}");
         }
-        let mut items: Vec<String> = Vec::with_capacity(rows.len());
-        for row in rows {
-            items.push(row.try_get(0).map_err(|_| SearchError::Internal)?);
-        }
-        Ok(items)
+        Ok(rows)
     }
 
     pub async fn count(&self, q: &str, getobjects: bool) -> Result<CountResult, SearchError> {
@@ -337,7 +1056,42 @@ impl GraphDB {
         &self,
         scenario: &scene::Scenario,
         replace_id: Option<i64>,
+        change: &Change<'_>,
+    ) -> Result<ScenarioDetails, ScenaryError> {
+        let op = if replace_id.is_some() {
+            "update"
+        } else {
+            "create"
+        };
+        self.save_scenario(scenario, replace_id, change, op).await
+    }
+
+    async fn save_scenario(
+        &self,
+        scenario: &scene::Scenario,
+        replace_id: Option<i64>,
+        change: &Change<'_>,
+        op: &str,
     ) -> Result<ScenarioDetails, ScenaryError> {
+        Self::validate_scenario(scenario).await?;
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            ScenaryError::Database
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start transaction: {}", e);
+            ScenaryError::Database
+        })?;
+        let details = Self::insert_scenario(&txn, scenario, replace_id, change, op).await?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit add_scenario transaction: {}", e);
+            ScenaryError::Database
+        })?;
+        Ok(details)
+    }
+
+    /// Checks the scenario definition, its rules and the patterns they reference
+    async fn validate_scenario(scenario: &scene::Scenario) -> Result<(), ScenaryError> {
         if scenario.name.is_empty() {
             return Err(ScenaryError::Invalid("Invalid name"));
         }
@@ -349,9 +1103,7 @@ impl GraphDB {
         if scenario.creator.is_empty() {
             return Err(ScenaryError::Invalid("Invalid creator"));
         }
-        if scenario.action.is_empty() {
-            return Err(ScenaryError::Invalid("Invalid action"));
-        }
+        scenario.action.validate().map_err(ScenaryError::Invalid)?;
         if pgrules::parse_to_sql(&scenario.local_query, pgrules::QueryType::ScenarioLocal).is_err()
         {
             return Err(ScenaryError::Invalid("Invalid local rule"));
@@ -368,44 +1120,68 @@ impl GraphDB {
             }
             clam::find_invalid_patttern(&context.global_query).await?;
         }
-        let mut client = self.write_pool.get().await.map_err(|e| {
-            error!("Failed to get read-write connection from pool: {e}");
-            ScenaryError::Database
-        })?;
+        Ok(())
+    }
+
+    /// Stores a (validated) scenario and records its version, within the given transaction
+    ///
+    /// When `replace_id` is set, the scenario with that id is replaced
+    async fn insert_scenario(
+        txn: &deadpool_postgres::Transaction<'_>,
+        scenario: &scene::Scenario,
+        replace_id: Option<i64>,
+        change: &Change<'_>,
+        op: &str,
+    ) -> Result<ScenarioDetails, ScenaryError> {
         let json_scenario = serde_json::to_value(scenario);
         if json_scenario.is_err() {
             return Err(ScenaryError::Invalid("Invalid scenario"));
         }
         let json_scenario = json_scenario.unwrap(); // checked above
-        let txn = client.transaction().await.map_err(|e| {
-            error!("Failed to start transaction: {}", e);
-            ScenaryError::Database
-        })?;
+        let mut replaced: Option<(String, serde_json::Value)> = None;
         if let Some(replace_id) = replace_id {
             let stmt = txn
-                .prepare_cached("DELETE FROM scenarios WHERE id = $1")
+                .prepare_cached("SELECT name, def FROM scenarios WHERE id = $1 FOR UPDATE")
                 .await
                 .map_err(|e| {
                     error!("Failed to prepare add_scenario statement: {}", e);
                     ScenaryError::Database
                 })?;
-            let deleted = txn.execute(&stmt, &[&replace_id]).await.map_err(|e| {
-                error!("Failed to delete to-be-replaced scenario: {}", e);
-                ScenaryError::Database
-            })?;
-            if deleted == 0 {
-                return Err(ScenaryError::NotFound);
-            }
+            let current = txn
+                .query_opt(&stmt, &[&replace_id])
+                .await
+                .map_err(|e| {
+                    error!("Failed to lock to-be-replaced scenario: {}", e);
+                    ScenaryError::Database
+                })?
+                .ok_or(ScenaryError::NotFound)?;
+            replaced = Some((
+                current
+                    .try_get("name")
+                    .map_err(|_| ScenaryError::Database)?,
+                current.try_get("def").map_err(|_| ScenaryError::Database)?,
+            ));
         }
-        let stmt = txn
-            .prepare_cached("INSERT INTO scenarios (name, def) VALUES ($1, $2) RETURNING id, t")
-            .await
-            .map_err(|e| {
-                error!("Failed to prepare add_scenario statement: {}", e);
-                ScenaryError::Database
-            })?;
+        // Note: replaced scenarios are updated in place so that their id, and
+        // therefore their statistics, are preserved
+        let (query, params): (&str, Vec<&(dyn tokio_postgres::types::ToSql + Sync)>) =
+            match &replace_id {
+                Some(replace_id) => (
+                    "UPDATE scenarios SET name = $2, def = $3, t = current_timestamp
+                     WHERE id = $1 RETURNING id, t",
+                    vec![replace_id, &scenario.name, &json_scenario],
+                ),
+                None => (
+                    "INSERT INTO scenarios (name, def) VALUES ($1, $2) RETURNING id, t",
+                    vec![&scenario.name, &json_scenario],
+                ),
+            };
+        let stmt = txn.prepare_cached(query).await.map_err(|e| {
+            error!("Failed to prepare add_scenario statement: {}", e);
+            ScenaryError::Database
+        })?;
         let row = txn
-            .query_one(&stmt, &[&scenario.name, &json_scenario])
+            .query_one(&stmt, &params)
             .await
             .map_err(|e| match e.code() {
                 Some(sqst)
@@ -419,34 +1195,125 @@ impl GraphDB {
                     ScenaryError::Database
                 }
             })?;
-        txn.commit().await.map_err(|e| {
-            error!("Failed to commit add_scenario transaction: {}", e);
-            ScenaryError::Database
-        })?;
+        let prev = replaced.as_ref().map(|(_, def)| def);
+        if let Some((old_name, old_def)) = &replaced {
+            if *old_name != scenario.name {
+                // Renamed: close the history of the old name
+                let comment = format!("Renamed to {}", scenario.name);
+                let rename = Change {
+                    author: change.author,
+                    comment: Some(change.comment.unwrap_or(&comment)),
+                };
+                history::record_version(txn, old_name, "delete", &rename, None, Some(old_def))
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to record scenario version: {}", e);
+                        ScenaryError::Database
+                    })?;
+            }
+        }
+        history::record_version(txn, &scenario.name, op, change, Some(&json_scenario), prev)
+            .await
+            .map_err(|e| {
+                error!("Failed to record scenario version: {}", e);
+                ScenaryError::Database
+            })?;
         Ok(ScenarioDetails {
             id: row.try_get("id").map_err(|_| ScenaryError::Database)?,
             name: scenario.name.clone(),
             creator: scenario.creator.clone(),
             description: scenario.description.clone(),
             t: row.try_get("t").map_err(|_| ScenaryError::Database)?,
-            action: scenario.action.clone(),
+            action: scenario.action.name.clone(),
+            enabled: scenario.enabled,
+            mode: scenario.mode,
+            orgs: scenario.orgs.clone(),
         })
     }
 
-    pub async fn del_scenario(&self, id: i64) -> Result<bool, ()> {
-        let client = self.write_pool.get().await.map_err(|e| {
+    pub async fn del_scenario(&self, id: i64, change: &Change<'_>) -> Result<bool, ()> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
             error!("Failed to get read-write connection from pool: {e}");
         })?;
-        let stmt = client
-            .prepare_cached("DELETE FROM scenarios WHERE id = $1")
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start transaction: {}", e);
+        })?;
+        let stmt = txn
+            .prepare_cached("DELETE FROM scenarios WHERE id = $1 RETURNING name, def")
             .await
             .map_err(|e| {
                 error!("Failed to prepare del_scenario statement: {}", e);
             })?;
-        let deleted = client.execute(&stmt, &[&id]).await.map_err(|e| {
+        let deleted = txn.query_opt(&stmt, &[&id]).await.map_err(|e| {
             error!("Failed to execute del_scenario statement: {}", e);
         })?;
-        Ok(deleted > 0)
+        let Some(deleted) = deleted else {
+            return Ok(false);
+        };
+        let name: String = deleted.try_get("name").map_err(|e| {
+            error!("Unexpected result from del_scenario statement: {}", e);
+        })?;
+        let def: serde_json::Value = deleted.try_get("def").map_err(|e| {
+            error!("Unexpected result from del_scenario statement: {}", e);
+        })?;
+        history::record_version(&txn, &name, "delete", change, None, Some(&def))
+            .await
+            .map_err(|e| {
+                error!("Failed to record scenario version: {}", e);
+            })?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit del_scenario transaction: {}", e);
+        })?;
+        Ok(true)
+    }
+
+    /// Restores a recorded version of a scenario
+    ///
+    /// The current scenario with the same name (if any) is replaced
+    pub async fn rollback_scenario(
+        &self,
+        name: &str,
+        version: i32,
+        change: &Change<'_>,
+    ) -> Result<ScenarioDetails, ScenaryError> {
+        let target = self
+            .get_scenario_version(name, version)
+            .await
+            .map_err(|_| ScenaryError::Database)?
+            .ok_or(ScenaryError::NotFound)?;
+        let def = target.def.as_ref().ok_or(ScenaryError::Invalid(
+            "Cannot roll back to a deleted version",
+        ))?;
+        let scenario: scene::Scenario = serde_json::from_value(def.clone())
+            .map_err(|_| ScenaryError::Invalid("The scenario version is no longer valid"))?;
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            ScenaryError::Database
+        })?;
+        let stmt = client
+            .prepare_cached("SELECT id FROM scenarios WHERE name = $1")
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare rollback_scenario statement: {}", e);
+                ScenaryError::Database
+            })?;
+        let current_id: Option<i64> = client
+            .query_opt(&stmt, &[&name])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute rollback_scenario statement: {}", e);
+                ScenaryError::Database
+            })?
+            .map(|row| row.try_get("id"))
+            .transpose()
+            .map_err(|_| ScenaryError::Database)?;
+        let comment = format!("Rollback to version {version}");
+        let change = Change {
+            author: change.author,
+            comment: Some(change.comment.unwrap_or(&comment)),
+        };
+        self.save_scenario(&scenario, current_id, &change, "rollback")
+            .await
     }
 
     pub async fn get_scenario(
@@ -496,7 +1363,10 @@ impl GraphDB {
                     t,
                     def->>'creator' AS creator,
                     def->>'description' AS description,
-                    def->>'action' AS action
+                    COALESCE(def->'action'->>'name', def->>'action') AS action,
+                    COALESCE(def->'enabled', 'true') AS enabled,
+                    COALESCE(def->'mode', to_jsonb('live'::text)) AS mode,
+                    COALESCE(def->'orgs', '[]') AS orgs
                   FROM scenarios
                   ORDER BY name ASC",
             )
@@ -518,6 +1388,9 @@ impl GraphDB {
                 description: row.try_get("description")?,
                 t: row.try_get("t")?,
                 action: row.try_get("action")?,
+                enabled: serde_json::from_value(row.try_get("enabled")?)?,
+                mode: serde_json::from_value(row.try_get("mode")?)?,
+                orgs: serde_json::from_value(row.try_get("orgs")?)?,
             })
         }
         Ok(res)
@@ -536,7 +1409,8 @@ impl GraphDB {
             .prepare_cached(
                 "SELECT
                     t,
-                    actions
+                    actions,
+                    verdict
                   FROM results
                   WHERE work_id = $1
                   ORDER BY t DESC
@@ -560,9 +1434,16 @@ impl GraphDB {
                 let t: std::time::SystemTime = row.try_get("t").ok()?;
                 let actions_json: serde_json::Value = row.try_get("actions").ok()?;
                 let actions: Vec<scene::WorkAction> = serde_json::from_value(actions_json).ok()?;
+                // Note: results saved before verdicts were introduced have none
+                let verdict = row
+                    .try_get::<_, Option<&str>>("verdict")
+                    .ok()?
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| scene::final_verdict(&actions));
                 Some(scene::WorkActions {
                     work_id: work_id.to_string(),
                     t,
+                    verdict,
                     actions,
                 })
             })
@@ -606,3 +1487,91 @@ fn row2resultkind(row: &tokio_postgres::Row) -> Result<JobResultKind, Box<dyn st
     }
     Ok(serde_json::from_value(serde_json::Value::Object(res))?)
 }
+
+/// Connects to the test database named by `ENDPOINT_TEST_DB`, migrating it
+/// to the current schema version
+#[cfg(test)]
+pub(crate) async fn test_graphdb() -> GraphDB {
+    let url = std::env::var("ENDPOINT_TEST_DB")
+        .expect("ENDPOINT_TEST_DB should be set to the connection string of a test database");
+    let pgcfg: tokio_postgres::Config = url.parse().expect("Invalid connection string");
+    let manager = deadpool_postgres::Manager::new(pgcfg, tokio_postgres::NoTls);
+    let pool = deadpool_postgres::Pool::builder(manager)
+        .max_size(4)
+        .build()
+        .unwrap();
+    let mut client = pool.get().await.unwrap();
+    let txn = client.transaction().await.unwrap();
+    txn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS version(v int NOT NULL);
+         LOCK TABLE version;
+         INSERT INTO version SELECT 0 WHERE NOT EXISTS (SELECT 1 FROM version);",
+    )
+    .await
+    .unwrap();
+    let current: i32 = txn
+        .query_one("SELECT v FROM version", &[])
+        .await
+        .unwrap()
+        .get(0);
+    let migrations = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../grapher/migrations");
+    for migration in current..shared::DB_SCHEMA_VERSION {
+        let body =
+            std::fs::read_to_string(migrations.join(format!("{:06}.sql", migration))).unwrap();
+        txn.batch_execute(&body).await.unwrap();
+    }
+    txn.execute("UPDATE version SET v = $1", &[&shared::DB_SCHEMA_VERSION])
+        .await
+        .unwrap();
+    txn.commit().await.unwrap();
+    GraphDB {
+        read_pool: pool.clone(),
+        write_pool: pool,
+        search_timeout_ms: 60000,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shared::fuzzy::{ssdeep_compare, tlsh_distance, Ssdeep, Tlsh};
+
+    #[tokio::test]
+    #[ignore = "requires a database (see ENDPOINT_TEST_DB)"]
+    async fn test_fuzzy_scores_match_sql() {
+        let db = super::test_graphdb().await;
+        let client = db.read_pool.get().await.unwrap();
+        let hashes: Vec<(String, String)> = (0..6u32)
+            .map(|seed| {
+                // Note: related samples sharing most of their content
+                let mut state = seed / 2 + 1;
+                let mut data: Vec<u8> = (0..20_000)
+                    .map(|_| {
+                        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                        b"abcdefgh ,.\n"[(state >> 16) as usize % 12]
+                    })
+                    .collect();
+                let edit = 1000 * (seed as usize % 2 + 1);
+                data[edit..edit * 2].fill(b'x');
+                let (mut ssdeep, mut tlsh) = (Ssdeep::new(), Tlsh::new());
+                ssdeep.update(&data);
+                tlsh.update(&data);
+                (ssdeep.digest().unwrap(), tlsh.digest().unwrap())
+            })
+            .collect();
+        for (s1, t1) in hashes.iter() {
+            for (s2, t2) in hashes.iter() {
+                let row = client
+                    .query_one(
+                        "SELECT ssdeep_compare($1, $2)::bigint, tlsh_distance($3, $4)::bigint",
+                        &[s1, s2, t1, t2],
+                    )
+                    .await
+                    .unwrap();
+                let sql_score: i64 = row.get(0);
+                let sql_distance: i64 = row.get(1);
+                assert_eq!(ssdeep_compare(s1, s2), Some(sql_score as u32));
+                assert_eq!(tlsh_distance(t1, t2), Some(sql_distance as u32));
+            }
+        }
+    }
+}
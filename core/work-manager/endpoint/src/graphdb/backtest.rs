@@ -0,0 +1,154 @@
+//! Scenario backtesting
+//!
+//! Evaluates an unsaved scenario against the works submitted in a time range,
+//! using the same queries as the director, without recording any result
+
+use super::{GraphDB, ScenaryError, SearchError};
+use shared::{
+    api::BacktestResult,
+    global::{GlobalQuery, Neighbors},
+    scene,
+};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+impl GraphDB {
+    /// Evaluates a scenario against the works submitted between `start` and `end`
+    ///
+    /// The scenario org scope is honored, its enabled flag and mode are not
+    ///
+    /// At most `max_candidates` works (the ones matching the local query) are
+    /// evaluated; the database is only accessed in read-only transactions
+    pub async fn backtest(
+        &self,
+        scenario: &scene::Scenario,
+        start: std::time::SystemTime,
+        end: std::time::SystemTime,
+        max_candidates: u32,
+    ) -> Result<BacktestResult, SearchError> {
+        let started = std::time::Instant::now();
+        let local = pgrules::parse_to_sql(&scenario.local_query, pgrules::QueryType::ScenarioLocal)
+            .map_err(|e| SearchError::Rule(format!("Invalid local rule: {e}")))?;
+        // Note: the same rule in search form is used to preselect the candidates
+        let preselect = pgrules::parse_to_sql(&scenario.local_query, pgrules::QueryType::Search)
+            .map_err(|e| SearchError::Rule(format!("Invalid local rule: {e}")))?;
+        let global = scenario
+            .context
+            .as_ref()
+            .map(|context| {
+                GlobalQuery::compile(&context.global_query)
+                    .map_err(|e| SearchError::Rule(format!("Invalid context rule: {e}")))
+            })
+            .transpose()?;
+        let queries = std::iter::once(&scenario.local_query)
+            .chain(scenario.context.as_ref().map(|c| &c.global_query));
+        for query in queries {
+            super::clam::find_invalid_patttern(query)
+                .await
+                .map_err(|e| match e {
+                    ScenaryError::Invalid(msg) => SearchError::Rule(msg.to_string()),
+                    ScenaryError::Signature(p) => {
+                        SearchError::Rule(format!("Invalid pattern: {p}"))
+                    }
+                    _ => SearchError::Internal,
+                })?;
+        }
+
+        let mut client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            SearchError::Internal
+        })?;
+        let txn = client
+            .build_transaction()
+            .read_only(true)
+            .isolation_level(tokio_postgres::IsolationLevel::RepeatableRead)
+            .start()
+            .await
+            .map_err(|e| {
+                error!("Failed to start transaction: {e}");
+                SearchError::Internal
+            })?;
+        txn.query(
+            &format!("SET LOCAL statement_timeout = {}", self.search_timeout_ms),
+            &[],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to set transaction timeout: {e}");
+            SearchError::Internal
+        })?;
+        let map_err = |e: tokio_postgres::Error| match e.code() {
+            Some(sqst) if *sqst == tokio_postgres::error::SqlState::QUERY_CANCELED => {
+                SearchError::Timeout
+            }
+            Some(sqst) if sqst.code().starts_with("42") => SearchError::Query(e.to_string()),
+            _ => {
+                error!("Failed to execute backtest statement: {}", e);
+                SearchError::Internal
+            }
+        };
+
+        let candidates_query = format!(
+            "SELECT work_id FROM objects
+               WHERE is_entry AND t >= $1 AND t < $2
+                 AND (cardinality($3::text[]) = 0 OR org = ANY($3))
+                 AND work_id IN (SELECT \"objects_0\".work_id {})
+               ORDER BY t LIMIT {}",
+            preselect.query,
+            u64::from(max_candidates) + 1
+        );
+        let mut candidates: Vec<String> = txn
+            .query(&candidates_query, &[&start, &end, &scenario.orgs])
+            .await
+            .map_err(map_err)?
+            .into_iter()
+            .map(|row| row.try_get(0))
+            .collect::<Result<_, _>>()
+            .map_err(map_err)?;
+        let truncated = candidates.len() > max_candidates as usize;
+        candidates.truncate(max_candidates as usize);
+
+        let local_stmt = txn
+            .prepare(&format!("SELECT EXISTS (SELECT 1 {})", local.query))
+            .await
+            .map_err(map_err)?;
+        let neighbors = if global.is_some() {
+            Some(Neighbors::prepare(&*txn).await.map_err(map_err)?)
+        } else {
+            None
+        };
+        let mut work_ids: Vec<String> = Vec::new();
+        let mut local_matches = 0usize;
+        for work_id in candidates {
+            let is_match: bool = txn
+                .query_one(&local_stmt, &[&work_id])
+                .await
+                .and_then(|row| row.try_get(0))
+                .map_err(map_err)?;
+            if !is_match {
+                continue;
+            }
+            local_matches += 1;
+            if let (Some(global), Some(neighbors)) = (&global, &neighbors) {
+                if !global
+                    .matches(&txn, neighbors, &work_id, &scenario.name)
+                    .await
+                    .map_err(map_err)?
+                {
+                    continue;
+                }
+            }
+            work_ids.push(work_id);
+        }
+        if let Err(e) = txn.rollback().await {
+            warn!("Failed to close backtest transaction: {e}");
+        }
+        Ok(BacktestResult {
+            count: work_ids.len(),
+            work_ids,
+            local_matches,
+            truncated,
+            time: started.elapsed().as_secs_f64(),
+        })
+    }
+}
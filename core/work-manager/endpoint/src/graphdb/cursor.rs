@@ -0,0 +1,64 @@
+//! Opaque pagination cursors
+//!
+//! Cursors hold the key of the last item returned in a page and are handed out
+//! to clients as hex encoded strings
+
+/// The keyset position of a page
+#[derive(Debug, PartialEq)]
+pub enum Cursor {
+    /// Position in a work id ordered list
+    Work(String),
+    /// Position in an object (database) id ordered list
+    Object(i64),
+}
+
+impl Cursor {
+    /// Returns the opaque representation of the cursor
+    pub fn encode(&self) -> String {
+        let plain = match self {
+            Self::Work(work_id) => format!("w:{work_id}"),
+            Self::Object(id) => format!("o:{id}"),
+        };
+        plain.bytes().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Parses an opaque cursor
+    pub fn decode(s: &str) -> Option<Self> {
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return None;
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..(i + 2)], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let plain = String::from_utf8(bytes).ok()?;
+        match plain.split_once(':')? {
+            ("w", work_id) if !work_id.is_empty() => Some(Self::Work(work_id.to_string())),
+            ("o", id) => id.parse().ok().map(Self::Object),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        for cursor in [
+            Cursor::Work("uXmZ2vjT6Bz2P4NtIWwVNyqF".to_string()),
+            Cursor::Object(0),
+            Cursor::Object(i64::MAX),
+        ] {
+            assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        }
+        let hex = |s: &str| s.bytes().map(|b| format!("{b:02x}")).collect::<String>();
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("7"), None);
+        assert_eq!(Cursor::decode("zz"), None);
+        assert_eq!(Cursor::decode(&hex("x:1")), None);
+        assert_eq!(Cursor::decode(&hex("o:a")), None);
+        assert_eq!(Cursor::decode(&hex("w:")), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Scenario versioning
+//!
+//! Every change to the scenarios table is recorded in the `scenario_versions`
+//! table, keyed by scenario name, along with its author, comment and a diff
+//! against the definition it supersedes
+
+use super::GraphDB;
+use serde_json::Value;
+use shared::api::ScenarioVersion;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The author and reason of a scenario change
+pub struct Change<'a> {
+    pub author: &'a str,
+    pub comment: Option<&'a str>,
+}
+
+/// Computes the top level differences between two scenario definitions
+pub fn diff(old: Option<&Value>, new: Option<&Value>) -> Value {
+    let empty = serde_json::Map::new();
+    let old = old.and_then(|v| v.as_object()).unwrap_or(&empty);
+    let new = new.and_then(|v| v.as_object()).unwrap_or(&empty);
+    let mut res = serde_json::Map::new();
+    // Note: missing fields are treated as null
+    for key in old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+    {
+        let o = old.get(key).unwrap_or(&Value::Null);
+        let n = new.get(key).unwrap_or(&Value::Null);
+        if o != n {
+            res.insert(key.clone(), serde_json::json!({ "old": o, "new": n }));
+        }
+    }
+    Value::Object(res)
+}
+
+/// Records a new version of a scenario
+///
+/// `def` is the new definition (`None` for deletions) and `prev` the
+/// definition it supersedes (if any)
+pub async fn record_version(
+    txn: &deadpool_postgres::Transaction<'_>,
+    name: &str,
+    op: &str,
+    change: &Change<'_>,
+    def: Option<&Value>,
+    prev: Option<&Value>,
+) -> Result<i32, tokio_postgres::Error> {
+    let stmt = txn
+        .prepare_cached(
+            "INSERT INTO scenario_versions (name, version, author, op, comment, def, diff)
+              SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5, $6
+              FROM scenario_versions WHERE name = $1
+              RETURNING version",
+        )
+        .await?;
+    let row = txn
+        .query_one(
+            &stmt,
+            &[
+                &name,
+                &change.author,
+                &op,
+                &change.comment,
+                &def,
+                &diff(prev, def),
+            ],
+        )
+        .await?;
+    row.try_get("version")
+}
+
+impl GraphDB {
+    /// Lists the recorded versions of a scenario, most recent first
+    pub async fn list_scenario_versions(
+        &self,
+        name: &str,
+    ) -> Result<Vec<ScenarioVersion>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT name, version, t, author, op, comment, diff
+                  FROM scenario_versions
+                  WHERE name = $1
+                  ORDER BY version DESC",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare list_scenario_versions statement: {}", e);
+                e
+            })?;
+        let rows = client.query(&stmt, &[&name]).await.map_err(|e| {
+            error!("Failed to execute list_scenario_versions statement: {}", e);
+            e
+        })?;
+        let mut res: Vec<ScenarioVersion> = Vec::with_capacity(rows.len());
+        for row in rows {
+            res.push(ScenarioVersion {
+                name: row.try_get("name")?,
+                version: row.try_get("version")?,
+                t: row.try_get("t")?,
+                author: row.try_get("author")?,
+                op: row.try_get("op")?,
+                comment: row.try_get("comment")?,
+                diff: row.try_get("diff")?,
+                def: None,
+            })
+        }
+        Ok(res)
+    }
+
+    /// Retrieves a recorded version of a scenario
+    pub async fn get_scenario_version(
+        &self,
+        name: &str,
+        version: i32,
+    ) -> Result<Option<ScenarioVersion>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT name, version, t, author, op, comment, diff, def
+                  FROM scenario_versions
+                  WHERE name = $1 AND version = $2",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_scenario_version statement: {}", e);
+                e
+            })?;
+        let row = client
+            .query_opt(&stmt, &[&name, &version])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute get_scenario_version statement: {}", e);
+                e
+            })?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(ScenarioVersion {
+            name: row.try_get("name")?,
+            version: row.try_get("version")?,
+            t: row.try_get("t")?,
+            author: row.try_get("author")?,
+            op: row.try_get("op")?,
+            comment: row.try_get("comment")?,
+            diff: row.try_get("diff")?,
+            def: row.try_get("def")?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        let old = json!({"name": "a", "description": "old", "local_query": "q"});
+        let new = json!({"name": "a", "description": "new", "context": null});
+        assert_eq!(
+            diff(Some(&old), Some(&new)),
+            json!({
+                "description": {"old": "old", "new": "new"},
+                "local_query": {"old": "q", "new": null},
+            })
+        );
+        assert_eq!(diff(Some(&old), Some(&old)), json!({}));
+        assert_eq!(
+            diff(None, Some(&json!({"name": "a"}))),
+            json!({"name": {"old": null, "new": "a"}})
+        );
+        assert_eq!(
+            diff(Some(&json!({"name": "a"})), None),
+            json!({"name": {"old": "a", "new": null}})
+        );
+    }
+}
@@ -0,0 +1,557 @@
+//! Reprocessing of historical works
+//!
+//! A reprocess job selects, in submission order, the works containing objects
+//! of a given type which were processed by a backend older than the requested
+//! version (or which carry no backend version at all). The works are handed
+//! out in small batches to the reprocessor task which re-enqueues them
+
+use super::GraphDB;
+use shared::{
+    api::{ReprocessAction, ReprocessJob},
+    object,
+};
+use std::time::{Duration, SystemTime};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// How long a claimed batch prevents other endpoints from working on the same job
+const LEASE: Duration = Duration::from_secs(600);
+
+/// The number of works scanned for each work to enqueue
+const SCAN_FACTOR: u32 = 10;
+
+const JOB_COLUMNS: &str = "
+    id, t, author, object_type, min_version, org, t_start, t_end, state,
+    scanned, enqueued, failed, t_updated,
+    (SELECT count(*) FROM reprocessed_works rw JOIN work_status ws ON ws.work_id = rw.new_work_id
+      WHERE rw.job_id = reprocess_jobs.id
+        AND ws.state <> 'completed'
+        AND (ws.expires_at IS NULL OR ws.expires_at > current_timestamp)) AS in_flight";
+
+/// Converts a row holding the [`JOB_COLUMNS`] into a job
+fn row2job(row: tokio_postgres::Row) -> Result<ReprocessJob, tokio_postgres::Error> {
+    Ok(ReprocessJob {
+        id: row.try_get("id")?,
+        t: row.try_get("t")?,
+        author: row.try_get("author")?,
+        object_type: row.try_get("object_type")?,
+        min_version: row.try_get("min_version")?,
+        org: row.try_get("org")?,
+        start: row.try_get("t_start")?,
+        end: row.try_get("t_end")?,
+        state: row.try_get("state")?,
+        scanned: row.try_get("scanned")?,
+        enqueued: row.try_get("enqueued")?,
+        failed: row.try_get("failed")?,
+        in_flight: row.try_get("in_flight")?,
+        t_updated: row.try_get("t_updated")?,
+    })
+}
+
+/// The outcome of a reprocess job state change
+pub enum ReprocessUpdate {
+    /// The job was updated
+    Updated(Box<ReprocessJob>),
+    /// The change does not apply to the current state of the job
+    Invalid,
+    /// No such job
+    NotFound,
+}
+
+/// A work to reprocess
+pub struct ReprocessCandidate {
+    /// The id of the work to reprocess
+    pub work_id: String,
+    /// The entry object of the work
+    pub info: object::Info,
+    /// The original relation metadata of the work
+    pub relation_metadata: object::Metadata,
+}
+
+/// A batch of works to reprocess
+pub struct ReprocessBatch {
+    pub job_id: i64,
+    pub candidates: Vec<ReprocessCandidate>,
+    cursor: Option<(SystemTime, String)>,
+    scanned: i64,
+    exhausted: bool,
+}
+
+/// Returns true if an object processed by the backend `version` needs reprocessing
+///
+/// Note: a missing version (empty) means the object was processed before
+/// versions were recorded or the backend failed
+fn is_outdated(version: &str, min_version: &semver::Version) -> bool {
+    if version.is_empty() {
+        return true;
+    }
+    semver::Version::parse(version)
+        .map(|v| v < *min_version)
+        .unwrap_or(false)
+}
+
+impl GraphDB {
+    /// Creates a new (running) reprocess job
+    pub async fn create_reprocess_job(
+        &self,
+        author: &str,
+        object_type: &str,
+        min_version: &semver::Version,
+        org: Option<&str>,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<ReprocessJob, Box<dyn std::error::Error>> {
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(&format!(
+                "INSERT INTO reprocess_jobs (author, object_type, min_version, org, t_start, t_end)
+                  VALUES ($1, $2, $3, $4, $5, $6)
+                  RETURNING {JOB_COLUMNS}"
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare create_reprocess_job statement: {}", e);
+                e
+            })?;
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &author,
+                    &object_type,
+                    &min_version.to_string(),
+                    &org,
+                    &start,
+                    &end,
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to execute create_reprocess_job statement: {}", e);
+                e
+            })?;
+        Ok(row2job(row)?)
+    }
+
+    /// Lists all the reprocess jobs, most recent first
+    pub async fn list_reprocess_jobs(
+        &self,
+    ) -> Result<Vec<ReprocessJob>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(&format!(
+                "SELECT {JOB_COLUMNS} FROM reprocess_jobs ORDER BY id DESC"
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare list_reprocess_jobs statement: {}", e);
+                e
+            })?;
+        let rows = client.query(&stmt, &[]).await.map_err(|e| {
+            error!("Failed to execute list_reprocess_jobs statement: {}", e);
+            e
+        })?;
+        Ok(rows.into_iter().map(row2job).collect::<Result<_, _>>()?)
+    }
+
+    /// Retrieves a reprocess job
+    pub async fn get_reprocess_job(
+        &self,
+        id: i64,
+    ) -> Result<Option<ReprocessJob>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(&format!(
+                "SELECT {JOB_COLUMNS} FROM reprocess_jobs WHERE id = $1"
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_reprocess_job statement: {}", e);
+                e
+            })?;
+        let row = client.query_opt(&stmt, &[&id]).await.map_err(|e| {
+            error!("Failed to execute get_reprocess_job statement: {}", e);
+            e
+        })?;
+        Ok(row.map(row2job).transpose()?)
+    }
+
+    /// Pauses, resumes or cancels a reprocess job
+    pub async fn update_reprocess_job(
+        &self,
+        id: i64,
+        action: ReprocessAction,
+    ) -> Result<ReprocessUpdate, Box<dyn std::error::Error>> {
+        let (from, to): (&[&str], &str) = match action {
+            ReprocessAction::Pause => (&["running"], "paused"),
+            ReprocessAction::Resume => (&["paused"], "running"),
+            ReprocessAction::Cancel => (&["running", "paused"], "cancelled"),
+        };
+        let client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(&format!(
+                "UPDATE reprocess_jobs SET state = $2, t_updated = current_timestamp
+                  WHERE id = $1 AND state = ANY($3)
+                  RETURNING {JOB_COLUMNS}"
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare update_reprocess_job statement: {}", e);
+                e
+            })?;
+        let row = client
+            .query_opt(&stmt, &[&id, &to, &from])
+            .await
+            .map_err(|e| {
+                error!("Failed to execute update_reprocess_job statement: {}", e);
+                e
+            })?;
+        if let Some(row) = row {
+            return Ok(ReprocessUpdate::Updated(Box::new(row2job(row)?)));
+        }
+        let exists = client
+            .query_opt("SELECT 1 FROM reprocess_jobs WHERE id = $1", &[&id])
+            .await
+            .map_err(|e| {
+                error!("Failed to lookup reprocess job: {}", e);
+                e
+            })?
+            .is_some();
+        Ok(if exists {
+            ReprocessUpdate::Invalid
+        } else {
+            ReprocessUpdate::NotFound
+        })
+    }
+
+    /// Claims the next batch of works to reprocess
+    ///
+    /// At most `batch_size` works are returned, fewer if `max_in_flight`
+    /// reprocessed works are still being processed
+    ///
+    /// The job is leased to the caller until the batch is recorded via
+    /// [`GraphDB::record_reprocess_batch`] (or the lease expires)
+    pub async fn claim_reprocess_batch(
+        &self,
+        batch_size: u32,
+        max_in_flight: u32,
+    ) -> Result<Option<ReprocessBatch>, Box<dyn std::error::Error>> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start transaction: {e}");
+            e
+        })?;
+        let in_flight: i64 = txn
+            .query_one(
+                "SELECT count(*) FROM reprocessed_works rw JOIN work_status ws ON ws.work_id = rw.new_work_id
+                  WHERE ws.state <> 'completed'
+                    AND (ws.expires_at IS NULL OR ws.expires_at > current_timestamp)",
+                &[],
+            )
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(|e| {
+                error!("Failed to count in flight reprocessed works: {e}");
+                e
+            })?;
+        let budget = i64::from(max_in_flight)
+            .saturating_sub(in_flight)
+            .min(i64::from(batch_size));
+        if budget <= 0 {
+            debug!("Reprocessing throttled: {in_flight} works in flight");
+            return Ok(None);
+        }
+        let Some(job) = txn
+            .query_opt(
+                "SELECT id, object_type, min_version, org, t_start, t_end, cursor_t, cursor_work_id
+                  FROM reprocess_jobs
+                  WHERE state = 'running' AND (leased_until IS NULL OR leased_until < current_timestamp)
+                  ORDER BY id
+                  LIMIT 1
+                  FOR UPDATE SKIP LOCKED",
+                &[],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to select reprocess job: {e}");
+                e
+            })?
+        else {
+            return Ok(None);
+        };
+        let job_id: i64 = job.try_get("id")?;
+        let object_type: &str = job.try_get("object_type")?;
+        let min_version = semver::Version::parse(job.try_get("min_version")?)?;
+        let org: Option<&str> = job.try_get("org")?;
+        let start: SystemTime = job.try_get("t_start")?;
+        let end: SystemTime = job.try_get("t_end")?;
+        let cursor_t: Option<SystemTime> = job.try_get("cursor_t")?;
+        let cursor_work_id: Option<&str> = job.try_get("cursor_work_id")?;
+        txn.execute(
+            "UPDATE reprocess_jobs SET leased_until = current_timestamp + make_interval(secs => $2)
+              WHERE id = $1",
+            &[&job_id, &LEASE.as_secs_f64()],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to lease reprocess job: {e}");
+            e
+        })?;
+
+        let limit = budget * i64::from(SCAN_FACTOR);
+        let stmt = txn
+            .prepare_cached(
+                "SELECT
+                    e.work_id, e.t, e.org, e.object_id, e.object_type, e.object_subtype,
+                    e.recursion_level, e.size, e.hashes, e.entropy, r.props,
+                    ARRAY(
+                      SELECT DISTINCT COALESCE(o.result->'ok'->'object_metadata'->>$8::text, '')
+                      FROM objects o WHERE o.work_id = e.work_id AND o.object_type = $1
+                    ) AS versions
+                  FROM objects e JOIN rels r ON r.child = e.id AND r.parent IS NULL
+                  WHERE e.is_entry AND e.t >= $2 AND e.t < $3
+                    AND ($4::text IS NULL OR e.org = $4)
+                    AND ($5::timestamptz IS NULL OR (e.t, e.work_id) > ($5, $6))
+                    AND EXISTS (SELECT 1 FROM objects o WHERE o.work_id = e.work_id AND o.object_type = $1)
+                  ORDER BY e.t, e.work_id
+                  LIMIT $7",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare claim_reprocess_batch statement: {e}");
+                e
+            })?;
+        let rows = txn
+            .query(
+                &stmt,
+                &[
+                    &object_type,
+                    &start,
+                    &end,
+                    &org,
+                    &cursor_t,
+                    &cursor_work_id,
+                    &limit,
+                    &shared::META_KEY_BACKEND_VERSION,
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to execute claim_reprocess_batch statement: {e}");
+                e
+            })?;
+        let mut batch = ReprocessBatch {
+            job_id,
+            candidates: Vec::new(),
+            cursor: None,
+            scanned: 0,
+            exhausted: rows.len() < limit as usize,
+        };
+        let ctime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs_f64();
+        for (i, row) in rows.iter().enumerate() {
+            let work_id: String = row.try_get("work_id")?;
+            batch.scanned += 1;
+            batch.cursor = Some((row.try_get("t")?, work_id.clone()));
+            let versions: Vec<String> = row.try_get("versions")?;
+            if !versions.iter().any(|v| is_outdated(v, &min_version)) {
+                continue;
+            }
+            let size: i64 = row.try_get("size")?;
+            batch.candidates.push(ReprocessCandidate {
+                work_id,
+                info: object::Info {
+                    org: row.try_get("org")?,
+                    object_id: row.try_get("object_id")?,
+                    object_type: row.try_get("object_type")?,
+                    object_subtype: row.try_get("object_subtype")?,
+                    recursion_level: row.try_get::<_, i32>("recursion_level")? as u32,
+                    size: size.try_into()?,
+                    hashes: serde_json::from_value(row.try_get("hashes")?)?,
+                    ctime,
+                    entropy: row.try_get("entropy")?,
+                },
+                relation_metadata: serde_json::from_value(row.try_get("props")?)?,
+            });
+            if batch.candidates.len() as i64 >= budget {
+                batch.exhausted &= i + 1 == rows.len();
+                break;
+            }
+        }
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit claim_reprocess_batch transaction: {e}");
+            e
+        })?;
+        Ok(Some(batch))
+    }
+
+    /// Records the outcome of a batch and releases the job lease
+    ///
+    /// `enqueued` holds the (original, new) work id pairs
+    pub async fn record_reprocess_batch(
+        &self,
+        batch: &ReprocessBatch,
+        enqueued: &[(String, String)],
+        failed: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            e
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start transaction: {e}");
+            e
+        })?;
+        let stmt = txn
+            .prepare_cached(
+                "INSERT INTO reprocessed_works (job_id, work_id, new_work_id) VALUES ($1, $2, $3)
+                  ON CONFLICT (job_id, work_id) DO UPDATE SET new_work_id = EXCLUDED.new_work_id",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare record_reprocess_batch statement: {e}");
+                e
+            })?;
+        for (work_id, new_work_id) in enqueued {
+            txn.execute(&stmt, &[&batch.job_id, work_id, new_work_id])
+                .await
+                .map_err(|e| {
+                    error!("Failed to record reprocessed work: {e}");
+                    e
+                })?;
+        }
+        let (cursor_t, cursor_work_id) = batch
+            .cursor
+            .as_ref()
+            .map(|(t, w)| (Some(t), Some(w)))
+            .unwrap_or_default();
+        txn.execute(
+            "UPDATE reprocess_jobs SET
+                cursor_t = COALESCE($2, cursor_t),
+                cursor_work_id = COALESCE($3, cursor_work_id),
+                scanned = scanned + $4,
+                enqueued = enqueued + $5,
+                failed = failed + $6,
+                state = CASE WHEN $7 AND state = 'running' THEN 'completed' ELSE state END,
+                t_updated = current_timestamp,
+                leased_until = NULL
+              WHERE id = $1",
+            &[
+                &batch.job_id,
+                &cursor_t,
+                &cursor_work_id,
+                &batch.scanned,
+                &(enqueued.len() as i64),
+                &(failed as i64),
+                &batch.exhausted,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to update reprocess job: {e}");
+            e
+        })?;
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit record_reprocess_batch transaction: {e}");
+            e
+        })?;
+        if batch.exhausted {
+            info!("Reprocess job {} completed", batch.job_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_outdated() {
+        let min = semver::Version::parse("1.2.0").unwrap();
+        assert!(is_outdated("", &min));
+        assert!(is_outdated("1.1.9", &min));
+        assert!(is_outdated("1.2.0-rc.1", &min));
+        assert!(!is_outdated("1.2.0", &min));
+        assert!(!is_outdated("2.0.0", &min));
+        assert!(!is_outdated("garbage", &min));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a database (see ENDPOINT_TEST_DB)"]
+    async fn test_claim_keeps_recursion_level() {
+        let graphdb = crate::graphdb::test_graphdb().await;
+        let client = graphdb.write_pool.get().await.unwrap();
+        let object_type = format!("TEST{}", shared::utils::random_string(8));
+        let work_id = shared::utils::random_string(shared::MSG_CORRID_LEN);
+        client
+            .execute(
+                "SELECT create_object_partitions(now() - interval '1 day', now() + interval '1 day')",
+                &[],
+            )
+            .await
+            .unwrap();
+        let id: i64 = client
+            .query_one(
+                "INSERT INTO objects
+                   (org, work_id, is_entry, object_id, object_type, recursion_level, size, hashes, t, result)
+                 VALUES ('ctx', $1, true, 'abc', $2, 1, 3, '{}', now(), '{}')
+                 RETURNING id",
+                &[&work_id, &object_type],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        client
+            .execute(
+                "INSERT INTO rels (parent, child, props) VALUES (NULL, $1, '{}')",
+                &[&id],
+            )
+            .await
+            .unwrap();
+        let job_id: i64 = client
+            .query_one(
+                "INSERT INTO reprocess_jobs (author, object_type, min_version, t_start, t_end)
+                 VALUES ('test', $1, '1.0.0', now() - interval '1 hour', now() + interval '1 hour')
+                 RETURNING id",
+                &[&object_type],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let batch = graphdb.claim_reprocess_batch(10, 100).await.unwrap();
+        client
+            .execute("DELETE FROM reprocess_jobs WHERE id = $1", &[&job_id])
+            .await
+            .unwrap();
+        client
+            .execute("DELETE FROM rels WHERE child = $1", &[&id])
+            .await
+            .unwrap();
+        client
+            .execute("DELETE FROM objects WHERE work_id = $1", &[&work_id])
+            .await
+            .unwrap();
+        let batch = batch.expect("The job should be claimed");
+        assert_eq!(batch.job_id, job_id);
+        assert_eq!(batch.candidates.len(), 1);
+        assert_eq!(batch.candidates[0].work_id, work_id);
+        assert_eq!(batch.candidates[0].info.recursion_level, 1);
+    }
+}
@@ -0,0 +1,171 @@
+//! Scenario statistics
+//!
+//! The statistics are collected by the directors in hourly buckets
+
+use super::GraphDB;
+use shared::api::{ScenarioStats, StatsPeriod};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+impl GraphDB {
+    /// Retrieves the statistics of a scenario over the last `hours` and `days`
+    pub async fn get_scenario_stats(
+        &self,
+        id: i64,
+        hours: u32,
+        days: u32,
+    ) -> Result<Option<ScenarioStats>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT
+                    (SELECT max(last_hit) FROM scenario_stats WHERE scenario_id = $1) AS last_hit
+                  FROM scenarios WHERE id = $1",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_scenario_stats statement: {}", e);
+                e
+            })?;
+        let Some(row) = client.query_opt(&stmt, &[&id]).await.map_err(|e| {
+            error!("Failed to execute get_scenario_stats statement: {}", e);
+            e
+        })?
+        else {
+            return Ok(None);
+        };
+        let last_hit: Option<std::time::SystemTime> = row.try_get("last_hit")?;
+        let last_hit = last_hit
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH))
+            .transpose()?
+            .map(|d| d.as_secs_f64());
+        let stmt = client
+            .prepare_cached(
+                "SELECT
+                    date_trunc($2, hour, 'UTC') AS t,
+                    sum(evaluations)::bigint AS evaluations,
+                    sum(matches)::bigint AS matches,
+                    COALESCE(sum(local_time) / NULLIF(sum(evaluations), 0), 0) AS avg_local_time,
+                    sum(global_evaluations)::bigint AS global_evaluations,
+                    COALESCE(sum(global_time) / NULLIF(sum(global_evaluations), 0), 0) AS avg_global_time
+                  FROM scenario_stats
+                  WHERE scenario_id = $1 AND hour >= date_trunc($2, now(), 'UTC') - $3::integer * ('1 ' || $2)::interval
+                  GROUP BY 1
+                  ORDER BY 1 DESC",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare get_scenario_stats statement: {}", e);
+                e
+            })?;
+        let mut periods = Vec::with_capacity(2);
+        for (unit, count) in [("hour", hours), ("day", days)] {
+            // Note: the current (partial) period is included
+            let count = count.saturating_sub(1) as i32;
+            let rows = client
+                .query(&stmt, &[&id, &unit, &count])
+                .await
+                .map_err(|e| {
+                    error!("Failed to execute get_scenario_stats statement: {}", e);
+                    e
+                })?;
+            let mut res: Vec<StatsPeriod> = Vec::with_capacity(rows.len());
+            for row in rows {
+                res.push(StatsPeriod {
+                    t: row.try_get("t")?,
+                    evaluations: row.try_get("evaluations")?,
+                    matches: row.try_get("matches")?,
+                    avg_local_time: row.try_get("avg_local_time")?,
+                    global_evaluations: row.try_get("global_evaluations")?,
+                    avg_global_time: row.try_get("avg_global_time")?,
+                });
+            }
+            periods.push(res);
+        }
+        let daily = periods.pop().unwrap_or_default();
+        let hourly = periods.pop().unwrap_or_default();
+        Ok(Some(ScenarioStats {
+            id,
+            last_hit,
+            hourly,
+            daily,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphdb::history::Change;
+
+    #[tokio::test]
+    #[ignore = "requires a database (see ENDPOINT_TEST_DB)"]
+    async fn test_stats_survive_edits() {
+        let graphdb = crate::graphdb::test_graphdb().await;
+        let mut client = graphdb.write_pool.get().await.unwrap();
+        // Note: everything happens in a transaction which is never committed
+        let txn = client.transaction().await.unwrap();
+        let mut scenario: shared::scene::Scenario = serde_json::from_value(serde_json::json!({
+            "name": "stats",
+            "creator": "test",
+            "description": "before",
+            "local_query": "object_type == \"ZIP\"",
+            "context": null,
+            "action": "ALLOW",
+        }))
+        .unwrap();
+        let change = Change {
+            author: "test",
+            comment: None,
+        };
+        let created = GraphDB::insert_scenario(&txn, &scenario, None, &change, "create")
+            .await
+            .unwrap();
+        txn.execute(
+            "INSERT INTO scenario_stats (scenario_id, hour, evaluations, matches)
+             VALUES ($1, date_trunc('hour', now()), 10, 2)",
+            &[&created.id],
+        )
+        .await
+        .unwrap();
+        let n_stats = |id: i64| {
+            let txn = &txn;
+            async move {
+                txn.query_one(
+                    "SELECT count(*) FROM scenario_stats WHERE scenario_id = $1",
+                    &[&id],
+                )
+                .await
+                .unwrap()
+                .get::<_, i64>(0)
+            }
+        };
+
+        // Edit
+        scenario.description = "after".to_string();
+        let edited = GraphDB::insert_scenario(&txn, &scenario, Some(created.id), &change, "update")
+            .await
+            .unwrap();
+        assert_eq!(edited.id, created.id);
+        assert_eq!(n_stats(edited.id).await, 1);
+
+        // Rename
+        scenario.name = "renamed".to_string();
+        let renamed =
+            GraphDB::insert_scenario(&txn, &scenario, Some(created.id), &change, "update")
+                .await
+                .unwrap();
+        assert_eq!(renamed.id, created.id);
+        assert_eq!(n_stats(renamed.id).await, 1);
+
+        // Delete
+        txn.execute("DELETE FROM scenarios WHERE id = $1", &[&created.id])
+            .await
+            .unwrap();
+        assert_eq!(n_stats(created.id).await, 0);
+        txn.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+//! Scenario export and import
+//!
+//! Imports are all-or-nothing: every scenario is validated before anything is
+//! written and all the changes are committed in a single transaction
+
+use super::{Change, GraphDB, ScenaryError};
+use shared::{
+    api::{ImportReport, OnConflict, Renamed},
+    scene,
+};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+impl GraphDB {
+    /// Retrieves the definitions of the given scenarios (or all of them), ordered by name
+    pub async fn export_scenarios(
+        &self,
+        ids: Option<&[i64]>,
+    ) -> Result<Vec<scene::Scenario>, Box<dyn std::error::Error>> {
+        let client = self.read_pool.get().await.map_err(|e| {
+            error!("Failed to get read-only connection from pool: {e}");
+            e
+        })?;
+        let stmt = client
+            .prepare_cached(
+                "SELECT def FROM scenarios WHERE $1::bigint[] IS NULL OR id = ANY($1) ORDER BY name",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare export_scenarios statement: {}", e);
+                e
+            })?;
+        let rows = client.query(&stmt, &[&ids]).await.map_err(|e| {
+            error!("Failed to execute export_scenarios statement: {}", e);
+            e
+        })?;
+        let mut res: Vec<scene::Scenario> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let def: serde_json::Value = row.try_get("def")?;
+            res.push(serde_json::from_value(def).map_err(|e| {
+                error!("Invalid scenario from export_scenarios statement: {}", e);
+                e
+            })?);
+        }
+        Ok(res)
+    }
+
+    /// Imports a set of scenarios atomically
+    ///
+    /// Nothing is imported if any of the scenarios is invalid
+    pub async fn import_scenarios(
+        &self,
+        scenarios: Vec<scene::Scenario>,
+        on_conflict: OnConflict,
+        change: &Change<'_>,
+    ) -> Result<ImportReport, ScenaryError> {
+        let mut names = std::collections::HashSet::new();
+        for scenario in scenarios.iter() {
+            Self::validate_scenario(scenario).await?;
+            if !names.insert(scenario.name.clone()) {
+                return Err(ScenaryError::Duplicate);
+            }
+        }
+        let mut client = self.write_pool.get().await.map_err(|e| {
+            error!("Failed to get read-write connection from pool: {e}");
+            ScenaryError::Database
+        })?;
+        let txn = client.transaction().await.map_err(|e| {
+            error!("Failed to start transaction: {}", e);
+            ScenaryError::Database
+        })?;
+        let stmt = txn
+            .prepare_cached("SELECT id FROM scenarios WHERE name = $1")
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare import_scenarios statement: {}", e);
+                ScenaryError::Database
+            })?;
+        let lookup = |name: String| {
+            let txn = &txn;
+            let stmt = &stmt;
+            async move {
+                txn.query_opt(stmt, &[&name])
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to execute import_scenarios statement: {}", e);
+                        ScenaryError::Database
+                    })?
+                    .map(|row| row.try_get::<_, i64>("id"))
+                    .transpose()
+                    .map_err(|_| ScenaryError::Database)
+            }
+        };
+        let mut report = ImportReport::default();
+        for mut scenario in scenarios {
+            let mut replace_id = lookup(scenario.name.clone()).await?;
+            if replace_id.is_some() {
+                match on_conflict {
+                    OnConflict::Skip => {
+                        report.skipped.push(scenario.name);
+                        continue;
+                    }
+                    OnConflict::Overwrite => {}
+                    OnConflict::Rename => {
+                        let from = std::mem::take(&mut scenario.name);
+                        let mut n = 2;
+                        let to = loop {
+                            let candidate = format!("{from}-{n}");
+                            if !names.contains(&candidate)
+                                && lookup(candidate.clone()).await?.is_none()
+                            {
+                                break candidate;
+                            }
+                            n += 1;
+                        };
+                        scenario.name = to.clone();
+                        replace_id = None;
+                        report.renamed.push(Renamed { from, to });
+                    }
+                }
+            }
+            Self::insert_scenario(&txn, &scenario, replace_id, change, "import").await?;
+            report.imported.push(scenario.name);
+        }
+        txn.commit().await.map_err(|e| {
+            error!("Failed to commit import_scenarios transaction: {}", e);
+            ScenaryError::Database
+        })?;
+        Ok(report)
+    }
+}
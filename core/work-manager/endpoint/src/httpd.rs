@@ -2,20 +2,38 @@
 //!
 //! Contextal platform API endpoints
 
+mod openapi;
 mod tempobj;
 
+use crate::bundle;
 use crate::graphdb;
+use crate::quota::{QuotaExceeded, Quotas};
+use crate::registry;
 use actix_multipart::form;
 use actix_web::{body, delete, error, get, http, route, web, HttpRequest, HttpResponse};
 use futures::{StreamExt, TryStreamExt};
 use metrics_exporter_prometheus::PrometheusHandle;
 use serde::{Deserialize, Serialize};
-use shared::{clamd, object, scene};
+use shared::{api, object, scene, typedet};
 use std::ops::Deref;
 use tempobj::TempObject;
 use tokio::sync::{mpsc, oneshot};
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+/// Fails unless the request carries the admin credentials
+fn check_admin(req: &HttpRequest, quotas: &Quotas) -> Result<(), error::Error> {
+    let authorization = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if quotas.is_admin(authorization) {
+        Ok(())
+    } else {
+        Err(error::ErrorUnauthorized("Invalid admin credentials"))
+    }
+}
 
 /// Miscellaneous limits
 #[derive(Clone)]
@@ -34,6 +52,7 @@ pub struct JobRequest {
     pub object: object::Info,
     pub ttl: std::time::Duration,
     pub max_recursion: u32,
+    pub priority: shared::object::Priority,
     pub reply_tx: oneshot::Sender<String>,
     pub relation_metadata: shared::object::Metadata,
 }
@@ -44,68 +63,128 @@ pub enum BrokerAction {
     Job(JobRequest),
     ApplyScenarios(Vec<String>),
     Reload,
+    InvalidateCache(shared::amqp::CacheInvalidation),
 }
 
 /// The prometheus endpoint
+#[utoipa::path(
+    tag = "metrics",
+    responses((status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"))
+)]
 #[get("/metrics")]
 async fn metrics(prom: web::Data<PrometheusHandle>) -> String {
     prom.render()
 }
 
 /// The form params for [`submit_v1`]
-#[derive(form::MultipartForm)]
+#[derive(form::MultipartForm, ToSchema)]
 #[multipart(deny_unknown_fields, duplicate_field = "deny")]
 struct SubmitFormV1 {
     /// Org
+    #[schema(value_type = Option<String>)]
     org: Option<form::text::Text<String>>,
     /// Relation metadata
+    #[schema(value_type = Option<Object>)]
     relation_metadata: Option<form::json::Json<shared::object::Metadata>>,
     /// The number of seconds allowed to fully complete this work request
+    #[schema(value_type = Option<u64>)]
     ttl: Option<form::text::Text<u64>>,
     /// The maximum recursion level a work can reach
+    #[schema(value_type = Option<u32>)]
     maxrec: Option<form::text::Text<u32>>,
+    /// The work priority (default "interactive"; use "bulk" for mass submissions)
+    #[schema(value_type = Option<shared::object::Priority>)]
+    priority: Option<form::text::Text<shared::object::Priority>>,
     /// The object data
+    #[schema(value_type = String, format = Binary)]
     object_data: TempObject,
     // FIXME: more params?
     // FIXME: do we want to force object_metadata via params?
     // FIXME: do we want to force the ftype?
 }
 
-/// The response object returned by [`submit_v1`]
-#[derive(Serialize)]
-struct SubmitResultV1 {
-    /// The id assigned to the work object
-    object_id: String,
-    /// The id assigned to the work request - used for retrieving graph results
-    work_id: String,
-    /// The effective ttl of the work
-    ttl: u64,
-}
-
 #[derive(Serialize)]
 struct Origin<'a> {
     peer: Option<&'a str>,
     real_peer: Option<&'a str>,
     ttl: u64,
     max_recursion: u32,
+    priority: shared::object::Priority,
 }
 
 /// The file submission endpoint
+#[utoipa::path(
+    tag = "works",
+    request_body(content = SubmitFormV1, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Work request queued", body = api::SubmitResult),
+        (status = 400, description = "Invalid submission"),
+        (status = 429, description = "Submission rate limit or daily quota exceeded"),
+    )
+)]
 #[route("/api/v1/submit", method = "POST", method = "PUT")]
+#[allow(clippy::too_many_arguments)]
 async fn submit_v1(
     req: HttpRequest,
     form::MultipartForm(submit_form): form::MultipartForm<SubmitFormV1>,
-    typedet: web::Data<clamd::Typedet>,
+    typedet: web::Data<typedet::Typedet>,
     tx: web::Data<mpsc::WeakSender<BrokerAction>>,
     objects_path: web::Data<String>,
     is_reprocess_enabled: web::Data<bool>,
-) -> Result<(web::Json<SubmitResultV1>, http::StatusCode), error::Error> {
-    // Get the temp object from the form and transform it into an object
+    graphdb: web::Data<graphdb::GraphDB>,
+    quotas: web::Data<Quotas>,
+) -> Result<(web::Json<api::SubmitResult>, http::StatusCode), error::Error> {
     let org = submit_form
         .org
         .as_ref()
         .map(|s| s.as_str())
         .unwrap_or("ctx");
+
+    // Enforce the org limits before the object is stored
+    let size = submit_form.object_data.size();
+    let reserved = match check_quotas(org, size, &quotas, &graphdb).await {
+        Ok(reserved) => reserved,
+        Err(e) => {
+            submit_form.object_data.remove().await;
+            return Err(e);
+        }
+    };
+    let org = org.to_string();
+    let res = enqueue_submission(
+        req,
+        submit_form,
+        &org,
+        &typedet,
+        &tx,
+        objects_path.as_str(),
+        &is_reprocess_enabled,
+        &graphdb,
+    )
+    .await;
+    // Note: failed submissions do not count against the daily quota
+    if res.is_ok() {
+        quotas.record_submission(&org, size);
+    } else if reserved {
+        if let Err(e) = graphdb.release_usage(&org, size).await {
+            warn!("Failed to release usage for org \"{org}\": {e}");
+        }
+    }
+    res
+}
+
+/// Stores, types and enqueues a submitted object
+#[allow(clippy::too_many_arguments)]
+async fn enqueue_submission(
+    req: HttpRequest,
+    submit_form: SubmitFormV1,
+    org: &str,
+    typedet: &typedet::Typedet,
+    tx: &mpsc::WeakSender<BrokerAction>,
+    objects_path: &str,
+    is_reprocess_enabled: &bool,
+    graphdb: &graphdb::GraphDB,
+) -> Result<(web::Json<api::SubmitResult>, http::StatusCode), error::Error> {
+    // Get the temp object from the form and transform it into an object
     let mut object = submit_form
         .object_data
         .into_object(org)
@@ -120,17 +199,17 @@ async fn submit_v1(
     let object_id = object.object_id.clone();
     debug!("Object \"{}\" complely received", object_id);
 
-    // Query clamd for file type
-    typedet
-        .set_ftype(&mut object, objects_path.get_ref())
+    // Determine the file type
+    let detection = typedet
+        .set_ftype(&mut object, objects_path)
         .await
         .map_err(|e| {
             error!("Typedet failed: {e}");
             error::ErrorInternalServerError("Internal error: object detection failed")
         })?;
     debug!(
-        "Object \"{}\" has type \"{}\"",
-        object_id, object.object_type
+        "Object \"{}\" has type \"{}\" (confidence {})",
+        object_id, object.object_type, detection.confidence
     );
 
     // Setup the content of the work request
@@ -145,16 +224,21 @@ async fn submit_v1(
         .map(|txt| txt.into_inner())
         .unwrap_or(shared::MAX_WORK_DEPTH)
         .clamp(1, shared::MAX_WORK_DEPTH);
+    let priority = submit_form
+        .priority
+        .map(|txt| txt.into_inner())
+        .unwrap_or_default();
     debug!(
-        "Posted job request for object \"{}\", with ttl {}, max recursion {}",
+        "Posted job request for object \"{}\", with ttl {}, max recursion {}, priority {:?}",
         object_id,
         ttl.as_secs(),
-        max_recursion
+        max_recursion,
+        priority
     );
     let mut relation_metadata = submit_form
         .relation_metadata
         .map(|m| m.into_inner())
-        .unwrap_or_else(shared::object::Metadata::new);
+        .unwrap_or_default();
     relation_metadata.insert(
         shared::META_KEY_ORIGIN.to_string(),
         serde_json::to_value(Origin {
@@ -162,17 +246,27 @@ async fn submit_v1(
             real_peer: req.connection_info().realip_remote_addr(),
             ttl: ttl.as_secs(),
             max_recursion,
+            priority,
         })
         .unwrap(),
     );
-    if !is_reprocess_enabled.get_ref() {
+    if !is_reprocess_enabled {
         relation_metadata.insert(shared::META_KEY_REPROCESSABLE.to_string(), false.into());
     }
+    // Polyglots are also processed as their other types
+    let interpretations = typedet.interpretations(&detection);
+    if !interpretations.is_empty() {
+        relation_metadata.insert(
+            shared::META_KEY_INTERPRETATIONS.to_string(),
+            serde_json::to_value(&interpretations).unwrap(),
+        );
+    }
     shared::object::sanitize_meta_keys(&mut relation_metadata);
     let jobreq = JobRequest {
         object,
         ttl,
         max_recursion,
+        priority,
         reply_tx,
         relation_metadata,
     };
@@ -194,8 +288,13 @@ async fn submit_v1(
         error!("Failed to get request id from publisher: {e}");
         error::ErrorInternalServerError("Internal error: work allocation falied")
     })?;
+
+    // Note: the work is already queued, failing to track it is not fatal
+    if let Err(e) = graphdb.add_work_status(&work_id, ttl).await {
+        warn!("Failed to record status for work \"{work_id}\": {e}");
+    }
     Ok((
-        web::Json(SubmitResultV1 {
+        web::Json(api::SubmitResult {
             object_id,
             work_id,
             ttl: ttl.as_secs(),
@@ -204,7 +303,60 @@ async fn submit_v1(
     ))
 }
 
+/// Checks and reserves a submission against the org limits
+///
+/// Returns whether the submission was reserved in the daily usage
+async fn check_quotas(
+    org: &str,
+    size: u64,
+    quotas: &Quotas,
+    graphdb: &graphdb::GraphDB,
+) -> Result<bool, error::Error> {
+    let res = match quotas.check_rate(org) {
+        Ok(()) => {
+            let limits = quotas.limits(org);
+            match graphdb
+                .reserve_usage(org, size, limits.daily_bytes, limits.daily_objects)
+                .await
+            {
+                Ok(true) => Ok(true),
+                Ok(false) => Err(Quotas::daily_exceeded()),
+                Err(e) => {
+                    // Note: usage accounting is not critical, the submission is accepted
+                    warn!("Failed to account usage for org \"{org}\": {e}");
+                    Ok(false)
+                }
+            }
+        }
+        Err(e) => Err(e),
+    };
+    res.map_err(|e| {
+        quotas.record_rejection(org, &e);
+        let msg = match e {
+            QuotaExceeded::Rate(_) => "Submission rate limit exceeded",
+            QuotaExceeded::Daily(_) => "Daily submission quota exceeded",
+        };
+        debug!("Submission from org \"{org}\" refused: {msg}");
+        let retry_after = e.retry_after().as_secs_f64().ceil() as u64;
+        error::InternalError::from_response(
+            msg,
+            HttpResponse::TooManyRequests()
+                .insert_header((http::header::RETRY_AFTER, retry_after.max(1).to_string()))
+                .body(msg),
+        )
+        .into()
+    })
+}
+
 /// The get_work_graph endpoint
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    responses(
+        (status = 200, description = "The complete work graph", body = shared::amqp::JobResult),
+        (status = 404, description = "No such work (or work not yet complete)"),
+    )
+)]
 #[get("/api/v1/get_work_graph/{work_id}")]
 async fn get_work_graph_v1(
     work_id: web::Path<String>,
@@ -222,28 +374,285 @@ async fn get_work_graph_v1(
         .ok_or_else(|| error::ErrorNotFound("No such work"))
 }
 
-#[derive(Deserialize, Debug)]
+/// The work status endpoint
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    responses(
+        (status = 200, description = "The work processing status", body = graphdb::WorkStatus),
+        (status = 404, description = "No such work"),
+    )
+)]
+#[get("/api/v1/work/{work_id}/status")]
+async fn get_work_status_v1(
+    work_id: web::Path<String>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::WorkStatus>, error::Error> {
+    debug!("Processing get_work_status for work_id {work_id}");
+    graphdb
+        .get_work_status(&work_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to lookup work status: {e}");
+            error::ErrorInternalServerError("Internal error: work status lookup failed")
+        })?
+        .map(web::Json)
+        .ok_or_else(|| error::ErrorNotFound("No such work"))
+}
+
+/// Delete a work and the objects no longer referenced by other works
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    responses(
+        (status = 204, description = "Work deleted"),
+        (status = 404, description = "No such work"),
+        (status = 409, description = "The work is on legal hold or still being processed"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/api/v1/work/{work_id}")]
+async fn delete_work_v1(
+    req: HttpRequest,
+    work_id: web::Path<String>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    objects_path: web::Data<String>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    debug!("Processing delete_work for work_id {work_id}");
+    let outcome = graphdb.delete_work(&work_id).await.map_err(|e| {
+        error!("Failed to delete work: {e}");
+        error::ErrorInternalServerError("Internal error: work deletion failed")
+    })?;
+    match outcome {
+        graphdb::DeleteOutcome::Deleted(unreferenced) => {
+            // Note: the same objects may be in use by works in flight or by cached
+            // results, so recent files are left to the purger
+            let removed =
+                crate::purge::remove_purged_objects(&graphdb, &objects_path, &unreferenced).await;
+            info!("Work \"{work_id}\" deleted ({removed} objects removed)");
+            Ok(HttpResponse::NoContent().finish())
+        }
+        graphdb::DeleteOutcome::OnHold => Err(error::ErrorConflict("Work is on legal hold")),
+        graphdb::DeleteOutcome::InProgress => {
+            Err(error::ErrorConflict("Work is still being processed"))
+        }
+        graphdb::DeleteOutcome::NotFound => Err(error::ErrorNotFound("No such work")),
+    }
+}
+
+/// The request body of [`set_legal_hold_v1`]
+#[derive(Deserialize, Default, ToSchema)]
+struct LegalHoldReqV1 {
+    /// The reason for the hold
+    reason: Option<String>,
+}
+
+/// Place a work on legal hold, exempting it from deletion and purging
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    request_body(content = Option<LegalHoldReqV1>),
+    responses(
+        (status = 204, description = "Hold placed"),
+        (status = 404, description = "No such work"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[route("/api/v1/work/{work_id}/hold", method = "POST", method = "PUT")]
+async fn set_legal_hold_v1(
+    req: HttpRequest,
+    work_id: web::Path<String>,
+    body: Option<web::Json<LegalHoldReqV1>>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    let reason = body.map(|b| b.into_inner()).unwrap_or_default().reason;
+    let placed = graphdb
+        .set_legal_hold(&work_id, reason.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Failed to set legal hold: {e}");
+            error::ErrorInternalServerError("Internal error: legal hold update failed")
+        })?;
+    if placed {
+        info!("Work \"{work_id}\" placed on legal hold");
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(error::ErrorNotFound("No such work"))
+    }
+}
+
+/// Lift the legal hold of a work
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    responses(
+        (status = 204, description = "Hold lifted"),
+        (status = 404, description = "The work is not on hold"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/api/v1/work/{work_id}/hold")]
+async fn clear_legal_hold_v1(
+    req: HttpRequest,
+    work_id: web::Path<String>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    let cleared = graphdb.clear_legal_hold(&work_id).await.map_err(|e| {
+        error!("Failed to clear legal hold: {e}");
+        error::ErrorInternalServerError("Internal error: legal hold update failed")
+    })?;
+    if cleared {
+        info!("Legal hold lifted from work \"{work_id}\"");
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(error::ErrorNotFound("Work is not on hold"))
+    }
+}
+
+/// Get the legal hold of a work
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id")),
+    responses(
+        (status = 200, description = "The legal hold", body = graphdb::LegalHold),
+        (status = 404, description = "The work is not on hold"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/api/v1/work/{work_id}/hold")]
+async fn get_legal_hold_v1(
+    req: HttpRequest,
+    work_id: web::Path<String>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::LegalHold>, error::Error> {
+    check_admin(&req, &quotas)?;
+    graphdb
+        .get_legal_hold(&work_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to lookup legal hold: {e}");
+            error::ErrorInternalServerError("Internal error: legal hold lookup failed")
+        })?
+        .map(web::Json)
+        .ok_or_else(|| error::ErrorNotFound("Work is not on hold"))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
 struct GetGraphsReq {
     work_ids: Vec<String>,
 }
 
+/// The graphs of the requested works, keyed by work id (null if not available)
 #[derive(Serialize)]
 struct GetGraphsResp(std::collections::HashMap<String, Option<shared::amqp::JobResult>>);
 
+impl utoipa::PartialSchema for GetGraphsResp {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        api::graphs_schema()
+            .description(Some(
+                "The graphs of the requested works, keyed by work id (null if not available)",
+            ))
+            .into()
+    }
+}
+
+impl ToSchema for GetGraphsResp {}
+
+/// The URL params for [`get_works_graphs_v1`]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetGraphsParamsV1 {
+    /// Requests a paginated result (implied by `cursor`)
+    paginate: Option<bool>,
+    /// The cursor returned with the previous page
+    cursor: Option<String>,
+}
+
+/// The response object returned by [`get_works_graphs_v1`]
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum GetGraphsResultV1 {
+    Graphs(GetGraphsResp),
+    Page(api::GraphsPage),
+}
+
+/// Retrieve multiple work graphs
+#[utoipa::path(
+    tag = "works",
+    params(GetGraphsParamsV1),
+    request_body = GetGraphsReq,
+    responses(
+        (status = 200, description = "The requested work graphs (a page if paginating)", body = GetGraphsResultV1),
+        (status = 400, description = "Too many work graphs requested or invalid cursor"),
+    )
+)]
 #[route("/api/v1/get_works_graphs", method = "POST", method = "PUT")]
 async fn get_works_graphs_v1(
+    params: web::Query<GetGraphsParamsV1>,
     req_body: web::Json<GetGraphsReq>,
     graphdb: web::Data<graphdb::GraphDB>,
     limits: web::Data<Limits>,
-) -> Result<web::Json<GetGraphsResp>, error::Error> {
-    if req_body.work_ids.len() > limits.max_work_results {
-        return Err(error::ErrorBadRequest(format!(
-            "Too many work graphs requested: (max {} allowed)",
-            limits.max_work_results
-        )));
+) -> Result<HttpResponse, error::Error> {
+    if !params.paginate.unwrap_or(false) && params.cursor.is_none() {
+        if req_body.work_ids.len() > limits.max_work_results {
+            return Err(error::ErrorBadRequest(format!(
+                "Too many work graphs requested: (max {} allowed)",
+                limits.max_work_results
+            )));
+        }
+        let graphs = get_graphs(&req_body.work_ids, &graphdb).await?;
+        return Ok(HttpResponse::Ok().json(GetGraphsResultV1::Graphs(graphs)));
     }
-    Ok(web::Json(GetGraphsResp(
-        futures::stream::iter(req_body.work_ids.iter().map(|work_id| async {
+
+    // Paginated: the requested ids are sorted and served in pages
+    let after = match params.cursor.as_deref().map(graphdb::Cursor::decode) {
+        None => None,
+        Some(Some(graphdb::Cursor::Work(work_id))) => Some(work_id),
+        Some(_) => return Err(error::ErrorBadRequest("Invalid cursor")),
+    };
+    let mut work_ids: Vec<&String> = req_body
+        .work_ids
+        .iter()
+        .filter(|work_id| after.as_ref().map_or(true, |after| *work_id > after))
+        .collect();
+    work_ids.sort_unstable();
+    work_ids.dedup();
+    let next_cursor = if work_ids.len() > limits.max_work_results {
+        work_ids.truncate(limits.max_work_results);
+        work_ids
+            .last()
+            .map(|work_id| graphdb::Cursor::Work(work_id.to_string()).encode())
+    } else {
+        None
+    };
+    let graphs = get_graphs(work_ids, &graphdb).await?;
+    Ok(
+        HttpResponse::Ok().json(GetGraphsResultV1::Page(api::GraphsPage {
+            graphs: graphs.0,
+            next_cursor,
+        })),
+    )
+}
+
+/// Retrieves the graphs of the provided works
+async fn get_graphs<I: IntoIterator<Item = S>, S: AsRef<str>>(
+    work_ids: I,
+    graphdb: &graphdb::GraphDB,
+) -> Result<GetGraphsResp, error::Error> {
+    Ok(GetGraphsResp(
+        futures::stream::iter(work_ids.into_iter().map(|work_id| async move {
+            let work_id = work_id.as_ref();
             graphdb
                 .get_work_graph(work_id)
                 .await
@@ -256,10 +665,19 @@ async fn get_works_graphs_v1(
             error!("Failed to lookup work graph: {e}");
             error::ErrorInternalServerError("Internal error: work graph lookup failed")
         })?,
-    )))
+    ))
 }
 
 /// Download object
+#[utoipa::path(
+    tag = "objects",
+    params(("object_id" = String, Path, description = "The object id")),
+    responses(
+        (status = 200, description = "The object data", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "Object not found"),
+    )
+)]
 #[get("/api/v1/get_object/{object_id}")]
 async fn get_object_v1(
     req: HttpRequest,
@@ -323,7 +741,8 @@ async fn get_object_v1(
 }
 
 /// The URL params for [`search_v1`] and [`count_v1`]
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct SearchParamsV1 {
     /// The search string
     q: String,
@@ -331,16 +750,61 @@ struct SearchParamsV1 {
     getobjects: Option<bool>,
     /// The maximum number of items to return
     maxitems: Option<u32>,
+    /// Requests a paginated result (implied by `cursor`)
+    paginate: Option<bool>,
+    /// The cursor returned with the previous page
+    cursor: Option<String>,
 }
 
-/// Search error
-#[derive(Serialize)]
-struct SearchError {
-    kind: &'static str,
-    message: String,
+/// Converts a search failure into a client facing error
+///
+/// Returns [`None`] for internal errors
+fn search_error(e: graphdb::SearchError) -> Option<api::SearchErrorBody> {
+    let (kind, message) = match e {
+        graphdb::SearchError::Rule(e) => ("Rule compilation error", e),
+        graphdb::SearchError::Query(e) => ("Query error", e),
+        graphdb::SearchError::InvalidCursor => (
+            "Invalid cursor",
+            "The provided cursor is not valid for this query".to_string(),
+        ),
+        graphdb::SearchError::Timeout => (
+            "Timeout",
+            "The query exceeded the maximum allowed run time".to_string(),
+        ),
+        graphdb::SearchError::Internal => return None,
+    };
+    Some(api::SearchErrorBody {
+        kind: kind.to_string(),
+        message,
+    })
+}
+
+/// Builds the response for a failed search
+fn search_error_response(e: graphdb::SearchError) -> HttpResponse {
+    match search_error(e) {
+        Some(e) => HttpResponse::BadRequest().json(e),
+        None => error::ErrorInternalServerError("Internal error: search error").into(),
+    }
+}
+
+/// The response object returned by [`search_v1`]
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum SearchResultV1 {
+    Items(Vec<String>),
+    Page(graphdb::SearchPage),
 }
 
 /// Search for works matching query
+#[utoipa::path(
+    tag = "search",
+    params(SearchParamsV1),
+    request_body(content = Option<SearchParamsV1>, description = "The search params (alternative to the query string)"),
+    responses(
+        (status = 200, description = "The matching work (or object) ids (a page if paginating)", body = SearchResultV1),
+        (status = 400, description = "Invalid query", body = api::SearchErrorBody),
+    )
+)]
 #[route("/api/v1/search", method = "GET", method = "POST", method = "PUT")]
 async fn search_v1(
     params: actix_web::Either<web::Json<SearchParamsV1>, web::Query<SearchParamsV1>>,
@@ -362,32 +826,145 @@ async fn search_v1(
         "Processing search query(getobjects: {}, max: {}): {}",
         getobjects, maxitems, params.q
     );
+    if params.paginate.unwrap_or(false) || params.cursor.is_some() {
+        return match graphdb
+            .search_page(
+                params.q.as_str(),
+                getobjects,
+                params.cursor.as_deref(),
+                maxitems,
+            )
+            .await
+        {
+            Ok(page) => HttpResponse::Ok().json(SearchResultV1::Page(page)),
+            Err(e) => search_error_response(e),
+        };
+    }
     match graphdb
         .search(params.q.as_str(), getobjects, maxitems)
         .await
     {
-        Ok(list) => HttpResponse::Ok().json(list),
-        Err(e) => match e {
-            graphdb::SearchError::Rule(e) => HttpResponse::BadRequest().json(SearchError {
-                kind: "Rule compilation error",
-                message: e,
-            }),
-            graphdb::SearchError::Query(e) => HttpResponse::BadRequest().json(SearchError {
-                kind: "Query error",
-                message: e,
-            }),
-            graphdb::SearchError::Internal => {
-                error::ErrorInternalServerError("Internal error: search error").into()
-            }
-            graphdb::SearchError::Timeout => HttpResponse::BadRequest().json(SearchError {
-                kind: "Timeout",
-                message: "The query exceeded the maximum allowed run time".to_string(),
-            }),
-        },
+        Ok(list) => HttpResponse::Ok().json(SearchResultV1::Items(list)),
+        Err(e) => search_error_response(e),
+    }
+}
+
+/// The URL params for [`search_export_v1`]
+#[derive(Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchExportParamsV1 {
+    /// The search string
+    q: String,
+    /// Requests the query to return objects rather than works
+    getobjects: Option<bool>,
+}
+
+/// Export all the works matching query
+///
+/// The results are streamed as newline delimited JSON objects; since the
+/// response status is committed before the export is complete, failures
+/// past the first page are reported as a final `error` line
+#[utoipa::path(
+    tag = "search",
+    params(SearchExportParamsV1),
+    request_body(content = Option<SearchExportParamsV1>, description = "The export params (alternative to the query string)"),
+    responses(
+        (status = 200, description = "One JSON object per line", body = String, content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid query", body = api::SearchErrorBody),
+    )
+)]
+#[route(
+    "/api/v1/search/export",
+    method = "GET",
+    method = "POST",
+    method = "PUT"
+)]
+async fn search_export_v1(
+    params: actix_web::Either<web::Json<SearchExportParamsV1>, web::Query<SearchExportParamsV1>>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    limits: web::Data<Limits>,
+) -> HttpResponse {
+    enum ExportState {
+        Emit(graphdb::SearchPage),
+        Fetch(String),
+        Done,
     }
+
+    let params = match params {
+        actix_web::Either::Left(v) => v.into_inner(),
+        actix_web::Either::Right(v) => v.into_inner(),
+    };
+    let getobjects = params.getobjects.unwrap_or(false);
+    let page_size = limits.max_search_results;
+    debug!(
+        "Processing search export(getobjects: {}): {}",
+        getobjects, params.q
+    );
+    // Note: the first page is retrieved upfront so that query errors are
+    // reported with the proper status
+    let first = match graphdb
+        .search_page(params.q.as_str(), getobjects, None, page_size)
+        .await
+    {
+        Ok(page) => page,
+        Err(e) => return search_error_response(e),
+    };
+    let key = if getobjects { "object_id" } else { "work_id" };
+    let q = params.q;
+    let stream = futures::stream::unfold(ExportState::Emit(first), move |state| {
+        let graphdb = graphdb.clone();
+        let q = q.clone();
+        async move {
+            let page = match state {
+                ExportState::Emit(page) => page,
+                ExportState::Fetch(cursor) => match graphdb
+                    .search_page(q.as_str(), getobjects, Some(&cursor), page_size)
+                    .await
+                {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let e = search_error(e).unwrap_or(api::SearchErrorBody {
+                            kind: "Internal error".to_string(),
+                            message: "search error".to_string(),
+                        });
+                        warn!("Search export aborted: {}", e.message);
+                        let mut line = serde_json::json!({ "error": e }).to_string();
+                        line.push('\n');
+                        return Some((
+                            Ok::<_, error::Error>(web::Bytes::from(line)),
+                            ExportState::Done,
+                        ));
+                    }
+                },
+                ExportState::Done => return None,
+            };
+            let mut chunk = String::new();
+            for item in page.items {
+                chunk.push_str(&serde_json::json!({ key: item }).to_string());
+                chunk.push('\n');
+            }
+            let next = match page.next_cursor {
+                Some(cursor) => ExportState::Fetch(cursor),
+                None => ExportState::Done,
+            };
+            Some((Ok(web::Bytes::from(chunk)), next))
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
 }
 
 /// Search for works matching query and return count
+#[utoipa::path(
+    tag = "search",
+    params(SearchParamsV1),
+    request_body(content = Option<SearchParamsV1>, description = "The search params (alternative to the query string)"),
+    responses(
+        (status = 200, description = "The number of matching works (or objects)", body = graphdb::CountResult),
+        (status = 400, description = "Invalid query", body = api::SearchErrorBody),
+    )
+)]
 #[route("/api/v1/count", method = "GET", method = "POST", method = "PUT")]
 async fn count_v1(
     params: actix_web::Either<web::Json<SearchParamsV1>, web::Query<SearchParamsV1>>,
@@ -404,47 +981,70 @@ async fn count_v1(
     );
     match graphdb.count(params.q.as_str(), getobjects).await {
         Ok(list) => HttpResponse::Ok().json(list),
-        Err(e) => match e {
-            graphdb::SearchError::Rule(e) => HttpResponse::BadRequest().json(SearchError {
-                kind: "Rule compilation error",
-                message: e,
-            }),
-            graphdb::SearchError::Query(e) => HttpResponse::BadRequest().json(SearchError {
-                kind: "Query error",
-                message: e,
-            }),
-            graphdb::SearchError::Internal => {
-                error::ErrorInternalServerError("Internal error: search error").into()
-            }
-            graphdb::SearchError::Timeout => HttpResponse::BadRequest().json(SearchError {
-                kind: "Timeout",
-                message: "The query exceeded the maximum allowed run time".to_string(),
-            }),
-        },
+        Err(e) => search_error_response(e),
     }
 }
 
-/// The URL params for [`search_v1`] and [`count_v1`]
-#[derive(Deserialize)]
+/// The URL params for [`add_scenario_v1`]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct AddScenarioParamsV1 {
     /// The id of the scenario to replace
     replace_id: Option<i64>,
+    /// The author of the change (defaults to the scenario creator)
+    author: Option<String>,
+    /// The reason for the change
+    comment: Option<String>,
 }
 
-#[derive(Serialize)]
+/// The URL params describing a scenario change
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScenarioChangeParamsV1 {
+    /// The author of the change
+    author: Option<String>,
+    /// The reason for the change
+    comment: Option<String>,
+}
+
+impl ScenarioChangeParamsV1 {
+    fn change(&self) -> graphdb::Change<'_> {
+        graphdb::Change {
+            author: self.author.as_deref().unwrap_or("anonymous"),
+            comment: self.comment.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 /// The error returned in case a ClamAV pattern is rejected
 struct PatternError {
     pattern_error: String,
 }
 /// Add scenario
+#[utoipa::path(
+    tag = "scenarios",
+    params(AddScenarioParamsV1),
+    request_body = scene::Scenario,
+    responses(
+        (status = 201, description = "Scenario created", body = graphdb::ScenarioDetails),
+        (status = 400, description = "Invalid scenario or signature", body = PatternError),
+        (status = 404, description = "The scenario identified by replace_id was not found"),
+        (status = 409, description = "Scenario exists"),
+    )
+)]
 #[route("/api/v1/scenarios", method = "POST", method = "PUT")]
 async fn add_scenario_v1(
     params: web::Query<AddScenarioParamsV1>,
     scenario: web::Json<scene::Scenario>,
     graphdb: web::Data<graphdb::GraphDB>,
 ) -> HttpResponse {
+    let change = graphdb::Change {
+        author: params.author.as_deref().unwrap_or(&scenario.creator),
+        comment: params.comment.as_deref(),
+    };
     match graphdb
-        .add_scenario(scenario.deref(), params.replace_id)
+        .add_scenario(scenario.deref(), params.replace_id, &change)
         .await
     {
         Ok(s) => HttpResponse::Created().json(s),
@@ -468,9 +1068,24 @@ async fn add_scenario_v1(
 }
 
 /// Delete scenario
+#[utoipa::path(
+    tag = "scenarios",
+    params(("id" = i64, Path, description = "The scenario id"), ScenarioChangeParamsV1),
+    responses(
+        (status = 204, description = "Scenario deleted"),
+        (status = 404, description = "No such scenario"),
+    )
+)]
 #[delete("/api/v1/scenarios/{id}")]
-async fn del_scenario_v1(id: web::Path<i64>, graphdb: web::Data<graphdb::GraphDB>) -> HttpResponse {
-    match graphdb.del_scenario(id.into_inner()).await {
+async fn del_scenario_v1(
+    id: web::Path<i64>,
+    params: web::Query<ScenarioChangeParamsV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> HttpResponse {
+    match graphdb
+        .del_scenario(id.into_inner(), &params.change())
+        .await
+    {
         Ok(true) => HttpResponse::NoContent().finish(),
         Ok(false) => HttpResponse::NotFound().finish(),
         Err(_) => HttpResponse::InternalServerError().body("Internal error: database error"),
@@ -478,6 +1093,14 @@ async fn del_scenario_v1(id: web::Path<i64>, graphdb: web::Data<graphdb::GraphDB
 }
 
 /// Get scenario details
+#[utoipa::path(
+    tag = "scenarios",
+    params(("id" = i64, Path, description = "The scenario id")),
+    responses(
+        (status = 200, description = "The scenario", body = scene::Scenario),
+        (status = 404, description = "No such scenario"),
+    )
+)]
 #[get("/api/v1/scenarios/{id}")]
 async fn get_scenario_v1(
     id: web::Path<i64>,
@@ -495,6 +1118,10 @@ async fn get_scenario_v1(
 }
 
 /// List scenarios
+#[utoipa::path(
+    tag = "scenarios",
+    responses((status = 200, description = "All the scenarios", body = Vec<graphdb::ScenarioDetails>))
+)]
 #[get("/api/v1/scenarios")]
 async fn list_scenarios_v1(
     graphdb: web::Data<graphdb::GraphDB>,
@@ -504,12 +1131,326 @@ async fn list_scenarios_v1(
     })?))
 }
 
-#[derive(Deserialize)]
+/// The URL params for [`get_scenario_stats_v1`]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScenarioStatsParamsV1 {
+    /// The number of hours to report (default 24, max 744)
+    hours: Option<u32>,
+    /// The number of days to report (default 30, max 366)
+    days: Option<u32>,
+}
+
+/// Get scenario statistics
+#[utoipa::path(
+    tag = "scenarios",
+    params(("id" = i64, Path, description = "The scenario id"), ScenarioStatsParamsV1),
+    responses(
+        (status = 200, description = "The scenario hit statistics", body = graphdb::ScenarioStats),
+        (status = 404, description = "No such scenario"),
+    )
+)]
+#[get("/api/v1/scenarios/{id}/stats")]
+async fn get_scenario_stats_v1(
+    id: web::Path<i64>,
+    params: web::Query<ScenarioStatsParamsV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::ScenarioStats>, error::Error> {
+    let hours = params.hours.unwrap_or(24).clamp(1, 744);
+    let days = params.days.unwrap_or(30).clamp(1, 366);
+    graphdb
+        .get_scenario_stats(id.into_inner(), hours, days)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?
+        .map(web::Json)
+        .ok_or_else(|| error::ErrorNotFound("No such scenario"))
+}
+
+/// List scenario versions
+#[utoipa::path(
+    tag = "scenarios",
+    params(("name" = String, Path, description = "The scenario name")),
+    responses((status = 200, description = "The recorded versions of the scenario, most recent first", body = Vec<graphdb::ScenarioVersion>))
+)]
+#[get("/api/v1/scenarios/history/{name}")]
+async fn list_scenario_versions_v1(
+    name: web::Path<String>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<Vec<graphdb::ScenarioVersion>>, error::Error> {
+    Ok(web::Json(
+        graphdb
+            .list_scenario_versions(&name)
+            .await
+            .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?,
+    ))
+}
+
+/// Get scenario version
+#[utoipa::path(
+    tag = "scenarios",
+    params(
+        ("name" = String, Path, description = "The scenario name"),
+        ("version" = i32, Path, description = "The scenario version"),
+    ),
+    responses(
+        (status = 200, description = "The scenario version", body = graphdb::ScenarioVersion),
+        (status = 404, description = "No such scenario version"),
+    )
+)]
+#[get("/api/v1/scenarios/history/{name}/{version}")]
+async fn get_scenario_version_v1(
+    path: web::Path<(String, i32)>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::ScenarioVersion>, error::Error> {
+    let (name, version) = path.into_inner();
+    graphdb
+        .get_scenario_version(&name, version)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?
+        .map(web::Json)
+        .ok_or_else(|| error::ErrorNotFound("No such scenario version"))
+}
+
+/// The request body for [`backtest_scenario_v1`]
+#[derive(Deserialize, ToSchema)]
+struct BacktestReqV1 {
+    /// The scenario to evaluate (not saved)
+    scenario: scene::Scenario,
+    /// The start of the time range (Unix time, inclusive)
+    start: f64,
+    /// The end of the time range (Unix time, exclusive, defaults to now)
+    end: Option<f64>,
+}
+
+/// Backtest scenario
+///
+/// Evaluates a scenario against the works submitted in a time range without
+/// saving the scenario or recording any result
+#[utoipa::path(
+    tag = "scenarios",
+    request_body = BacktestReqV1,
+    responses(
+        (status = 200, description = "The backtest outcome", body = graphdb::BacktestResult),
+        (status = 400, description = "Invalid scenario or time range", body = api::SearchErrorBody),
+    )
+)]
+#[route("/api/v1/scenarios/backtest", method = "POST", method = "PUT")]
+async fn backtest_scenario_v1(
+    req_body: web::Json<BacktestReqV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    limits: web::Data<Limits>,
+) -> HttpResponse {
+    let to_time = |t: f64| {
+        std::time::Duration::try_from_secs_f64(t)
+            .ok()
+            .and_then(|d| std::time::UNIX_EPOCH.checked_add(d))
+    };
+    let start = to_time(req_body.start);
+    let end = match req_body.end {
+        Some(end) => to_time(end),
+        None => Some(std::time::SystemTime::now()),
+    };
+    let (Some(start), Some(end)) = (start, end) else {
+        return HttpResponse::BadRequest().body("Invalid time range");
+    };
+    if end <= start {
+        return HttpResponse::BadRequest().body("Invalid time range");
+    }
+    match graphdb
+        .backtest(&req_body.scenario, start, end, limits.max_search_results)
+        .await
+    {
+        Ok(res) => HttpResponse::Ok().json(res),
+        Err(e) => search_error_response(e),
+    }
+}
+
+/// Roll back scenario
+///
+/// Restores a recorded version of the scenario and requests that all directors
+/// reload their rules
+#[utoipa::path(
+    tag = "scenarios",
+    params(
+        ("name" = String, Path, description = "The scenario name"),
+        ("version" = i32, Path, description = "The scenario version to restore"),
+        ScenarioChangeParamsV1,
+    ),
+    responses(
+        (status = 200, description = "Scenario restored", body = graphdb::ScenarioDetails),
+        (status = 400, description = "The version cannot be restored"),
+        (status = 404, description = "No such scenario version"),
+        (status = 409, description = "Scenario exists"),
+    )
+)]
+#[route(
+    "/api/v1/scenarios/history/{name}/{version}/rollback",
+    method = "POST",
+    method = "PUT"
+)]
+async fn rollback_scenario_v1(
+    path: web::Path<(String, i32)>,
+    params: web::Query<ScenarioChangeParamsV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    tx: web::Data<mpsc::WeakSender<BrokerAction>>,
+) -> Result<HttpResponse, error::Error> {
+    let (name, version) = path.into_inner();
+    let details = match graphdb
+        .rollback_scenario(&name, version, &params.change())
+        .await
+    {
+        Ok(details) => details,
+        Err(e) => {
+            return Ok(match e {
+                graphdb::ScenaryError::Invalid(e) => HttpResponse::BadRequest().body(e),
+                graphdb::ScenaryError::Signature(p) => {
+                    HttpResponse::BadRequest().json(PatternError { pattern_error: p })
+                }
+                graphdb::ScenaryError::Duplicate => {
+                    HttpResponse::Conflict().body("Scenario exists")
+                }
+                graphdb::ScenaryError::NotFound => {
+                    HttpResponse::NotFound().body("No such scenario version")
+                }
+                graphdb::ScenaryError::Database => {
+                    HttpResponse::InternalServerError().body("Internal error: database error")
+                }
+                graphdb::ScenaryError::Internal => {
+                    HttpResponse::InternalServerError().body("Internal error: IO error")
+                }
+            })
+        }
+    };
+    request_reload(&tx).await?;
+    Ok(HttpResponse::Ok().json(details))
+}
+
+/// The request body for [`export_scenarios_v1`]
+#[derive(Deserialize, ToSchema)]
+struct ExportReqV1 {
+    /// The ids of the scenarios to export (all of them if omitted)
+    ids: Option<Vec<i64>>,
+}
+
+/// Export scenarios
+///
+/// Returns the requested scenarios as a bundle, signed if a bundle key is
+/// configured
+#[utoipa::path(
+    tag = "scenarios",
+    request_body = ExportReqV1,
+    responses((status = 200, description = "The scenario bundle", body = bundle::Bundle))
+)]
+#[route("/api/v1/scenarios/export", method = "POST")]
+async fn export_scenarios_v1(
+    req_body: web::Json<ExportReqV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    bundle_key: web::Data<bundle::BundleKey>,
+) -> Result<web::Json<bundle::Bundle>, error::Error> {
+    let scenarios = graphdb
+        .export_scenarios(req_body.ids.as_deref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?;
+    let bundle = bundle_key.bundle(scenarios).map_err(|e| {
+        error!("Failed to create scenario bundle: {e}");
+        error::ErrorInternalServerError("Internal error: bundle creation failed")
+    })?;
+    Ok(web::Json(bundle))
+}
+
+/// The URL params for [`import_scenarios_v1`]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportParamsV1 {
+    /// How to handle scenarios whose name is already in use (default: `skip`)
+    #[serde(default)]
+    on_conflict: graphdb::OnConflict,
+    /// The author of the change
+    author: Option<String>,
+    /// The reason for the change
+    comment: Option<String>,
+}
+
+/// Import scenarios
+///
+/// Verifies and imports a scenario bundle atomically, then requests that all
+/// directors reload their rules
+#[utoipa::path(
+    tag = "scenarios",
+    params(ImportParamsV1),
+    request_body = bundle::Bundle,
+    responses(
+        (status = 200, description = "Bundle imported", body = graphdb::ImportReport),
+        (status = 400, description = "Invalid bundle or scenario"),
+        (status = 403, description = "Bundle import is disabled (no bundle key configured)"),
+        (status = 409, description = "Duplicate scenario name in bundle"),
+    )
+)]
+#[route("/api/v1/scenarios/import", method = "POST", method = "PUT")]
+async fn import_scenarios_v1(
+    req_body: web::Json<bundle::Bundle>,
+    params: web::Query<ImportParamsV1>,
+    graphdb: web::Data<graphdb::GraphDB>,
+    bundle_key: web::Data<bundle::BundleKey>,
+    tx: web::Data<mpsc::WeakSender<BrokerAction>>,
+) -> Result<HttpResponse, error::Error> {
+    if !bundle_key.can_import() {
+        warn!("Refusing bundle import: no bundle key configured");
+        return Err(error::ErrorForbidden(
+            "Bundle import is disabled (no bundle key configured)",
+        ));
+    }
+    let bundle = req_body.into_inner();
+    if let Err(e) = bundle_key.verify(&bundle) {
+        return Ok(HttpResponse::BadRequest().body(e));
+    }
+    let change = graphdb::Change {
+        author: params.author.as_deref().unwrap_or("anonymous"),
+        comment: params.comment.as_deref(),
+    };
+    let report = match graphdb
+        .import_scenarios(bundle.scenarios, params.on_conflict, &change)
+        .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            return Ok(match e {
+                graphdb::ScenaryError::Invalid(e) => HttpResponse::BadRequest().body(e),
+                graphdb::ScenaryError::Signature(p) => {
+                    HttpResponse::BadRequest().json(PatternError { pattern_error: p })
+                }
+                graphdb::ScenaryError::Duplicate => {
+                    HttpResponse::Conflict().body("Duplicate scenario name in bundle")
+                }
+                graphdb::ScenaryError::NotFound => {
+                    HttpResponse::Conflict().body("Scenario changed during import")
+                }
+                graphdb::ScenaryError::Database => {
+                    HttpResponse::InternalServerError().body("Internal error: database error")
+                }
+                graphdb::ScenaryError::Internal => {
+                    HttpResponse::InternalServerError().body("Internal error: IO error")
+                }
+            })
+        }
+    };
+    request_reload(&tx).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The URL params for [`get_work_actions_v1`]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct ActionLimitsV1 {
     maxitems: Option<u32>,
 }
 
 /// Get work actions
+#[utoipa::path(
+    tag = "works",
+    params(("work_id" = String, Path, description = "The work id"), ActionLimitsV1),
+    responses((status = 200, description = "The most recent actions of the work", body = Vec<shared::scene::WorkActions>))
+)]
 #[get("/api/v1/actions/{work_id}")]
 async fn get_work_actions_v1(
     work_id: web::Path<String>,
@@ -532,10 +1473,20 @@ async fn get_work_actions_v1(
 }
 
 /// Request that all directors reload their rules
+#[utoipa::path(
+    tag = "scenarios",
+    responses((status = 204, description = "Reload requested"))
+)]
 #[route("/api/v1/scenarios/reload", method = "POST", method = "PUT")]
 async fn reload_actions_v1(
     tx: web::Data<mpsc::WeakSender<BrokerAction>>,
 ) -> Result<HttpResponse, error::Error> {
+    request_reload(&tx).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Sends a reload request to the publisher
+async fn request_reload(tx: &mpsc::WeakSender<BrokerAction>) -> Result<(), error::Error> {
     // Send the request to the publisher (tx is Weak and needs upgrading)
     tx.upgrade()
         .ok_or_else(|| {
@@ -547,11 +1498,18 @@ async fn reload_actions_v1(
         .map_err(|e| {
             error!("Failed to communicate with publisher: {e}");
             error::ErrorInternalServerError("Internal error: publisher communication failed")
-        })?;
-    Ok(HttpResponse::NoContent().finish())
+        })
 }
 
 /// (Re-)Apply scenarios
+#[utoipa::path(
+    tag = "scenarios",
+    request_body = GetGraphsReq,
+    responses(
+        (status = 204, description = "Scenario application requested"),
+        (status = 400, description = "Too many works requested"),
+    )
+)]
 #[route("/api/v1/scenarios/apply", method = "POST", method = "PUT")]
 async fn apply_scenarios_v1(
     req_body: web::Json<GetGraphsReq>,
@@ -580,20 +1538,316 @@ async fn apply_scenarios_v1(
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// Get the current (UTC) day submission usage of all orgs
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "The usage of all orgs", body = Vec<api::OrgUsage>),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/api/v1/admin/usage")]
+async fn get_usage_v1(
+    req: HttpRequest,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<Vec<api::OrgUsage>>, error::Error> {
+    check_admin(&req, &quotas)?;
+    let usage = graphdb
+        .get_usage()
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: failed to get usage"))?;
+    Ok(web::Json(
+        usage
+            .into_iter()
+            .map(|u| {
+                let limits = quotas.limits(&u.org);
+                api::OrgUsage {
+                    org: u.org,
+                    objects: u.objects,
+                    bytes: u.bytes,
+                    daily_objects_limit: limits.daily_objects,
+                    daily_bytes_limit: limits.daily_bytes,
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Reset the submission usage and rate limit of an org
+#[utoipa::path(
+    tag = "admin",
+    params(("org" = String, Path, description = "The org")),
+    responses(
+        (status = 204, description = "Usage reset"),
+        (status = 401, description = "Invalid admin credentials"),
+        (status = 404, description = "No usage recorded for the org"),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/api/v1/admin/usage/{org}")]
+async fn reset_usage_v1(
+    req: HttpRequest,
+    org: web::Path<String>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    let had_bucket = quotas.reset_rate(&org);
+    let had_usage = graphdb
+        .reset_usage(&org)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: failed to reset usage"))?;
+    info!("Usage of org \"{org}\" reset");
+    if had_bucket || had_usage {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+/// Create a reprocess job
+///
+/// Historical works containing objects of the given type which were processed
+/// by an older backend are re-enqueued in the background, with their original
+/// relation metadata and a link to the superseded work
+#[utoipa::path(
+    tag = "admin",
+    request_body = api::ReprocessRequest,
+    responses(
+        (status = 201, description = "Reprocess job created", body = graphdb::ReprocessJob),
+        (status = 400, description = "Invalid version or time range"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[route("/api/v1/admin/reprocess", method = "POST", method = "PUT")]
+async fn create_reprocess_job_v1(
+    req: HttpRequest,
+    req_body: web::Json<api::ReprocessRequest>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    let min_version = semver::Version::parse(&req_body.min_version)
+        .map_err(|e| error::ErrorBadRequest(format!("Invalid version: {e}")))?;
+    let to_time = |t: f64| {
+        std::time::Duration::try_from_secs_f64(t)
+            .ok()
+            .and_then(|d| std::time::UNIX_EPOCH.checked_add(d))
+    };
+    let start = to_time(req_body.start.unwrap_or(0.0));
+    let end = match req_body.end {
+        Some(end) => to_time(end),
+        None => Some(std::time::SystemTime::now()),
+    };
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(error::ErrorBadRequest("Invalid time range"));
+    };
+    if end <= start {
+        return Err(error::ErrorBadRequest("Invalid time range"));
+    }
+    let job = graphdb
+        .create_reprocess_job(
+            req_body.author.as_deref().unwrap_or("anonymous"),
+            &req_body.object_type,
+            &min_version,
+            req_body.org.as_deref(),
+            start,
+            end,
+        )
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?;
+    info!(
+        "Reprocess job created for \"{}\" objects older than {min_version}",
+        req_body.object_type
+    );
+    Ok(HttpResponse::Created().json(job))
+}
+
+/// Drop cached backend results
+///
+/// All the workers drop their cached results matching the request; an empty
+/// request (`{}`) drops all the cached results
+#[utoipa::path(
+    tag = "admin",
+    request_body = shared::amqp::CacheInvalidation,
+    responses(
+        (status = 204, description = "Invalidation requested"),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[route("/api/v1/admin/cache/invalidate", method = "POST", method = "PUT")]
+async fn invalidate_cache_v1(
+    req: HttpRequest,
+    req_body: web::Json<shared::amqp::CacheInvalidation>,
+    quotas: web::Data<Quotas>,
+    tx: web::Data<mpsc::WeakSender<BrokerAction>>,
+) -> Result<HttpResponse, error::Error> {
+    check_admin(&req, &quotas)?;
+    // Send the request to the publisher (tx is Weak and needs upgrading)
+    tx.upgrade()
+        .ok_or_else(|| {
+            error!("Cannot upgrade Sender: publisher lost");
+            error::ErrorInternalServerError("Internal error: publisher lost")
+        })?
+        .send(BrokerAction::InvalidateCache(req_body.into_inner()))
+        .await
+        .map_err(|e| {
+            error!("Failed to communicate with publisher: {e}");
+            error::ErrorInternalServerError("Internal error: publisher communication failed")
+        })?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the backends
+///
+/// The backends currently announced by the workers, along with their health
+/// and capabilities; backends which do not advertise support for the objects
+/// their worker is fed are flagged as misconfigured
+#[utoipa::path(
+    tag = "backends",
+    responses(
+        (status = 200, description = "The live backends, sorted by worker type", body = Vec<shared::amqp::BackendAnnouncement>),
+    )
+)]
+#[get("/api/v1/backends")]
+async fn list_backends_v1(
+    registry: web::Data<registry::Registry>,
+) -> web::Json<Vec<shared::amqp::BackendAnnouncement>> {
+    web::Json(registry.list())
+}
+
+/// List the reprocess jobs
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "The reprocess jobs, most recent first", body = Vec<graphdb::ReprocessJob>),
+        (status = 401, description = "Invalid admin credentials"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/api/v1/admin/reprocess")]
+async fn list_reprocess_jobs_v1(
+    req: HttpRequest,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<Vec<graphdb::ReprocessJob>>, error::Error> {
+    check_admin(&req, &quotas)?;
+    Ok(web::Json(graphdb.list_reprocess_jobs().await.map_err(
+        |_| error::ErrorInternalServerError("Internal error: database error"),
+    )?))
+}
+
+/// Get a reprocess job
+#[utoipa::path(
+    tag = "admin",
+    params(("id" = i64, Path, description = "The reprocess job id")),
+    responses(
+        (status = 200, description = "The reprocess job and its progress", body = graphdb::ReprocessJob),
+        (status = 401, description = "Invalid admin credentials"),
+        (status = 404, description = "No such reprocess job"),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/api/v1/admin/reprocess/{id}")]
+async fn get_reprocess_job_v1(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::ReprocessJob>, error::Error> {
+    check_admin(&req, &quotas)?;
+    graphdb
+        .get_reprocess_job(*id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?
+        .map(web::Json)
+        .ok_or_else(|| error::ErrorNotFound("No such reprocess job"))
+}
+
+/// Pause, resume or cancel a reprocess job
+#[utoipa::path(
+    tag = "admin",
+    params(
+        ("id" = i64, Path, description = "The reprocess job id"),
+        ("action" = graphdb::ReprocessAction, Path, description = "The state change"),
+    ),
+    responses(
+        (status = 200, description = "The updated reprocess job", body = graphdb::ReprocessJob),
+        (status = 401, description = "Invalid admin credentials"),
+        (status = 404, description = "No such reprocess job"),
+        (status = 409, description = "The change does not apply to the job state"),
+    ),
+    security(("admin_token" = []))
+)]
+#[route(
+    "/api/v1/admin/reprocess/{id}/{action}",
+    method = "POST",
+    method = "PUT"
+)]
+async fn update_reprocess_job_v1(
+    req: HttpRequest,
+    path: web::Path<(i64, graphdb::ReprocessAction)>,
+    quotas: web::Data<Quotas>,
+    graphdb: web::Data<graphdb::GraphDB>,
+) -> Result<web::Json<graphdb::ReprocessJob>, error::Error> {
+    check_admin(&req, &quotas)?;
+    let (id, action) = path.into_inner();
+    match graphdb
+        .update_reprocess_job(id, action)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal error: database error"))?
+    {
+        graphdb::ReprocessUpdate::Updated(job) => {
+            info!("Reprocess job {id} updated ({action:?})");
+            Ok(web::Json(*job))
+        }
+        graphdb::ReprocessUpdate::Invalid => Err(error::ErrorConflict(
+            "The change does not apply to the job state",
+        )),
+        graphdb::ReprocessUpdate::NotFound => Err(error::ErrorNotFound("No such reprocess job")),
+    }
+}
+
 /// Sets up the URL routing
 pub fn app_setup(cfg: &mut web::ServiceConfig) {
     cfg.service(metrics)
         .service(submit_v1)
         .service(get_work_graph_v1)
         .service(get_works_graphs_v1)
+        .service(get_work_status_v1)
         .service(get_object_v1)
         .service(search_v1)
+        .service(search_export_v1)
         .service(count_v1)
         .service(add_scenario_v1)
         .service(del_scenario_v1)
         .service(get_scenario_v1)
         .service(list_scenarios_v1)
+        .service(get_scenario_stats_v1)
+        .service(list_scenario_versions_v1)
+        .service(get_scenario_version_v1)
+        .service(rollback_scenario_v1)
+        .service(backtest_scenario_v1)
+        .service(export_scenarios_v1)
+        .service(import_scenarios_v1)
         .service(get_work_actions_v1)
         .service(reload_actions_v1)
-        .service(apply_scenarios_v1);
+        .service(apply_scenarios_v1)
+        .service(delete_work_v1)
+        .service(set_legal_hold_v1)
+        .service(clear_legal_hold_v1)
+        .service(get_legal_hold_v1)
+        .service(get_usage_v1)
+        .service(reset_usage_v1)
+        .service(create_reprocess_job_v1)
+        .service(list_reprocess_jobs_v1)
+        .service(get_reprocess_job_v1)
+        .service(update_reprocess_job_v1)
+        .service(invalidate_cache_v1)
+        .service(list_backends_v1)
+        .service(openapi::openapi_v1);
 }
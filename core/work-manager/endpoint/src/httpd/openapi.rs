@@ -0,0 +1,507 @@
+//! OpenAPI document
+//!
+//! The document is generated from the route annotations in the parent module
+//! and is served at `/api/v1/openapi.json`
+
+use actix_web::{get, HttpResponse};
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+/// The API description
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Contextal Platform API"),
+    paths(
+        super::metrics,
+        super::submit_v1,
+        super::get_work_graph_v1,
+        super::get_work_status_v1,
+        super::delete_work_v1,
+        super::set_legal_hold_v1,
+        super::clear_legal_hold_v1,
+        super::get_legal_hold_v1,
+        super::get_works_graphs_v1,
+        super::get_object_v1,
+        super::search_v1,
+        super::search_export_v1,
+        super::count_v1,
+        super::add_scenario_v1,
+        super::del_scenario_v1,
+        super::get_scenario_v1,
+        super::list_scenarios_v1,
+        super::get_scenario_stats_v1,
+        super::list_scenario_versions_v1,
+        super::get_scenario_version_v1,
+        super::rollback_scenario_v1,
+        super::backtest_scenario_v1,
+        super::export_scenarios_v1,
+        super::import_scenarios_v1,
+        super::get_work_actions_v1,
+        super::reload_actions_v1,
+        super::apply_scenarios_v1,
+        super::get_usage_v1,
+        super::reset_usage_v1,
+        super::create_reprocess_job_v1,
+        super::list_reprocess_jobs_v1,
+        super::get_reprocess_job_v1,
+        super::update_reprocess_job_v1,
+        super::invalidate_cache_v1,
+        super::list_backends_v1,
+        openapi_v1,
+    ),
+    tags(
+        (name = "works", description = "Work submission and results"),
+        (name = "objects", description = "Object retrieval"),
+        (name = "search", description = "Work and object search"),
+        (name = "scenarios", description = "Scenario management"),
+        (name = "admin", description = "Administration"),
+        (name = "backends", description = "Worker backends"),
+        (name = "metrics", description = "Service metrics"),
+    ),
+    modifiers(&AdminSecurity)
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token authentication of the admin routes
+struct AdminSecurity;
+
+impl Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+    }
+}
+
+/// The OpenAPI document endpoint
+#[utoipa::path(
+    tag = "metrics",
+    responses((status = 200, description = "The OpenAPI document of this API", body = Object))
+)]
+#[get("/api/v1/openapi.json")]
+async fn openapi_v1() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Map, Value};
+    use shared::api;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// Checks `value` against a (tiny) subset of JSON Schema: just enough to
+    /// cover what utoipa generates for the API payloads
+    fn validate(
+        schemas: &Map<String, Value>,
+        schema: &Value,
+        value: &Value,
+        at: &str,
+    ) -> Result<(), String> {
+        if let Some(r) = schema.get("$ref").and_then(|r| r.as_str()) {
+            let target = r
+                .strip_prefix("#/components/schemas/")
+                .and_then(|name| schemas.get(name))
+                .ok_or_else(|| format!("{at}: unknown schema {r}"))?;
+            return validate(schemas, target, value, at);
+        }
+        let matches = |s: &Value| validate(schemas, s, value, at).is_ok();
+        if let Some(all) = schema.get("allOf").and_then(|s| s.as_array()) {
+            for s in all {
+                validate(schemas, s, value, at)?;
+            }
+        }
+        if let Some(one) = schema.get("oneOf").and_then(|s| s.as_array()) {
+            let n = one.iter().filter(|s| matches(s)).count();
+            if n != 1 {
+                return Err(format!("{at}: {value} matches {n} oneOf schemas"));
+            }
+        }
+        if let Some(any) = schema.get("anyOf").and_then(|s| s.as_array()) {
+            if !any.iter().any(matches) {
+                return Err(format!("{at}: {value} matches no anyOf schema"));
+            }
+        }
+        if let Some(variants) = schema.get("enum").and_then(|s| s.as_array()) {
+            if !variants.contains(value) {
+                return Err(format!("{at}: {value} is not one of {variants:?}"));
+            }
+        }
+        if let Some(ty) = schema.get("type") {
+            let types: Vec<&str> = match ty {
+                Value::Array(types) => types.iter().filter_map(|t| t.as_str()).collect(),
+                ty => ty.as_str().into_iter().collect(),
+            };
+            let ok = types.iter().any(|t| match *t {
+                "null" => value.is_null(),
+                "boolean" => value.is_boolean(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "string" => value.is_string(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => false,
+            });
+            if !ok {
+                return Err(format!("{at}: {value} is not of type {ty}"));
+            }
+        }
+        if let (Some(min), Some(n)) = (
+            schema.get("minimum").and_then(|m| m.as_f64()),
+            value.as_f64(),
+        ) {
+            if n < min {
+                return Err(format!("{at}: {n} is less than {min}"));
+            }
+        }
+        if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+            for (i, item) in array.iter().enumerate() {
+                validate(schemas, items, item, &format!("{at}[{i}]"))?;
+            }
+        }
+        if let Some(obj) = value.as_object() {
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            let required = schema.get("required").and_then(|r| r.as_array());
+            for key in required.into_iter().flatten().filter_map(|r| r.as_str()) {
+                if !obj.contains_key(key) {
+                    return Err(format!("{at}: missing required property {key}"));
+                }
+            }
+            for (key, v) in obj {
+                let at = format!("{at}.{key}");
+                match (
+                    properties.and_then(|p| p.get(key)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(s), _) => validate(schemas, s, v, &at)?,
+                    (None, Some(Value::Bool(true))) => {}
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(format!("{at}: unexpected property"))
+                    }
+                    (None, Some(s)) => validate(schemas, s, v, &at)?,
+                    // Note: structs must document all their fields
+                    (None, None) if properties.is_some() => {
+                        return Err(format!("{at}: undocumented property"))
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `value` conforms to the named schema and survives a
+    /// deserialization round trip unaltered
+    fn check<T: Serialize + DeserializeOwned>(schemas: &Map<String, Value>, name: &str, value: T) {
+        let json = serde_json::to_value(&value).unwrap();
+        let schema = schemas
+            .get(name)
+            .unwrap_or_else(|| panic!("missing schema {name}"));
+        if let Err(e) = validate(schemas, schema, &json, name) {
+            panic!("{e}");
+        }
+        let back: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            serde_json::to_value(back).unwrap(),
+            json,
+            "{name} round trip"
+        );
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let doc = ApiDoc::openapi();
+        for path in [
+            "/metrics",
+            "/api/v1/submit",
+            "/api/v1/get_work_graph/{work_id}",
+            "/api/v1/work/{work_id}/status",
+            "/api/v1/work/{work_id}",
+            "/api/v1/work/{work_id}/hold",
+            "/api/v1/get_works_graphs",
+            "/api/v1/get_object/{object_id}",
+            "/api/v1/search",
+            "/api/v1/search/export",
+            "/api/v1/count",
+            "/api/v1/scenarios",
+            "/api/v1/scenarios/{id}",
+            "/api/v1/scenarios/{id}/stats",
+            "/api/v1/scenarios/history/{name}",
+            "/api/v1/scenarios/history/{name}/{version}",
+            "/api/v1/scenarios/history/{name}/{version}/rollback",
+            "/api/v1/scenarios/backtest",
+            "/api/v1/scenarios/export",
+            "/api/v1/scenarios/import",
+            "/api/v1/actions/{work_id}",
+            "/api/v1/scenarios/reload",
+            "/api/v1/scenarios/apply",
+            "/api/v1/admin/usage",
+            "/api/v1/admin/usage/{org}",
+            "/api/v1/admin/reprocess",
+            "/api/v1/admin/reprocess/{id}",
+            "/api/v1/admin/reprocess/{id}/{action}",
+            "/api/v1/backends",
+            "/api/v1/openapi.json",
+        ] {
+            assert!(doc.paths.paths.contains_key(path), "missing path {path}");
+        }
+        let schemas = doc.components.expect("no components").schemas;
+        for schema in ["SubmitResultV1", "SearchParamsV1", "Scenario", "JobResult"] {
+            assert!(schemas.contains_key(schema), "missing schema {schema}");
+        }
+    }
+
+    #[test]
+    fn test_api_payloads() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        let t = |secs: f64| -> SystemTime { UNIX_EPOCH + Duration::from_secs_f64(secs) };
+        let scenario = json!({
+            "name": "test",
+            "compatible_with": ">=1.0.0",
+            "creator": "me",
+            "description": "A test scenario",
+            "local_query": "object_type == \"ZIP\"",
+            "context": { "global_query": "true" },
+            "action": {
+                "name": "BLOCK",
+                "verdict": "BLOCK",
+                "severity": "high",
+                "mitre_techniques": ["T1566.001"],
+                "priority": 1,
+                "params": { "key": "value" },
+                "executors": ["webhook"],
+            },
+            "mode": "monitor",
+            "orgs": ["acme"],
+        });
+
+        let status = &schemas["WorkStatus"];
+        for invalid in [
+            json!({ "work_id": "w", "state": "queued" }),
+            json!({
+                "work_id": "w",
+                "state": "lost",
+                "objects_done": 0,
+                "objects_pending": 0,
+                "t_queued": 0.0,
+                "t_updated": 0.0,
+            }),
+            json!({
+                "work_id": "w",
+                "state": "queued",
+                "objects_done": 0,
+                "objects_pending": 0,
+                "t_queued": 0.0,
+                "t_updated": 0.0,
+                "extra": 1,
+            }),
+        ] {
+            assert!(validate(schemas, status, &invalid, "WorkStatus").is_err());
+        }
+
+        check(
+            schemas,
+            "SubmitResultV1",
+            api::SubmitResult {
+                object_id: "o".to_string(),
+                work_id: "w".to_string(),
+                ttl: 300,
+            },
+        );
+        for expires_at in [None, Some(t(1_700_000_600.25))] {
+            check(
+                schemas,
+                "WorkStatus",
+                api::WorkStatus {
+                    work_id: "w".to_string(),
+                    state: api::WorkState::InProgress,
+                    objects_done: 2,
+                    objects_pending: 1,
+                    t_queued: t(1_700_000_000.5),
+                    t_updated: t(1_700_000_001.0),
+                    expires_at,
+                },
+            );
+        }
+        for reason in [None, Some("litigation".to_string())] {
+            check(
+                schemas,
+                "LegalHold",
+                api::LegalHold {
+                    work_id: "w".to_string(),
+                    t: t(1_700_000_000.5),
+                    reason,
+                },
+            );
+        }
+        check(
+            schemas,
+            "GetGraphsPageResp",
+            api::GraphsPage {
+                graphs: [("w".to_string(), None)].into(),
+                next_cursor: Some("c".to_string()),
+            },
+        );
+        check(
+            schemas,
+            "SearchPage",
+            api::SearchPage {
+                items: vec!["w".to_string()],
+                next_cursor: None,
+            },
+        );
+        check(schemas, "CountResult", api::CountResult { count: 42 });
+        check(
+            schemas,
+            "SearchError",
+            api::SearchErrorBody {
+                kind: "ParseError".to_string(),
+                message: "bad query".to_string(),
+            },
+        );
+        check(
+            schemas,
+            "ScenarioDetails",
+            api::ScenarioDetails {
+                id: 1,
+                name: "test".to_string(),
+                creator: "me".to_string(),
+                description: "A test scenario".to_string(),
+                t: t(1_700_000_000.5),
+                action: "BLOCK".to_string(),
+                enabled: false,
+                mode: shared::scene::ScenarioMode::Monitor,
+                orgs: vec!["acme".to_string()],
+            },
+        );
+        for def in [None, Some(scenario.clone())] {
+            check(
+                schemas,
+                "ScenarioVersion",
+                api::ScenarioVersion {
+                    name: "test".to_string(),
+                    version: 2,
+                    t: t(1_700_000_000.5),
+                    author: "me".to_string(),
+                    op: "update".to_string(),
+                    comment: Some("why".to_string()),
+                    diff: json!({ "enabled": { "old": true, "new": false } }),
+                    def,
+                },
+            );
+        }
+        let period = api::StatsPeriod {
+            t: t(1_699_999_200.0),
+            evaluations: 10,
+            matches: 3,
+            avg_local_time: 0.25,
+            global_evaluations: 3,
+            avg_global_time: 0.5,
+        };
+        check(
+            schemas,
+            "ScenarioStats",
+            api::ScenarioStats {
+                id: 1,
+                last_hit: Some(1_700_000_000.5),
+                hourly: vec![period.clone()],
+                daily: vec![period],
+            },
+        );
+        check(
+            schemas,
+            "BacktestResult",
+            api::BacktestResult {
+                work_ids: vec!["w".to_string()],
+                count: 1,
+                local_matches: 2,
+                truncated: true,
+                time: 0.125,
+            },
+        );
+        for signature in [None, Some("00ff".to_string())] {
+            check(
+                schemas,
+                "Bundle",
+                api::Bundle {
+                    manifest: api::Manifest {
+                        format: 1,
+                        platform_version: "1.0.0".to_string(),
+                        created: 1_700_000_000.5,
+                        scenarios: vec![api::ManifestEntry {
+                            name: "test".to_string(),
+                            compatible_with: ">=1.0.0".to_string(),
+                            sha256: "00".repeat(32),
+                        }],
+                    },
+                    scenarios: vec![serde_json::from_value(scenario.clone()).unwrap()],
+                    signature,
+                },
+            );
+        }
+        check(
+            schemas,
+            "ImportReport",
+            api::ImportReport {
+                imported: vec!["a".to_string()],
+                skipped: vec!["b".to_string()],
+                renamed: vec![api::Renamed {
+                    from: "c".to_string(),
+                    to: "c-1".to_string(),
+                }],
+            },
+        );
+        check(
+            schemas,
+            "OrgUsageV1",
+            api::OrgUsage {
+                org: "acme".to_string(),
+                objects: 5,
+                bytes: 1024,
+                daily_objects_limit: Some(100),
+                daily_bytes_limit: None,
+            },
+        );
+        check(schemas, "ReprocessReqV1", api::ReprocessRequest::default());
+        check(
+            schemas,
+            "ReprocessReqV1",
+            api::ReprocessRequest {
+                object_type: "ZIP".to_string(),
+                min_version: "1.2.0".to_string(),
+                org: Some("acme".to_string()),
+                start: Some(1_600_000_000.0),
+                end: Some(1_700_000_000.0),
+                author: Some("me".to_string()),
+            },
+        );
+        check(
+            schemas,
+            "ReprocessJob",
+            api::ReprocessJob {
+                id: 1,
+                t: t(1_700_000_000.5),
+                author: "me".to_string(),
+                object_type: "ZIP".to_string(),
+                min_version: "1.2.0".to_string(),
+                org: None,
+                start: t(1_600_000_000.0),
+                end: t(1_700_000_000.0),
+                state: "running".to_string(),
+                scanned: 10,
+                enqueued: 8,
+                failed: 1,
+                in_flight: 3,
+                t_updated: t(1_700_000_001.0),
+            },
+        );
+    }
+}
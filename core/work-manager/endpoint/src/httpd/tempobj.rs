@@ -26,6 +26,7 @@ pub struct TempObject {
     ent: ShannonEntropy,
     objects_path: std::path::PathBuf,
     ctime: f64,
+    size: u64,
 }
 
 impl TempObject {
@@ -42,6 +43,7 @@ impl TempObject {
             ent: ShannonEntropy::new(),
             objects_path: objects_path.into(),
             ctime,
+            size: 0,
         })
     }
 
@@ -95,6 +97,11 @@ impl TempObject {
         }
     }
 
+    /// The number of bytes received so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
     pub async fn remove(self) {
         tokio::fs::remove_file(self.name).await.ok();
     }
@@ -111,6 +118,7 @@ impl AsyncWrite for TempObject {
         if let Poll::Ready(res) = &res {
             match res {
                 Ok(sz) => {
+                    me.size += *sz as u64;
                     me.hashes.update(&buf[0..*sz]);
                     me.ent.update(&buf[0..*sz]);
                 }
@@ -5,16 +5,21 @@
 //!
 //! For the API see the [httpd] module
 mod amqp;
+mod bundle;
 mod config;
 mod graphdb;
 mod httpd;
+mod purge;
+mod quota;
+mod registry;
+mod reprocess;
 
 use actix_web::{
     dev::Service,
     {web, App, HttpServer},
 };
 use metrics_exporter_prometheus::PrometheusBuilder;
-use shared::clamd;
+use shared::typedet;
 use std::net::{Ipv4Addr, SocketAddr};
 use tokio::sync::mpsc;
 #[allow(unused_imports)]
@@ -46,6 +51,7 @@ async fn post_requests(
                         req.object,
                         req.ttl,
                         req.max_recursion,
+                        req.priority,
                         req.relation_metadata,
                     )
                     .await
@@ -73,6 +79,16 @@ async fn post_requests(
                     break;
                 }
             }
+            httpd::BrokerAction::InvalidateCache(invalidation) => {
+                if broker
+                    .request_cache_invalidation(&invalidation)
+                    .await
+                    .is_err()
+                {
+                    error!("Failed to publish cache invalidation request");
+                    break;
+                }
+            }
         }
     }
     broker.close().await;
@@ -100,8 +116,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     metrics::describe_histogram!(RESPONSE_TIME, metrics::Unit::Seconds, "HTTP response time");
     metrics::describe_counter!(WORK_COUNT, "Number of work requests published");
 
-    let clamd = web::Data::new(clamd::Typedet::new(&config.typedet));
+    let typedet = web::Data::new(typedet::Typedet::new(&config.typedet)?);
     let broker = amqp::Broker::new(&config.broker).await?;
+    let registry = web::Data::new(registry::Registry::default());
+    let listener = match registry::Listener::new(&config.broker, registry.clone()).await {
+        Ok(v) => v,
+        Err(e) => {
+            broker.close().await;
+            return Err(e);
+        }
+    };
     let (tx, rx) = mpsc::channel::<httpd::BrokerAction>(100);
     let data_tx = web::Data::new(tx.downgrade());
     let max_submit_size = config.get_max_submit_size();
@@ -111,6 +135,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_action_results: config.get_max_action_results(),
     });
     let is_reprocess_enabled = web::Data::new(config.is_reprocess_enabled());
+    let bundle_key = web::Data::new(bundle::BundleKey::new(
+        config.bundle_key.as_deref(),
+        config.are_unsigned_bundles_allowed(),
+    ));
+    let quotas = web::Data::new(quota::Quotas::new(config.quotas));
+    let retention = config.retention;
+    let purger = retention.is_enabled().then(|| {
+        purge::purger(
+            graphdb.get_ref().clone(),
+            retention,
+            config.objects_path.clone(),
+        )
+    });
+    let reprocessor = reprocess::reprocessor(
+        graphdb.get_ref().clone(),
+        config.reprocess,
+        tx.downgrade(),
+        config.objects_path.clone(),
+    );
     let objects_path = web::Data::new(config.objects_path);
 
     let server = HttpServer::new(move || {
@@ -143,7 +186,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             })
             .configure(httpd::app_setup)
             .app_data(data_tx.clone())
-            .app_data(clamd.clone())
+            .app_data(typedet.clone())
             .app_data(prom.clone())
             .app_data(objects_path.clone())
             .app_data(graphdb.clone())
@@ -152,6 +195,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             )
             .app_data(limits.clone())
             .app_data(is_reprocess_enabled.clone())
+            .app_data(quotas.clone())
+            .app_data(bundle_key.clone())
+            .app_data(registry.clone())
     })
     .bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port)));
     let server = match server {
@@ -159,12 +205,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(e) => {
             error!("Failed to create HTTP server: {}", e);
             broker.close().await;
+            listener.close().await;
             return Err(e.into());
         }
     };
     let mut jset = tokio::task::JoinSet::new();
     jset.spawn(post_requests(broker, rx));
+    jset.spawn(listener.run());
     jset.spawn(server.run());
+    if let Some(purger) = purger {
+        jset.spawn(purger);
+    }
+    jset.spawn(reprocessor);
 
     // Await termination of either future
     jset.join_next().await;
@@ -0,0 +1,143 @@
+//! Work retention
+//!
+//! Periodically purges the works which exceeded their retention period and
+//! removes the object files no longer referenced by any work
+//!
+//! Files which may still be in use by a work in flight are recorded and removed
+//! by a later run
+
+use crate::{config::RetentionConfig, graphdb::GraphDB};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const PURGED_WORKS: &str = "endpoint_purged_works_total";
+const PURGED_OBJECTS: &str = "endpoint_purged_objects_total";
+
+/// Removes the files of the given objects from the objects store
+///
+/// Files modified within `min_age` are retained as they may belong to a work
+/// which is still being processed
+///
+/// Returns the number of files removed and the ids of the objects retained
+async fn remove_objects(
+    objects_path: &str,
+    object_ids: &[String],
+    min_age: std::time::Duration,
+) -> (usize, Vec<String>) {
+    let mut removed = 0;
+    let mut retained = Vec::new();
+    for object_id in object_ids {
+        if object_id.is_empty() || !object_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            warn!("Refusing to remove object with invalid id \"{object_id}\"");
+            continue;
+        }
+        let path = std::path::Path::new(objects_path).join(object_id);
+        let is_recent = tokio::fs::metadata(&path)
+            .await
+            .and_then(|m| m.modified())
+            .map(|t| t.elapsed().map(|age| age < min_age).unwrap_or(true))
+            .unwrap_or(false);
+        if is_recent {
+            debug!("Retaining recently modified object \"{object_id}\"");
+            retained.push(object_id.clone());
+            continue;
+        }
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove object \"{}\": {}", path.display(), e),
+        }
+    }
+    metrics::counter!(PURGED_OBJECTS).increment(removed as u64);
+    (removed, retained)
+}
+
+/// Removes the files of the given unreferenced objects, deferring the removal of
+/// the recently modified ones to a later purge run
+///
+/// Returns the number of files removed
+pub async fn remove_purged_objects(
+    graphdb: &GraphDB,
+    objects_path: &str,
+    object_ids: &[String],
+) -> usize {
+    let (removed, retained) = remove_objects(objects_path, object_ids, shared::MAX_WORK_TTL).await;
+    if let Err(e) = graphdb
+        .defer_object_removals(&retained)
+        .await
+        .map_err(|e| e.to_string())
+    {
+        error!(
+            "Failed to defer the removal of {} objects: {e}",
+            retained.len()
+        );
+    }
+    removed
+}
+
+/// Purges stale works in batches
+async fn purge(graphdb: &GraphDB, config: &RetentionConfig, objects_path: &str) {
+    let batch_size = config.get_purge_batch_size() as usize;
+    let mut total = 0;
+    // Note: the error is stringified as it is not Send
+    match graphdb
+        .take_object_removals(shared::MAX_WORK_TTL)
+        .await
+        .map_err(|e| e.to_string())
+    {
+        Ok(deferred) if !deferred.is_empty() => {
+            let removed = remove_purged_objects(graphdb, objects_path, &deferred).await;
+            debug!("Removed {removed} of {} deferred objects", deferred.len());
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to retrieve the deferred object removals: {e}"),
+    }
+    loop {
+        // Note: the error is stringified as it is not Send
+        let res = graphdb
+            .purge_stale_works(config)
+            .await
+            .map_err(|e| e.to_string());
+        match res {
+            Ok(Some((nworks, unreferenced))) => {
+                metrics::counter!(PURGED_WORKS).increment(nworks as u64);
+                total += nworks;
+                let removed = remove_purged_objects(graphdb, objects_path, &unreferenced).await;
+                debug!("Purged {nworks} works and {removed} objects");
+                if nworks < batch_size {
+                    break;
+                }
+            }
+            Ok(None) => {
+                debug!("Purge in progress elsewhere, skipping");
+                break;
+            }
+            Err(e) => {
+                error!("Purge failed: {e}");
+                break;
+            }
+        }
+    }
+    if total > 0 {
+        info!("Purged {total} stale works");
+    }
+}
+
+/// The purger task
+///
+/// Note: this function never returns
+pub async fn purger(
+    graphdb: GraphDB,
+    config: RetentionConfig,
+    objects_path: String,
+) -> Result<(), std::io::Error> {
+    metrics::describe_counter!(PURGED_WORKS, "Number of works purged");
+    metrics::describe_counter!(PURGED_OBJECTS, "Number of object files removed");
+    info!("Purger started");
+    let mut interval = tokio::time::interval(config.get_purge_interval());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        purge(&graphdb, &config, &objects_path).await;
+    }
+}
@@ -0,0 +1,301 @@
+//! Submission rate limiting and quotas
+//!
+//! Submission rates are limited per org through in-memory token buckets; daily
+//! quotas are accounted in the database (see [`crate::graphdb::GraphDB::reserve_usage`])
+//! so that they are shared among all the endpoint instances
+
+use crate::config::{QuotaLimits, QuotasConfig};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const REJECT_COUNT: &str = "endpoint_quota_rejections_total";
+const SUBMITTED_OBJECTS: &str = "endpoint_submitted_objects_total";
+const SUBMITTED_BYTES: &str = "endpoint_submitted_bytes_total";
+
+/// The interval at which idle token buckets are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// The number of token buckets above which idle buckets are dropped immediately
+const SWEEP_THRESHOLD: usize = 10000;
+/// The metrics label of the orgs without a configured quota
+const OTHER_ORGS_LABEL: &str = "other";
+
+/// The reason a submission was refused
+#[derive(Debug, PartialEq)]
+pub enum QuotaExceeded {
+    /// The submission rate is too high
+    Rate(Duration),
+    /// The daily quota is exhausted
+    Daily(Duration),
+}
+
+impl QuotaExceeded {
+    /// The time after which the submission may be retried
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            Self::Rate(d) | Self::Daily(d) => *d,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Self::Rate(_) => "rate",
+            Self::Daily(_) => "daily_quota",
+        }
+    }
+}
+
+/// A token bucket
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    /// Consumes a token or returns the time until one is available
+    fn take(&mut self, rate: f64, burst: f64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    /// Checks if the bucket would be full by now (i.e. it is equivalent to a new bucket)
+    fn is_idle(&self, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens + elapsed * rate >= burst
+    }
+}
+
+/// The token buckets of the orgs which submitted recently
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+/// The submission limiter
+pub struct Quotas {
+    config: QuotasConfig,
+    buckets: Mutex<Buckets>,
+}
+
+impl Quotas {
+    /// Creates a new limiter
+    pub fn new(config: QuotasConfig) -> Self {
+        metrics::describe_counter!(REJECT_COUNT, "Number of submissions refused due to limits");
+        metrics::describe_counter!(SUBMITTED_OBJECTS, "Number of objects accepted per org");
+        metrics::describe_counter!(
+            SUBMITTED_BYTES,
+            metrics::Unit::Bytes,
+            "Number of bytes accepted per org"
+        );
+        if config.admin_token.is_none() {
+            warn!("No admin token configured: the admin routes are disabled");
+        }
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the effective limits of an org
+    pub fn limits(&self, org: &str) -> QuotaLimits {
+        match self.config.orgs.get(org) {
+            Some(limits) => limits.or(&self.config.default),
+            None => self.config.default.clone(),
+        }
+    }
+
+    /// Checks and accounts a submission against the org rate limit
+    pub fn check_rate(&self, org: &str) -> Result<(), QuotaExceeded> {
+        let limits = self.limits(org);
+        let Some(rate) = limits.rate.filter(|r| *r > 0.0) else {
+            return Ok(());
+        };
+        let burst = f64::from(limits.burst.unwrap_or(1).max(1));
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // Note: orgs are client provided, so idle buckets must not be retained
+        if now.saturating_duration_since(buckets.last_sweep) >= SWEEP_INTERVAL
+            || buckets.buckets.len() >= SWEEP_THRESHOLD
+        {
+            buckets.last_sweep = now;
+            buckets.buckets.retain(|org, bucket| {
+                let limits = self.limits(org);
+                match limits.rate.filter(|r| *r > 0.0) {
+                    Some(rate) => {
+                        let burst = f64::from(limits.burst.unwrap_or(1).max(1));
+                        !bucket.is_idle(rate, burst, now)
+                    }
+                    None => false,
+                }
+            });
+        }
+        buckets
+            .buckets
+            .entry(org.to_string())
+            .or_insert(Bucket {
+                tokens: burst,
+                last: now,
+            })
+            .take(rate, burst, now)
+            .map_err(QuotaExceeded::Rate)
+    }
+
+    /// Refills the token bucket of an org, returns true if one existed
+    pub fn reset_rate(&self, org: &str) -> bool {
+        self.buckets.lock().unwrap().buckets.remove(org).is_some()
+    }
+
+    /// Returns the error for an exhausted daily quota
+    pub fn daily_exceeded() -> QuotaExceeded {
+        let secs_of_day = std::time::UNIX_EPOCH
+            .elapsed()
+            .map(|d| d.as_secs() % 86400)
+            .unwrap_or(0);
+        QuotaExceeded::Daily(Duration::from_secs(86400 - secs_of_day))
+    }
+
+    /// Returns the metrics label of an org
+    ///
+    /// Note: orgs are client provided, so only the configured ones get their own series
+    fn org_label(&self, org: &str) -> String {
+        if self.config.orgs.contains_key(org) {
+            org.to_string()
+        } else {
+            OTHER_ORGS_LABEL.to_string()
+        }
+    }
+
+    /// Accounts a rejected submission
+    pub fn record_rejection(&self, org: &str, reason: &QuotaExceeded) {
+        metrics::counter!(
+            REJECT_COUNT,
+            "org" => self.org_label(org),
+            "reason" => reason.reason(),
+        )
+        .increment(1);
+    }
+
+    /// Accounts an accepted submission
+    pub fn record_submission(&self, org: &str, size: u64) {
+        let label = self.org_label(org);
+        metrics::counter!(SUBMITTED_OBJECTS, "org" => label.clone()).increment(1);
+        metrics::counter!(SUBMITTED_BYTES, "org" => label).increment(size);
+    }
+
+    /// Checks the admin credentials in the `Authorization` header value
+    ///
+    /// Note: without a configured admin token nobody is admin
+    pub fn is_admin(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = self.config.admin_token.as_deref() else {
+            return false;
+        };
+        let Some(given) = authorization.and_then(|v| v.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        // Note: constant time comparison
+        given.len() == token.len()
+            && given
+                .bytes()
+                .zip(token.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            last: start,
+        };
+        assert_eq!(bucket.take(0.5, 2.0, start), Ok(()));
+        assert_eq!(bucket.take(0.5, 2.0, start), Ok(()));
+        assert_eq!(
+            bucket.take(0.5, 2.0, start),
+            Err(Duration::from_secs_f64(2.0))
+        );
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            bucket.take(0.5, 2.0, later),
+            Err(Duration::from_secs_f64(1.0))
+        );
+        let much_later = start + Duration::from_secs(3600);
+        assert_eq!(bucket.take(0.5, 2.0, much_later), Ok(()));
+        assert_eq!(bucket.take(0.5, 2.0, much_later), Ok(()));
+        assert!(bucket.take(0.5, 2.0, much_later).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let default = QuotaLimits {
+            rate: Some(1.0),
+            burst: Some(5),
+            daily_bytes: Some(1000),
+            daily_objects: None,
+        };
+        let quotas = Quotas::new(QuotasConfig {
+            default: default.clone(),
+            orgs: HashMap::from([(
+                "big".to_string(),
+                QuotaLimits {
+                    daily_bytes: Some(10000),
+                    ..Default::default()
+                },
+            )]),
+            admin_token: Some("secret".to_string()),
+        });
+        assert_eq!(quotas.limits("ctx"), default);
+        assert_eq!(quotas.limits("big").daily_bytes, Some(10000));
+        assert_eq!(quotas.limits("big").burst, Some(5));
+        assert_eq!(quotas.org_label("big"), "big");
+        assert_eq!(quotas.org_label("ctx"), OTHER_ORGS_LABEL);
+        assert!(quotas.is_admin(Some("Bearer secret")));
+        assert!(!quotas.is_admin(Some("Bearer secreT")));
+        assert!(!quotas.is_admin(None));
+        let quotas = Quotas::new(QuotasConfig::default());
+        assert!(!quotas.is_admin(None));
+        assert!(!quotas.is_admin(Some("Bearer ")));
+    }
+
+    #[test]
+    fn test_idle_buckets() {
+        let quotas = Quotas::new(QuotasConfig {
+            default: QuotaLimits {
+                rate: Some(1.0),
+                burst: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        for i in 0..SWEEP_THRESHOLD {
+            assert_eq!(quotas.check_rate(&format!("org{i}")), Ok(()));
+        }
+        assert_eq!(
+            quotas.buckets.lock().unwrap().buckets.len(),
+            SWEEP_THRESHOLD
+        );
+        // Make all the buckets idle
+        for bucket in quotas.buckets.lock().unwrap().buckets.values_mut() {
+            bucket.last -= Duration::from_secs(2);
+        }
+        assert_eq!(quotas.check_rate("other"), Ok(()));
+        assert_eq!(quotas.buckets.lock().unwrap().buckets.len(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Backend registry
+//!
+//! Keeps track of the backends announced by the workers (see
+//! [`BackendAnnouncement`]); announcements which are not renewed in time
+//! are dropped
+
+use amqprs::{
+    channel::{BasicConsumeArguments, Channel, ConsumerMessage},
+    connection::Connection,
+};
+use shared::{amqp::BackendAnnouncement, config::BrokerConfig};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The maximum validity of an announcement
+const MAX_TTL: Duration = Duration::from_secs(3600);
+
+/// The announced backends, keyed on the worker instance
+#[derive(Default)]
+pub struct Registry {
+    backends: Mutex<HashMap<String, (BackendAnnouncement, Instant)>>,
+}
+
+impl Registry {
+    /// Records (or renews) a backend announcement
+    fn announce(&self, announcement: BackendAnnouncement) {
+        let expires_at = Instant::now() + Duration::from_secs(announcement.ttl_secs).min(MAX_TTL);
+        let mut backends = self.backends.lock().unwrap();
+        if !backends.contains_key(&announcement.instance) {
+            info!(
+                "New \"{}\" backend announced (instance {})",
+                announcement.worker_type, announcement.instance
+            );
+        }
+        backends.insert(announcement.instance.clone(), (announcement, expires_at));
+    }
+
+    /// Returns the live backends, sorted by worker type
+    pub fn list(&self) -> Vec<BackendAnnouncement> {
+        let now = Instant::now();
+        let mut backends = self.backends.lock().unwrap();
+        backends.retain(|_, (_, expires_at)| *expires_at > now);
+        let mut res: Vec<BackendAnnouncement> = backends.values().map(|(a, _)| a.clone()).collect();
+        res.sort_by(|a, b| {
+            a.worker_type
+                .cmp(&b.worker_type)
+                .then_with(|| a.instance.cmp(&b.instance))
+        });
+        res
+    }
+}
+
+/// Receives the backend announcements published by the workers
+pub struct Listener {
+    connection: Connection,
+    channel: Channel,
+    ctag: String,
+    subscription: UnboundedReceiver<ConsumerMessage>,
+    registry: actix_web::web::Data<Registry>,
+}
+
+impl Listener {
+    /// Connects to the broker and subscribes to the backend announcements
+    pub async fn new(
+        broker_cfg: &BrokerConfig,
+        registry: actix_web::web::Data<Registry>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let connection = shared::amqp::connect(broker_cfg).await?;
+        let channel = match shared::amqp::open_channel(&connection).await {
+            Err(e) => {
+                shared::amqp::close_connection(connection).await;
+                return Err(e);
+            }
+            Ok(v) => v,
+        };
+        match async {
+            let queue = shared::amqp::declare_backends_queue(&channel).await?;
+            let args = BasicConsumeArguments::new(&queue, "")
+                .auto_ack(true)
+                .exclusive(true)
+                .finish();
+            let (ctag, subscription) = channel.basic_consume_rx(args).await.map_err(|e| {
+                error!(
+                    "Failed to subscribe to the backend registry queue \"{}\": {}",
+                    queue, e
+                );
+                e
+            })?;
+            debug!(
+                "Subscribed to the backend registry queue \"{}\" with ctag {}",
+                queue, ctag
+            );
+            Ok((ctag, subscription))
+        }
+        .await
+        {
+            Ok((ctag, subscription)) => Ok(Self {
+                connection,
+                channel,
+                ctag,
+                subscription,
+                registry,
+            }),
+            Err(e) => {
+                shared::amqp::close_channel(channel).await;
+                shared::amqp::close_connection(connection).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Cleanly unsubscribes, closes the channel and disconnects
+    pub async fn close(self) {
+        shared::amqp::unsubscribe(&self.channel, &self.ctag).await;
+        shared::amqp::close_channel(self.channel).await;
+        shared::amqp::close_connection(self.connection).await;
+    }
+
+    /// Records the received announcements into the registry
+    ///
+    /// Malformed messages are ignored (the queue is auto-ack)
+    ///
+    /// Note: never returns except on broker errors
+    pub async fn run(mut self) -> Result<(), std::io::Error> {
+        info!("Backend registry listener started");
+        while let Some(msg) = self.subscription.recv().await {
+            let bprops = msg.basic_properties.as_ref().unwrap();
+            match bprops.message_type() {
+                Some(v) if v == shared::BACKEND_ANNOUNCE_TYPE => {}
+                _ => {
+                    warn!("Backend announcement message has missing or invalid type");
+                    continue;
+                }
+            };
+            match serde_json::from_slice(msg.content.as_ref().unwrap()) {
+                Ok(announcement) => self.registry.announce(announcement),
+                Err(e) => warn!("Backend announcement message has invalid payload: {}", e),
+            }
+        }
+        self.close().await;
+        info!("Backend registry listener exited");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn announcement(worker_type: &str, instance: &str, ttl_secs: u64) -> BackendAnnouncement {
+        BackendAnnouncement {
+            worker_type: worker_type.to_string(),
+            instance: instance.to_string(),
+            name: None,
+            version: None,
+            concurrency: 1,
+            object_types: Vec::new(),
+            limits: shared::object::Metadata::new(),
+            symbols: Vec::new(),
+            metadata_keys: Vec::new(),
+            healthy: true,
+            misconfigured: false,
+            ttl_secs,
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::default();
+        registry.announce(announcement("ZIP", "b", 30));
+        registry.announce(announcement("LNK", "c", 30));
+        registry.announce(announcement("ZIP", "a", 30));
+        registry.announce(announcement("Text", "d", 0));
+        let backends: Vec<(String, String)> = registry
+            .list()
+            .into_iter()
+            .map(|a| (a.worker_type, a.instance))
+            .collect();
+        assert_eq!(
+            backends,
+            [("LNK", "c"), ("ZIP", "a"), ("ZIP", "b")].map(|(t, i)| (t.to_string(), i.to_string()))
+        );
+
+        // Renewals replace the previous announcement
+        let mut renewed = announcement("ZIP", "a", 30);
+        renewed.healthy = false;
+        registry.announce(renewed);
+        let backends = registry.list();
+        assert_eq!(backends.len(), 3);
+        assert!(!backends[1].healthy);
+    }
+}
@@ -0,0 +1,160 @@
+//! Reprocessing of historical works
+//!
+//! Periodically re-enqueues, in small batches, the works selected by the
+//! running reprocess jobs. The number of reprocessed works being processed at
+//! any time is capped so that live submissions are not starved
+
+use crate::{
+    config::ReprocessConfig,
+    graphdb::{GraphDB, ReprocessCandidate},
+    httpd::{BrokerAction, JobRequest},
+};
+use tokio::sync::{mpsc, oneshot};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const REPROCESSED_WORKS: &str = "endpoint_reprocessed_works_total";
+const REPROCESS_FAILURES: &str = "endpoint_reprocess_failures_total";
+
+/// Re-enqueues a work with its original relation metadata
+///
+/// Returns the id of the new work
+async fn enqueue(
+    candidate: ReprocessCandidate,
+    graphdb: &GraphDB,
+    tx: &mpsc::WeakSender<BrokerAction>,
+    objects_path: &str,
+) -> Result<String, String> {
+    let path = std::path::Path::new(objects_path).join(&candidate.info.object_id);
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Err(format!(
+            "object \"{}\" is no longer available",
+            candidate.info.object_id
+        ));
+    }
+    let mut relation_metadata = candidate.relation_metadata;
+    let (mut ttl, mut max_recursion) = (None, None);
+    if let Some(serde_json::Value::Object(origin)) = relation_metadata.get(shared::META_KEY_ORIGIN)
+    {
+        ttl = origin.get("ttl").and_then(|v| v.as_u64());
+        max_recursion = origin
+            .get("max_recursion")
+            .and_then(|v| v.as_u64())
+            .and_then(|v| u32::try_from(v).ok());
+    }
+    let ttl = ttl
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(shared::MAX_WORK_TTL)
+        .min(shared::MAX_WORK_TTL);
+    let max_recursion = max_recursion
+        .unwrap_or(shared::MAX_WORK_DEPTH)
+        .clamp(1, shared::MAX_WORK_DEPTH);
+    relation_metadata.insert(
+        shared::META_KEY_REPROCESS_OF.to_string(),
+        candidate.work_id.into(),
+    );
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let jobreq = JobRequest {
+        object: candidate.info,
+        ttl,
+        max_recursion,
+        // Note: reprocessing is never interactive
+        priority: shared::object::Priority::Bulk,
+        reply_tx,
+        relation_metadata,
+    };
+    tx.upgrade()
+        .ok_or("publisher lost")?
+        .send(BrokerAction::Job(jobreq))
+        .await
+        .map_err(|e| format!("failed to communicate with publisher: {e}"))?;
+    let work_id = reply_rx
+        .await
+        .map_err(|e| format!("failed to get request id from publisher: {e}"))?;
+    // Note: the work is already queued, failing to track it is not fatal
+    if let Err(e) = graphdb.add_work_status(&work_id, ttl).await {
+        warn!("Failed to record status for work \"{work_id}\": {e}");
+    }
+    Ok(work_id)
+}
+
+/// Processes a single batch
+async fn reprocess(
+    graphdb: &GraphDB,
+    config: &ReprocessConfig,
+    tx: &mpsc::WeakSender<BrokerAction>,
+    objects_path: &str,
+) {
+    // Note: the error is stringified as it is not Send
+    let batch = graphdb
+        .claim_reprocess_batch(config.get_batch_size(), config.get_max_in_flight())
+        .await
+        .map_err(|e| e.to_string());
+    let mut batch = match batch {
+        Ok(Some(batch)) => batch,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to claim reprocess batch: {e}");
+            return;
+        }
+    };
+    let mut enqueued: Vec<(String, String)> = Vec::with_capacity(batch.candidates.len());
+    let mut failed = 0usize;
+    for candidate in std::mem::take(&mut batch.candidates) {
+        let work_id = candidate.work_id.clone();
+        match enqueue(candidate, graphdb, tx, objects_path).await {
+            Ok(new_work_id) => {
+                info!("Reprocessing work \"{work_id}\" as \"{new_work_id}\"");
+                enqueued.push((work_id, new_work_id));
+            }
+            Err(e) => {
+                warn!("Failed to reprocess work \"{work_id}\": {e}");
+                failed += 1;
+            }
+        }
+    }
+    metrics::counter!(REPROCESSED_WORKS).increment(enqueued.len() as u64);
+    metrics::counter!(REPROCESS_FAILURES).increment(failed as u64);
+    let res = graphdb
+        .record_reprocess_batch(&batch, &enqueued, failed)
+        .await
+        .map_err(|e| e.to_string());
+    if let Err(e) = res {
+        error!(
+            "Failed to record progress of reprocess job {}: {e}",
+            batch.job_id
+        );
+        return;
+    }
+    debug!(
+        "Reprocess job {}: {} works enqueued, {failed} failed",
+        batch.job_id,
+        enqueued.len()
+    );
+}
+
+/// The reprocessor task
+///
+/// Note: this function never returns
+pub async fn reprocessor(
+    graphdb: GraphDB,
+    config: ReprocessConfig,
+    tx: mpsc::WeakSender<BrokerAction>,
+    objects_path: String,
+) -> Result<(), std::io::Error> {
+    metrics::describe_counter!(
+        REPROCESSED_WORKS,
+        "Number of works re-enqueued for reprocessing"
+    );
+    metrics::describe_counter!(
+        REPROCESS_FAILURES,
+        "Number of works which could not be re-enqueued for reprocessing"
+    );
+    info!("Reprocessor started");
+    let mut interval = tokio::time::interval(config.get_interval());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        reprocess(&graphdb, &config, &tx, &objects_path).await;
+    }
+}
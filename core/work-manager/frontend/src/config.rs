@@ -19,13 +19,22 @@ pub struct Config {
     /// The clamd symbols scanner
     pub clamd: ClamdServiceConfig,
     #[cfg(feature = "backend")]
-    /// Object type detection server
-    pub typedet: ClamdServiceConfig,
+    /// Object type detection
+    #[serde(default)]
+    pub typedet: shared::config::TypedetConfig,
     #[cfg(feature = "backend")]
     /// The backend options
     pub backend: BackendConfig,
+    #[cfg(feature = "backend")]
+    /// The backend result cache
+    #[serde(default)]
+    pub cache: CacheConfig,
     /// The path to the objects store
     pub objects_path: String,
+    /// The number of interactive requests picked for each bulk request
+    interactive_weight: Option<u32>,
+    /// The maximum number of requests processed at the same time
+    max_concurrency: Option<usize>,
 }
 
 #[cfg(feature = "backend")]
@@ -38,6 +47,109 @@ pub struct BackendConfig {
     pub args: Option<Vec<String>>,
     /// The port to connect to
     pub port: u16,
+    /// The backend sandbox
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+}
+
+#[cfg(feature = "backend")]
+#[derive(Deserialize, Default)]
+/// Backend sandbox configuration
+pub struct SandboxConfig {
+    /// Isolate the backend in its own namespaces, with a read-only view of the objects store
+    namespaces: Option<bool>,
+    /// Filter out the system calls backends have no use for
+    seccomp: Option<bool>,
+    /// Allow outbound network access
+    network: Option<bool>,
+    /// The maximum resident memory (and data segment) of the backend, in MiB
+    max_memory_mb: Option<u64>,
+    /// The maximum CPU time spent on a single request, in seconds
+    max_cpu_secs: Option<f64>,
+    /// The maximum size of the files written by the backend, in MiB
+    max_file_size_mb: Option<u64>,
+}
+
+#[cfg(feature = "backend")]
+impl SandboxConfig {
+    /// Whether the backend is isolated in its own namespaces (default false)
+    pub fn get_namespaces(&self) -> bool {
+        self.namespaces.unwrap_or(false)
+    }
+
+    /// Whether the backend system calls are filtered (default false)
+    ///
+    /// Always true with namespaces, which would otherwise let the backend
+    /// remount the objects store read-write
+    pub fn get_seccomp(&self) -> bool {
+        self.seccomp.unwrap_or(false) || self.get_namespaces()
+    }
+
+    /// Whether the backend can open outbound connections (default true)
+    pub fn get_network(&self) -> bool {
+        self.network.unwrap_or(true)
+    }
+
+    /// The maximum resident memory of the backend, in bytes (default unlimited)
+    pub fn get_max_memory(&self) -> Option<u64> {
+        self.max_memory_mb.map(|v| v.saturating_mul(1024 * 1024))
+    }
+
+    /// The maximum CPU time spent on a single request (default unlimited)
+    pub fn get_max_cpu(&self) -> Option<std::time::Duration> {
+        self.max_cpu_secs
+            .and_then(|v| std::time::Duration::try_from_secs_f64(v).ok())
+    }
+
+    /// The maximum size of the files written by the backend, in bytes (default unlimited)
+    pub fn get_max_file_size(&self) -> Option<u64> {
+        self.max_file_size_mb.map(|v| v.saturating_mul(1024 * 1024))
+    }
+
+    /// Whether any sandboxing is configured
+    pub fn is_enabled(&self) -> bool {
+        self.get_namespaces()
+            || self.get_seccomp()
+            || !self.get_network()
+            || self.get_max_memory().is_some()
+            || self.get_max_cpu().is_some()
+            || self.get_max_file_size().is_some()
+    }
+}
+
+#[cfg(feature = "backend")]
+#[derive(Deserialize, Default)]
+/// Backend result cache configuration
+pub struct CacheConfig {
+    /// The lifetime of cached results, in seconds
+    ttl_secs: Option<u64>,
+    /// The maximum number of cached results
+    max_entries: Option<usize>,
+    /// Whether results are kept separate for each organization
+    per_org: Option<bool>,
+}
+
+#[cfg(feature = "backend")]
+impl CacheConfig {
+    /// The lifetime of cached results (default 1 hour)
+    ///
+    /// A zero value disables the cache
+    pub fn get_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_secs.unwrap_or(3600))
+    }
+
+    /// The maximum number of cached results (default 10000)
+    pub fn get_max_entries(&self) -> usize {
+        self.max_entries.unwrap_or(10000)
+    }
+
+    /// Whether results are kept separate for each organization (default false)
+    ///
+    /// By default results are shared across organizations, so the timing of a
+    /// job may reveal whether the same object was already seen by another one
+    pub fn is_per_org(&self) -> bool {
+        self.per_org.unwrap_or(false)
+    }
 }
 
 impl Config {
@@ -53,6 +165,19 @@ impl Config {
             })
     }
 
+    /// The number of interactive requests picked for each bulk request, when
+    /// both lanes have requests pending (default 4)
+    pub fn get_interactive_weight(&self) -> u32 {
+        self.interactive_weight.unwrap_or(4).max(1)
+    }
+
+    /// The maximum number of requests processed at the same time (default 8)
+    ///
+    /// This caps the concurrency level declared by the backend
+    pub fn get_max_concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or(8).max(1)
+    }
+
     pub fn get_worker_type(&self) -> &str {
         #[cfg(feature = "backend")]
         {
@@ -20,8 +20,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         error!("Failed to setup the prometheus builder: {}", e);
         e
     })?;
-    let manager = wrkmgr::WorkManager::new(config).await?;
-    if manager.manage_jobs().await {
+    if wrkmgr::run(config).await? {
         Ok(())
     } else {
         Err("Exiting due to error condition".into())
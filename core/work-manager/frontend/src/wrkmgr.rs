@@ -1,29 +1,270 @@
 //! Job management
 mod amqp;
 mod backend;
+#[cfg(feature = "backend")]
+mod cache;
 mod metrics;
+#[cfg(feature = "backend")]
+mod sandbox;
 
 use crate::config::Config;
 use amqp::TimeRemaining;
-use backend::BackendResultKind;
+use backend::{BackendResultKind, BackendResultOk};
 use futures::prelude::*;
+#[cfg(feature = "backend")]
+use shared::typedet;
 use shared::{clamd, object};
+use std::sync::Arc;
+#[cfg(feature = "backend")]
+use std::sync::Mutex;
 use tokio::signal::unix;
+use tokio::sync::watch;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
 /// The work manager
+///
+/// Each work manager processes one job at a time; as many work managers as the
+/// backend concurrency level (capped by `max_concurrency`) are run at the same
+/// time (see [`run()`])
 pub struct WorkManager {
     #[cfg(feature = "backend")]
-    config: Config,
+    config: Arc<Config>,
     clamd: clamd::Clamd,
     #[cfg(feature = "backend")]
-    typedet: clamd::Typedet,
+    typedet: Arc<typedet::Typedet>,
     broker: amqp::Broker,
-    backend: backend::Backend,
+    backend: Arc<backend::Backend>,
+    #[cfg(feature = "backend")]
+    cache: Arc<Mutex<cache::ResultCache>>,
+    shutdown: Arc<watch::Sender<Shutdown>>,
     check_backend: bool,
 }
 
+/// The state shared among all the work managers
+#[derive(Clone)]
+struct Shared {
+    #[cfg(feature = "backend")]
+    config: Arc<Config>,
+    #[cfg(feature = "backend")]
+    typedet: Arc<typedet::Typedet>,
+    backend: Arc<backend::Backend>,
+    #[cfg(feature = "backend")]
+    cache: Arc<Mutex<cache::ResultCache>>,
+    shutdown: Arc<watch::Sender<Shutdown>>,
+}
+
+/// The reason for shutting down the work managers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shutdown {
+    Running,
+    Signal,
+    Error,
+}
+
+/// Requests all the work managers to shut down (unless already requested)
+fn request_shutdown(shutdown: &watch::Sender<Shutdown>, reason: Shutdown) {
+    shutdown.send_if_modified(|state| {
+        if *state == Shutdown::Running {
+            *state = reason;
+            true
+        } else {
+            false
+        }
+    });
+}
+
+/// Spawns the backend and runs the work managers until a signal is received
+///
+/// Returns `true` if a signal was received or `false` on fatal errors
+pub async fn run(config: Config) -> Result<bool, Box<dyn std::error::Error>> {
+    metrics::init_metrics();
+    let config = Arc::new(config);
+    let backend = Arc::new(backend::Backend::new(&config)?);
+    let info = backend.handshake().await;
+    let concurrency = info.concurrency.min(config.get_max_concurrency());
+    if concurrency < info.concurrency {
+        warn!(
+            "Backend concurrency level {} capped to {} (see max_concurrency)",
+            info.concurrency, concurrency
+        );
+    }
+    if is_misconfigured(&info, config.get_worker_type()) {
+        error!(
+            "The backend does not advertise support for \"{}\" objects (supported types: {})",
+            config.get_worker_type(),
+            info.object_types.join(", ")
+        );
+    }
+    let announcer = amqp::Announcer::new(&config).await?;
+    let (shutdown, mut state) = watch::channel(Shutdown::Running);
+    let shared = Shared {
+        #[cfg(feature = "backend")]
+        typedet: Arc::new(typedet::Typedet::new(&config.typedet)?),
+        #[cfg(feature = "backend")]
+        cache: Arc::new(Mutex::new(cache::ResultCache::new(&config.cache))),
+        backend,
+        shutdown: Arc::new(shutdown),
+        #[cfg(feature = "backend")]
+        config: config.clone(),
+    };
+
+    let mut managers = Vec::with_capacity(concurrency);
+    for _ in 0..concurrency {
+        match WorkManager::new(&config, shared.clone()).await {
+            Ok(manager) => managers.push(manager),
+            Err(e) => {
+                for manager in managers {
+                    manager.broker.close().await;
+                }
+                announcer.close().await;
+                return Err(e);
+            }
+        }
+    }
+    info!("Running {} work managers", managers.len());
+
+    let signals = shared.shutdown.clone();
+    tokio::spawn(async move {
+        let signal = async {
+            let mut sigint = match unix::signal(unix::SignalKind::interrupt()) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to setup SIGINT handler: {}", e);
+                    return;
+                }
+            };
+            let mut sigterm = match unix::signal(unix::SignalKind::terminate()) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to setup SIGTERM handler: {}", e);
+                    return;
+                }
+            };
+            let mut sigquit = match unix::signal(unix::SignalKind::quit()) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to setup SIGQUIT handler: {}", e);
+                    return;
+                }
+            };
+            tokio::select!(
+                _ = sigint.recv() => debug!("SIGINT received"),
+                _ = sigterm.recv() => debug!("SIGTERM received"),
+                _ = sigquit.recv() => debug!("SIGQUIT received"),
+            )
+        };
+        signal.await;
+        request_shutdown(&signals, Shutdown::Signal);
+    });
+
+    let monitor = monitor_backend(
+        shared.backend.clone(),
+        info,
+        concurrency,
+        announcer,
+        config.get_worker_type().to_string(),
+        shared.shutdown.clone(),
+    );
+    futures::future::join(
+        futures::future::join_all(managers.into_iter().map(|manager| manager.manage_jobs())),
+        monitor,
+    )
+    .await;
+    let signal_received = *state.borrow_and_update() == Shutdown::Signal;
+    if signal_received {
+        info!("Signal caught, exiting");
+    } else {
+        // A fatal issue occurred: exit as cleanly as possible so the broker knows
+        error!("Exiting due to error condition");
+    }
+    Ok(signal_received)
+}
+
+/// The interval between backend health checks
+const HEALTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// The number of consecutive failed health checks after which the backend is deemed stuck
+const MAX_HEALTH_CHECK_FAILURES: u32 = 3;
+
+/// Checks if the backend advertises object types other than the worker type
+fn is_misconfigured(info: &backend::BackendInfo, worker_type: &str) -> bool {
+    !info.object_types.is_empty() && !info.object_types.iter().any(|t| t == worker_type)
+}
+
+/// Periodically checks the backend health and publishes its announcement
+///
+/// The announced concurrency level is the effective one (i.e. capped)
+///
+/// A backend which neither replies to the health checks nor produces any
+/// result for [`MAX_HEALTH_CHECK_FAILURES`] consecutive idle checks is deemed stuck:
+/// in that case, or if the announcement cannot be published, all the work
+/// managers are stopped
+async fn monitor_backend(
+    backend: Arc<backend::Backend>,
+    mut info: backend::BackendInfo,
+    concurrency: usize,
+    announcer: amqp::Announcer,
+    worker_type: String,
+    shutdown: Arc<watch::Sender<Shutdown>>,
+) {
+    let mut state = shutdown.subscribe();
+    let instance = shared::utils::random_string(16);
+    let mut failures = 0u32;
+    loop {
+        if *state.borrow_and_update() != Shutdown::Running {
+            break;
+        }
+        let announcement = shared::amqp::BackendAnnouncement {
+            worker_type: worker_type.clone(),
+            instance: instance.clone(),
+            name: Some(info.name.clone()).filter(|v| !v.is_empty()),
+            version: Some(info.version.clone()).filter(|v| !v.is_empty()),
+            concurrency,
+            object_types: info.object_types.clone(),
+            limits: info.limits.clone(),
+            symbols: info.symbols.clone(),
+            metadata_keys: info.metadata_keys.clone(),
+            healthy: failures == 0,
+            misconfigured: is_misconfigured(&info, &worker_type),
+            ttl_secs: (HEALTH_CHECK_INTERVAL * MAX_HEALTH_CHECK_FAILURES).as_secs(),
+        };
+        if announcer.announce(&announcement).await.is_err() {
+            request_shutdown(&shutdown, Shutdown::Error);
+            break;
+        }
+
+        let replies = backend.replies();
+        let ping = tokio::select!(
+            _ = state.changed() => break,
+            ping = async {
+                tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+                backend.ping().await
+            } => ping,
+        );
+        match ping {
+            Some(v) => {
+                failures = 0;
+                info = v;
+            }
+            // Note: a busy backend may not reply in time but still produces results
+            None if backend.replies() != replies => failures = 0,
+            // Note: a long request may keep the backend busy for longer than the
+            // health checks allow; stuck requests are dealt with by the job TTL
+            None if backend.is_busy() => {}
+            None => {
+                failures += 1;
+                warn!("Backend health check failed ({failures}/{MAX_HEALTH_CHECK_FAILURES})");
+                if failures >= MAX_HEALTH_CHECK_FAILURES {
+                    error!("Backend has stopped responding");
+                    request_shutdown(&shutdown, Shutdown::Error);
+                    break;
+                }
+            }
+        }
+    }
+    announcer.close().await;
+}
+
 enum ProcessResult<T> {
     Success(T),
     Requeue,
@@ -32,34 +273,94 @@ enum ProcessResult<T> {
 }
 use ProcessResult::*;
 
-const PERF_META_KEY: &str = "_perf";
+/// The outcome of [`WorkManager::call_clamd_and_backend`]
+#[cfg_attr(not(feature = "backend"), allow(dead_code))]
+struct BackendOutcome {
+    /// The backend result, merged with the clamd and parent symbols
+    result: backend::BackendResult,
+    /// A copy of the backend produced symbols and object metadata (fresh results only)
+    output: Option<(Vec<String>, object::Metadata)>,
+    /// The objects created for the leading children (those streamed by the backend)
+    #[cfg(feature = "backend")]
+    streamed: Vec<ProcessResult<StreamedObject>>,
+    /// The child stream the streamed children were published in
+    #[cfg(feature = "backend")]
+    stream_key: Option<String>,
+}
 
-fn sanitize_backend_symbols(symbols: &mut [String]) {
-    for sym in symbols.iter_mut() {
-        let sanitized_sym = sym
-            .to_ascii_uppercase()
-            .replace(|c: char| !(c.is_ascii_alphanumeric() || c == '_'), "_");
-        if sanitized_sym != *sym {
-            warn!("Symbol \"{}\" sanitized to \"{}\"", sym, sanitized_sym);
-            *sym = sanitized_sym;
+/// A streamed child object, its interpretations and its (already published) child jobs
+#[cfg(feature = "backend")]
+type StreamedObject = (
+    object::Info,
+    Vec<typedet::Candidate>,
+    Vec<amqp::PendingChildKind>,
+);
+
+/// The maximum number of children concurrently turned into objects
+#[cfg(feature = "backend")]
+const MAXIMUM_CONCURRENCY: usize = 8;
+
+/// Turns the child jobs into pending children
+#[cfg(feature = "backend")]
+fn into_pending(
+    jobs: Vec<shared::backend::ChildJob>,
+    max_recursion: u32,
+) -> impl Iterator<Item = amqp::PendingChildKind> {
+    jobs.into_iter()
+        .map(move |job| amqp::PendingChildKind::new(job, max_recursion))
+}
+
+/// Turns a child streamed by the backend into a typed object and creates its child jobs
+#[cfg(feature = "backend")]
+async fn streamed_child_to_object(
+    child: &backend::BackendResultChild,
+    parent: &object::Descriptor,
+    objects_path: &str,
+    typedet: &typedet::Typedet,
+    ttl: std::time::Duration,
+) -> ProcessResult<StreamedObject> {
+    let recursion_level = parent.info.recursion_level + 1;
+    let mut object = match shared::backend::child_to_object(
+        child,
+        &parent.info.org,
+        objects_path,
+        recursion_level,
+        parent.info.ctime,
+    )
+    .await
+    {
+        Ok(object) => object,
+        Err(_) => return Exit,
+    };
+    let mut interpretations = Vec::new();
+    if object.object_type.is_empty() {
+        match tokio::time::timeout(ttl, typedet.set_ftype(&mut object, objects_path)).await {
+            Ok(Ok(detection)) => interpretations = typedet.interpretations(&detection),
+            Ok(Err(_)) => return Requeue,
+            Err(_) => return Timeout,
         }
     }
+    let jobs = shared::backend::child_jobs(object.clone(), &interpretations, child.clone(), parent);
+    let jobs = into_pending(jobs, parent.max_recursion).collect();
+    Success((object, interpretations, jobs))
 }
 
 /// The work manager
 impl WorkManager {
-    /// Creates a new work manager, spawning the backend and connecting to the broker
-    pub async fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
-        metrics::init_metrics();
+    /// Creates a new work manager, connecting to the broker
+    async fn new(config: &Config, shared: Shared) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
             clamd: clamd::Clamd::new(&config.clamd),
+            broker: amqp::Broker::new(config).await?,
             #[cfg(feature = "backend")]
-            typedet: clamd::Typedet::new(&config.typedet),
-            backend: backend::Backend::new(&config)?,
-            check_backend: false,
-            broker: amqp::Broker::new(&config).await?,
+            config: shared.config,
+            #[cfg(feature = "backend")]
+            typedet: shared.typedet,
+            backend: shared.backend,
             #[cfg(feature = "backend")]
-            config,
+            cache: shared.cache,
+            shutdown: shared.shutdown,
+            check_backend: false,
         })
     }
 
@@ -71,9 +372,10 @@ impl WorkManager {
     /// 2. [in parallel]
     ///
     ///   * retrieve ClamAV symbols for the object
-    ///   * invoke the backend on the object
+    ///   * invoke the backend on the object (unless its result is cached)
+    ///   * turn the children streamed by the backend into objects and post their jobs
     ///
-    /// 3. turn backend produced children into objects
+    /// 3. turn the remaining backend produced children into objects (or reuse the cached ones)
     ///
     /// 4. post the job result
     ///
@@ -81,48 +383,21 @@ impl WorkManager {
     /// - the backend stays alive
     /// - the broker stays connected
     /// - the child object job results are retrieved
-    pub async fn manage_jobs(mut self) -> bool {
-        let mut sigtask: tokio::task::JoinHandle<_> = tokio::spawn(async move {
-            let mut sigint = match unix::signal(unix::SignalKind::interrupt()) {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("Failed to setup SIGINT handler: {}", e);
-                    return;
-                }
-            };
-            let mut sigterm = match unix::signal(unix::SignalKind::terminate()) {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("Failed to setup SIGTERM handler: {}", e);
-                    return;
-                }
-            };
-            let mut sigquit = match unix::signal(unix::SignalKind::quit()) {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("Failed to setup SIGQUIT handler: {}", e);
-                    return;
-                }
-            };
-            tokio::select!(
-                _ = sigint.recv() => debug!("SIGINT received"),
-                _ = sigterm.recv() => debug!("SIGTERM received"),
-                _ = sigquit.recv() => debug!("SIGQUIT received"),
-            )
-        });
+    ///
+    /// All the work managers are stopped as soon as any of them hits a fatal error
+    async fn manage_jobs(mut self) {
+        let mut shutdown = self.shutdown.subscribe();
 
         // Job reception loop
         debug!("Entering job management loop");
-        let mut signal_received = false;
         loop {
-            // Break out if a signal was received
-            if sigtask.is_finished() {
-                signal_received = true;
+            // Break out if a shutdown was requested
+            if *shutdown.borrow_and_update() != Shutdown::Running {
                 break;
             }
 
             // Recheck backend availability if we suspect it could be stuck
-            if self.check_backend && !self.backend.is_working().await {
+            if self.check_backend && self.backend.ping().await.is_none() {
                 error!("Backend has stopped working");
                 break;
             } else {
@@ -131,10 +406,7 @@ impl WorkManager {
 
             // Get the first available request or break out on error
             let job_request = tokio::select!(
-                _ = &mut sigtask => {
-                    signal_received = true;
-                    break;
-                },
+                _ = shutdown.changed() => break,
                 v = self.get_next_request() => match v {
                     Some(req) => req,
                     None => break,
@@ -177,14 +449,9 @@ impl WorkManager {
             }
         }
 
-        if signal_received {
-            info!("Signal caught, exiting");
-        } else {
-            // A fatal issue occurred: exit as cleanly as possible so the broker knows
-            error!("Exiting due to error condition");
-        }
+        // Stop the other work managers too (if not already stopping)
+        request_shutdown(&self.shutdown, Shutdown::Error);
         self.broker.close().await;
-        signal_received
     }
 
     #[tracing::instrument(level=tracing::Level::ERROR, skip_all, fields(object_id = %job_request.object.info.object_id))]
@@ -196,8 +463,27 @@ impl WorkManager {
             ttl.as_millis()
         );
 
+        // Look up the backend result cache
+        #[cfg(feature = "backend")]
+        let cached = {
+            let mut cache = self.cache.lock().unwrap();
+            for invalidation in self.broker.take_cache_invalidations() {
+                cache.invalidate(&invalidation);
+            }
+            cache.key(&job_request.object).and_then(|key| {
+                cache.get(&key, &job_request.object.info, &self.config.objects_path)
+            })
+        };
+        #[cfg(not(feature = "backend"))]
+        let cached: Option<(BackendResultOk, ())> = None;
+        #[cfg_attr(not(feature = "backend"), allow(unused_variables))]
+        let (cached_res, cached_children) = cached.unzip();
+
         // Pass the object to clam and to the backend
-        let backend_res = match self.call_clamd_and_backend(&job_request, &ttl).await {
+        let outcome = match self
+            .call_clamd_and_backend(&job_request, &ttl, cached_res)
+            .await
+        {
             Success(v) => v,
             Timeout => return self.post_timed_out(job_request).await.is_ok(),
             Requeue => return self.broker.reject_job_request(&job_request).await.is_ok(),
@@ -209,16 +495,29 @@ impl WorkManager {
 
         let pending = {
             // Process children if backend succeeded
-            match backend_res.result {
+            match outcome.result.result {
                 // Backend returned an ok result with possible children
                 BackendResultKind::ok(res) => {
+                    #[cfg(feature = "backend")]
+                    let mut res = res;
+                    #[cfg(feature = "backend")]
+                    let stream_key = outcome.stream_key;
                     // Turn backend children into objects
                     #[cfg(feature = "backend")]
                     let pending_children: Vec<amqp::PendingChildKind> = {
-                        let child_objects = match self
-                            .children_to_objects(&res.children, &job_request)
-                            .await
-                        {
+                        let nstreamed = outcome.streamed.len();
+                        let child_objects = match cached_children {
+                            Some(children) => Success((children, Vec::new())),
+                            None => {
+                                self.children_to_objects(
+                                    &res.children,
+                                    outcome.streamed,
+                                    &job_request,
+                                )
+                                .await
+                            }
+                        };
+                        let (child_objects, mut pending_children) = match child_objects {
                             Success(children) => children,
                             Timeout => {
                                 warn!("Job expired while processing its children");
@@ -232,39 +531,50 @@ impl WorkManager {
                                 return false;
                             }
                         };
-                        let global_relmeta = job_request
-                            .object
-                            .relation_metadata
-                            .get(shared::META_KEY_GLOBAL);
-                        child_objects
-                            .into_iter()
-                            .zip(res.children.into_iter())
-                            .map(|(o, mut c)| {
-                                // Merge global relation_metadata into child relation_metadata
-                                if let Some(global_meta) = global_relmeta {
-                                    c.relation_metadata.insert(
-                                        shared::META_KEY_GLOBAL.to_string(),
-                                        global_meta.clone(),
-                                    );
-                                }
-                                sanitize_backend_symbols(&mut c.symbols);
-                                object::sanitize_meta_keys(&mut c.relation_metadata);
-                                amqp::PendingChildKind::new(
-                                    o,
-                                    c.symbols,
-                                    c.relation_metadata,
-                                    job_request.object.max_recursion,
-                                )
-                            })
-                            .collect()
+                        // Cache fresh results (and learn the backend version)
+                        if let Some((symbols, object_metadata)) = outcome.output {
+                            self.cache.lock().unwrap().insert(
+                                &job_request.object,
+                                symbols,
+                                object_metadata,
+                                &child_objects,
+                                &res.children,
+                            );
+                        }
+                        // Note: the jobs of the streamed children are already created
+                        pending_children.extend(
+                            child_objects
+                                .into_iter()
+                                .zip(res.children.into_iter())
+                                .skip(nstreamed)
+                                .flat_map(|((o, interpretations), c)| {
+                                    let parent = &job_request.object;
+                                    let jobs =
+                                        shared::backend::child_jobs(o, &interpretations, c, parent);
+                                    into_pending(jobs, parent.max_recursion)
+                                }),
+                        );
+                        // Entry polyglots have no parent: their interpretations are attached as children
+                        if job_request.object.info.recursion_level == 1 {
+                            let parent = &job_request.object;
+                            let children = shared::backend::entry_interpretations(parent);
+                            if !children.is_empty() {
+                                res.symbols
+                                    .push(shared::backend::POLYGLOT_SYMBOL.to_string());
+                                pending_children
+                                    .extend(into_pending(children, parent.max_recursion));
+                            }
+                        }
+                        pending_children
                     };
                     #[cfg(not(feature = "backend"))]
-                    let pending_children: Vec<amqp::PendingChildKind> = Vec::new();
+                    let (pending_children, stream_key) = (Vec::new(), None);
                     amqp::PendingResult::new_ok(
                         job_request,
                         res.symbols,
                         res.object_metadata,
                         pending_children,
+                        stream_key,
                     )
                 }
                 // Backend returned an error result
@@ -325,29 +635,105 @@ impl WorkManager {
         }
     }
 
-    /// Retrieves the next request or None in case the broker or the backend failed
+    /// Retrieves the clamd symbols and the backend result for the object
+    ///
+    /// The backend is not invoked if a `cached` result is provided
+    ///
+    /// Children streamed by the backend are turned into objects while the backend
+    /// is still at work, and their jobs are posted right away; they are placed in
+    /// front of the result children
     ///
     /// Note: This also asynchronously processes the result queue
     async fn call_clamd_and_backend(
         &mut self,
         job_request: &amqp::JobRequest,
         ttl: &std::time::Duration,
-    ) -> ProcessResult<backend::BackendResult> {
+        cached: Option<BackendResultOk>,
+    ) -> ProcessResult<BackendOutcome> {
         let obj = &job_request.object;
-        let (clamd_res, backend_res) = tokio::select!(
-            // Await both clamd and the backend
-            workres = futures::future::join(
-                // NOTE: clamd and the backend are invoked in parallel for perf
-                // this means the backend will not see clam-generated symbols
-                tokio::time::timeout(*ttl, self.clamd.get_symbols(&obj.info.object_id)),
-                tokio::time::timeout(*ttl, self.backend.invoke(obj))
-            ) => workres,
-            // ...unless the broker has fatal issues
-            _ = self.broker.process_child_queue() => {
-                // Note: process_child_queue never exits except on serious errors
-                return Exit; // HARD failure
+        let is_cached = cached.is_some();
+        let (children_tx, children_rx) = tokio::sync::mpsc::unbounded_channel();
+        let backend = &self.backend;
+        let backend_fut = async move {
+            match cached {
+                Some(res) => Ok((
+                    backend::BackendResult {
+                        result: BackendResultKind::ok(res),
+                    },
+                    0.0,
+                )),
+                None => backend.invoke(obj, children_tx).await,
             }
+        };
+        // Note: streamed children are published in a child stream as soon as they are ready
+        #[cfg(feature = "backend")]
+        let stream_key = (!is_cached).then(|| self.broker.open_child_stream(job_request));
+        #[cfg(feature = "backend")]
+        let streamed = {
+            let objects_path = self.config.objects_path.as_str();
+            let typedet = self.typedet.as_ref();
+            let ttl = *ttl;
+            futures::stream::unfold(children_rx, |mut rx| async move {
+                rx.recv().await.map(|child| (child, rx))
+            })
+            .map(move |child| async move {
+                let object =
+                    streamed_child_to_object(&child, obj, objects_path, typedet, ttl).await;
+                (child, object)
+            })
+            .buffered(MAXIMUM_CONCURRENCY)
+        };
+        #[cfg(not(feature = "backend"))]
+        let streamed = {
+            drop(children_rx);
+            futures::stream::empty::<(backend::BackendResultChild, ())>()
+        };
+        let mut streamed = std::pin::pin!(streamed);
+        let mut streamed_children = Vec::new();
+        let mut streamed_done = false;
+        #[cfg(feature = "backend")]
+        let mut stream_failed = false;
+
+        let work = futures::future::join(
+            // NOTE: clamd and the backend are invoked in parallel for perf
+            // this means the backend will not see clam-generated symbols
+            tokio::time::timeout(*ttl, self.clamd.get_symbols(&obj.info.object_id)),
+            tokio::time::timeout(*ttl, backend_fut),
         );
+        let mut work = std::pin::pin!(work);
+        let mut workres = None;
+        while workres.is_none() || !streamed_done {
+            tokio::select!(
+                // Await both clamd and the backend
+                res = &mut work, if workres.is_none() => workres = Some(res),
+                // ...while creating and publishing the streamed children
+                child = streamed.next(), if !streamed_done => match child {
+                    #[cfg_attr(not(feature = "backend"), allow(unused_mut))]
+                    Some(mut child) => {
+                        #[cfg(feature = "backend")]
+                        match (&stream_key, &mut child.1) {
+                            (Some(key), Success((_, _, jobs))) if !stream_failed => {
+                                for job in jobs.iter_mut() {
+                                    if self.broker.publish_streamed_child(key, job, job_request).await.is_err() {
+                                        return Exit; // HARD failure
+                                    }
+                                }
+                            }
+                            // Note: the job is going to fail, no more children are published
+                            _ => stream_failed = true,
+                        }
+                        streamed_children.push(child);
+                    }
+                    None => streamed_done = true,
+                },
+                // ...unless the broker has fatal issues
+                _ = self.broker.process_child_queue() => {
+                    // Note: process_child_queue never exits except on serious errors
+                    return Exit; // HARD failure
+                }
+            );
+        }
+        let (clamd_res, backend_res) = workres.unwrap();
 
         // Something timed out
         if clamd_res.is_err() || backend_res.is_err() {
@@ -375,6 +761,9 @@ impl WorkManager {
             return Requeue;
         }
         let (mut backend_res, backend_time) = backend_res.unwrap();
+        let mut backend_output = None;
+        #[cfg(feature = "backend")]
+        let mut streamed = Vec::with_capacity(streamed_children.len());
 
         // If backend succeeded merge together:
         // - existing symbols (set by the parent)
@@ -383,103 +772,123 @@ impl WorkManager {
         if let BackendResultKind::ok(res) = &mut backend_res.result {
             let mut perf_meta = object::Metadata::new();
             perf_meta.insert("time_backend".into(), serde_json::Value::from(backend_time));
-            sanitize_backend_symbols(&mut res.symbols);
-            debug!("Merged {} exising symbols", obj.symbols.len());
-            for sym in obj.symbols.iter() {
-                res.symbols.push(sym.to_string())
+            if is_cached {
+                perf_meta.insert("cached".into(), serde_json::Value::from(true));
             }
-            match clamd_res {
-                Err(e) => {
-                    warn!("Clamd failed: {e}");
-                    res.symbols.push("AV_SCAN_INCOMPLETE".to_string());
-                }
-                Ok((clamd_symbols, scan_time)) => {
-                    if !clamd_symbols.is_empty() {
-                        debug!("Merged {} symbols from clamd", clamd_symbols.len());
-                        let mut infected = false;
-                        for sym in clamd_symbols {
-                            if sym.starts_with("ContexQL.") {
-                                res.symbols.push(sym);
-                            } else {
-                                infected = true;
-                                res.symbols.push(format!("INFECTED-CLAM-{}", sym));
-                            }
-                        }
-                        if infected {
-                            res.symbols.push("INFECTED".to_string());
-                        }
-                    }
-                    perf_meta.insert("time_clamd".into(), serde_json::Value::from(scan_time));
-                }
+            shared::backend::sanitize_backend_symbols(&mut res.symbols);
+            if !is_cached {
+                backend_output = Some((res.symbols.clone(), res.object_metadata.clone()));
+            }
+            shared::backend::merge_result(res, &obj.symbols, Some(clamd_res), perf_meta);
+
+            // Prepend the streamed children
+            let mut children = Vec::with_capacity(streamed_children.len() + res.children.len());
+            #[cfg_attr(not(feature = "backend"), allow(unused_variables))]
+            for (child, object) in streamed_children {
+                children.push(child);
+                #[cfg(feature = "backend")]
+                streamed.push(object);
             }
-            res.object_metadata
-                .insert(PERF_META_KEY.into(), perf_meta.into());
-            object::sanitize_meta_keys(&mut res.object_metadata);
-            res.symbols.sort_unstable();
-            res.symbols.dedup();
+            children.append(&mut res.children);
+            res.children = children;
         }
 
-        Success(backend_res)
+        Success(BackendOutcome {
+            result: backend_res,
+            output: backend_output,
+            #[cfg(feature = "backend")]
+            streamed,
+            #[cfg(feature = "backend")]
+            stream_key,
+        })
     }
 
     // Turns backend children into objects via atomic copy+move and removes the tempfiles
     //
-    // Note: this is serialized because hashing is CPU heavy
+    // The leading children may have already been turned into `streamed` objects
+    // (see call_clamd_and_backend); the others are processed concurrently
+    //
+    // The objects are returned along with the child jobs of the streamed children
     #[cfg(feature = "backend")]
+    #[allow(clippy::type_complexity)]
     async fn children_to_objects(
         &mut self,
         children: &[backend::BackendResultChild],
+        streamed: Vec<ProcessResult<StreamedObject>>,
         job_request: &amqp::JobRequest,
-    ) -> ProcessResult<Vec<shared::object::Info>> {
+    ) -> ProcessResult<(
+        Vec<(shared::object::Info, Vec<typedet::Candidate>)>,
+        Vec<amqp::PendingChildKind>,
+    )> {
         let recursion_level = job_request.object.info.recursion_level + 1;
         let ctime = job_request.object.info.ctime;
-        let mut objects: Vec<shared::object::Info> = Vec::with_capacity(children.len());
-
-        // Atomically turn children into objects
-        for c in children.iter() {
-            if let Some(ref path) = c.path {
-                // FIXME: process_children has little chance of being executed, should i spawn()?
-                tokio::select! {
-                    obj = shared::object::Info::new_from_file(&job_request.object.info.org, path, &self.config.objects_path, recursion_level, ctime) => match obj {
-                        Ok(mut object) => {
-                            if let Some(forced) = &c.force_type {
-                                object.set_type(forced);
-                            }
-                            objects.push(object)
-                        },
-                        Err(_) => break, // HARD fail, rejection below
-                    },
-                    _ = self.broker.process_child_queue() => {
-                        // Note: process_child_queue never exits except on serious errors
-                        break; // HARD fail, rejection below
-                    }
-                };
-            } else {
-                objects.push(shared::object::Info::new_failed(
-                    &job_request.object.info.org,
-                    recursion_level,
-                    ctime,
-                ));
+        let org = job_request.object.info.org.as_str();
+        let objects_path = self.config.objects_path.as_str();
+        let nstreamed = streamed.len();
+        let remove_tempfiles = || {
+            for c in children.iter().skip(nstreamed) {
+                if let Some(ref path) = c.path {
+                    std::fs::remove_file(path).ok();
+                }
             }
-        }
-        // Remove backend temp files
-        for c in children.iter() {
-            if let Some(ref path) = c.path {
-                std::fs::remove_file(path).ok();
+        };
+
+        // Collect the streamed children
+        let mut streamed_objects = Vec::with_capacity(nstreamed);
+        let mut streamed_jobs = Vec::new();
+        for object in streamed {
+            match object {
+                Success((object, interpretations, jobs)) => {
+                    streamed_objects.push((object, interpretations));
+                    streamed_jobs.extend(jobs);
+                }
+                Exit => {
+                    warn!("Child object creation failed");
+                    remove_tempfiles();
+                    return Exit; // HARD fail
+                }
+                failure => {
+                    remove_tempfiles();
+                    return match failure {
+                        Timeout => Timeout,
+                        _ => Requeue,
+                    };
+                }
             }
         }
+
+        // Atomically turn the remaining children into objects
+        let remaining = futures::stream::iter(children.iter().skip(nstreamed))
+            .map(|c| async move {
+                shared::backend::child_to_object(c, org, objects_path, recursion_level, ctime)
+                    .await
+                    .ok()
+            })
+            .buffered(MAXIMUM_CONCURRENCY)
+            .collect::<Vec<_>>();
+        let remaining = tokio::select! {
+            remaining = remaining => remaining,
+            _ = self.broker.process_child_queue() => {
+                // Note: process_child_queue never exits except on serious errors
+                // Remove the backend temp files which were not processed
+                remove_tempfiles();
+                return Exit; // HARD fail
+            }
+        };
         // Return failure if any error occurred above
-        if objects.len() != children.len() {
-            warn!("Child object creation failed");
-            return Exit; // HARD fail
-        }
+        let objects: Option<Vec<shared::object::Info>> = remaining.into_iter().collect();
+        let mut objects = match objects {
+            Some(objects) => objects,
+            None => {
+                warn!("Child object creation failed");
+                return Exit; // HARD fail
+            }
+        };
 
         let ttl = job_request
             .time_remaining()
             .unwrap_or(std::time::Duration::ZERO);
 
-        const MAXIMUM_CONCURRENCY: usize = 8;
-
         let nobj = objects.len();
         let nskip = objects.iter().filter(|o| o.is_skipped()).count();
         let nempty = objects.iter().filter(|o| o.is_empty()).count();
@@ -496,14 +905,22 @@ impl WorkManager {
              \tforced: {nforced}\n\
              \tto-ftype: {n2ftype}"
         );
+        let mut interpretations: Vec<Vec<typedet::Candidate>> = vec![Vec::new(); nobj];
         if n2ftype == 0 {
-            return Success(objects);
+            streamed_objects.extend(objects.into_iter().zip(interpretations));
+            return Success((streamed_objects, streamed_jobs));
         }
+        let typedet = &self.typedet;
+        let objects_path = &self.config.objects_path;
         let stream_of_futures = futures::stream::iter(
             objects
                 .iter_mut()
-                .filter(|o| o.object_type.is_empty())
-                .map(|o| self.typedet.set_ftype(o, &self.config.objects_path)),
+                .enumerate()
+                .filter(|(_, o)| o.object_type.is_empty())
+                .map(|(i, o)| async move {
+                    let detection = typedet.set_ftype(o, objects_path).await?;
+                    Ok::<_, std::io::Error>((i, typedet.interpretations(&detection)))
+                }),
         )
         .buffer_unordered(MAXIMUM_CONCURRENCY)
         .take_while(|res| future::ready(res.is_ok()));
@@ -524,7 +941,11 @@ impl WorkManager {
                         } else {
                             // All children succeeded
                             debug!("All children types were set");
-                            Success(objects)
+                            for (i, candidates) in results.into_iter().flatten() {
+                                interpretations[i] = candidates;
+                            }
+                            streamed_objects.extend(objects.into_iter().zip(interpretations));
+                            Success((streamed_objects, streamed_jobs))
                         }
                     }
                 }
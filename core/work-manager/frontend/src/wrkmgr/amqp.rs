@@ -6,20 +6,22 @@
 //! - Posting child job requests
 //! - Receiving child job results
 //! - Publishing job results
+//! - Receiving backend result cache invalidation requests
+//! - Publishing the backend announcements
 //!
 //! It additionally contains a few support structures and utility functions
 use super::metrics;
 use crate::config::Config;
 use amqprs::{
     channel::{
-        BasicAckArguments, BasicConsumeArguments, BasicPublishArguments, BasicRejectArguments,
-        Channel, ConsumerMessage, QueueDeclareArguments,
+        BasicAckArguments, BasicConsumeArguments, BasicPublishArguments, BasicQosArguments,
+        BasicRejectArguments, Channel, ConsumerMessage, QueueDeclareArguments,
     },
     connection::Connection,
     BasicProperties, FieldTable, FieldValue,
 };
 use shared::{
-    amqp::{self, JobResult, JobResultKind, JobResultOk},
+    amqp::{self, BackendAnnouncement, CacheInvalidation, JobResult, JobResultKind, JobResultOk},
     object, utils,
 };
 use std::collections::HashMap;
@@ -55,6 +57,8 @@ pub struct JobRequest {
     expiration_ts: SystemTime,
     /// The number of delivery attempts
     pub delivery_count: i64,
+    /// Whether this is a work (rather than a child job) request
+    is_work: bool,
     /// The reception time
     recvd_at: std::time::Instant,
     /// The object descriptor received in the request
@@ -82,8 +86,14 @@ pub struct PendingResult {
     reply_to: String,
     correlation_id: String,
     expiration_ts: SystemTime,
+    is_work: bool,
+    work_id: Option<String>,
+    /// The work priority (inherited by the children)
+    priority: object::Priority,
     result: JobResult,
     children: Vec<PendingChildKind>,
+    /// The child stream the children were published in, if any (see [`Broker::open_child_stream`])
+    stream_key: Option<String>,
 }
 
 /// A child for which a result is pending
@@ -94,6 +104,8 @@ pub struct PendingChild {
     symbols: Vec<String>,
     relation_metadata: object::Metadata,
     max_recursion: u32,
+    /// Whether the child job request was already published
+    published: bool,
 }
 
 impl PendingChild {
@@ -109,6 +121,7 @@ impl PendingChild {
             symbols,
             relation_metadata,
             max_recursion,
+            published: false,
         }
     }
 }
@@ -120,6 +133,8 @@ impl<'a> From<&'a PendingChild> for object::DescriptorRef<'a> {
             symbols: &pchild.symbols,
             relation_metadata: &pchild.relation_metadata,
             max_recursion: pchild.max_recursion,
+            work_id: None,
+            priority: object::Priority::default(),
         }
     }
 }
@@ -132,40 +147,30 @@ pub enum PendingChildKind {
 }
 
 impl PendingChildKind {
-    pub fn new(
-        info: object::Info,
-        symbols: Vec<String>,
-        relation_metadata: object::Metadata,
-        max_recursion: u32,
-    ) -> Self {
-        if info.is_skipped() || info.is_empty() {
-            Self::Complete(JobResult {
-                info,
-                relation_metadata,
-                result: JobResultKind::ok(JobResultOk {
-                    symbols,
-                    object_metadata: object::Metadata::new(),
-                    children: Vec::new(),
-                }),
-            })
-        } else {
+    pub fn new(job: shared::backend::ChildJob, max_recursion: u32) -> Self {
+        if job.needs_processing() {
             Self::Pending(PendingChild::new(
-                info,
-                symbols,
-                relation_metadata,
+                job.info,
+                job.symbols,
+                job.relation_metadata,
                 max_recursion,
             ))
+        } else {
+            Self::Complete(job.into_result())
         }
     }
 }
 
 impl PendingResult {
     /// Creates a new successful PendingResult
+    ///
+    /// The `stream_key` is the child stream the leading children were published in
     pub fn new_ok(
         job_request: JobRequest,
         symbols: Vec<String>,
         object_metadata: object::Metadata,
         children: Vec<PendingChildKind>,
+        stream_key: Option<String>,
     ) -> Self {
         let processing_time = job_request.recvd_at.elapsed();
         metrics::set_job_processing_time(&processing_time);
@@ -174,6 +179,9 @@ impl PendingResult {
             reply_to: job_request.reply_to,
             correlation_id: job_request.correlation_id,
             expiration_ts: job_request.expiration_ts,
+            is_work: job_request.is_work,
+            work_id: job_request.object.work_id,
+            priority: job_request.object.priority,
             result: JobResult {
                 info: job_request.object.info,
                 // Note: carried over unmodified
@@ -186,6 +194,7 @@ impl PendingResult {
                 }),
             },
             children,
+            stream_key,
         }
     }
 
@@ -196,6 +205,9 @@ impl PendingResult {
             reply_to: job_request.reply_to,
             correlation_id: job_request.correlation_id,
             expiration_ts: job_request.expiration_ts,
+            is_work: job_request.is_work,
+            work_id: job_request.object.work_id,
+            priority: job_request.object.priority,
             result: JobResult {
                 info: job_request.object.info,
                 // Note: carried over unmodified
@@ -203,7 +215,28 @@ impl PendingResult {
                 result: JobResultKind::error(error),
             },
             children: Vec::new(),
+            stream_key: None,
+        }
+    }
+
+    /// Stores the result of the child job with the provided correlation id
+    ///
+    /// Returns false if no such child is pending
+    fn complete_child(&mut self, correlation_id: &str, mut result: JobResult) -> bool {
+        for child in self.children.iter_mut() {
+            if let PendingChildKind::Pending(pchild) = child {
+                if pchild.correlation_id == correlation_id {
+                    info!(
+                        "Received result for object \"{}\" (child of \"{}\")",
+                        result.info.object_id, self.result.info.object_id
+                    );
+                    result.info.ctime = self.result.info.ctime;
+                    *child = PendingChildKind::Complete(result);
+                    return true;
+                }
+            }
         }
+        false
     }
 
     /// Checks if all the child jobs are complete
@@ -228,7 +261,8 @@ impl TimeRemaining for PendingResult {
 /// An AMQP interface to the message broker
 ///
 /// For practical reasons this is internally split between a "current object" interface
-/// (see [`ObjQueue`]) and a child job interface (see [`ChildQueue`])
+/// (see [`ObjQueue`]), a child job interface (see [`ChildQueue`]) and a cache
+/// invalidation interface (see [`InvalidationQueue`])
 ///
 /// Note: all the error paths are pedantic and will insist on explicit close
 /// rather than relying on Drop. This behavior is recommended by the docs.
@@ -236,6 +270,7 @@ pub struct Broker {
     connection: Connection,
     objq: ObjQueue,
     childq: ChildQueue,
+    invalq: InvalidationQueue,
 }
 
 impl Broker {
@@ -253,10 +288,26 @@ impl Broker {
             Ok(v) => v,
         };
 
-        // Create the object channel and queue
-        let request_queue = utils::get_queue_for(config.get_worker_type());
-        let objq = match ObjQueue::new(&connection, &request_queue).await {
+        // Create the object channel and queues
+        let objq = match ObjQueue::new(
+            &connection,
+            config.get_worker_type(),
+            config.get_interactive_weight(),
+        )
+        .await
+        {
+            Err(e) => {
+                childq.close().await;
+                amqp::close_connection(connection).await;
+                return Err(e);
+            }
+            Ok(v) => v,
+        };
+
+        // Create the cache invalidation channel and queue
+        let invalq = match InvalidationQueue::new(&connection).await {
             Err(e) => {
+                objq.close().await;
                 childq.close().await;
                 amqp::close_connection(connection).await;
                 return Err(e);
@@ -268,6 +319,7 @@ impl Broker {
             connection,
             objq,
             childq,
+            invalq,
         })
     }
 
@@ -278,6 +330,7 @@ impl Broker {
         }
         self.objq.close().await;
         self.childq.close().await;
+        self.invalq.close().await;
         amqp::close_connection(self.connection).await;
     }
 
@@ -306,11 +359,20 @@ impl Broker {
                     if self.objq.publish_job_result(job).await.is_err() {
                         return None;
                     }
+                },
+                msg = self.invalq.subscription.recv() => {
+                    self.invalq.add_invalidation(msg?);
                 }
             )
         }
     }
 
+    /// Returns (and forgets) the cache invalidation requests received so far
+    #[cfg_attr(not(feature = "backend"), allow(dead_code))]
+    pub fn take_cache_invalidations(&mut self) -> Vec<CacheInvalidation> {
+        std::mem::take(&mut self.invalq.invalidations)
+    }
+
     /// Asynchronously handles child job result receptions and job result publication
     ///
     /// Never returns except on a fatal condition
@@ -329,6 +391,56 @@ impl Broker {
         }
     }
 
+    /// Opens a child stream, returning its key
+    ///
+    /// Child job requests can be published in the stream (see
+    /// [`publish_streamed_child`](Self::publish_streamed_child)) before their
+    /// parent result is known; their results are held until the parent result
+    /// is set up for publishing with the same key
+    ///
+    /// Note: streams which are never completed are dropped once expired
+    #[cfg_attr(not(feature = "backend"), allow(dead_code))]
+    pub fn open_child_stream(&mut self, job_request: &JobRequest) -> String {
+        let now = SystemTime::now();
+        self.childq
+            .streams
+            .retain(|_, (expiration_ts, _)| *expiration_ts > now);
+        let key = utils::random_string(shared::MSG_CORRID_LEN);
+        self.childq
+            .streams
+            .insert(key.clone(), (job_request.expiration_ts, Vec::new()));
+        key
+    }
+
+    /// Publishes the job request of a streamed child right away
+    ///
+    /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
+    #[cfg_attr(not(feature = "backend"), allow(dead_code))]
+    pub async fn publish_streamed_child(
+        &mut self,
+        stream_key: &str,
+        child: &mut PendingChildKind,
+        job_request: &JobRequest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let PendingChildKind::Pending(pchild) = child {
+            self.childq
+                .publish_job_request(
+                    stream_key,
+                    pchild,
+                    &job_request.expiration_ts,
+                    job_request.object.work_id.as_deref(),
+                    job_request.object.priority,
+                )
+                .await?;
+            pchild.published = true;
+            info!(
+                "Posted job request for object \"{}\" streamed child of \"{}\"",
+                pchild.info.object_id, job_request.object.info.object_id
+            );
+        }
+        Ok(())
+    }
+
     /// Sets the provided result up for publishing
     ///
     /// Publishing will happen asynchronously when all its child jobs are completed
@@ -338,7 +450,30 @@ impl Broker {
         &mut self,
         pending: PendingResult,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if pending.is_complete() || pending.is_expired() {
+        let is_final = pending.is_complete() || pending.is_expired();
+        if let Some(work_id) = &pending.work_id {
+            let progress = amqp::WorkProgress {
+                work_id: work_id.clone(),
+                objects_queued: if is_final {
+                    0
+                } else {
+                    pending
+                        .children
+                        .iter()
+                        .filter(|c| matches!(c, PendingChildKind::Pending(_)))
+                        .count() as u64
+                },
+                objects_done: 1,
+            };
+            // Note: progress is informative only, failures are not fatal
+            if let Err(e) = amqp::publish_work_progress(&progress, &self.objq.channel).await {
+                warn!("Failed to publish progress for work \"{}\": {}", work_id, e);
+            }
+        }
+        if is_final {
+            if let Some(stream_key) = &pending.stream_key {
+                self.childq.streams.remove(stream_key);
+            }
             info!(
                 "Job for object \"{}\" is complete",
                 pending.result.info.object_id
@@ -361,71 +496,136 @@ impl Broker {
 /// - Receive job requests from parent to this worker (manual ack) - see
 ///   [`get_request()`](Self::get_request)
 /// - Publish our job results - see [`publish_job_result()`](Self::publish_job_result)
+///
+/// Job requests are received from one queue (lane) per [`Priority`](object::Priority);
+/// when both lanes have requests available, up to `interactive_weight` interactive
+/// requests are picked for each bulk request
+///
+/// Only [`REQUEST_PREFETCH`] requests per lane are delivered ahead of time: the
+/// others are left in the queues for the other workers to pick, so that the lane
+/// weighting applies across all the workers rather than to a private backlog
 struct ObjQueue {
     channel: Channel,
+    interactive: Lane,
+    bulk: Lane,
+    interactive_weight: u32,
+    /// The number of interactive requests picked since the last bulk request
+    interactive_streak: u32,
+}
+
+/// The maximum number of unacknowledged job requests delivered from each lane
+///
+/// Each work manager processes one job at a time, so this allows one request per
+/// lane to be ready while another is being processed
+const REQUEST_PREFETCH: u16 = 2;
+
+/// A job request queue subscription
+struct Lane {
     ctag: String,
     subscription: UnboundedReceiver<ConsumerMessage>,
 }
 
+impl Lane {
+    /// Declares the (durable) job request queue and subscribes to it
+    async fn new(channel: &Channel, queue_name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        debug!("Declaring the job request queue \"{}\"...", queue_name);
+        let mut args = FieldTable::new();
+        args.insert("x-queue-type".try_into().unwrap(), "quorum".into());
+        args.insert(
+            "x-message-ttl".try_into().unwrap(),
+            FieldValue::i((shared::MAX_WORK_TTL * 2).as_millis().try_into().unwrap()),
+        );
+        let qargs = QueueDeclareArguments::new(queue_name)
+            .durable(true)
+            .arguments(args)
+            .finish();
+        let (_, message_count, consumer_count) = channel
+            .queue_declare(qargs)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to declare the job request queue \"{}\": {}",
+                    queue_name, e
+                );
+                e
+            })?
+            .unwrap();
+        debug!(
+            "Job request queue \"{}\" successfully declared ({} messages, {} consumers)",
+            queue_name, message_count, consumer_count
+        );
+
+        // Subscribe to the job request queue
+        let args = BasicConsumeArguments::default()
+            .queue(queue_name.to_string())
+            .auto_ack(false)
+            .finish();
+        let (ctag, subscription) = channel.basic_consume_rx(args).await.map_err(|e| {
+            error!(
+                "Failed to subscribe to the job request queue \"{}\": {}",
+                queue_name, e
+            );
+            e
+        })?;
+        debug!(
+            "Subscribed to the job request queue \"{}\" with ctag {}",
+            queue_name, ctag
+        );
+        Ok(Self { ctag, subscription })
+    }
+}
+
 impl ObjQueue {
-    /// Creates a new channel declares the proper (durable) queue and subscribes to it
-    async fn new(conn: &Connection, queue_name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Creates a new channel declares the proper (durable) queues and subscribes to them
+    async fn new(
+        conn: &Connection,
+        worker_type: &str,
+        interactive_weight: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         // Create channel
         let channel = amqp::open_channel(conn).await?;
 
         match async {
-            // Create or join the work request queue
-            debug!("Declaring the job request queue \"{}\"...", queue_name);
-            let mut args = FieldTable::new();
-            args.insert("x-queue-type".try_into().unwrap(), "quorum".into());
-            args.insert(
-                "x-message-ttl".try_into().unwrap(),
-                FieldValue::i((shared::MAX_WORK_TTL * 2).as_millis().try_into().unwrap()),
-            );
-            let qargs = QueueDeclareArguments::new(queue_name)
-                .durable(true)
-                .arguments(args)
-                .finish();
-            let (_, message_count, consumer_count) = channel
-                .queue_declare(qargs)
+            // Limit the job requests delivered ahead of time (per lane)
+            channel
+                .basic_qos(BasicQosArguments::new(0, REQUEST_PREFETCH, false))
                 .await
                 .map_err(|e| {
-                    error!(
-                        "Failed to declare the job request queue \"{}\": {}",
-                        queue_name, e
-                    );
+                    error!("Failed to set the job request prefetch: {}", e);
                     e
-                })?
-                .unwrap();
-            debug!(
-                "Job request queue \"{}\" successfully declared ({} messages, {} consumers)",
-                queue_name, message_count, consumer_count
-            );
+                })?;
 
-            // Subscribe to the job request queue
-            let args = BasicConsumeArguments::default()
-                .queue(queue_name.to_string())
-                .auto_ack(false)
-                .finish();
-            let (ctag, subscription) = channel.basic_consume_rx(args).await.map_err(|e| {
-                error!(
-                    "Failed to subscribe to the job request queue \"{}\": {}",
-                    queue_name, e
-                );
-                e
-            })?;
-            debug!(
-                "Subscribed to the job request queue \"{}\" with ctag {}",
-                queue_name, ctag
-            );
-            Ok((ctag, subscription))
+            // Create or join the work status queue
+            amqp::declare_status_queue(&channel).await?;
+
+            // Create or join the work request queues
+            let interactive = Lane::new(
+                &channel,
+                &utils::get_lane_for(worker_type, object::Priority::Interactive),
+            )
+            .await?;
+            let bulk = match Lane::new(
+                &channel,
+                &utils::get_lane_for(worker_type, object::Priority::Bulk),
+            )
+            .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    amqp::unsubscribe(&channel, &interactive.ctag).await;
+                    return Err(e);
+                }
+            };
+            Ok((interactive, bulk))
         }
         .await
         {
-            Ok((ctag, subscription)) => Ok(Self {
+            Ok((interactive, bulk)) => Ok(Self {
                 channel,
-                ctag,
-                subscription,
+                interactive,
+                bulk,
+                interactive_weight,
+                interactive_streak: 0,
             }),
             Err(e) => {
                 amqp::close_channel(channel).await;
@@ -436,10 +636,39 @@ impl ObjQueue {
 
     /// Cleanly unsubscribes and closes the channel
     async fn close(self) {
-        amqp::unsubscribe(&self.channel, &self.ctag).await;
+        amqp::unsubscribe(&self.channel, &self.interactive.ctag).await;
+        amqp::unsubscribe(&self.channel, &self.bulk.ctag).await;
         amqp::close_channel(self.channel).await;
     }
 
+    /// Receives the next job request message, picking from the lanes with weighted fairness
+    ///
+    /// Note: a [`None`] result indicates that the channel was closed
+    async fn recv(&mut self) -> Option<ConsumerMessage> {
+        let bulk_turn = self.interactive_streak >= self.interactive_weight;
+        let (first, second) = if bulk_turn {
+            (&mut self.bulk, &mut self.interactive)
+        } else {
+            (&mut self.interactive, &mut self.bulk)
+        };
+        let (msg, is_bulk) = if let Ok(msg) = first.subscription.try_recv() {
+            (Some(msg), bulk_turn)
+        } else if let Ok(msg) = second.subscription.try_recv() {
+            (Some(msg), !bulk_turn)
+        } else {
+            tokio::select!(
+                msg = self.interactive.subscription.recv() => (msg, false),
+                msg = self.bulk.subscription.recv() => (msg, true),
+            )
+        };
+        if is_bulk {
+            self.interactive_streak = 0;
+        } else {
+            self.interactive_streak = self.interactive_streak.saturating_add(1);
+        }
+        msg
+    }
+
     /// Retrieves the first available job request from the queue
     ///
     /// It additionally validates the message, its properties and body; non conformant
@@ -450,7 +679,7 @@ impl ObjQueue {
         loop {
             debug!("Awaiting job requests...");
             // Receive broker message
-            let msg = match self.subscription.recv().await {
+            let msg = match self.recv().await {
                 Some(m) => m,
                 None => {
                     error!("Failed to receive job request: channel closed by server");
@@ -539,7 +768,7 @@ impl ObjQueue {
                 }
                 continue;
             };
-            let object: object::Descriptor =
+            let mut object: object::Descriptor =
                 match serde_json::from_slice(msg.content.as_ref().unwrap()) {
                     Ok(v) => v,
                     Err(e) => {
@@ -551,12 +780,24 @@ impl ObjQueue {
                     }
                 };
 
+            let is_work = matches!(
+                bprops
+                    .headers()
+                    .and_then(|hdrs| hdrs.get(&"work_request".try_into().unwrap())),
+                Some(FieldValue::t(true))
+            );
+            if is_work && object.work_id.is_none() {
+                // Work requests are identified by their correlation_id
+                object.work_id = Some(correlation_id.to_string());
+            }
+
             let ret = JobRequest {
                 delivery_tag,
                 reply_to: reply_to.to_string(),
                 correlation_id: correlation_id.to_string(),
                 expiration_ts,
                 delivery_count,
+                is_work,
                 recvd_at,
                 object,
             };
@@ -602,6 +843,7 @@ impl ObjQueue {
     }
 
     /// Posts a complete job result to the result message queue
+    #[allow(clippy::unnecessary_unwrap)]
     pub async fn publish_job_result(
         &mut self,
         mut pending: PendingResult,
@@ -626,7 +868,7 @@ impl ObjQueue {
                 })
                 .collect();
         }
-        if pending.correlation_id.len() == shared::MSG_CORRID_LEN {
+        if pending.is_work {
             let elapsed = pending
                 .result
                 .info
@@ -687,6 +929,8 @@ struct ChildQueue {
     ctag: String,
     subscription: UnboundedReceiver<ConsumerMessage>,
     jobs: HashMap<String, PendingResult>,
+    /// The open child streams: their expiration and the child results received so far
+    streams: HashMap<String, (SystemTime, Vec<(String, JobResult)>)>,
 }
 
 impl ChildQueue {
@@ -744,6 +988,7 @@ impl ChildQueue {
                 ctag,
                 subscription,
                 jobs: HashMap::new(),
+                streams: HashMap::new(),
             }),
             Err(e) => {
                 amqp::close_channel(channel).await;
@@ -765,19 +1010,37 @@ impl ChildQueue {
     /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
     async fn add_pending_job(
         &mut self,
-        pending: PendingResult,
+        mut pending: PendingResult,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let key = utils::random_string(shared::MSG_CORRID_LEN);
+        let key = pending
+            .stream_key
+            .take()
+            .unwrap_or_else(|| utils::random_string(shared::MSG_CORRID_LEN));
         for child in pending.children.iter() {
             if let PendingChildKind::Pending(pchild) = child {
-                self.publish_job_request(&key, pchild, &pending.expiration_ts)
-                    .await?;
+                if pchild.published {
+                    continue;
+                }
+                self.publish_job_request(
+                    &key,
+                    pchild,
+                    &pending.expiration_ts,
+                    pending.work_id.as_deref(),
+                    pending.priority,
+                )
+                .await?;
                 info!(
                     "Posted job request for object \"{}\" child of \"{}\"",
                     pchild.info.object_id, pending.result.info.object_id
                 );
             }
         }
+        // Collect the results of the streamed children received so far
+        if let Some((_, results)) = self.streams.remove(&key) {
+            for (subkey, result) in results {
+                pending.complete_child(&subkey, result);
+            }
+        }
         debug!("New pending job added: {:#?}", pending);
         self.jobs.insert(key, pending);
         metrics::set_waiting_count(self.jobs.len());
@@ -792,10 +1055,14 @@ impl ChildQueue {
         key: &str,
         child: &PendingChild,
         expiration_ts: &SystemTime,
+        work_id: Option<&str>,
+        priority: object::Priority,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let correlation_id = format!("{}.{}", key, child.correlation_id);
         let ttl = expiration_ts.time_remaining().unwrap_or_default();
-        let child_ref: object::DescriptorRef = child.into();
+        let mut child_ref: object::DescriptorRef = child.into();
+        child_ref.work_id = work_id;
+        child_ref.priority = priority;
         shared::amqp::publish_job_request(
             &self.channel,
             child_ref,
@@ -932,34 +1199,173 @@ impl ChildQueue {
     }
 
     async fn await_completed_job(&mut self) -> Option<PendingResult> {
-        // Note: unknown keys are the result of duplicated messages and are
-        // therefore silently ignored below
-        'await_results: loop {
-            let (key, subkey, mut result) = self.get_child_result().await?;
+        // Note: unknown keys are the result of duplicated messages (or of the
+        // children of failed streams) and are therefore silently ignored below
+        loop {
+            let (key, subkey, result) = self.get_child_result().await?;
             if let Some(job) = self.jobs.get_mut(&key) {
-                for child in job.children.iter_mut() {
-                    if let PendingChildKind::Pending(pchild) = child {
-                        if pchild.correlation_id == subkey {
-                            info!(
-                                "Received result for object \"{}\" (child of \"{}\")",
-                                result.info.object_id, job.result.info.object_id
-                            );
-                            result.info.ctime = job.result.info.ctime;
-                            *child = PendingChildKind::Complete(result);
-                            if job.is_complete() {
-                                debug!(
-                                    "Result for object \"{}\" is now complete",
-                                    job.result.info.object_id
-                                );
-                                let job = self.jobs.remove(&key);
-                                metrics::set_waiting_count(self.jobs.len());
-                                return job;
-                            }
-                            continue 'await_results;
-                        }
-                    }
+                if job.complete_child(&subkey, result) && job.is_complete() {
+                    debug!(
+                        "Result for object \"{}\" is now complete",
+                        job.result.info.object_id
+                    );
+                    let job = self.jobs.remove(&key);
+                    metrics::set_waiting_count(self.jobs.len());
+                    return job;
+                }
+            } else if let Some((_, results)) = self.streams.get_mut(&key) {
+                // Streamed child whose parent result is not known yet
+                results.push((subkey, result));
+            }
+        }
+    }
+}
+
+/// Receives backend result cache invalidation requests (exclusive queue)
+///
+/// Requests are only collected here and applied before the next job is run
+struct InvalidationQueue {
+    channel: Channel,
+    ctag: String,
+    subscription: UnboundedReceiver<ConsumerMessage>,
+    invalidations: Vec<CacheInvalidation>,
+}
+
+impl InvalidationQueue {
+    /// The number of pending requests above which the whole cache is invalidated
+    const MAX_PENDING: usize = 64;
+
+    /// Creates a new channel, declares an exclusive queue and subscribes to it
+    async fn new(conn: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let channel = amqp::open_channel(conn).await?;
+
+        match async {
+            let queue = amqp::declare_cache_invalidation_queue(&channel).await?;
+            let args = BasicConsumeArguments::new(&queue, "")
+                .auto_ack(true)
+                .exclusive(true)
+                .finish();
+            let (ctag, subscription) = channel.basic_consume_rx(args).await.map_err(|e| {
+                error!(
+                    "Failed to subscribe to the cache invalidation queue \"{}\": {}",
+                    queue, e
+                );
+                e
+            })?;
+            debug!(
+                "Subscribed to the cache invalidation queue \"{}\" with ctag {}",
+                queue, ctag
+            );
+            Ok((ctag, subscription))
+        }
+        .await
+        {
+            Ok((ctag, subscription)) => Ok(Self {
+                channel,
+                ctag,
+                subscription,
+                invalidations: Vec::new(),
+            }),
+            Err(e) => {
+                amqp::close_channel(channel).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Cleanly unsubscribes and closes the channel
+    async fn close(self) {
+        amqp::unsubscribe(&self.channel, &self.ctag).await;
+        amqp::close_channel(self.channel).await;
+    }
+
+    /// Validates and stores a cache invalidation request
+    ///
+    /// Malformed messages are ignored (the queue is auto-ack)
+    fn add_invalidation(&mut self, msg: ConsumerMessage) {
+        let bprops = msg.basic_properties.as_ref().unwrap();
+        match bprops.message_type() {
+            Some(v) if v == shared::CACHE_INVALIDATE_TYPE => {}
+            _ => {
+                warn!("Cache invalidation message has missing or invalid type");
+                return;
+            }
+        };
+        let invalidation: CacheInvalidation =
+            match serde_json::from_slice(msg.content.as_ref().unwrap()) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Cache invalidation message has invalid payload: {}", e);
+                    return;
                 }
+            };
+        debug!("Cache invalidation received: {:?}", invalidation);
+        if self.invalidations.len() >= Self::MAX_PENDING {
+            // Too many requests: simply drop everything
+            self.invalidations = vec![CacheInvalidation::default()];
+        } else {
+            self.invalidations.push(invalidation);
+        }
+    }
+}
+
+/// Publishes the backend status and capabilities to the registry
+///
+/// Note: the [`close()`](Self::close) fn must be called before drop
+pub struct Announcer {
+    connection: Connection,
+    channel: Channel,
+}
+
+impl Announcer {
+    /// Connects to the broker and declares the backend registry exchange
+    pub async fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let connection = amqp::connect(&config.broker).await?;
+        let channel = match amqp::open_channel(&connection).await {
+            Err(e) => {
+                amqp::close_connection(connection).await;
+                return Err(e);
             }
+            Ok(v) => v,
+        };
+        if let Err(e) = amqp::declare_backends_exchange(&channel).await {
+            amqp::close_channel(channel).await;
+            amqp::close_connection(connection).await;
+            return Err(e);
         }
+        Ok(Self {
+            connection,
+            channel,
+        })
+    }
+
+    /// Cleanly closes the channel and disconnects
+    pub async fn close(self) {
+        amqp::close_channel(self.channel).await;
+        amqp::close_connection(self.connection).await;
+    }
+
+    /// Publishes a backend announcement
+    ///
+    /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
+    pub async fn announce(
+        &self,
+        announcement: &BackendAnnouncement,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        amqp::publish_backend_announcement(announcement, &self.channel)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to publish backend announcement to {}: {}",
+                    shared::BACKENDS_EXCHANGE_NAME,
+                    e
+                );
+                e
+            })?;
+        debug!(
+            "Posted backend announcement to {}",
+            shared::BACKENDS_EXCHANGE_NAME
+        );
+        Ok(())
     }
 }
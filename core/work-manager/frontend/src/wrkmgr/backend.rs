@@ -6,61 +6,53 @@
 //!
 //! The backend standard output and error is collected and logged through
 //! this program
+//!
+//! Backends may hand children over ahead of their result, one JSON object per
+//! line in the form `{"child": {...}}`, followed by the result itself; this is
+//! only done if requested by the frontend (see `stream_children` below)
+//!
+//! Each request is sent over its own connection: backends which declare a
+//! concurrency level are sent that many requests at the same time
+//!
+//! The concurrency level and the backend capabilities are learned through a
+//! hello request (see [`Backend::handshake()`]), which is also periodically
+//! repeated to check that the backend is still alive (see [`Backend::ping()`])
 use crate::config::Config;
-use serde::{Deserialize, Serialize};
+pub use shared::backend::{
+    BackendInfo, BackendResult, BackendResultChild, BackendResultKind, BackendResultOk,
+};
 use shared::object;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
-/// The JSON struct passed to the backend
-#[derive(Serialize)]
-struct BackendRequest<'a> {
-    object: &'a object::Info,
-    symbols: &'a Vec<String>,
-    relation_metadata: &'a object::Metadata,
-}
-
-/// The JSON result produced by the worker
-#[derive(Debug, Deserialize)]
-pub struct BackendResult {
-    #[serde(flatten)]
-    pub result: BackendResultKind,
-}
-
-/// The ok/error portion of the result
-#[derive(Debug, Deserialize)]
-#[allow(non_camel_case_types)]
-pub enum BackendResultKind {
-    error(String),
-    ok(BackendResultOk),
-}
-
-/// The ok portion of the result
-#[derive(Debug, Deserialize)]
-pub struct BackendResultOk {
-    pub symbols: Vec<String>,
-    pub object_metadata: object::Metadata,
-    pub children: Vec<BackendResultChild>,
-}
-
-/// A child object extracted by the backend
-#[derive(Debug, Deserialize)]
-pub struct BackendResultChild {
-    pub path: Option<String>,
-    pub force_type: Option<String>,
-    pub symbols: Vec<String>,
-    pub relation_metadata: object::Metadata,
-}
-
 #[cfg(feature = "backend")]
 pub mod inner {
+    use super::super::sandbox;
     use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    /// The backend termination details
+    #[derive(Clone, Default)]
+    struct Termination {
+        /// The sandbox limit violation which got the backend killed
+        violation: Option<sandbox::Violation>,
+        /// The request which caused the violation, if known
+        culprit: Option<String>,
+    }
 
     /// A backend worker
     pub struct Backend {
-        process: tokio::process::Child,
         addr: String,
+        /// Set once the backend process has exited
+        exited: tokio::sync::watch::Receiver<Option<Termination>>,
+        /// The requests being processed
+        requests: sandbox::Requests,
+        sandboxed: bool,
+        /// The number of replies received so far
+        replies: AtomicU64,
     }
 
     impl Backend {
@@ -76,6 +68,7 @@ pub mod inner {
             if let Some(args) = &config.backend.args {
                 command.args(args);
             }
+            sandbox::configure(command, &config.backend.sandbox, &config.objects_path)?;
             let mut process = command.spawn().map_err(|e| {
                 error!("Failed to spawn backend process: {}", e);
                 e
@@ -96,20 +89,71 @@ pub mod inner {
                     debug!("Backend(err): {}", line);
                 }
             });
+
+            // Monitor the process (and enforce the sandbox limits)
+            // Note: the process is killed when the task is dropped (i.e. on exit)
+            let requests = sandbox::Requests::default();
+            let mut monitor = process.id().and_then(|pid| {
+                sandbox::Monitor::new(pid, &config.backend.sandbox, requests.clone())
+            });
+            let (exited_tx, exited) = tokio::sync::watch::channel(None);
+            let sole_request = requests.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(sandbox::Monitor::INTERVAL);
+                let mut violation = None;
+                let status = loop {
+                    tokio::select! {
+                        status = process.wait() => break status,
+                        _ = interval.tick(), if violation.is_none() => {
+                            violation = monitor.as_mut().and_then(|m| m.check());
+                            if violation.is_some() {
+                                process.start_kill().ok();
+                            }
+                        }
+                    }
+                };
+                match status {
+                    Ok(exit_code) => {
+                        error!("Backend has exited with {}", exit_code);
+                        if violation.is_none() {
+                            violation = exit_code
+                                .signal()
+                                .and_then(sandbox::Violation::from_signal)
+                                .map(|v| (v, sole_request.sole()));
+                        }
+                    }
+                    Err(e) => error!("Failed to check backend status: {}", e),
+                }
+                let (violation, culprit) = violation.unzip();
+                if let Some(violation) = violation {
+                    error!("Backend killed due to a sandbox limit violation ({violation:?})");
+                }
+                exited_tx.send_replace(Some(Termination {
+                    violation,
+                    culprit: culprit.flatten(),
+                }));
+            });
             info!(
                 "Successfully spawned backend \"{}\" listening on port {}",
                 config.backend.path, config.backend.port
             );
             Ok(Self {
-                process,
+                exited,
+                requests,
+                sandboxed: config.backend.sandbox.is_enabled(),
+                replies: AtomicU64::new(0),
                 addr: format!("localhost:{}", config.backend.port),
             })
         }
 
         /// Sends a JSON job request to the backend; awaits and return the result
+        ///
+        /// Children streamed by the backend are forwarded to `children` as soon as
+        /// they are received and are not part of the returned result
         pub async fn invoke(
             &self,
             object_descriptor: &object::Descriptor,
+            children: UnboundedSender<BackendResultChild>,
         ) -> Result<(BackendResult, f64), Box<dyn std::error::Error>> {
             let object = &object_descriptor.info;
             let symbols = &object_descriptor.symbols;
@@ -118,13 +162,7 @@ pub mod inner {
             if object.recursion_level >= max_recursion {
                 info!("Max recursion level ({max_recursion}) reached, backend not invoked");
                 return Ok((
-                    BackendResult {
-                        result: BackendResultKind::ok(BackendResultOk {
-                            symbols: vec!["TOODEEP".to_string()],
-                            object_metadata: object::Metadata::new(),
-                            children: Vec::new(),
-                        }),
-                    },
+                    BackendResult::with_symbols(vec!["TOODEEP".to_string()]),
                     f64::NAN,
                 ));
             }
@@ -133,51 +171,77 @@ pub mod inner {
                 object.recursion_level, max_recursion
             );
             let start = std::time::Instant::now();
+            let correlation_id = shared::utils::random_string(shared::MSG_CORRID_LEN);
+            let _request = self.requests.track(&correlation_id);
+            let mut nstreamed = 0usize;
             let reply = async {
                 let mut stream = tokio::net::TcpStream::connect(&self.addr).await?;
-                let req = BackendRequest {
+                let req = shared::backend::BackendRequest {
                     object,
                     symbols,
                     relation_metadata,
+                    stream_children: true,
+                    correlation_id: &correlation_id,
                 };
                 let req_json = serde_json::to_string(&req).unwrap();
                 stream.write_all(req_json.as_bytes()).await?;
                 stream.shutdown().await?;
-                let reply = shared::utils::read_all(&mut stream).await?;
+                // Note: streamed children come first, each on its own line; anything
+                // else is the (possibly multi-line) result
+                let mut reader = tokio::io::BufReader::new(stream);
+                let mut reply: Vec<u8> = Vec::new();
+                let mut line: Vec<u8> = Vec::new();
+                loop {
+                    line.clear();
+                    if reader.read_until(b'\n', &mut line).await? == 0 {
+                        break;
+                    }
+                    if reply.is_empty() {
+                        if let Ok(streamed) =
+                            serde_json::from_slice::<shared::backend::StreamedChild>(&line)
+                        {
+                            debug!("Backend streamed child: {:#?}", streamed.child);
+                            nstreamed += 1;
+                            // Note: the receiver is only gone if the job is being abandoned
+                            children.send(streamed.child).ok();
+                            continue;
+                        }
+                    }
+                    reply.extend_from_slice(&line);
+                }
                 Ok::<Vec<u8>, Box<dyn std::error::Error>>(reply)
             }
-            .await
-            .map_err(|e| {
-                // Communication failure is a HARD error
-                error!("Communication with the backend failed: {e}");
-                e
-            })?;
-            // Note: there's a little extra work here to ensure preference to the 'error'
-            // path in case the reply object is ambiguous and contains both the 'ok' and
-            // 'error' keys
-            let mut res: serde_json::Value = serde_json::from_slice(&reply).map_err(|e| {
-                // This is a HARD error
-                error!("Invalid JSON reply received from backend: {e}");
-                e
-            })?;
-            // After this point the reply is valid JSON
-            if res.get("error").is_some() && res.get("ok").is_some() {
-                warn!("Backend returned 'error' and 'ok' and at the same time, assuming error");
-                res.as_object_mut().unwrap().remove("ok");
-            }
-            let res: BackendResult = serde_json::from_value(res).map_err(|e| {
-                // Backend returned an invalid reply
-                // This is a HARD error
-                error!("Invalid reply received from backend: {e}");
-                e
-            })?;
+            .await;
+            let reply = match reply {
+                Ok(reply) if !reply.is_empty() => {
+                    self.replies.fetch_add(1, Ordering::Relaxed);
+                    reply
+                }
+                reply => {
+                    // The backend may have been killed while processing this very request
+                    if let Some(violation) = self.get_violation(&correlation_id).await {
+                        warn!("Object caused a sandbox limit violation ({violation:?})");
+                        return Ok((
+                            BackendResult::with_symbols(vec![violation.symbol().to_string()]),
+                            start.elapsed().as_secs_f64(),
+                        ));
+                    }
+                    reply.map_err(|e| {
+                        // Communication failure is a HARD error
+                        error!("Communication with the backend failed: {e}");
+                        e
+                    })?
+                }
+            };
+            let res = shared::backend::parse_reply(&reply, &correlation_id)?;
             debug!("Backend result received: {:#?}", res);
             match &res.result {
                 BackendResultKind::ok(r) => {
                     info!(
-                        "Backend produced {} symbols and {} children",
+                        "Backend produced {} symbols and {} children ({} streamed)",
                         r.symbols.len(),
-                        r.children.len(),
+                        r.children.len() + nstreamed,
+                        nstreamed,
                     );
                 }
                 BackendResultKind::error(ref e) => {
@@ -188,25 +252,102 @@ pub mod inner {
         }
 
         /// Waits indefinitely for the backend to exit
-        pub async fn wait(&mut self) {
-            let status = self.process.wait().await;
-            match status {
-                Ok(exit_code) => error!("Backend has exited with {}", exit_code),
-                Err(e) => error!("Failed to check backend status: {}", e),
+        pub async fn wait(&self) {
+            let mut exited = self.exited.clone();
+            exited.wait_for(|exited| exited.is_some()).await.ok();
+        }
+
+        /// Retrieves the sandbox limit violation caused by the request, if any
+        async fn get_violation(&self, correlation_id: &str) -> Option<sandbox::Violation> {
+            if !self.sandboxed {
+                return None;
+            }
+            // Note: the backend exit may be noticed slightly after the connection drop
+            let mut exited = self.exited.clone();
+            let termination = tokio::time::timeout(
+                std::time::Duration::from_secs(1),
+                exited.wait_for(|exited| exited.is_some()),
+            )
+            .await
+            .ok()?
+            .ok()?;
+            let termination = termination.as_ref()?;
+            if termination.culprit.as_deref() == Some(correlation_id) {
+                termination.violation
+            } else {
+                None
             }
         }
 
-        /// Checks if backend is working
-        pub async fn is_working(&self) -> bool {
-            let reply = tokio::time::timeout(std::time::Duration::from_secs(2), async {
-                let mut stream = tokio::net::TcpStream::connect(&self.addr).await?;
-                stream.write_all(b"{}").await?;
-                stream.shutdown().await?;
-                let reply = shared::utils::read_all(&mut stream).await?;
-                Ok::<Vec<u8>, Box<dyn std::error::Error>>(reply)
-            })
+        /// Sends a hello request to the backend and retrieves its capabilities
+        ///
+        /// Returns [`None`] if the backend does not reply in a timely fashion
+        pub async fn ping(&self) -> Option<BackendInfo> {
+            let reply = tokio::time::timeout(
+                std::time::Duration::from_secs(2),
+                shared::backend::hello(&self.addr),
+            )
             .await;
-            reply.is_ok()
+            match reply {
+                Ok(Ok(info)) => {
+                    self.replies.fetch_add(1, Ordering::Relaxed);
+                    Some(info)
+                }
+                Ok(Err(e)) => {
+                    debug!("Backend ping failed: {e}");
+                    None
+                }
+                Err(_) => {
+                    debug!("Backend ping timed out");
+                    None
+                }
+            }
+        }
+
+        /// Waits for the backend to come up and retrieves its capabilities
+        ///
+        /// Backends not replying at all are sent one request at a time
+        pub async fn handshake(&self) -> BackendInfo {
+            const ATTEMPTS: usize = 50;
+            for _ in 0..ATTEMPTS {
+                let info = tokio::select! {
+                    info = self.ping() => info,
+                    _ = self.wait() => break,
+                };
+                // Note: the backend may not be listening yet
+                if let Some(info) = info {
+                    info!(
+                        "Backend {} {} ready, concurrency level: {}",
+                        if info.name.is_empty() {
+                            "(unnamed)"
+                        } else {
+                            &info.name
+                        },
+                        if info.version.is_empty() {
+                            "(unversioned)"
+                        } else {
+                            &info.version
+                        },
+                        info.concurrency
+                    );
+                    return info;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
+            warn!("Failed to complete the handshake with the backend");
+            BackendInfo::default()
+        }
+
+        /// Returns the number of replies (to job and hello requests) received so far
+        ///
+        /// This is used to tell a stuck backend from a busy one
+        pub fn replies(&self) -> u64 {
+            self.replies.load(Ordering::Relaxed)
+        }
+
+        /// Returns true if some request is being processed by the backend
+        pub fn is_busy(&self) -> bool {
+            !self.requests.is_empty()
         }
     }
 }
@@ -230,31 +371,41 @@ pub mod inner {
         pub async fn invoke(
             &self,
             _object_descriptor: &object::Descriptor,
+            _children: tokio::sync::mpsc::UnboundedSender<BackendResultChild>,
         ) -> Result<(BackendResult, f64), Box<dyn std::error::Error>> {
-            Ok((
-                BackendResult {
-                    result: BackendResultKind::ok(BackendResultOk {
-                        symbols: Vec::new(),
-                        object_metadata: object::Metadata::new(),
-                        children: Vec::new(),
-                    }),
-                },
-                f64::NAN,
-            ))
+            Ok((BackendResult::with_symbols(Vec::new()), f64::NAN))
         }
 
         /// Waits indefinitely for the backend to exit
         #[inline]
-        pub async fn wait(&mut self) -> ! {
+        pub async fn wait(&self) -> ! {
             loop {
                 tokio::time::sleep(tokio::time::Duration::MAX).await;
             }
         }
 
-        /// Checks if backend is working
+        /// Sends a hello request to the backend and retrieves its capabilities
+        #[inline]
+        pub async fn ping(&self) -> Option<BackendInfo> {
+            Some(BackendInfo::default())
+        }
+
+        /// Waits for the backend to come up and retrieves its capabilities
+        #[inline]
+        pub async fn handshake(&self) -> BackendInfo {
+            BackendInfo::default()
+        }
+
+        /// Returns the number of replies (to job and hello requests) received so far
+        #[inline]
+        pub fn replies(&self) -> u64 {
+            0
+        }
+
+        /// Returns true if some request is being processed by the backend
         #[inline]
-        pub async fn is_working(&self) -> bool {
-            return true;
+        pub fn is_busy(&self) -> bool {
+            false
         }
     }
 }
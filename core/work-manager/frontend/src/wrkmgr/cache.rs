@@ -0,0 +1,397 @@
+//! Backend result cache
+//!
+//! Backends are deterministic for a given input, therefore objects which are
+//! seen over and over (e.g. logos and signatures attached to emails) only need
+//! to be analysed once: their results are reused until they expire, get evicted
+//! or are explicitly invalidated
+//!
+//! Results are keyed on the object content and type, on the job inputs (symbols
+//! and relation metadata) and on the version of the backend which produced them
+//!
+//! The organization is not part of the key unless `per_org` is set: a cached
+//! result is therefore reused across organizations
+use super::backend::{BackendResultChild, BackendResultOk};
+use super::metrics;
+use sha2::{Digest, Sha256};
+use shared::{amqp::CacheInvalidation, object, typedet};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// Relation metadata keys which are not seen by backends and are therefore not part of the key
+const IGNORED_META_KEYS: [&str; 2] = [shared::META_KEY_ORIGIN, shared::META_KEY_REPROCESS_OF];
+
+/// Marks an object file as in use by refreshing its modification time
+///
+/// Returns false if the file is no longer available
+fn touch_object(path: &std::path::Path) -> bool {
+    std::fs::File::open(path)
+        .and_then(|f| f.set_modified(std::time::SystemTime::now()))
+        .is_ok()
+}
+
+/// A child object, as produced by the backend
+struct CachedChild {
+    info: object::Info,
+    interpretations: Vec<typedet::Candidate>,
+    symbols: Vec<String>,
+    relation_metadata: object::Metadata,
+}
+
+/// A successful backend result
+struct CachedResult {
+    object_id: String,
+    object_type: String,
+    symbols: Vec<String>,
+    object_metadata: object::Metadata,
+    children: Vec<CachedChild>,
+    expires_at: Instant,
+}
+
+/// A cache hit: the backend result and its (already existing) child objects
+pub type CacheHit = (
+    BackendResultOk,
+    Vec<(object::Info, Vec<typedet::Candidate>)>,
+);
+
+/// The backend result cache
+pub struct ResultCache {
+    ttl: Duration,
+    max_entries: usize,
+    per_org: bool,
+    /// The backend version, as learned from the last backend result
+    backend_version: Option<String>,
+    entries: HashMap<String, CachedResult>,
+    /// The insertion order (used for expiration and eviction)
+    order: VecDeque<(String, Instant)>,
+}
+
+impl ResultCache {
+    /// Creates a new, empty cache
+    pub fn new(config: &crate::config::CacheConfig) -> Self {
+        Self {
+            ttl: config.get_ttl(),
+            max_entries: config.get_max_entries(),
+            per_org: config.is_per_org(),
+            backend_version: None,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Computes the cache key for the provided job
+    ///
+    /// Returns [`None`] if the result cannot be cached (cache disabled, unknown
+    /// backend version or maximum recursion level reached)
+    pub fn key(&self, descriptor: &object::Descriptor) -> Option<String> {
+        self.key_for(descriptor, self.backend_version.as_deref()?)
+    }
+
+    /// Computes the cache key for the provided job and backend version
+    fn key_for(&self, descriptor: &object::Descriptor, backend_version: &str) -> Option<String> {
+        if self.ttl.is_zero() || self.max_entries == 0 {
+            return None;
+        }
+        let info = &descriptor.info;
+        if info.recursion_level >= descriptor.max_recursion {
+            return None;
+        }
+        let relation_metadata: object::Metadata = descriptor
+            .relation_metadata
+            .iter()
+            .filter(|(k, _)| !IGNORED_META_KEYS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let input = serde_json::json!([
+            backend_version,
+            info.object_id,
+            info.object_type,
+            info.object_subtype,
+            descriptor.symbols,
+            relation_metadata,
+            self.per_org.then_some(&info.org),
+        ]);
+        Some(format!("{:x}", Sha256::digest(input.to_string())))
+    }
+
+    /// Retrieves a cached result for the provided object
+    ///
+    /// The children are re-materialised for the current job; results whose
+    /// child objects are no longer available in the objects store are dropped
+    ///
+    /// Note: the child objects are touched, so that the files of the works in
+    /// flight are not removed by the endpoint purger (see `endpoint/src/purge.rs`)
+    pub fn get(&mut self, key: &str, info: &object::Info, objects_path: &str) -> Option<CacheHit> {
+        let usable = match self.entries.get(key) {
+            None => {
+                metrics::result_cache_miss();
+                return None;
+            }
+            Some(entry) => {
+                entry.expires_at > Instant::now()
+                    && entry.children.iter().all(|c| {
+                        c.info.is_skipped()
+                            || c.info.is_empty()
+                            || touch_object(
+                                &std::path::Path::new(objects_path).join(&c.info.object_id),
+                            )
+                    })
+            }
+        };
+        if !usable {
+            debug!("Cached result dropped (expired or missing children)");
+            self.entries.remove(key);
+            metrics::set_result_cache_entries(self.entries.len());
+            metrics::result_cache_miss();
+            return None;
+        }
+        let entry = self.entries.get(key)?;
+        let recursion_level = info.recursion_level + 1;
+        let mut children = Vec::with_capacity(entry.children.len());
+        let mut child_objects = Vec::with_capacity(entry.children.len());
+        for c in entry.children.iter() {
+            let mut child_info = c.info.clone();
+            child_info.org = info.org.clone();
+            child_info.recursion_level = recursion_level;
+            child_info.ctime = info.ctime;
+            child_objects.push((child_info, c.interpretations.clone()));
+            children.push(BackendResultChild {
+                path: None,
+                force_type: None,
+                symbols: c.symbols.clone(),
+                relation_metadata: c.relation_metadata.clone(),
+            });
+        }
+        info!(
+            "Reusing cached backend result ({} symbols and {} children)",
+            entry.symbols.len(),
+            children.len()
+        );
+        metrics::result_cache_hit();
+        Some((
+            BackendResultOk {
+                symbols: entry.symbols.clone(),
+                object_metadata: entry.object_metadata.clone(),
+                children,
+            },
+            child_objects,
+        ))
+    }
+
+    /// Stores a successful backend result and learns the backend version from it
+    ///
+    /// The result is keyed on the backend version which produced it; when the
+    /// backend version changes the whole cache is flushed first
+    pub fn insert(
+        &mut self,
+        descriptor: &object::Descriptor,
+        symbols: Vec<String>,
+        object_metadata: object::Metadata,
+        child_objects: &[(object::Info, Vec<typedet::Candidate>)],
+        children: &[BackendResultChild],
+    ) {
+        let backend_version = object_metadata
+            .get(shared::META_KEY_BACKEND_VERSION)
+            .and_then(|v| v.as_str());
+        if backend_version != self.backend_version.as_deref() {
+            if !self.entries.is_empty() {
+                info!("Backend version changed, flushing the result cache");
+            }
+            self.backend_version = backend_version.map(|v| v.to_string());
+            self.clear();
+        }
+        let key = match backend_version.and_then(|v| self.key_for(descriptor, v)) {
+            Some(key) => key,
+            None => return,
+        };
+        let info = &descriptor.info;
+
+        // Drop expired results and make room for the new one
+        let now = Instant::now();
+        while let Some((old_key, expires_at)) = self.order.front() {
+            if *expires_at > now && self.entries.len() < self.max_entries {
+                break;
+            }
+            if self
+                .entries
+                .get(old_key)
+                .is_some_and(|e| e.expires_at == *expires_at)
+            {
+                self.entries.remove(old_key);
+            }
+            self.order.pop_front();
+        }
+
+        let expires_at = now + self.ttl;
+        let entry = CachedResult {
+            object_id: info.object_id.clone(),
+            object_type: info.object_type.clone(),
+            symbols,
+            object_metadata,
+            children: child_objects
+                .iter()
+                .zip(children.iter())
+                .map(|((info, interpretations), c)| CachedChild {
+                    info: info.clone(),
+                    interpretations: interpretations.clone(),
+                    symbols: c.symbols.clone(),
+                    relation_metadata: c.relation_metadata.clone(),
+                })
+                .collect(),
+            expires_at,
+        };
+        self.entries.insert(key.clone(), entry);
+        self.order.push_back((key, expires_at));
+        metrics::set_result_cache_entries(self.entries.len());
+    }
+
+    /// Drops the cached results matching the invalidation request
+    pub fn invalidate(&mut self, invalidation: &CacheInvalidation) {
+        if invalidation.object_id.is_none() && invalidation.object_type.is_none() {
+            info!("Result cache invalidated");
+            self.clear();
+            return;
+        }
+        let before = self.entries.len();
+        self.entries.retain(|_, e| {
+            !(invalidation
+                .object_id
+                .as_ref()
+                .map_or(true, |v| *v == e.object_id)
+                && invalidation
+                    .object_type
+                    .as_ref()
+                    .map_or(true, |v| *v == e.object_type))
+        });
+        info!(
+            "Result cache invalidated ({} results dropped)",
+            before - self.entries.len()
+        );
+        metrics::set_result_cache_entries(self.entries.len());
+    }
+
+    /// Drops all the cached results
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        metrics::set_result_cache_entries(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(object_id: &str, object_type: &str) -> object::Descriptor {
+        let mut info = object::Info::new_failed("org", 1, 0.0);
+        info.object_id = object_id.to_string();
+        info.object_type = object_type.to_string();
+        info.size = 1;
+        object::Descriptor {
+            info,
+            symbols: Vec::new(),
+            relation_metadata: object::Metadata::new(),
+            max_recursion: 5,
+            work_id: None,
+            priority: object::Priority::default(),
+        }
+    }
+
+    fn versioned_metadata(version: &str) -> object::Metadata {
+        let mut meta = object::Metadata::new();
+        meta.insert(shared::META_KEY_BACKEND_VERSION.to_string(), version.into());
+        meta
+    }
+
+    fn cache() -> ResultCache {
+        let mut cache = ResultCache::new(&crate::config::CacheConfig::default());
+        cache.insert(
+            &descriptor("x", "X"),
+            Vec::new(),
+            versioned_metadata("1.0.0"),
+            &[],
+            &[],
+        );
+        cache
+    }
+
+    fn store(cache: &mut ResultCache, desc: &object::Descriptor) -> String {
+        cache.insert(
+            desc,
+            vec!["SYM".to_string()],
+            versioned_metadata("1.0.0"),
+            &[],
+            &[],
+        );
+        cache.key(desc).unwrap()
+    }
+
+    #[test]
+    fn test_key() {
+        let mut cache = ResultCache::new(&crate::config::CacheConfig::default());
+        let mut desc = descriptor("a", "A");
+        assert!(cache.key(&desc).is_none(), "version unknown");
+        cache = self::cache();
+        let key = cache.key(&desc).unwrap();
+        desc.relation_metadata
+            .insert(shared::META_KEY_ORIGIN.to_string(), "here".into());
+        desc.info.org = "another".to_string();
+        assert_eq!(cache.key(&desc).unwrap(), key);
+        desc.relation_metadata
+            .insert(shared::META_KEY_GLOBAL.to_string(), "pw".into());
+        assert_ne!(cache.key(&desc).unwrap(), key);
+        desc.info.recursion_level = desc.max_recursion;
+        assert!(cache.key(&desc).is_none(), "too deep");
+    }
+
+    #[test]
+    fn test_get_and_invalidate() {
+        let mut cache = cache();
+        let a = descriptor("a", "A");
+        let b = descriptor("b", "B");
+        let key_a = store(&mut cache, &a);
+        let key_b = store(&mut cache, &b);
+        let (res, children) = cache.get(&key_a, &a.info, "/nonexistent").unwrap();
+        assert_eq!(res.symbols, ["SYM"]);
+        assert!(children.is_empty());
+
+        cache.invalidate(&CacheInvalidation {
+            object_id: None,
+            object_type: Some("B".to_string()),
+        });
+        assert!(cache.get(&key_a, &a.info, "/nonexistent").is_some());
+        assert!(cache.get(&key_b, &b.info, "/nonexistent").is_none());
+        cache.invalidate(&CacheInvalidation::default());
+        assert!(cache.get(&key_a, &a.info, "/nonexistent").is_none());
+
+        // A new backend version flushes the cache
+        let key_a = store(&mut cache, &a);
+        cache.insert(&b, Vec::new(), versioned_metadata("2.0.0"), &[], &[]);
+        assert!(cache.get(&key_a, &a.info, "/nonexistent").is_none());
+        assert_ne!(cache.key(&a).unwrap(), key_a);
+        // ...and the result which taught it is cached
+        let key_b = cache.key(&b).unwrap();
+        assert!(cache.get(&key_b, &b.info, "/nonexistent").is_some());
+    }
+
+    #[test]
+    fn test_first_result_cached() {
+        let mut cache = ResultCache::new(&crate::config::CacheConfig::default());
+        let a = descriptor("a", "A");
+        assert!(cache.key(&a).is_none());
+        let key = store(&mut cache, &a);
+        assert!(cache.get(&key, &a.info, "/nonexistent").is_some());
+    }
+
+    #[test]
+    fn test_per_org() {
+        let config: crate::config::CacheConfig =
+            serde_json::from_value(serde_json::json!({ "per_org": true })).unwrap();
+        let mut cache = ResultCache::new(&config);
+        let mut desc = descriptor("a", "A");
+        let key = store(&mut cache, &desc);
+        desc.info.org = "another".to_string();
+        assert_ne!(cache.key(&desc).unwrap(), key);
+    }
+}
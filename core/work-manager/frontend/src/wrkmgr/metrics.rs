@@ -7,6 +7,9 @@ const JOB_RESCHEDULED: &str = "frontend_jobs_rescheduled_total";
 const JOBS_WAITING: &str = "frontend_job_waiting_count";
 const OBJ_COMPLETED_TIME: &str = "frontend_object_processing_time_seconds";
 const WORK_PROC_TIME: &str = "frontend_work_processing_time_seconds";
+const CACHE_HITS: &str = "frontend_result_cache_hits_total";
+const CACHE_MISSES: &str = "frontend_result_cache_misses_total";
+const CACHE_ENTRIES: &str = "frontend_result_cache_entries_count";
 
 pub fn init_metrics() {
     metrics::describe_counter!(JOB_RECEIVED, "Total number of received jobs");
@@ -28,6 +31,9 @@ pub fn init_metrics() {
         metrics::Unit::Seconds,
         "Time to fully process a work request"
     );
+    metrics::describe_counter!(CACHE_HITS, "Total number of backend result cache hits");
+    metrics::describe_counter!(CACHE_MISSES, "Total number of backend result cache misses");
+    metrics::describe_gauge!(CACHE_ENTRIES, "Number of cached backend results");
 }
 
 pub fn job_received() {
@@ -61,3 +67,18 @@ pub fn set_job_processing_time(elapsed: &std::time::Duration) {
 pub fn set_work_processing_time(elapsed: std::time::Duration) {
     metrics::histogram!(WORK_PROC_TIME).record(elapsed.as_secs_f64());
 }
+
+#[cfg_attr(not(feature = "backend"), allow(dead_code))]
+pub fn result_cache_hit() {
+    metrics::counter!(CACHE_HITS).increment(1);
+}
+
+#[cfg_attr(not(feature = "backend"), allow(dead_code))]
+pub fn result_cache_miss() {
+    metrics::counter!(CACHE_MISSES).increment(1);
+}
+
+#[cfg_attr(not(feature = "backend"), allow(dead_code))]
+pub fn set_result_cache_entries(count: usize) {
+    metrics::gauge!(CACHE_ENTRIES).set(count as f64);
+}
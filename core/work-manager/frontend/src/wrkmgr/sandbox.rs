@@ -0,0 +1,535 @@
+//! Backend sandboxing
+//!
+//! Backends parse hostile input, often through C libraries; the backend process
+//! can therefore be (see [`SandboxConfig`]):
+//! - isolated in its own user, mount, IPC and UTS namespaces, with a read-only
+//!   view of the objects store
+//! - restricted from the system calls it has no use for (seccomp)
+//! - denied outbound network access
+//! - subject to memory, CPU time and file size limits
+//!
+//! Note: the backend is not placed in its own network namespace as the frontend
+//! talks to it over TCP; outbound traffic is rejected via seccomp instead (no
+//! connect, no datagram or raw sockets, no addressed or fast open sends and no
+//! io_uring, which would bypass the filters)
+//!
+//! Note: namespaces imply seccomp, as the read-only view of the objects store
+//! could otherwise be remounted read-write from within the namespaces
+//!
+//! Limit violations get the backend killed; the offending request, if known, is
+//! then concluded with a distinct symbol (see [`Violation::symbol()`])
+use crate::config::SandboxConfig;
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The system calls backends have no use for
+const DENIED_SYSCALLS: [i64; 34] = [
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_mount_setattr,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_userfaultfd,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_syslog,
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+];
+
+/// A sandbox limit violation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    Memory,
+    Cpu,
+    FileSize,
+    Syscall,
+}
+
+impl Violation {
+    /// The symbol attached to the object which caused the violation
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Memory => "SANDBOX_MEMORY_LIMIT",
+            Self::Cpu => "SANDBOX_CPU_LIMIT",
+            Self::FileSize => "SANDBOX_FILESIZE_LIMIT",
+            Self::Syscall => "SANDBOX_SYSCALL_LIMIT",
+        }
+    }
+
+    /// The violation which results in the backend being killed by `signal`
+    ///
+    /// Note: CPU time is limited per request, by the [`Monitor`]
+    pub fn from_signal(signal: i32) -> Option<Self> {
+        match signal {
+            libc::SIGXFSZ => Some(Self::FileSize),
+            libc::SIGSYS => Some(Self::Syscall),
+            _ => None,
+        }
+    }
+}
+
+/// Applies the configured sandbox to the backend command
+pub fn configure(
+    command: &mut tokio::process::Command,
+    config: &SandboxConfig,
+    objects_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Note: everything is prepared here as the child must not allocate after fork
+    let namespaces = if config.get_namespaces() {
+        Some(Namespaces::new(objects_path)?)
+    } else {
+        None
+    };
+    let filters = seccomp_filters(config).map_err(|e| {
+        error!("Failed to create the seccomp filters: {e}");
+        e
+    })?;
+    let max_file_size = config.get_max_file_size();
+    let max_memory = config.get_max_memory();
+    if namespaces.is_none() && filters.is_empty() && max_file_size.is_none() && max_memory.is_none()
+    {
+        return Ok(());
+    }
+    // SAFETY: the closure only issues (async-signal-safe) system calls
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(namespaces) = &namespaces {
+                namespaces.enter()?;
+            }
+            if let Some(size) = max_file_size {
+                let limit = libc::rlimit {
+                    rlim_cur: size,
+                    rlim_max: size,
+                };
+                check(libc::setrlimit(libc::RLIMIT_FSIZE, &limit))?;
+            }
+            // Note: the resident memory is checked by the Monitor, this is a
+            // backstop against allocations growing faster than it can notice
+            if let Some(size) = max_memory {
+                let limit = libc::rlimit {
+                    rlim_cur: size,
+                    rlim_max: size,
+                };
+                check(libc::setrlimit(libc::RLIMIT_DATA, &limit))?;
+            }
+            // Note: this must come last as the filters deny unshare and mount
+            for filter in filters.iter() {
+                seccompiler::apply_filter(filter).map_err(|_| std::io::Error::last_os_error())?;
+            }
+            Ok(())
+        });
+    }
+    info!(
+        "Backend sandboxed (namespaces: {}, seccomp: {}, network: {})",
+        config.get_namespaces(),
+        config.get_seccomp(),
+        config.get_network()
+    );
+    Ok(())
+}
+
+/// Creates the seccomp filters
+fn seccomp_filters(config: &SandboxConfig) -> Result<Vec<BpfProgram>, Box<dyn std::error::Error>> {
+    let arch: seccompiler::TargetArch = std::env::consts::ARCH.try_into()?;
+    let mut filters = Vec::new();
+    if config.get_seccomp() {
+        let rules = DENIED_SYSCALLS
+            .iter()
+            .map(|syscall| (*syscall, Vec::new()))
+            .collect();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::KillProcess,
+            arch,
+        )?;
+        filters.push(filter.try_into()?);
+    }
+    if !config.get_network() {
+        // Note: the backend must still be able to accept connections from the frontend
+        let socket_type = |socket_type: i32| {
+            SeccompRule::new(vec![SeccompCondition::new(
+                1,
+                SeccompCmpArgLen::Dword,
+                SeccompCmpOp::MaskedEq(0xf),
+                socket_type as u64,
+            )?])
+        };
+        let flag = |arg: u8, flag: i32| {
+            SeccompRule::new(vec![SeccompCondition::new(
+                arg,
+                SeccompCmpArgLen::Dword,
+                SeccompCmpOp::MaskedEq(flag as u64),
+                flag as u64,
+            )?])
+        };
+        let rules = [
+            (libc::SYS_connect, Vec::new()),
+            (
+                libc::SYS_socket,
+                vec![
+                    socket_type(libc::SOCK_DGRAM)?,
+                    socket_type(libc::SOCK_RAW)?,
+                    socket_type(libc::SOCK_RDM)?,
+                    socket_type(libc::SOCK_SEQPACKET)?,
+                ],
+            ),
+            // Addressed sends, which include TCP fast open
+            (
+                libc::SYS_sendto,
+                vec![SeccompRule::new(vec![SeccompCondition::new(
+                    4,
+                    SeccompCmpArgLen::Qword,
+                    SeccompCmpOp::Ne,
+                    0,
+                )?])?],
+            ),
+            // Note: the address of sendmsg cannot be inspected
+            (libc::SYS_sendmsg, vec![flag(2, libc::MSG_FASTOPEN)?]),
+            (libc::SYS_sendmmsg, vec![flag(3, libc::MSG_FASTOPEN)?]),
+            (libc::SYS_io_uring_setup, Vec::new()),
+            (libc::SYS_io_uring_enter, Vec::new()),
+            (libc::SYS_io_uring_register, Vec::new()),
+        ]
+        .into_iter()
+        .collect();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EACCES as u32),
+            arch,
+        )?;
+        filters.push(filter.try_into()?);
+    }
+    Ok(filters)
+}
+
+/// Converts a system call return value into a [`Result`]
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes `data` to the file at `path` (in the child process)
+fn write_file(path: &CStr, data: &[u8]) -> std::io::Result<()> {
+    // SAFETY: path is NUL terminated and data is valid for its length
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        check(fd)?;
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written != data.len() as isize {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The namespaces the backend is isolated in
+struct Namespaces {
+    /// Whether a user namespace is needed (i.e. not running as root)
+    user: bool,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    objects_path: CString,
+    /// The flags to remount the objects store with
+    remount_flags: libc::c_ulong,
+}
+
+impl Namespaces {
+    fn new(objects_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = CString::new(objects_path)?;
+        // SAFETY: path is NUL terminated and stat is a valid, writable, statvfs
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::statvfs(path.as_ptr(), &mut stat) }).map_err(|e| {
+            error!("Failed to stat the objects store \"{objects_path}\": {e}");
+            e
+        })?;
+        // Note: a remount must retain the locked flags of the original mount
+        let mut remount_flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+        for (st_flag, ms_flag) in [
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+        ] {
+            if stat.f_flag & st_flag != 0 {
+                remount_flags |= ms_flag;
+            }
+        }
+        // SAFETY: always successful
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        Ok(Self {
+            user: uid != 0,
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            objects_path: path,
+            remount_flags,
+        })
+    }
+
+    /// Enters the namespaces (in the child process)
+    fn enter(&self) -> std::io::Result<()> {
+        let mut flags = libc::CLONE_NEWNS | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+        if self.user {
+            flags |= libc::CLONE_NEWUSER;
+        }
+        let path = self.objects_path.as_ptr();
+        // SAFETY: all the pointers are either NULL or NUL terminated strings
+        unsafe {
+            check(libc::unshare(flags))?;
+            if self.user {
+                write_file(c"/proc/self/setgroups", b"deny")?;
+                write_file(c"/proc/self/uid_map", &self.uid_map)?;
+                write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            }
+            // Keep the mount changes private
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                path,
+                path,
+                std::ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                std::ptr::null(),
+                path,
+                std::ptr::null(),
+                self.remount_flags,
+                std::ptr::null(),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// The requests being processed by the backend, along with the CPU time spent on them
+#[derive(Clone, Default)]
+pub struct Requests(Arc<Mutex<HashMap<String, f64>>>);
+
+impl Requests {
+    /// Tracks a request until the returned guard is dropped
+    pub fn track(&self, correlation_id: &str) -> RequestGuard {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(correlation_id.to_string(), 0.0);
+        RequestGuard {
+            requests: self.clone(),
+            correlation_id: correlation_id.to_string(),
+        }
+    }
+
+    /// The request being processed, if it's the only one
+    pub fn sole(&self) -> Option<String> {
+        let requests = self.0.lock().unwrap();
+        if requests.len() == 1 {
+            requests.keys().next().cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if no request is being processed
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// A tracked request (see [`Requests::track()`])
+pub struct RequestGuard {
+    requests: Requests,
+    correlation_id: String,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.requests.0.lock().unwrap().remove(&self.correlation_id);
+    }
+}
+
+/// Enforces the backend memory and CPU time limits
+pub struct Monitor {
+    pid: u32,
+    max_memory: Option<u64>,
+    max_cpu: Option<f64>,
+    requests: Requests,
+    /// The backend CPU time at the last check
+    cpu_time: f64,
+    clock_ticks: f64,
+    page_size: u64,
+}
+
+impl Monitor {
+    /// How often the limits are checked
+    pub const INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    /// Creates a new monitor for the backend process `pid`
+    ///
+    /// Returns [`None`] if neither memory nor CPU time are limited
+    pub fn new(pid: u32, config: &SandboxConfig, requests: Requests) -> Option<Self> {
+        let max_memory = config.get_max_memory();
+        let max_cpu = config.get_max_cpu().map(|v| v.as_secs_f64());
+        if max_memory.is_none() && max_cpu.is_none() {
+            return None;
+        }
+        // SAFETY: always successful
+        let (clock_ticks, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        Some(Self {
+            pid,
+            max_memory,
+            max_cpu,
+            requests,
+            cpu_time: 0.0,
+            clock_ticks: clock_ticks as f64,
+            page_size: page_size as u64,
+        })
+    }
+
+    /// Reads the backend CPU time (in seconds) and resident memory (in bytes)
+    fn usage(&self) -> Option<(f64, u64)> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", self.pid)).ok()?;
+        // Note: the process name may contain spaces and parentheses
+        let fields: Vec<&str> = stat.get(stat.rfind(')')? + 2..)?.split(' ').collect();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        let rss: u64 = fields.get(21)?.parse().ok()?;
+        Some((
+            (utime + stime) as f64 / self.clock_ticks,
+            rss * self.page_size,
+        ))
+    }
+
+    /// Checks the limits
+    ///
+    /// The CPU time is split evenly among the requests being processed
+    ///
+    /// Returns the violation, if any, along with the offending request, if known
+    pub fn check(&mut self) -> Option<(Violation, Option<String>)> {
+        let (cpu_time, rss) = self.usage()?;
+        let elapsed = cpu_time - self.cpu_time;
+        self.cpu_time = cpu_time;
+        if let Some(max_cpu) = self.max_cpu {
+            let mut requests = self.requests.0.lock().unwrap();
+            let share = elapsed / requests.len().max(1) as f64;
+            for (correlation_id, used) in requests.iter_mut() {
+                *used += share;
+                if *used > max_cpu {
+                    return Some((Violation::Cpu, Some(correlation_id.clone())));
+                }
+            }
+        }
+        if self.max_memory.is_some_and(|max| rss > max) {
+            return Some((Violation::Memory, self.requests.sole()));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces_imply_seccomp() {
+        let config: SandboxConfig =
+            serde_json::from_value(serde_json::json!({"namespaces": true, "seccomp": false}))
+                .unwrap();
+        assert!(config.get_seccomp());
+        assert_eq!(seccomp_filters(&config).unwrap().len(), 1);
+        let config: SandboxConfig =
+            serde_json::from_value(serde_json::json!({"network": false})).unwrap();
+        assert!(!config.get_seccomp());
+        assert_eq!(seccomp_filters(&config).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires mount namespaces (root or unprivileged user namespaces)"]
+    async fn test_objects_store_stays_read_only() {
+        use std::os::unix::process::ExitStatusExt;
+        let objects_path = std::env::temp_dir().join(format!("sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(&objects_path).unwrap();
+        let config: SandboxConfig =
+            serde_json::from_value(serde_json::json!({"namespaces": true})).unwrap();
+        let run = |script: &str| {
+            let mut command = tokio::process::Command::new("/bin/sh");
+            command.args(["-c", script, "sh"]).arg(&objects_path);
+            configure(&mut command, &config, objects_path.to_str().unwrap()).unwrap();
+            command.status()
+        };
+        let status = run("echo x > \"$1/file\"").await.unwrap();
+        assert!(!status.success(), "objects store is writable");
+        let status = run("exec mount -o remount,bind,rw \"$1\"").await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGSYS));
+        std::fs::remove_dir_all(&objects_path).ok();
+    }
+
+    #[test]
+    fn test_monitor() {
+        let config: SandboxConfig =
+            serde_json::from_value(serde_json::json!({"max_cpu_secs": 0.0, "max_memory_mb": 1}))
+                .unwrap();
+        let requests = Requests::default();
+        let mut monitor = Monitor::new(std::process::id(), &config, requests.clone()).unwrap();
+        while monitor.usage().unwrap().0 == 0.0 {
+            std::hint::black_box((0..100000u64).sum::<u64>());
+        }
+        let request = requests.track("a");
+        assert_eq!(
+            monitor.check(),
+            Some((Violation::Cpu, Some("a".to_string())))
+        );
+        drop(request);
+        assert!(requests.sole().is_none());
+        assert_eq!(monitor.check(), Some((Violation::Memory, None)));
+    }
+}
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS work_status (
+    work_id text NOT NULL PRIMARY KEY,
+    state text NOT NULL DEFAULT 'queued',
+    objects_queued bigint NOT NULL DEFAULT 1,
+    objects_done bigint NOT NULL DEFAULT 0,
+    t_queued timestamptz NOT NULL DEFAULT current_timestamp,
+    t_updated timestamptz NOT NULL DEFAULT current_timestamp,
+    expires_at timestamptz NULL,
+    CONSTRAINT valid_state CHECK (state IN ('queued', 'in_progress', 'completed'))
+);
+CREATE INDEX IF NOT EXISTS ws_t_queued_idx ON work_status USING btree (t_queued);
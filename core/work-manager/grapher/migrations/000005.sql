@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS org_usage (
+    org text NOT NULL,
+    day date NOT NULL,
+    objects bigint NOT NULL DEFAULT 0,
+    bytes bigint NOT NULL DEFAULT 0,
+    PRIMARY KEY (org, day)
+);
+CREATE INDEX IF NOT EXISTS ou_day_idx ON org_usage USING btree (day);
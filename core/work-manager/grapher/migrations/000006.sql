@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS legal_holds (
+    work_id text NOT NULL PRIMARY KEY,
+    t timestamptz NOT NULL DEFAULT current_timestamp,
+    reason text NULL
+);
+
+-- Object files of purged works which could not be removed yet, as they may
+-- still be in use by a work in flight
+CREATE TABLE IF NOT EXISTS deferred_object_removals (
+    object_id text NOT NULL PRIMARY KEY,
+    t timestamptz NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1 @@
+ALTER TABLE results ADD COLUMN IF NOT EXISTS verdict text NULL;
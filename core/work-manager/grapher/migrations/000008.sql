@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS scenario_versions (
+    name text NOT NULL,
+    version integer NOT NULL,
+    t timestamptz NOT NULL DEFAULT current_timestamp,
+    author text NOT NULL,
+    op text NOT NULL,
+    comment text NULL,
+    def jsonb NULL,
+    diff jsonb NOT NULL,
+    PRIMARY KEY (name, version)
+);
+INSERT INTO scenario_versions (name, version, t, author, op, comment, def, diff)
+  SELECT
+    name,
+    1,
+    t,
+    COALESCE(def->>'creator', ''),
+    'create',
+    'Imported',
+    def,
+    (SELECT jsonb_object_agg(key, jsonb_build_object('old', NULL, 'new', value)) FROM jsonb_each(def))
+  FROM scenarios
+  ON CONFLICT DO NOTHING;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS scenario_stats (
+    scenario_id bigint NOT NULL REFERENCES scenarios(id) ON DELETE CASCADE,
+    hour timestamptz NOT NULL,
+    evaluations bigint NOT NULL DEFAULT 0,
+    matches bigint NOT NULL DEFAULT 0,
+    local_time double precision NOT NULL DEFAULT 0,
+    global_evaluations bigint NOT NULL DEFAULT 0,
+    global_time double precision NOT NULL DEFAULT 0,
+    last_hit timestamptz NULL,
+    PRIMARY KEY (scenario_id, hour)
+);
+CREATE INDEX IF NOT EXISTS ss_hour_idx ON scenario_stats USING btree (hour);
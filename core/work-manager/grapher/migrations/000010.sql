@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS reprocess_jobs (
+    id bigserial NOT NULL PRIMARY KEY,
+    t timestamptz NOT NULL DEFAULT current_timestamp,
+    author text NOT NULL,
+    object_type text NOT NULL,
+    min_version text NOT NULL,
+    org text NULL,
+    t_start timestamptz NOT NULL,
+    t_end timestamptz NOT NULL,
+    state text NOT NULL DEFAULT 'running',
+    cursor_t timestamptz NULL,
+    cursor_work_id text NULL,
+    scanned bigint NOT NULL DEFAULT 0,
+    enqueued bigint NOT NULL DEFAULT 0,
+    failed bigint NOT NULL DEFAULT 0,
+    t_updated timestamptz NOT NULL DEFAULT current_timestamp,
+    leased_until timestamptz NULL,
+    CONSTRAINT valid_state CHECK (state IN ('running', 'paused', 'cancelled', 'completed'))
+);
+
+CREATE TABLE IF NOT EXISTS reprocessed_works (
+    job_id bigint NOT NULL REFERENCES reprocess_jobs(id) ON DELETE CASCADE,
+    work_id text NOT NULL,
+    new_work_id text NOT NULL,
+    t timestamptz NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (job_id, work_id)
+);
+CREATE INDEX IF NOT EXISTS rw_new_work_id_idx ON reprocessed_works USING hash (new_work_id);
@@ -0,0 +1,154 @@
+-- Range partitioning of the objects table on t
+--
+-- Note: rels can no longer reference objects via foreign keys (the unique keys
+-- of a partitioned table must include the partition key); relationships are
+-- removed explicitly along with their objects
+
+CREATE TABLE IF NOT EXISTS object_partitions (
+    name text NOT NULL PRIMARY KEY,
+    t_start timestamptz NOT NULL,
+    t_end timestamptz NOT NULL,
+    detached_at timestamptz NULL
+);
+CREATE INDEX IF NOT EXISTS op_t_start_idx ON object_partitions USING btree (t_start);
+
+-- Creates the partitions needed to cover the [t_from, t_to) range
+--
+-- New partitions span one step ('day' or 'week', in UTC) and are trimmed to
+-- fit between existing partitions; the objects in the default partition which
+-- fall in a new partition are moved there; returns the number of partitions
+-- created
+CREATE OR REPLACE FUNCTION public.create_object_partitions(t_from timestamptz, t_to timestamptz, step text DEFAULT 'day')
+ RETURNS integer
+ LANGUAGE plpgsql
+AS $function$
+DECLARE
+  p_start timestamptz;
+  p_end timestamptz;
+  p_name text;
+  bound timestamptz;
+  created integer := 0;
+BEGIN
+  IF step NOT IN ('day', 'week') THEN
+    RAISE EXCEPTION 'Invalid partition step "%"', step;
+  END IF;
+  p_start := date_trunc(step, t_from, 'UTC');
+  WHILE p_start < t_to LOOP
+    SELECT max(t_end) INTO bound FROM object_partitions WHERE t_start <= p_start AND t_end > p_start;
+    IF bound IS NOT NULL THEN
+      p_start := bound;
+      CONTINUE;
+    END IF;
+    p_end := ((date_trunc(step, p_start, 'UTC') AT TIME ZONE 'UTC') + ('1 ' || step)::interval) AT TIME ZONE 'UTC';
+    SELECT min(t_start) INTO bound FROM object_partitions WHERE t_start > p_start AND t_start < p_end;
+    IF bound IS NOT NULL THEN
+      p_end := bound;
+    END IF;
+    p_name := 'objects_p' || to_char(p_start AT TIME ZONE 'UTC', 'YYYYMMDD');
+    IF EXISTS (SELECT 1 FROM objects_default WHERE t >= p_start AND t < p_end) THEN
+      -- A range partition cannot be created over rows in the default partition
+      ALTER TABLE objects DETACH PARTITION objects_default;
+      EXECUTE format('CREATE TABLE %I PARTITION OF objects FOR VALUES FROM (%L) TO (%L)', p_name, p_start, p_end);
+      INSERT INTO objects SELECT * FROM objects_default WHERE t >= p_start AND t < p_end;
+      DELETE FROM objects_default WHERE t >= p_start AND t < p_end;
+      ALTER TABLE objects ATTACH PARTITION objects_default DEFAULT;
+    ELSE
+      EXECUTE format('CREATE TABLE %I PARTITION OF objects FOR VALUES FROM (%L) TO (%L)', p_name, p_start, p_end);
+    END IF;
+    EXECUTE format('CREATE INDEX ON %I USING btree (id)', p_name);
+    INSERT INTO object_partitions (name, t_start, t_end) VALUES (p_name, p_start, p_end);
+    created := created + 1;
+    p_start := p_end;
+  END LOOP;
+  RETURN created;
+END;
+$function$;
+
+-- Detaches the partitions whose range ends before older_than
+--
+-- The rels of the detached objects are moved out of the rels table into a
+-- table named after the partition with a "_rels" suffix. The detached tables
+-- are retained and are no longer visible to queries: they can be archived or
+-- dropped (along with their rels table); re-attaching a partition requires
+-- moving its rels back into the rels table. Returns the names of the detached
+-- partitions
+CREATE OR REPLACE FUNCTION public.detach_object_partitions(older_than timestamptz)
+ RETURNS SETOF text
+ LANGUAGE plpgsql
+AS $function$
+DECLARE
+  p record;
+BEGIN
+  FOR p IN SELECT name FROM object_partitions WHERE detached_at IS NULL AND t_end <= older_than ORDER BY t_start LOOP
+    EXECUTE format('ALTER TABLE objects DETACH PARTITION %I', p.name);
+    -- Note: all the objects of a work (hence both ends of its rels) share the same t
+    EXECUTE format('CREATE TABLE %I AS SELECT r.* FROM rels AS r WHERE r.child IN (SELECT id FROM %I)', p.name || '_rels', p.name);
+    EXECUTE format('DELETE FROM rels WHERE child IN (SELECT id FROM %I)', p.name);
+    UPDATE object_partitions SET detached_at = current_timestamp WHERE name = p.name;
+    RETURN NEXT p.name;
+  END LOOP;
+END;
+$function$;
+
+-- The existing objects table becomes the first partition (objects_legacy)
+--
+-- Note: the rows are neither copied nor re-indexed; attaching the table only
+-- takes a sequential scan to validate its range, which ends right after the
+-- latest object. The legacy partition is detached like any other partition
+-- once it is old enough
+ALTER TABLE rels DROP CONSTRAINT IF EXISTS rels_parent_fkey;
+ALTER TABLE rels DROP CONSTRAINT IF EXISTS rels_child_fkey;
+ALTER TABLE objects RENAME TO objects_legacy;
+ALTER TABLE objects_legacy RENAME CONSTRAINT objects_pkey TO objects_legacy_pkey;
+ALTER INDEX IF EXISTS o_work_id_idx RENAME TO objects_legacy_work_id_idx;
+ALTER INDEX IF EXISTS o_object_id_idx RENAME TO objects_legacy_object_id_idx;
+ALTER INDEX IF EXISTS o_object_type_idx RENAME TO objects_legacy_object_type_idx;
+ALTER INDEX IF EXISTS o_t_idx RENAME TO objects_legacy_t_idx;
+ALTER INDEX IF EXISTS o_result_idx RENAME TO objects_legacy_result_idx;
+
+-- Note: the primary key of a partitioned table must include the partition key,
+-- so ids (which come from a sequence) are only indexed within each partition
+CREATE TABLE objects (
+    id bigint NOT NULL DEFAULT nextval('objects_id_seq'),
+    org text NOT NULL,
+    work_id text NOT NULL,
+    is_entry boolean NOT NULL,
+    object_id text NOT NULL,
+    object_type text NOT NULL,
+    object_subtype text NULL,
+    recursion_level integer NOT NULL,
+    size bigint NOT NULL,
+    hashes jsonb NOT NULL,
+    t timestamp with time zone NOT NULL,
+    result jsonb NOT NULL,
+    entropy double precision NULL
+) PARTITION BY RANGE (t);
+ALTER SEQUENCE objects_id_seq OWNED BY objects.id;
+
+-- Note: the matching indexes of the legacy table are reused on attach
+CREATE INDEX o_work_id_idx ON objects USING hash (work_id);
+CREATE INDEX o_object_id_idx ON objects USING hash (object_id);
+CREATE INDEX o_object_type_idx ON objects USING hash (object_type);
+CREATE INDEX o_t_idx ON objects USING btree (t);
+CREATE INDEX o_result_idx ON objects USING GIN (result);
+
+DO $migration$
+DECLARE
+  cutoff timestamptz;
+BEGIN
+  SELECT max(t) + interval '1 microsecond' INTO cutoff FROM objects_legacy;
+  IF cutoff IS NULL THEN
+    DROP TABLE objects_legacy;
+  ELSE
+    EXECUTE format('ALTER TABLE objects ATTACH PARTITION objects_legacy FOR VALUES FROM (MINVALUE) TO (%L)', cutoff);
+    INSERT INTO object_partitions (name, t_start, t_end) VALUES ('objects_legacy', '-infinity', cutoff);
+  END IF;
+END;
+$migration$;
+
+-- Objects outside of all the partition ranges (e.g. due to clock skew, or to
+-- the partition maintenance lagging behind) land in the default partition
+CREATE TABLE objects_default PARTITION OF objects DEFAULT;
+CREATE INDEX ON objects_default USING btree (id);
+
+SELECT create_object_partitions(current_timestamp, current_timestamp + interval '7 days');
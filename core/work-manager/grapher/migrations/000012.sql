@@ -0,0 +1,162 @@
+-- Fuzzy hash comparison (ssdeep and TLSH) for the ContexQL @similar_hash function
+--
+-- Note: these must produce the same results as shared::fuzzy
+
+-- Returns the block size of an ssdeep hash (NULL if invalid)
+CREATE OR REPLACE FUNCTION public.ssdeep_blocksize(h text)
+ RETURNS bigint
+ LANGUAGE sql
+ IMMUTABLE PARALLEL SAFE
+AS $function$
+  SELECT CASE WHEN h ~ '^[0-9]{1,10}:[^:]*:' THEN split_part(h, ':', 1)::bigint END
+$function$;
+
+-- Scores two ssdeep block hashes (with sequences already eliminated)
+CREATE OR REPLACE FUNCTION public.ssdeep_score_strings(s1 text, s2 text, block_size bigint)
+ RETURNS integer
+ LANGUAGE plpgsql
+ IMMUTABLE PARALLEL SAFE
+AS $function$
+DECLARE
+  l1 integer := length(s1);
+  l2 integer := length(s2);
+  prev integer[];
+  cur integer[];
+  common boolean := false;
+  score integer;
+BEGIN
+  IF l1 > 64 OR l2 > 64 THEN
+    RETURN 0;
+  END IF;
+  FOR i IN 1..l1 - 6 LOOP
+    IF position(substr(s1, i, 7) IN s2) > 0 THEN
+      common := true;
+      EXIT;
+    END IF;
+  END LOOP;
+  IF NOT common THEN
+    RETURN 0;
+  END IF;
+  -- Weighted edit distance: insertions and removals cost 1, replacements cost 2
+  prev := array(SELECT generate_series(0, l2));
+  FOR i IN 1..l1 LOOP
+    cur := ARRAY[i];
+    FOR j IN 1..l2 LOOP
+      cur[j + 1] := least(
+        prev[j] + CASE WHEN substr(s1, i, 1) = substr(s2, j, 1) THEN 0 ELSE 2 END,
+        prev[j + 1] + 1,
+        cur[j] + 1
+      );
+    END LOOP;
+    prev := cur;
+  END LOOP;
+  score := prev[l2 + 1] * 64 / (l1 + l2);
+  score := 100 * score / 64;
+  IF score >= 100 THEN
+    RETURN 0;
+  END IF;
+  score := 100 - score;
+  IF block_size >= 45 THEN
+    RETURN score;
+  END IF;
+  RETURN least(score, block_size / 3 * least(l1, l2));
+END;
+$function$;
+
+-- Compares two ssdeep hashes: returns a score from 0 (no similarity) to 100
+-- (identical), or NULL if any hash is invalid
+CREATE OR REPLACE FUNCTION public.ssdeep_compare(h1 text, h2 text)
+ RETURNS integer
+ LANGUAGE plpgsql
+ IMMUTABLE PARALLEL SAFE
+AS $function$
+DECLARE
+  bs1 bigint := ssdeep_blocksize(h1);
+  bs2 bigint := ssdeep_blocksize(h2);
+  h1b1 text;
+  h1b2 text;
+  h2b1 text;
+  h2b2 text;
+BEGIN
+  IF bs1 IS NULL OR bs2 IS NULL THEN
+    RETURN NULL;
+  END IF;
+  IF bs1 <> bs2 AND bs1 * 2 <> bs2 AND bs2 * 2 <> bs1 THEN
+    RETURN 0;
+  END IF;
+  h1b1 := regexp_replace(split_part(h1, ':', 2), '(.)\1{3,}', '\1\1\1', 'g');
+  h1b2 := regexp_replace(split_part(split_part(h1, ':', 3), ',', 1), '(.)\1{3,}', '\1\1\1', 'g');
+  h2b1 := regexp_replace(split_part(h2, ':', 2), '(.)\1{3,}', '\1\1\1', 'g');
+  h2b2 := regexp_replace(split_part(split_part(h2, ':', 3), ',', 1), '(.)\1{3,}', '\1\1\1', 'g');
+  IF bs1 = bs2 AND h1b1 = h2b1 AND h1b2 = h2b2 THEN
+    RETURN 100;
+  END IF;
+  IF bs1 = bs2 THEN
+    RETURN greatest(ssdeep_score_strings(h1b1, h2b1, bs1), ssdeep_score_strings(h1b2, h2b2, bs1 * 2));
+  ELSIF bs1 * 2 = bs2 THEN
+    RETURN ssdeep_score_strings(h1b2, h2b1, bs2);
+  ELSE
+    RETURN ssdeep_score_strings(h1b1, h2b2, bs1);
+  END IF;
+END;
+$function$;
+
+-- Returns the (log) length value of a TLSH hash (NULL if invalid)
+CREATE OR REPLACE FUNCTION public.tlsh_lvalue(h text)
+ RETURNS integer
+ LANGUAGE sql
+ IMMUTABLE PARALLEL SAFE
+AS $function$
+  SELECT CASE WHEN h ~ '^T1[0-9A-Fa-f]{70}$' THEN ('x' || substr(h, 6, 1) || substr(h, 5, 1))::bit(8)::integer END
+$function$;
+
+-- Computes the distance between two TLSH hashes: returns 0 for identical
+-- hashes and grows with dissimilarity, or NULL if any hash is invalid
+CREATE OR REPLACE FUNCTION public.tlsh_distance(h1 text, h2 text)
+ RETURNS integer
+ LANGUAGE plpgsql
+ IMMUTABLE PARALLEL SAFE
+AS $function$
+DECLARE
+  b1 bytea;
+  b2 bytea;
+  x integer;
+  y integer;
+  d integer;
+  diff integer;
+BEGIN
+  IF h1 !~ '^T1[0-9A-Fa-f]{70}$' OR h2 !~ '^T1[0-9A-Fa-f]{70}$' THEN
+    RETURN NULL;
+  END IF;
+  b1 := decode(substr(h1, 3), 'hex');
+  b2 := decode(substr(h2, 3), 'hex');
+  d := abs(tlsh_lvalue(h1) - tlsh_lvalue(h2));
+  d := least(d, 256 - d);
+  diff := CASE WHEN d <= 1 THEN d ELSE d * 12 END;
+  d := abs((get_byte(b1, 2) >> 4) - (get_byte(b2, 2) >> 4));
+  d := least(d, 16 - d);
+  diff := diff + CASE WHEN d <= 1 THEN d ELSE (d - 1) * 12 END;
+  d := abs((get_byte(b1, 2) & 15) - (get_byte(b2, 2) & 15));
+  d := least(d, 16 - d);
+  diff := diff + CASE WHEN d <= 1 THEN d ELSE (d - 1) * 12 END;
+  IF get_byte(b1, 0) <> get_byte(b2, 0) THEN
+    diff := diff + 1;
+  END IF;
+  FOR i IN 3..34 LOOP
+    x := get_byte(b1, i);
+    y := get_byte(b2, i);
+    IF x <> y THEN
+      FOR j IN 0..3 LOOP
+        d := abs(((x >> (j * 2)) & 3) - ((y >> (j * 2)) & 3));
+        diff := diff + CASE WHEN d = 3 THEN 6 ELSE d END;
+      END LOOP;
+    END IF;
+  END LOOP;
+  RETURN diff;
+END;
+$function$;
+
+-- Pre-filters used by @similar_hash (only hashes with a compatible block size
+-- or a close enough length can be similar)
+CREATE INDEX IF NOT EXISTS o_ssdeep_blocksize_idx ON objects USING btree (ssdeep_blocksize(hashes->>'ssdeep'));
+CREATE INDEX IF NOT EXISTS o_tlsh_lvalue_idx ON objects USING btree (tlsh_lvalue(hashes->>'tlsh'));
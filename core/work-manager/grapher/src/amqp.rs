@@ -1,10 +1,10 @@
 //! Message broker AMQP communication
 //!
-//! This module handles the reception of work results
+//! This module handles the reception of work results and work progress updates
 use amqprs::{
     channel::{
-        BasicAckArguments, BasicConsumeArguments, BasicPublishArguments, BasicQosArguments,
-        BasicRejectArguments, Channel, QueueDeclareArguments,
+        BasicAckArguments, BasicConsumeArguments, BasicRejectArguments, Channel,
+        QueueDeclareArguments,
     },
     connection::Connection,
     consumer::AsyncConsumer,
@@ -25,6 +25,7 @@ pub struct Broker {
     connection: Connection,
     channel: Channel,
     ctag: String,
+    status_ctag: String,
 }
 
 impl Broker {
@@ -32,6 +33,7 @@ impl Broker {
     pub async fn new(
         broker_cfg: &BrokerConfig,
         graphdb: crate::graphdb::GraphDB,
+        status_tracker: crate::status::StatusTracker,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Describe metrics
         metrics::describe_counter!(WORK_COUNT, "Total number of work requests processed");
@@ -114,14 +116,38 @@ impl Broker {
                 shared::RESULTS_QUEUE_NAME,
                 ctag
             );
-            Ok(ctag)
+
+            // Create or join the work status queue and subscribe to it
+            shared::amqp::declare_status_queue(&channel).await?;
+            let args = BasicConsumeArguments::default()
+                .queue(shared::STATUS_QUEUE_NAME.to_owned())
+                .auto_ack(false)
+                .finish();
+            let status_ctag = channel
+                .basic_consume(status_tracker, args)
+                .await
+                .map_err(|e| {
+                    error!(
+                        "Failed to subscribe to the work status queue \"{}\": {}",
+                        shared::STATUS_QUEUE_NAME,
+                        e
+                    );
+                    e
+                })?;
+            debug!(
+                "Subscribed to the work status queue \"{}\" with ctag {}",
+                shared::STATUS_QUEUE_NAME,
+                status_ctag
+            );
+            Ok((ctag, status_ctag))
         }
         .await
         {
-            Ok(ctag) => Ok(Self {
+            Ok((ctag, status_ctag)) => Ok(Self {
                 connection,
                 channel,
                 ctag,
+                status_ctag,
             }),
             Err(e) => {
                 shared::amqp::close_channel(channel).await;
@@ -134,6 +160,7 @@ impl Broker {
     /// Cleanly unsubscribes, closes the channel and disconnects
     pub async fn close(self) {
         shared::amqp::unsubscribe(&self.channel, &self.ctag).await;
+        shared::amqp::unsubscribe(&self.channel, &self.status_ctag).await;
         shared::amqp::close_channel(self.channel).await;
         shared::amqp::close_connection(self.connection).await;
     }
@@ -224,6 +251,7 @@ impl AsyncConsumer for crate::graphdb::GraphDB {
                     let mut relation_metadata = work.relation_metadata;
                     let mut max_recursion: Option<u32> = None;
                     let mut ttl: Option<std::time::Duration> = None;
+                    let mut priority = shared::object::Priority::default();
                     if let Some(serde_json::Value::Object(map)) =
                         relation_metadata.get(shared::META_KEY_ORIGIN)
                     {
@@ -239,6 +267,9 @@ impl AsyncConsumer for crate::graphdb::GraphDB {
                                 ttl = Some(std::time::Duration::from_secs(v));
                             }
                         }
+                        if let Some(v) = map.get("priority") {
+                            priority = serde_json::from_value(v.clone()).unwrap_or_default();
+                        }
                     }
                     relation_metadata
                         .insert(shared::META_KEY_REPROCESSABLE.to_string(), false.into());
@@ -261,6 +292,8 @@ impl AsyncConsumer for crate::graphdb::GraphDB {
                         max_recursion: max_recursion
                             .unwrap_or(shared::MAX_WORK_DEPTH)
                             .min(shared::MAX_WORK_DEPTH),
+                        work_id: Some(work_id.to_string()),
+                        priority,
                     };
                     info!("Reprocessing work \"{}\" for decryption", work_id);
                     let resubmit_ok = publish_job_request(
@@ -330,7 +363,7 @@ impl AsyncConsumer for crate::graphdb::GraphDB {
 /// Rejects a work result message which will be re-published later
 ///
 /// Note: an [`Error`](std::error::Error) result indicates a fatal condition
-async fn reject_tag(
+pub async fn reject_tag(
     channel: &Channel,
     delivery_tag: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -351,7 +384,10 @@ async fn reject_tag(
 /// Acknowledges a work result message, removing it from the broker
 ///
 /// Note: a [`Error`](std::error::Error) result indicates a fatal condition
-async fn ack_tag(channel: &Channel, delivery_tag: u64) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn ack_tag(
+    channel: &Channel,
+    delivery_tag: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
     debug!("Sending ack for delivery_tag={}", delivery_tag);
     channel
         .basic_ack(BasicAckArguments::new(delivery_tag, false))
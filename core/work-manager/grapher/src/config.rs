@@ -15,6 +15,9 @@ pub struct Config {
     pub broker: BrokerConfig,
     /// GraphDB configuration
     pub write_db: DBConfig,
+    /// Objects table partitioning
+    #[serde(default)]
+    pub partitions: PartitionsConfig,
 }
 
 impl Config {
@@ -30,3 +33,47 @@ impl Config {
             })
     }
 }
+
+#[derive(Deserialize, Debug, Default, Clone)]
+/// Objects table partitioning configuration
+pub struct PartitionsConfig {
+    interval: Option<PartitionInterval>,
+    lookahead_days: Option<u32>,
+    detach_after_days: Option<u32>,
+    maintenance_interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// The time span of each objects partition
+pub enum PartitionInterval {
+    Day,
+    Week,
+}
+
+impl PartitionsConfig {
+    /// The span of new partitions as a PostgreSQL `date_trunc` field (default `day`)
+    pub fn get_interval(&self) -> &'static str {
+        match self.interval.unwrap_or(PartitionInterval::Day) {
+            PartitionInterval::Day => "day",
+            PartitionInterval::Week => "week",
+        }
+    }
+
+    /// How far ahead partitions are created (default 7 days)
+    pub fn get_lookahead_days(&self) -> u32 {
+        self.lookahead_days.unwrap_or(7).max(1)
+    }
+
+    /// Age after which partitions are detached (unset or 0 means never)
+    ///
+    /// Note: detached partitions (and their rels) are retained until dropped
+    pub fn get_detach_after_days(&self) -> Option<u32> {
+        self.detach_after_days.filter(|d| *d > 0)
+    }
+
+    /// Interval between maintenance runs (default 1 hour)
+    pub fn get_maintenance_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.maintenance_interval_secs.unwrap_or(3600).max(1))
+    }
+}
@@ -14,6 +14,7 @@ pub struct GraphDB {
     failure_notifier: std::sync::Arc<tokio::sync::Notify>,
     node_stmt: tokio_postgres::Statement,
     rel_stmt: tokio_postgres::Statement,
+    status_stmt: tokio_postgres::Statement,
 }
 
 impl GraphDB {
@@ -27,29 +28,7 @@ impl GraphDB {
             metrics::Unit::Seconds,
             "Time to fully process a work request and generate the result graph"
         );
-        let mut pgcfg = tokio_postgres::Config::new();
-        pgcfg
-            .application_name("grapher")
-            .dbname(&config.dbname)
-            .host(&config.host)
-            .port(config.port)
-            .user(&config.user)
-            .password(&config.pass)
-            .target_session_attrs(tokio_postgres::config::TargetSessionAttrs::ReadWrite);
-        let (mut client, conn) = pgcfg.connect(tokio_postgres::NoTls).await.map_err(|e| {
-            error!(
-                "Failed to connect to graph database {} at {}:{}: {}",
-                config.dbname, config.host, config.port, e
-            );
-            e
-        })?;
-        let failure_notifier_moved = failure_notifier.clone();
-        tokio::spawn(async move {
-            if let Err(e) = conn.await {
-                error!("Database connection error: {}", e);
-                failure_notifier_moved.notify_one();
-            }
-        });
+        let mut client = connect(config, "grapher", failure_notifier.clone()).await?;
 
         if let Err(e) = apply_migrations("migrations", &mut client).await {
             error!("Database migration failed");
@@ -83,6 +62,19 @@ impl GraphDB {
                 e
             })?;
 
+        let status_stmt = client
+            .prepare(
+                "INSERT INTO work_status (work_id, state) VALUES ($1, 'completed')
+                 ON CONFLICT (work_id) DO UPDATE SET
+                   state = 'completed',
+                   t_updated = current_timestamp",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare work status update statement: {}", e);
+                e
+            })?;
+
         debug!(
             "Connected to GraphDB {} at {}:{}",
             config.dbname, config.host, config.port
@@ -92,6 +84,7 @@ impl GraphDB {
             failure_notifier,
             node_stmt,
             rel_stmt,
+            status_stmt,
         })
     }
 
@@ -224,14 +217,24 @@ impl GraphDB {
             error!("Failed to start transaction: {}", e);
             e
         })?;
-        if let Err(e) = Box::pin(save_graph(
-            &txn,
-            result,
-            None,
-            &work_id,
-            &self.node_stmt,
-            &self.rel_stmt,
-        ))
+        if let Err(e) = async {
+            Box::pin(save_graph(
+                &txn,
+                result,
+                None,
+                &work_id,
+                &self.node_stmt,
+                &self.rel_stmt,
+            ))
+            .await?;
+            txn.execute(&self.status_stmt, &[&work_id])
+                .await
+                .map_err(|e| {
+                    error!("Failed to update work status: {}", e);
+                    e
+                })?;
+            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        }
         .await
         {
             error!("Failed to save work \"{}\": {}", work_id, e);
@@ -261,6 +264,40 @@ impl GraphDB {
     }
 }
 
+/// Connects to the graph database
+///
+/// Connection errors are reported via the provided notifier
+pub async fn connect(
+    config: &shared::config::DBConfig,
+    application_name: &str,
+    failure_notifier: std::sync::Arc<tokio::sync::Notify>,
+) -> Result<tokio_postgres::Client, Box<dyn std::error::Error>> {
+    let mut pgcfg = tokio_postgres::Config::new();
+    pgcfg
+        .application_name(application_name)
+        .dbname(&config.dbname)
+        .host(&config.host)
+        .port(config.port)
+        .user(&config.user)
+        .password(&config.pass)
+        .target_session_attrs(tokio_postgres::config::TargetSessionAttrs::ReadWrite);
+    let (client, conn) = pgcfg.connect(tokio_postgres::NoTls).await.map_err(|e| {
+        error!(
+            "Failed to connect to graph database {} at {}:{}: {}",
+            config.dbname, config.host, config.port, e
+        );
+        e
+    })?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            error!("Database connection error: {}", e);
+            failure_notifier.notify_one();
+        }
+    });
+
+    Ok(client)
+}
+
 const NUL_REPLACEMENT: &str = "\u{f2b3}";
 fn replace_nul(json: &mut serde_json::Value) {
     match json {
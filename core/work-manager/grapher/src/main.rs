@@ -1,6 +1,8 @@
 mod amqp;
 mod config;
 mod graphdb;
+mod partitions;
+mod status;
 
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::sync::Arc;
@@ -53,7 +55,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let graphdb = graphdb::GraphDB::new(&config.write_db, failure_notice.clone()).await?;
-    let broker = amqp::Broker::new(&config.broker, graphdb).await?;
+    let partitions = partitions::PartitionManager::new(
+        &config.write_db,
+        config.partitions,
+        failure_notice.clone(),
+    )
+    .await?;
+    tokio::spawn(partitions.run());
+    let status_tracker =
+        status::StatusTracker::new(&config.write_db, failure_notice.clone()).await?;
+    let broker = amqp::Broker::new(&config.broker, graphdb, status_tracker).await?;
 
     info!("Grapher started");
     let ret = tokio::select!(
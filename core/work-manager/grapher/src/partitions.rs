@@ -0,0 +1,97 @@
+//! Objects table partition maintenance
+//!
+//! The objects table is range partitioned on the work creation time. This
+//! module creates the partitions ahead of time and detaches the expired ones
+//!
+//! Detached partitions are retained as standalone tables, with their rels moved
+//! out to a `<partition>_rels` table; dropping both reclaims the space (see the
+//! `detach_object_partitions` function in the migrations)
+
+use crate::config::PartitionsConfig;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const PARTITIONS_CREATED: &str = "grapher_object_partitions_created_total";
+const PARTITIONS_DETACHED: &str = "grapher_object_partitions_detached_total";
+
+/// The partition manager
+pub struct PartitionManager {
+    client: tokio_postgres::Client,
+    config: PartitionsConfig,
+}
+
+impl PartitionManager {
+    /// Creates a new partition manager with its own connection to the graph database
+    ///
+    /// The partitions needed in the near future are created immediately
+    pub async fn new(
+        db_config: &shared::config::DBConfig,
+        config: PartitionsConfig,
+        failure_notifier: std::sync::Arc<tokio::sync::Notify>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        metrics::describe_counter!(PARTITIONS_CREATED, "Number of objects partitions created");
+        metrics::describe_counter!(PARTITIONS_DETACHED, "Number of objects partitions detached");
+        let client =
+            crate::graphdb::connect(db_config, "grapher_partitions", failure_notifier).await?;
+        let manager = Self { client, config };
+        manager.maintain().await.map_err(|e| {
+            error!("Failed to maintain the objects partitions: {e}");
+            e
+        })?;
+        Ok(manager)
+    }
+
+    /// Creates the upcoming partitions and detaches the expired ones
+    async fn maintain(&self) -> Result<(), tokio_postgres::Error> {
+        let created: i32 = self
+            .client
+            .query_one(
+                "SELECT create_object_partitions(
+                   current_timestamp,
+                   current_timestamp + make_interval(days => $1),
+                   $2
+                 )",
+                &[
+                    &(self.config.get_lookahead_days() as i32),
+                    &self.config.get_interval(),
+                ],
+            )
+            .await?
+            .try_get(0)?;
+        if created > 0 {
+            info!("Created {created} objects partitions");
+            metrics::counter!(PARTITIONS_CREATED).increment(created as u64);
+        }
+        if let Some(days) = self.config.get_detach_after_days() {
+            let detached = self
+                .client
+                .query(
+                    "SELECT detach_object_partitions(current_timestamp - make_interval(days => $1))",
+                    &[&(days as i32)],
+                )
+                .await?;
+            for row in detached.iter() {
+                let name: &str = row.try_get(0)?;
+                info!("Detached objects partition {name}");
+            }
+            metrics::counter!(PARTITIONS_DETACHED).increment(detached.len() as u64);
+        }
+        Ok(())
+    }
+
+    /// Periodically maintains the partitions
+    ///
+    /// Note: this function never returns
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.config.get_maintenance_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // Note: the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.maintain().await {
+                error!("Failed to maintain the objects partitions: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Work status tracking
+//!
+//! This module receives work progress updates and accounts them in GraphDB
+
+use amqprs::{channel::Channel, consumer::AsyncConsumer, BasicProperties, Deliver};
+use shared::amqp::WorkProgress;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const PROGRESS_COUNT: &str = "grapher_progress_updates_total";
+
+/// The work status tracker
+pub struct StatusTracker {
+    client: tokio_postgres::Client,
+    failure_notifier: std::sync::Arc<tokio::sync::Notify>,
+    progress_stmt: tokio_postgres::Statement,
+}
+
+impl StatusTracker {
+    /// Creates a new status tracker with its own connection to the graph database
+    pub async fn new(
+        config: &shared::config::DBConfig,
+        failure_notifier: std::sync::Arc<tokio::sync::Notify>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        metrics::describe_counter!(PROGRESS_COUNT, "Total number of work progress updates");
+        let client =
+            crate::graphdb::connect(config, "grapher_status", failure_notifier.clone()).await?;
+        // Note: updates may be received before the endpoint records the work
+        // in which case a new entry is created with the root object accounted
+        let progress_stmt = client
+            .prepare(
+                "INSERT INTO work_status (work_id, state, objects_queued, objects_done)
+                 VALUES ($1, 'in_progress', 1 + $2, $3)
+                 ON CONFLICT (work_id) DO UPDATE SET
+                   state = CASE work_status.state
+                     WHEN 'queued' THEN 'in_progress'
+                     ELSE work_status.state
+                   END,
+                   objects_queued = work_status.objects_queued + $2,
+                   objects_done = work_status.objects_done + $3,
+                   t_updated = current_timestamp",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to prepare work progress statement: {}", e);
+                e
+            })?;
+        Ok(Self {
+            client,
+            failure_notifier,
+            progress_stmt,
+        })
+    }
+
+    /// Accounts a work progress update
+    async fn update(&self, progress: &WorkProgress) -> Result<(), tokio_postgres::Error> {
+        let queued = i64::try_from(progress.objects_queued).unwrap_or(i64::MAX);
+        let done = i64::try_from(progress.objects_done).unwrap_or(i64::MAX);
+        self.client
+            .execute(&self.progress_stmt, &[&progress.work_id, &queued, &done])
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncConsumer for StatusTracker {
+    /// Accounts the received work progress update
+    ///
+    /// Progress updates are informative: malformed messages are logged and dropped
+    async fn consume(
+        &mut self,
+        channel: &Channel,
+        deliver: Deliver,
+        bprops: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        let delivery_tag = deliver.delivery_tag();
+        debug!(
+            "Received work progress message with delivery_tag={}\n",
+            delivery_tag
+        );
+
+        let progress: Option<WorkProgress> = match (bprops.message_type(), bprops.content_type()) {
+            (Some(mt), Some(ct))
+                if mt == shared::PROGRESS_TYPE && ct == shared::MSG_CONTENT_TYPE =>
+            {
+                serde_json::from_slice(&content)
+                    .inspect_err(|e| warn!("Work progress message has invalid payload: {}", e))
+                    .ok()
+            }
+            _ => {
+                warn!("Work progress message has missing or invalid type");
+                None
+            }
+        };
+        if let Some(progress) = progress {
+            debug!("Work progress received: {:?}", progress);
+            metrics::counter!(PROGRESS_COUNT).increment(1);
+            if let Err(e) = self.update(&progress).await {
+                error!("Graph database error, exiting: {}", e);
+                crate::amqp::reject_tag(channel, delivery_tag).await.ok();
+                self.failure_notifier.notify_one();
+                return;
+            }
+        }
+        if let Err(e) = crate::amqp::ack_tag(channel, delivery_tag).await {
+            error!("Broker error, exiting: {}", e);
+            self.failure_notifier.notify_one();
+        }
+    }
+}
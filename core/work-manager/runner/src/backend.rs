@@ -0,0 +1,313 @@
+//! Local backend workers
+//!
+//! Each executable found in the backends directory is spawned on its own local
+//! port and asked for its capabilities via the hello request, just like the
+//! frontend does; the backend is then sent the objects of the types it
+//! advertises or, if it advertises none, of the type named after the executable
+//! (e.g. a `ZIP` symlink to `unzip-rs`)
+//!
+//! Backends read their configuration from `backend.toml` in their working
+//! directory: a `<name>.toml` file next to the executable is copied there, if
+//! present; the listening address and the objects and output paths are always
+//! overridden via the environment
+use shared::backend::{BackendInfo, BackendResult};
+use shared::object;
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The number of attempts at spawning a backend on a free port
+const SPAWN_ATTEMPTS: usize = 3;
+
+/// A local backend worker
+pub struct Backend {
+    /// The executable name
+    name: String,
+    /// The advertised capabilities
+    info: BackendInfo,
+    addr: String,
+    /// Limits the requests in flight to the backend concurrency level
+    slots: tokio::sync::Semaphore,
+    /// The backend process (killed on drop)
+    ///
+    /// Not present for backends which are not spawned by the runner (tests)
+    process: Option<Mutex<tokio::process::Child>>,
+}
+
+impl Backend {
+    /// Spawns the backend and waits for it to come up
+    ///
+    /// Note: the backend is assigned a port which is free at the time of the
+    /// check; if the backend exits before coming up (e.g. because the port was
+    /// taken in the meantime), it's spawned again on another port
+    async fn spawn(
+        path: &Path,
+        workdir: &Path,
+        objects_path: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        tokio::fs::create_dir_all(workdir).await.map_err(|e| {
+            error!(
+                "Failed to create directory \"{}\": {}",
+                workdir.display(),
+                e
+            );
+            e
+        })?;
+        let config = format!("{}.toml", path.display());
+        if Path::new(&config).is_file() {
+            tokio::fs::copy(&config, workdir.join("backend.toml"))
+                .await
+                .map_err(|e| {
+                    error!("Failed to copy backend configuration \"{}\": {}", config, e);
+                    e
+                })?;
+        }
+
+        let mut attempt = 1;
+        loop {
+            let port = std::net::TcpListener::bind("127.0.0.1:0")?
+                .local_addr()?
+                .port();
+            // Note: the standard output is reserved for the result
+            let stdout = {
+                use std::os::fd::AsFd;
+                std::io::stderr().as_fd().try_clone_to_owned()?
+            };
+            let process = tokio::process::Command::new(path)
+                .current_dir(workdir)
+                .env("BACKEND__HOST", "127.0.0.1")
+                .env("BACKEND__PORT", port.to_string())
+                .env("BACKEND__OBJECTS_PATH", objects_path)
+                .env("BACKEND__OUTPUT_PATH", workdir)
+                .stdin(std::process::Stdio::null())
+                .stdout(stdout)
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| {
+                    error!("Failed to spawn backend \"{}\": {}", path.display(), e);
+                    e
+                })?;
+            let backend = Self::new(&name, &format!("127.0.0.1:{port}"), Some(process));
+            match backend.handshake().await {
+                Ok(info) => return Ok(backend.with_info(info)),
+                Err(_) if backend.has_exited() && attempt < SPAWN_ATTEMPTS => {
+                    warn!("Backend \"{name}\" exited while coming up, spawning it again");
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Connects to a backend which is already running
+    #[cfg(test)]
+    pub async fn connect(name: &str, addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let backend = Self::new(name, addr, None);
+        let info = backend.handshake().await?;
+        Ok(backend.with_info(info))
+    }
+
+    fn new(name: &str, addr: &str, process: Option<tokio::process::Child>) -> Self {
+        Self {
+            name: name.to_string(),
+            info: BackendInfo::default(),
+            addr: addr.to_string(),
+            slots: tokio::sync::Semaphore::new(1),
+            process: process.map(Mutex::new),
+        }
+    }
+
+    /// Sets the backend capabilities, as learned through the handshake
+    fn with_info(mut self, info: BackendInfo) -> Self {
+        self.slots = tokio::sync::Semaphore::new(info.concurrency);
+        self.info = info;
+        self
+    }
+
+    /// Returns true if the backend process has exited
+    fn has_exited(&self) -> bool {
+        self.process
+            .as_ref()
+            .is_some_and(|p| matches!(p.lock().unwrap().try_wait(), Ok(Some(_))))
+    }
+
+    /// Waits for the backend to come up and retrieves its capabilities
+    async fn handshake(&self) -> Result<BackendInfo, Box<dyn std::error::Error>> {
+        const ATTEMPTS: usize = 50;
+        for _ in 0..ATTEMPTS {
+            if self.has_exited() {
+                error!("Backend \"{}\" exited", self.name);
+                return Err("Backend exited".into());
+            }
+            let reply = tokio::time::timeout(
+                std::time::Duration::from_secs(2),
+                shared::backend::hello(&self.addr),
+            )
+            .await;
+            match reply {
+                Ok(Ok(info)) => return Ok(info),
+                // Note: the backend may not be listening yet
+                Ok(Err(e)) => debug!("Backend \"{}\" not yet available: {}", self.name, e),
+                Err(_) => debug!("Backend \"{}\" not yet available: timed out", self.name),
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        error!("Backend \"{}\" did not come up", self.name);
+        Err("Backend handshake failed".into())
+    }
+
+    /// Returns the object types processed by the backend
+    fn object_types(&self) -> Vec<String> {
+        if self.info.object_types.is_empty() {
+            vec![self.name.clone()]
+        } else {
+            self.info.object_types.clone()
+        }
+    }
+
+    /// Sends a JSON job request to the backend; awaits and return the result
+    ///
+    /// Requests in excess of the backend concurrency level are queued
+    pub async fn invoke(
+        &self,
+        descriptor: &object::Descriptor,
+    ) -> Result<(BackendResult, f64), Box<dyn std::error::Error>> {
+        let object = &descriptor.info;
+        let max_recursion = descriptor.max_recursion;
+        if object.recursion_level >= max_recursion {
+            info!("Max recursion level ({max_recursion}) reached, backend not invoked");
+            return Ok((
+                BackendResult::with_symbols(vec!["TOODEEP".to_string()]),
+                f64::NAN,
+            ));
+        }
+        let _slot = self.slots.acquire().await?;
+        debug!(
+            "Backend \"{}\" invoked on object \"{}\" at recursion level {}/{}",
+            self.name, object.object_id, object.recursion_level, max_recursion
+        );
+        let start = std::time::Instant::now();
+        let correlation_id = shared::utils::random_string(shared::MSG_CORRID_LEN);
+        let reply = async {
+            let mut stream = tokio::net::TcpStream::connect(&self.addr).await?;
+            // Note: children are only collected from the result
+            let req = shared::backend::BackendRequest {
+                object,
+                symbols: &descriptor.symbols,
+                relation_metadata: &descriptor.relation_metadata,
+                stream_children: false,
+                correlation_id: &correlation_id,
+            };
+            let req_json = serde_json::to_string(&req).unwrap();
+            stream.write_all(req_json.as_bytes()).await?;
+            stream.shutdown().await?;
+            shared::utils::read_all(&mut stream).await
+        }
+        .await
+        .map_err(|e| {
+            error!("Communication with backend \"{}\" failed: {}", self.name, e);
+            e
+        })?;
+        let res = shared::backend::parse_reply(&reply, &correlation_id)?;
+        Ok((res, start.elapsed().as_secs_f64()))
+    }
+}
+
+/// The local backends, keyed on the (uppercase) object type they process
+pub struct Backends(HashMap<String, Arc<Backend>>);
+
+impl Backends {
+    /// Spawns all the executables in `dir` as backends
+    ///
+    /// Each backend gets its own working directory inside `workdir`
+    pub async fn spawn(
+        dir: &Path,
+        workdir: &Path,
+        objects_path: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Note: backends run in their own working directory
+        let dir = std::fs::canonicalize(dir)?;
+        let dir = dir.as_path();
+        let mut paths = Vec::new();
+        let entries = std::fs::read_dir(dir).map_err(|e| {
+            error!(
+                "Failed to read backends directory \"{}\": {}",
+                dir.display(),
+                e
+            );
+            e
+        })?;
+        for entry in entries {
+            let path = entry?.path();
+            // Note: symlinks are followed
+            let is_executable = std::fs::metadata(&path)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if is_executable {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        if paths.is_empty() {
+            error!("No backends found in \"{}\"", dir.display());
+            return Err("No backends found".into());
+        }
+
+        let workdirs: Vec<_> = paths
+            .iter()
+            .map(|path| workdir.join(path.file_name().unwrap_or_default()))
+            .collect();
+        let spawned = futures::future::join_all(
+            paths
+                .iter()
+                .zip(&workdirs)
+                .map(|(path, workdir)| Backend::spawn(path, workdir, objects_path)),
+        )
+        .await;
+        let backends = spawned.into_iter().collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(backends))
+    }
+
+    /// Maps the object types to the backends processing them
+    pub fn new(backends: Vec<Backend>) -> Self {
+        let mut by_type: HashMap<String, Arc<Backend>> = HashMap::new();
+        for backend in backends {
+            let backend = Arc::new(backend);
+            for object_type in backend.object_types() {
+                match by_type.get(&object_type.to_uppercase()) {
+                    Some(other) => warn!(
+                        "Object type \"{}\" is processed by both \"{}\" and \"{}\", using the former",
+                        object_type, other.name, backend.name
+                    ),
+                    None => {
+                        info!(
+                            "Backend \"{}\" ({} {}) processes \"{}\" objects (concurrency level: {})",
+                            backend.name,
+                            backend.info.name,
+                            backend.info.version,
+                            object_type,
+                            backend.info.concurrency
+                        );
+                        by_type.insert(object_type.to_uppercase(), backend.clone());
+                    }
+                }
+            }
+        }
+        Self(by_type)
+    }
+
+    /// Retrieves the backend processing the given object type
+    ///
+    /// Note: object types are matched case insensitively, like worker queues are
+    pub fn get(&self, object_type: &str) -> Option<&Backend> {
+        self.0.get(&object_type.to_uppercase()).map(|b| b.as_ref())
+    }
+}
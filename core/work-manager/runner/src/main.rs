@@ -0,0 +1,184 @@
+mod backend;
+mod pipeline;
+
+use clap::Parser;
+use shared::{clamd, config, object, typedet};
+use std::path::{Path, PathBuf};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::prelude::*;
+
+/// Processes a file locally, without the platform, and outputs the work result
+///
+/// All the executables in the backends directory are spawned and fed the objects
+/// of the types they process; the output is the same JSON the grapher stores
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// The file to process
+    file: String,
+    /// The directory containing the backend executables
+    backends: PathBuf,
+    /// The organization the object belongs to
+    #[arg(long, default_value = "ctx")]
+    org: String,
+    /// The maximum recursion level
+    #[arg(long, default_value_t = shared::MAX_WORK_DEPTH)]
+    max_recursion: u32,
+    /// The work time to live, in seconds
+    #[arg(long, default_value_t = shared::MAX_WORK_TTL.as_secs())]
+    ttl: u64,
+    /// Additional type detection signature files
+    #[arg(long)]
+    signatures: Vec<String>,
+    /// The clamd address (HOST:PORT), for AV symbols and type detection fallback
+    ///
+    /// Note: clamd must have access to the objects path
+    #[arg(long)]
+    clamd: Option<String>,
+    /// The objects store (default: a temporary directory, removed on exit)
+    #[arg(long)]
+    objects_path: Option<String>,
+    /// Pretty print the result
+    #[arg(long)]
+    pretty: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Note: the standard output is reserved for the result
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+    let args = Args::parse();
+    let tmpdir =
+        std::env::temp_dir().join(format!("ctx-runner-{}", shared::utils::random_string(8)));
+    std::fs::create_dir_all(&tmpdir).map_err(|e| {
+        error!("Failed to create directory \"{}\": {}", tmpdir.display(), e);
+        e
+    })?;
+    let res = run(&args, &tmpdir).await;
+    if let Err(e) = std::fs::remove_dir_all(&tmpdir) {
+        warn!("Failed to remove directory \"{}\": {}", tmpdir.display(), e);
+    }
+    let result = res?;
+    let json = if args.pretty {
+        serde_json::to_string_pretty(&result)?
+    } else {
+        serde_json::to_string(&result)?
+    };
+    println!("{json}");
+    Ok(())
+}
+
+/// Parses a HOST:PORT address
+fn parse_addr(addr: &str) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    let (host, port) = addr.rsplit_once(':').ok_or("Missing port")?;
+    Ok((host.to_string(), port.parse()?))
+}
+
+/// Processes the file, using `tmpdir` for all local storage
+async fn run(
+    args: &Args,
+    tmpdir: &Path,
+) -> Result<shared::amqp::JobResult, Box<dyn std::error::Error>> {
+    let objects_path = match &args.objects_path {
+        Some(path) => path.clone(),
+        None => tmpdir.join("objects").to_string_lossy().into_owned(),
+    };
+    tokio::fs::create_dir_all(&objects_path)
+        .await
+        .map_err(|e| {
+            error!("Failed to create directory \"{}\": {}", objects_path, e);
+            e
+        })?;
+    // Note: backends run in their own working directory
+    let objects_path = std::fs::canonicalize(&objects_path)?
+        .to_string_lossy()
+        .into_owned();
+    let clamd_addr = args
+        .clamd
+        .as_deref()
+        .map(|addr| {
+            parse_addr(addr).map_err(|e| {
+                error!("Invalid clamd address \"{}\": {}", addr, e);
+                e
+            })
+        })
+        .transpose()?;
+    let clamd_config = |(host, port): &(String, u16)| config::ClamdServiceConfig {
+        host: host.clone(),
+        port: *port,
+        objects_path: objects_path.clone(),
+    };
+    let mut typedet_config = config::TypedetConfig::default();
+    typedet_config.signatures = Some(args.signatures.clone());
+    typedet_config.clamd = clamd_addr.as_ref().map(clamd_config);
+    let typedet = typedet::Typedet::new(&typedet_config)?;
+
+    // Store and type the entry object
+    let ctime = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0f64);
+    let mut object = object::Info::new_from_file(&args.org, &args.file, &objects_path, 1, ctime)
+        .await
+        .map_err(|e| {
+            error!("Failed to store \"{}\": {}", args.file, e);
+            e
+        })?;
+    if object.is_empty() {
+        error!("Empty object");
+        return Err("Empty object".into());
+    }
+    let detection = typedet.set_ftype(&mut object, &objects_path).await?;
+    info!(
+        "Object \"{}\" has type \"{}\" (confidence {})",
+        object.object_id, object.object_type, detection.confidence
+    );
+    let ttl = std::time::Duration::from_secs(args.ttl).min(shared::MAX_WORK_TTL);
+    let max_recursion = args.max_recursion.clamp(1, shared::MAX_WORK_DEPTH);
+    let mut relation_metadata = object::Metadata::new();
+    relation_metadata.insert(
+        shared::META_KEY_ORIGIN.to_string(),
+        serde_json::json!({
+            "peer": null,
+            "real_peer": null,
+            "ttl": ttl.as_secs(),
+            "max_recursion": max_recursion,
+            "priority": object::Priority::default(),
+        }),
+    );
+    let interpretations = typedet.interpretations(&detection);
+    if !interpretations.is_empty() {
+        relation_metadata.insert(
+            shared::META_KEY_INTERPRETATIONS.to_string(),
+            serde_json::to_value(&interpretations).unwrap(),
+        );
+    }
+    object::sanitize_meta_keys(&mut relation_metadata);
+
+    // Process the work
+    let backends =
+        backend::Backends::spawn(&args.backends, &tmpdir.join("backends"), &objects_path).await?;
+    let pipeline = pipeline::Pipeline {
+        typedet,
+        clamd: clamd_addr
+            .as_ref()
+            .map(|addr| clamd::Clamd::new(&clamd_config(addr))),
+        backends,
+        objects_path,
+        deadline: tokio::time::Instant::now() + ttl,
+    };
+    pipeline
+        .process(object::Descriptor {
+            info: object,
+            symbols: Vec::new(),
+            relation_metadata,
+            max_recursion,
+            work_id: None,
+            priority: object::Priority::default(),
+        })
+        .await
+}
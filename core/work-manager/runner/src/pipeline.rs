@@ -0,0 +1,335 @@
+//! In-process job processing
+//!
+//! Objects are processed the same way the frontends do (see `frontend/src/wrkmgr.rs`),
+//! through the same backend result processing (see [`shared::backend`]), except
+//! that child jobs are run right away, concurrently, rather than being published
+//! to the broker
+//!
+//! Platform outcomes which only depend on the lack of a worker are reproduced as
+//! well: objects whose type no backend processes get a "Time out" result, exactly
+//! like the objects which are never picked up in the platform
+use crate::backend::Backends;
+use futures::future::{FutureExt, LocalBoxFuture};
+use shared::amqp::{JobResult, JobResultKind, JobResultOk};
+use shared::backend::{BackendResultChild, BackendResultKind, ChildJob};
+use shared::{clamd, object, typedet};
+use tokio::time::Instant;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The local pipeline
+pub struct Pipeline {
+    pub typedet: typedet::Typedet,
+    pub clamd: Option<clamd::Clamd>,
+    pub backends: Backends,
+    pub objects_path: String,
+    /// The work expiration
+    pub deadline: Instant,
+}
+
+/// Builds a failed job result
+fn error_result(descriptor: object::Descriptor, error: &str) -> JobResult {
+    JobResult {
+        info: descriptor.info,
+        relation_metadata: descriptor.relation_metadata,
+        result: JobResultKind::error(error.to_string()),
+    }
+}
+
+impl Pipeline {
+    /// Processes an object and, recursively, all its children
+    ///
+    /// Note: an [`Error`](std::error::Error) result indicates a local failure
+    /// (e.g. the objects store is not writable)
+    pub fn process(
+        &self,
+        descriptor: object::Descriptor,
+    ) -> LocalBoxFuture<'_, Result<JobResult, Box<dyn std::error::Error>>> {
+        async move {
+            let info = &descriptor.info;
+            let backend = match self.backends.get(&info.object_type) {
+                Some(backend) => backend,
+                None => {
+                    warn!(
+                        "No backend for \"{}\" objects, object \"{}\" not processed",
+                        info.object_type, info.object_id
+                    );
+                    return Ok(error_result(descriptor, "Time out"));
+                }
+            };
+            let ttl = self.deadline.saturating_duration_since(Instant::now());
+
+            // Pass the object to clamd and to the backend
+            let clamd_fut = async {
+                match &self.clamd {
+                    Some(clamd) => Some(clamd.get_symbols(&info.object_id).await),
+                    None => None,
+                }
+            };
+            let (clamd_res, backend_res) = futures::future::join(
+                tokio::time::timeout(ttl, clamd_fut),
+                tokio::time::timeout(ttl, backend.invoke(&descriptor)),
+            )
+            .await;
+            let (clamd_res, backend_res) = match (clamd_res, backend_res) {
+                (Ok(clamd_res), Ok(backend_res)) => (clamd_res, backend_res),
+                _ => {
+                    warn!("Job for object \"{}\" expired", info.object_id);
+                    return Ok(error_result(descriptor, "Time out"));
+                }
+            };
+            let (backend_res, backend_time) = match backend_res {
+                Ok(v) => v,
+                // Note: the platform redelivers the job until it gives up
+                Err(_) => return Ok(error_result(descriptor, "Max retries")),
+            };
+            let mut res = match backend_res.result {
+                BackendResultKind::ok(res) => res,
+                BackendResultKind::error(e) => {
+                    info!("Backend failed on object \"{}\": {}", info.object_id, e);
+                    return Ok(error_result(descriptor, &e));
+                }
+            };
+
+            // Merge the backend, parent and clamd symbols
+            let mut perf_meta = object::Metadata::new();
+            perf_meta.insert("time_backend".into(), serde_json::Value::from(backend_time));
+            shared::backend::sanitize_backend_symbols(&mut res.symbols);
+            shared::backend::merge_result(&mut res, &descriptor.symbols, clamd_res, perf_meta);
+
+            // Turn the backend children into objects
+            let child_objects = self.children_to_objects(&res.children, info).await?;
+            let mut children: Vec<ChildJob> = child_objects
+                .into_iter()
+                .zip(res.children)
+                .flat_map(|((o, interpretations), c)| {
+                    shared::backend::child_jobs(o, &interpretations, c, &descriptor)
+                })
+                .collect();
+            // Entry polyglots have no parent: their interpretations are attached as children
+            if info.recursion_level == 1 {
+                let interpretations = shared::backend::entry_interpretations(&descriptor);
+                if !interpretations.is_empty() {
+                    res.symbols
+                        .push(shared::backend::POLYGLOT_SYMBOL.to_string());
+                    children.extend(interpretations);
+                }
+            }
+
+            // Process the children
+            let max_recursion = descriptor.max_recursion;
+            let children = futures::future::try_join_all(children.into_iter().map(|c| {
+                if c.needs_processing() {
+                    self.process(object::Descriptor {
+                        info: c.info,
+                        symbols: c.symbols,
+                        relation_metadata: c.relation_metadata,
+                        max_recursion,
+                        work_id: None,
+                        priority: descriptor.priority,
+                    })
+                } else {
+                    futures::future::ready(Ok(c.into_result())).boxed_local()
+                }
+            }))
+            .await?;
+            info!(
+                "Job for object \"{}\" is complete ({} symbols and {} children)",
+                descriptor.info.object_id,
+                res.symbols.len(),
+                children.len()
+            );
+            Ok(JobResult {
+                info: descriptor.info,
+                relation_metadata: descriptor.relation_metadata,
+                result: JobResultKind::ok(JobResultOk {
+                    symbols: res.symbols,
+                    object_metadata: res.object_metadata,
+                    children,
+                }),
+            })
+        }
+        .boxed_local()
+    }
+
+    /// Turns the backend children into (typed) objects and removes their tempfiles
+    ///
+    /// Children which the backend failed to extract become failed objects
+    async fn children_to_objects(
+        &self,
+        children: &[BackendResultChild],
+        parent: &object::Info,
+    ) -> Result<Vec<(object::Info, Vec<typedet::Candidate>)>, Box<dyn std::error::Error>> {
+        let mut objects = Vec::with_capacity(children.len());
+        for child in children {
+            let mut object = shared::backend::child_to_object(
+                child,
+                &parent.org,
+                &self.objects_path,
+                parent.recursion_level + 1,
+                parent.ctime,
+            )
+            .await?;
+            let interpretations = if object.object_type.is_empty() {
+                let detection = self
+                    .typedet
+                    .set_ftype(&mut object, &self.objects_path)
+                    .await?;
+                self.typedet.interpretations(&detection)
+            } else {
+                Vec::new()
+            };
+            objects.push((object, interpretations));
+        }
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::Backend;
+    use tokio::io::AsyncWriteExt;
+
+    /// Replies to the backend requests like an archive backend would
+    ///
+    /// Entry objects get three children: an extracted one, a failed one and one
+    /// of a type with no backend; children get no children
+    async fn stub_backend(listener: tokio::net::TcpListener, scratch: std::path::PathBuf) {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let request = shared::utils::read_all(&mut stream).await.unwrap();
+            let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+            let reply = if request.get("hello").is_some() {
+                serde_json::json!({
+                    "name": "stub",
+                    "version": "1.0",
+                    "object_types": ["STUB"],
+                    "concurrency": 2,
+                })
+            } else if request["object"]["recursion_level"] == 1 {
+                let path = scratch.join("child");
+                std::fs::write(&path, b"child contents").unwrap();
+                let extracted = scratch.join("other");
+                std::fs::write(&extracted, b"other contents").unwrap();
+                serde_json::json!({
+                    "ok": {
+                        "symbols": ["entry-sym"],
+                        "object_metadata": { "answer": 42 },
+                        "children": [
+                            {
+                                "path": path,
+                                "force_type": "STUB",
+                                "symbols": ["child"],
+                                "relation_metadata": { "name": "a" },
+                            },
+                            {
+                                "path": null,
+                                "force_type": null,
+                                "symbols": ["FAILED"],
+                                "relation_metadata": { "name": "b" },
+                            },
+                            {
+                                "path": extracted,
+                                "force_type": "NOBACKEND",
+                                "symbols": [],
+                                "relation_metadata": { "name": "c" },
+                            },
+                        ],
+                    },
+                    "correlation_id": request["correlation_id"],
+                })
+            } else {
+                serde_json::json!({
+                    "ok": {
+                        "symbols": ["LEAF"],
+                        "object_metadata": {},
+                        "children": [],
+                    },
+                    "correlation_id": request["correlation_id"],
+                })
+            };
+            stream
+                .write_all(reply.to_string().as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    fn ok(result: &JobResult) -> &JobResultOk {
+        match &result.result {
+            JobResultKind::ok(ok) => ok,
+            JobResultKind::error(e) => panic!("Unexpected error result: {e}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_with_stub_backend() {
+        let tmpdir =
+            std::env::temp_dir().join(format!("ctx-runner-{}", shared::utils::random_string(8)));
+        let objects_path = tmpdir.join("objects");
+        let scratch = tmpdir.join("scratch");
+        std::fs::create_dir_all(&objects_path).unwrap();
+        std::fs::create_dir_all(&scratch).unwrap();
+        let objects_path = objects_path.to_string_lossy().into_owned();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(stub_backend(listener, scratch.clone()));
+        let backend = Backend::connect("stub", &addr).await.unwrap();
+
+        let entry = tmpdir.join("entry").to_string_lossy().into_owned();
+        std::fs::write(&entry, b"entry contents").unwrap();
+        let mut info = object::Info::new_from_file("org", &entry, &objects_path, 1, 0.0)
+            .await
+            .unwrap();
+        info.set_type("STUB");
+        let pipeline = Pipeline {
+            typedet: typedet::Typedet::new(&Default::default()).unwrap(),
+            clamd: None,
+            backends: Backends::new(vec![backend]),
+            objects_path,
+            deadline: Instant::now() + std::time::Duration::from_secs(60),
+        };
+        let result = pipeline
+            .process(object::Descriptor {
+                info,
+                symbols: vec!["PARENT".to_string()],
+                relation_metadata: object::Metadata::new(),
+                max_recursion: 5,
+                work_id: None,
+                priority: object::Priority::default(),
+            })
+            .await
+            .unwrap();
+
+        // The backend symbols are sanitized and merged with the parent ones
+        let res = ok(&result);
+        assert_eq!(result.info.object_type, "STUB");
+        assert_eq!(res.symbols, ["ENTRY_SYM", "PARENT"]);
+        assert_eq!(res.object_metadata["answer"], 42);
+        assert!(res.object_metadata[shared::backend::PERF_META_KEY]["time_backend"].is_number());
+        assert_eq!(res.children.len(), 3);
+
+        // The extracted child is processed in turn
+        let child = &res.children[0];
+        assert_eq!(child.info.object_type, "STUB");
+        assert_eq!(child.info.recursion_level, 2);
+        assert_eq!(child.relation_metadata["name"], "a");
+        assert_eq!(ok(child).symbols, ["CHILD", "LEAF"]);
+        assert!(ok(child).children.is_empty());
+
+        // The failed child is not processed
+        let child = &res.children[1];
+        assert!(child.info.is_skipped());
+        assert_eq!(ok(child).symbols, ["FAILED"]);
+
+        // Objects of types with no backend time out
+        let child = &res.children[2];
+        assert_eq!(child.info.object_type, "NOBACKEND");
+        assert!(matches!(&child.result, JobResultKind::error(e) if e == "Time out"));
+
+        // The child tempfiles are removed
+        assert_eq!(std::fs::read_dir(&scratch).unwrap().count(), 0);
+        std::fs::remove_dir_all(&tmpdir).ok();
+    }
+}
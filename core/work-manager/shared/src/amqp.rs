@@ -17,25 +17,31 @@ use tracing::{debug, error, info, warn};
 
 /// The job result as published to the broker
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct JobResult {
     #[serde(flatten)]
     pub info: crate::object::Info,
     #[serde(serialize_with = "crate::object::serialize_meta")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
     pub relation_metadata: crate::object::Metadata,
     #[serde(flatten)]
     pub result: JobResultKind,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 /// The "ok" part of the job result - present if the job succeeded
 pub struct JobResultOk {
     pub symbols: Vec<String>,
     #[serde(serialize_with = "crate::object::serialize_meta")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
     pub object_metadata: crate::object::Metadata,
+    #[cfg_attr(feature = "openapi", schema(no_recursion))]
     pub children: Vec<JobResult>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[allow(non_camel_case_types)]
 /// Indicates whether the job succeeded or failed
 pub enum JobResultKind {
@@ -303,6 +309,9 @@ pub async fn close_channel(chan: Channel) {
 }
 
 /// Posts a job request
+///
+/// Requests without a `reply_queue` are work requests (their result is stored by
+/// the grapher) and are flagged as such via the `work_request` header
 pub async fn publish_job_request(
     channel: &Channel,
     object_ref: crate::object::DescriptorRef<'_>,
@@ -321,6 +330,10 @@ pub async fn publish_job_request(
         "expiration_ts".try_into().unwrap(),
         FieldValue::T(expiration_ts),
     );
+    headers.insert(
+        "work_request".try_into().unwrap(),
+        FieldValue::t(reply_queue.is_none()),
+    );
     let bprops = BasicProperties::default()
         .with_content_type(crate::MSG_CONTENT_TYPE)
         .with_correlation_id(&correlation_id)
@@ -330,7 +343,7 @@ pub async fn publish_job_request(
         .with_reply_to(reply_queue.unwrap_or(crate::RESULTS_QUEUE_NAME))
         .finish();
     let args = BasicPublishArguments::default()
-        .routing_key(object_ref.info.request_queue())
+        .routing_key(object_ref.request_queue())
         .finish();
     let request_json = serde_json::to_string(&object_ref).unwrap();
     channel
@@ -340,7 +353,7 @@ pub async fn publish_job_request(
             error!(
                 "Failed to publish job request for object \"{}\" to {}: {}",
                 object_ref.info.object_id,
-                object_ref.info.request_queue(),
+                object_ref.request_queue(),
                 e
             );
             e
@@ -348,7 +361,7 @@ pub async fn publish_job_request(
     debug!(
         "Posted job request for object \"{}\" to {} with correlation_id {}",
         object_ref.info.object_id,
-        object_ref.info.request_queue(),
+        object_ref.request_queue(),
         correlation_id
     );
     Ok(correlation_id)
@@ -407,60 +420,258 @@ pub async fn request_apply_scenarios(
         .await
 }
 
-pub async fn declare_reload_queue(channel: &Channel) -> Result<String, Box<dyn std::error::Error>> {
-    // Declare reload exchange and queue
+/// A work progress update (as published by workers)
+///
+/// Updates are deltas: each processed object reports itself as done along with
+/// the number of child objects it has queued for processing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkProgress {
+    /// The work id
+    pub work_id: String,
+    /// The number of newly queued objects
+    pub objects_queued: u64,
+    /// The number of newly processed objects
+    pub objects_done: u64,
+}
+
+pub async fn declare_status_queue(channel: &Channel) -> Result<(), Box<dyn std::error::Error>> {
+    debug!(
+        "Declaring the work status queue \"{}\"...",
+        crate::STATUS_QUEUE_NAME
+    );
+    let mut args = FieldTable::new();
+    args.insert("x-queue-type".try_into().unwrap(), "quorum".into());
+    args.insert(
+        "x-message-ttl".try_into().unwrap(),
+        FieldValue::i((crate::MAX_WORK_TTL * 2).as_millis().try_into().unwrap()),
+    );
+    let qargs = QueueDeclareArguments::new(crate::STATUS_QUEUE_NAME)
+        .durable(true)
+        .arguments(args)
+        .finish();
+
+    let (_, message_count, consumer_count) = channel
+        .queue_declare(qargs)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to declare the work status queue \"{}\": {}",
+                crate::STATUS_QUEUE_NAME,
+                e
+            );
+            e
+        })?
+        .unwrap();
     debug!(
-        "Declaring the reload exchange \"{}\"...",
-        crate::SC_RELOAD_EXCHANGE_NAME
+        "Work status queue \"{}\" successfully declared ({} messages, {} consumers)",
+        crate::STATUS_QUEUE_NAME,
+        message_count,
+        consumer_count
     );
+    Ok(())
+}
+
+pub async fn publish_work_progress(
+    progress: &WorkProgress,
+    channel: &Channel,
+) -> Result<(), amqprs::error::Error> {
+    let bprops = BasicProperties::default()
+        .with_content_type(super::MSG_CONTENT_TYPE)
+        .with_message_type(super::PROGRESS_TYPE)
+        .finish();
+    let args = BasicPublishArguments::default()
+        .routing_key(super::STATUS_QUEUE_NAME.to_string())
+        .finish();
+    let progress_json = serde_json::to_string(progress).unwrap();
+    channel
+        .basic_publish(bprops, progress_json.into_bytes(), args)
+        .await
+}
+
+/// A request to drop cached backend results (as published to the workers)
+///
+/// Results matching all the provided fields are dropped; an empty request
+/// drops all the cached results
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CacheInvalidation {
+    /// Only drop the results for this object id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_id: Option<String>,
+    /// Only drop the results for this object type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_type: Option<String>,
+}
+
+/// A worker backend status and capabilities (as periodically published by the workers)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BackendAnnouncement {
+    /// The worker type
+    pub worker_type: String,
+    /// The (random) id of the worker instance
+    pub instance: String,
+    /// The backend name (if advertised)
+    pub name: Option<String>,
+    /// The backend version (if advertised)
+    pub version: Option<String>,
+    /// The number of requests the backend can process at the same time
+    pub concurrency: usize,
+    /// The object types the backend can process (if advertised)
+    pub object_types: Vec<String>,
+    /// The limits enforced by the backend (if advertised)
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub limits: crate::object::Metadata,
+    /// The symbols the backend can emit (if advertised)
+    pub symbols: Vec<String>,
+    /// The object metadata keys the backend can emit (if advertised)
+    pub metadata_keys: Vec<String>,
+    /// Whether the backend responded to the last health check
+    pub healthy: bool,
+    /// Whether the backend does not process the worker type objects
+    pub misconfigured: bool,
+    /// The number of seconds this announcement is valid for
+    pub ttl_secs: u64,
+}
+
+/// Declares a (durable) fanout exchange
+async fn declare_fanout_exchange(
+    channel: &Channel,
+    exchange: &str,
+    description: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    debug!("Declaring the {description} exchange \"{exchange}\"...");
     channel
         .exchange_declare(
-            ExchangeDeclareArguments::of_type(crate::SC_RELOAD_EXCHANGE_NAME, ExchangeType::Fanout)
+            ExchangeDeclareArguments::of_type(exchange, ExchangeType::Fanout)
                 .durable(true)
                 .finish(),
         )
         .await
         .map_err(|e| {
-            error!("Failed to declare the reload exchange: {}", e);
+            error!("Failed to declare the {description} exchange: {}", e);
             e
         })?;
+    Ok(())
+}
+
+/// Declares a fanout exchange along with an exclusive queue bound to it
+///
+/// Returns the (server generated) queue name
+async fn declare_fanout_queue(
+    channel: &Channel,
+    exchange: &str,
+    description: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    declare_fanout_exchange(channel, exchange, description).await?;
     let qargs = QueueDeclareArguments::default()
         .exclusive(true)
         .auto_delete(true)
         .finish();
-    let (reload_queue, message_count, consumer_count) = channel
+    let (queue, message_count, consumer_count) = channel
         .queue_declare(qargs)
         .await
         .map_err(|e| {
-            error!("Failed to declare the reload queue: {}", e);
+            error!("Failed to declare the {description} queue: {}", e);
             e
         })?
         .unwrap();
     debug!(
-        "Reload queue \"{}\" successfully declared ({} messages, {} consumers)",
-        reload_queue, message_count, consumer_count
+        "The {} queue \"{}\" successfully declared ({} messages, {} consumers)",
+        description, queue, message_count, consumer_count
     );
 
-    // Bind the reload queue to the reload exchange
+    // Bind the queue to the exchange
     channel
-        .queue_bind(QueueBindArguments::new(
-            &reload_queue,
-            crate::SC_RELOAD_EXCHANGE_NAME,
-            "",
-        ))
+        .queue_bind(QueueBindArguments::new(&queue, exchange, ""))
         .await
         .map_err(|e| {
-            error!("Failed to bind the reload queue to its exchange: {}", e);
+            error!(
+                "Failed to bind the {description} queue to its exchange: {}",
+                e
+            );
             e
         })?;
     debug!(
-        "Reload queue \"{}\" declared ({} messages, {} consumers) and bound to {}",
-        reload_queue,
-        message_count,
-        consumer_count,
-        crate::SC_RELOAD_EXCHANGE_NAME
+        "The {} queue \"{}\" declared ({} messages, {} consumers) and bound to {}",
+        description, queue, message_count, consumer_count, exchange
     );
-    Ok(reload_queue)
+    Ok(queue)
+}
+
+pub async fn declare_reload_queue(channel: &Channel) -> Result<String, Box<dyn std::error::Error>> {
+    declare_fanout_queue(channel, crate::SC_RELOAD_EXCHANGE_NAME, "reload").await
+}
+
+/// Declares the cache invalidation exchange (used by publishers)
+pub async fn declare_cache_invalidation_exchange(
+    channel: &Channel,
+) -> Result<(), Box<dyn std::error::Error>> {
+    declare_fanout_exchange(
+        channel,
+        crate::CACHE_INVALIDATE_EXCHANGE_NAME,
+        "cache invalidation",
+    )
+    .await
+}
+
+/// Declares the cache invalidation exchange and an exclusive queue bound to it
+pub async fn declare_cache_invalidation_queue(
+    channel: &Channel,
+) -> Result<String, Box<dyn std::error::Error>> {
+    declare_fanout_queue(
+        channel,
+        crate::CACHE_INVALIDATE_EXCHANGE_NAME,
+        "cache invalidation",
+    )
+    .await
+}
+
+/// Declares the backend registry exchange (used by publishers)
+pub async fn declare_backends_exchange(
+    channel: &Channel,
+) -> Result<(), Box<dyn std::error::Error>> {
+    declare_fanout_exchange(channel, crate::BACKENDS_EXCHANGE_NAME, "backend registry").await
+}
+
+/// Declares the backend registry exchange and an exclusive queue bound to it
+pub async fn declare_backends_queue(
+    channel: &Channel,
+) -> Result<String, Box<dyn std::error::Error>> {
+    declare_fanout_queue(channel, crate::BACKENDS_EXCHANGE_NAME, "backend registry").await
+}
+
+/// Publishes a backend announcement to the registry
+pub async fn publish_backend_announcement(
+    announcement: &BackendAnnouncement,
+    channel: &Channel,
+) -> Result<(), amqprs::error::Error> {
+    let bprops = BasicProperties::default()
+        .with_content_type(super::MSG_CONTENT_TYPE)
+        .with_message_type(super::BACKEND_ANNOUNCE_TYPE)
+        .with_expiration(&(announcement.ttl_secs * 1000).to_string())
+        .finish();
+    let args = BasicPublishArguments::new(super::BACKENDS_EXCHANGE_NAME, "");
+    let announcement_json = serde_json::to_string(announcement).unwrap();
+    channel
+        .basic_publish(bprops, announcement_json.into_bytes(), args)
+        .await
+}
+
+/// Publishes a backend result cache invalidation request to all the workers
+pub async fn publish_cache_invalidation(
+    invalidation: &CacheInvalidation,
+    channel: &Channel,
+) -> Result<(), amqprs::error::Error> {
+    let bprops = BasicProperties::default()
+        .with_content_type(super::MSG_CONTENT_TYPE)
+        .with_message_type(super::CACHE_INVALIDATE_TYPE)
+        .finish();
+    let args = BasicPublishArguments::new(super::CACHE_INVALIDATE_EXCHANGE_NAME, "");
+    let invalidation_json = serde_json::to_string(invalidation).unwrap();
+    channel
+        .basic_publish(bprops, invalidation_json.into_bytes(), args)
+        .await
 }
 
 #[cfg(test)]
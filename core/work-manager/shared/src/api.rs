@@ -0,0 +1,416 @@
+//! The endpoint API payloads
+//!
+//! These types are served by the endpoint and consumed by the API client, so
+//! that both sides agree on the wire format
+
+use crate::{amqp::JobResult, scene};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// The response object returned by the submission endpoint
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema), schema(as = SubmitResultV1))]
+pub struct SubmitResult {
+    /// The id assigned to the work object
+    pub object_id: String,
+    /// The id assigned to the work request - used for retrieving graph results
+    pub work_id: String,
+    /// The effective ttl of the work
+    pub ttl: u64,
+}
+
+/// The processing state of a work
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum WorkState {
+    Queued,
+    InProgress,
+    Completed,
+    TimedOut,
+}
+
+/// The processing status of a work
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WorkStatus {
+    pub work_id: String,
+    pub state: WorkState,
+    pub objects_done: i64,
+    pub objects_pending: i64,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t_queued: SystemTime,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t_updated: SystemTime,
+    #[serde(
+        serialize_with = "crate::time_to_f64_opt",
+        deserialize_with = "crate::f64_to_time_opt"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<f64>))]
+    pub expires_at: Option<SystemTime>,
+}
+
+/// The legal hold placed on a work
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LegalHold {
+    pub work_id: String,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t: SystemTime,
+    pub reason: Option<String>,
+}
+
+/// A page of work graphs
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema), schema(as = GetGraphsPageResp))]
+pub struct GraphsPage {
+    /// The graphs keyed by work id (null if not available)
+    #[cfg_attr(feature = "openapi", schema(schema_with = graphs_schema))]
+    pub graphs: HashMap<String, Option<JobResult>>,
+    /// The cursor to the next page, if any
+    pub next_cursor: Option<String>,
+}
+
+/// Returns the schema of the work graph maps, whose values are null for the
+/// graphs which are not available
+///
+/// Needed because utoipa cannot describe maps with nullable values
+#[cfg(feature = "openapi")]
+pub fn graphs_schema() -> utoipa::openapi::schema::ObjectBuilder {
+    use utoipa::openapi::{
+        schema::{ObjectBuilder, OneOfBuilder, Schema, Type},
+        Ref,
+    };
+    ObjectBuilder::new()
+        .additional_properties(Some(Schema::OneOf(
+            OneOfBuilder::new()
+                .item(ObjectBuilder::new().schema_type(Type::Null))
+                .item(Ref::from_schema_name("JobResult"))
+                .build(),
+        )))
+        .property_names(Some(ObjectBuilder::new().schema_type(Type::String)))
+}
+
+/// A page of search results
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SearchPage {
+    /// The matching work (or object) ids
+    pub items: Vec<String>,
+    /// The cursor to the next page, if any
+    pub next_cursor: Option<String>,
+}
+
+/// The number of matching works (or objects)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CountResult {
+    pub count: i64,
+}
+
+/// The error returned when a search query is rejected or fails
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema), schema(as = SearchError))]
+pub struct SearchErrorBody {
+    pub kind: String,
+    pub message: String,
+}
+
+/// The summary of a stored scenario
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScenarioDetails {
+    pub id: i64,
+    pub name: String,
+    pub creator: String,
+    pub description: String,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t: SystemTime,
+    pub action: String,
+    /// Whether the scenario is evaluated at all
+    pub enabled: bool,
+    /// Whether matches are acted upon or just recorded
+    pub mode: scene::ScenarioMode,
+    /// The orgs whose works the scenario applies to (empty means all)
+    pub orgs: Vec<String>,
+}
+
+/// A recorded scenario version
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScenarioVersion {
+    pub name: String,
+    pub version: i32,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t: SystemTime,
+    pub author: String,
+    /// The change type (`create`, `update`, `delete`, `rollback` or `import`)
+    pub op: String,
+    pub comment: Option<String>,
+    /// The changed fields, each as an `{"old": ..., "new": ...}` object
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub diff: serde_json::Value,
+    /// The scenario definition (omitted in listings and for deletions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub def: Option<serde_json::Value>,
+}
+
+/// The statistics of a scenario over a period
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatsPeriod {
+    /// The start of the period
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t: SystemTime,
+    /// The number of works the scenario was evaluated against
+    pub evaluations: i64,
+    /// The number of works the scenario matched
+    pub matches: i64,
+    /// The average local query evaluation time in seconds
+    pub avg_local_time: f64,
+    /// The number of global query evaluations
+    pub global_evaluations: i64,
+    /// The average global query evaluation time in seconds
+    pub avg_global_time: f64,
+}
+
+/// The statistics of a scenario
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScenarioStats {
+    pub id: i64,
+    /// The time of the last match (Unix time)
+    pub last_hit: Option<f64>,
+    /// The hourly statistics, most recent first
+    pub hourly: Vec<StatsPeriod>,
+    /// The daily (UTC) statistics, most recent first
+    pub daily: Vec<StatsPeriod>,
+}
+
+/// The outcome of a backtest
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BacktestResult {
+    /// The matching works, oldest first
+    pub work_ids: Vec<String>,
+    /// The number of matching works
+    pub count: usize,
+    /// The number of works which matched the local query
+    pub local_matches: usize,
+    /// Whether the time range held more candidate works than evaluated
+    pub truncated: bool,
+    /// The execution time in seconds
+    pub time: f64,
+}
+
+/// A scenario bundle manifest entry
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ManifestEntry {
+    /// The scenario name
+    pub name: String,
+    /// The scenario version requirements
+    pub compatible_with: String,
+    /// The SHA-256 of the scenario definition
+    pub sha256: String,
+}
+
+/// A scenario bundle manifest
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Manifest {
+    /// The bundle format version
+    pub format: u32,
+    /// The version of the exporting platform
+    pub platform_version: String,
+    /// The export time (Unix time)
+    pub created: f64,
+    /// The scenarios in the bundle
+    pub scenarios: Vec<ManifestEntry>,
+}
+
+/// A set of exported scenarios
+///
+/// Bundles must be passed back for import unaltered
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub scenarios: Vec<scene::Scenario>,
+    /// The hex encoded HMAC-SHA256 of the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// How to handle imported scenarios whose name is already in use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Keep the existing scenario
+    #[default]
+    Skip,
+    /// Replace the existing scenario
+    Overwrite,
+    /// Import the scenario under a new name
+    Rename,
+}
+
+/// A scenario imported under a new name
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Renamed {
+    pub from: String,
+    pub to: String,
+}
+
+/// The outcome of a bundle import
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportReport {
+    /// The names of the imported scenarios (including the overwritten ones)
+    pub imported: Vec<String>,
+    /// The names of the scenarios which were not imported due to a conflict
+    pub skipped: Vec<String>,
+    /// The scenarios imported under a new name
+    pub renamed: Vec<Renamed>,
+}
+
+/// The submission usage of an org on the current (UTC) day
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema), schema(as = OrgUsageV1))]
+pub struct OrgUsage {
+    pub org: String,
+    /// The number of objects submitted today
+    pub objects: i64,
+    /// The number of bytes submitted today
+    pub bytes: i64,
+    /// The daily objects limit
+    pub daily_objects_limit: Option<u64>,
+    /// The daily bytes limit
+    pub daily_bytes_limit: Option<u64>,
+}
+
+/// A request to reprocess historical works
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema), schema(as = ReprocessReqV1))]
+pub struct ReprocessRequest {
+    /// The object type handled by the upgraded backend
+    pub object_type: String,
+    /// The upgraded backend version: works containing objects processed by
+    /// lower versions are reprocessed
+    pub min_version: String,
+    /// Restrict the reprocessing to the works of this org
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// The start of the submission time range (Unix time, inclusive, defaults to the epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    /// The end of the submission time range (Unix time, exclusive, defaults to now)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    /// The author of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+/// A reprocess job
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReprocessJob {
+    pub id: i64,
+    /// The job creation time
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t: SystemTime,
+    pub author: String,
+    /// The object type handled by the upgraded backend
+    pub object_type: String,
+    /// Objects processed by backend versions lower than this are reprocessed
+    pub min_version: String,
+    /// The org the works are restricted to
+    pub org: Option<String>,
+    /// The start of the submission time range
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub start: SystemTime,
+    /// The end of the submission time range
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub end: SystemTime,
+    /// The job state (`running`, `paused`, `cancelled` or `completed`)
+    pub state: String,
+    /// The number of works examined so far
+    pub scanned: i64,
+    /// The number of works re-enqueued so far
+    pub enqueued: i64,
+    /// The number of works which could not be re-enqueued
+    pub failed: i64,
+    /// The number of re-enqueued works still being processed
+    pub in_flight: i64,
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub t_updated: SystemTime,
+}
+
+/// A reprocess job state change
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ReprocessAction {
+    /// Suspend a running job
+    Pause,
+    /// Resume a paused job
+    Resume,
+    /// Stop a running or paused job for good
+    Cancel,
+}
+
+impl ReprocessAction {
+    /// Returns the action name, as used in the route path
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Cancel => "cancel",
+        }
+    }
+}
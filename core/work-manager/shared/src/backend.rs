@@ -0,0 +1,455 @@
+//! The backend protocol and the processing of the backend results
+//!
+//! Backends are sent one JSON request per connection (see [`BackendRequest`])
+//! and reply with a JSON result (see [`BackendResult`]); they are also asked
+//! for their capabilities via the hello request (see [`hello()`])
+//!
+//! The frontends and the local pipeline runner both turn the backend results
+//! into job results through the routines below, so that they produce the same
+//! results for the same objects
+use crate::object;
+use crate::typedet;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The object metadata key holding the processing times
+pub const PERF_META_KEY: &str = "_perf";
+/// The symbol set on polyglot objects
+pub const POLYGLOT_SYMBOL: &str = "POLYGLOT";
+
+/// The JSON struct passed to the backend
+#[derive(Serialize)]
+pub struct BackendRequest<'a> {
+    pub object: &'a object::Info,
+    pub symbols: &'a Vec<String>,
+    pub relation_metadata: &'a object::Metadata,
+    /// Accept children ahead of the result
+    pub stream_children: bool,
+    /// The request id (echoed back in the result)
+    pub correlation_id: &'a str,
+}
+
+/// The backend capabilities, as advertised in reply to the hello request
+///
+/// Backends not supporting the handshake get the defaults
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackendInfo {
+    /// The number of requests the backend can process at the same time
+    pub concurrency: usize,
+    /// The backend name
+    pub name: String,
+    /// The backend version
+    pub version: String,
+    /// The object types the backend is meant to process
+    pub object_types: Vec<String>,
+    /// The limits the backend enforces on the objects it processes
+    pub limits: object::Metadata,
+    /// The symbols the backend can produce
+    pub symbols: Vec<String>,
+    /// The object metadata keys the backend can produce
+    pub metadata_keys: Vec<String>,
+}
+
+impl Default for BackendInfo {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            name: String::new(),
+            version: String::new(),
+            object_types: Vec::new(),
+            limits: object::Metadata::new(),
+            symbols: Vec::new(),
+            metadata_keys: Vec::new(),
+        }
+    }
+}
+
+/// A child handed over by the backend ahead of the result
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamedChild {
+    pub child: BackendResultChild,
+}
+
+/// The JSON result produced by the worker
+#[derive(Debug, Deserialize)]
+pub struct BackendResult {
+    #[serde(flatten)]
+    pub result: BackendResultKind,
+}
+
+impl BackendResult {
+    /// Creates a successful result carrying only the given symbols
+    ///
+    /// This is used when the backend is not invoked or cannot produce a result
+    pub fn with_symbols(symbols: Vec<String>) -> Self {
+        Self {
+            result: BackendResultKind::ok(BackendResultOk {
+                symbols,
+                object_metadata: object::Metadata::new(),
+                children: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// The ok/error portion of the result
+#[derive(Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum BackendResultKind {
+    error(String),
+    ok(BackendResultOk),
+}
+
+/// The ok portion of the result
+#[derive(Debug, Deserialize)]
+pub struct BackendResultOk {
+    pub symbols: Vec<String>,
+    pub object_metadata: object::Metadata,
+    pub children: Vec<BackendResultChild>,
+}
+
+/// A child object extracted by the backend
+#[derive(Clone, Debug, Deserialize)]
+pub struct BackendResultChild {
+    pub path: Option<String>,
+    pub force_type: Option<String>,
+    pub symbols: Vec<String>,
+    pub relation_metadata: object::Metadata,
+}
+
+/// Sends a hello request to the backend listening on `addr` and retrieves its capabilities
+///
+/// Note: backends not supporting the handshake just drop the connection, in
+/// which case the default capabilities are returned
+pub async fn hello(addr: &str) -> Result<BackendInfo, Box<dyn std::error::Error>> {
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    stream.write_all(br#"{"hello":{}}"#).await?;
+    stream.shutdown().await?;
+    let reply = crate::utils::read_all(&mut stream).await?;
+    let mut info = serde_json::from_slice::<BackendInfo>(&reply).unwrap_or_default();
+    info.concurrency = info.concurrency.max(1);
+    Ok(info)
+}
+
+/// Parses the backend reply to the request identified by `correlation_id`
+///
+/// Note: all the errors here are HARD errors
+pub fn parse_reply(
+    reply: &[u8],
+    correlation_id: &str,
+) -> Result<BackendResult, Box<dyn std::error::Error>> {
+    // Note: there's a little extra work here to ensure preference to the 'error'
+    // path in case the reply object is ambiguous and contains both the 'ok' and
+    // 'error' keys
+    let mut res: serde_json::Value = serde_json::from_slice(reply).map_err(|e| {
+        error!("Invalid JSON reply received from backend: {e}");
+        e
+    })?;
+    // After this point the reply is valid JSON
+    if res.get("error").is_some() && res.get("ok").is_some() {
+        warn!("Backend returned 'error' and 'ok' and at the same time, assuming error");
+        res.as_object_mut().unwrap().remove("ok");
+    }
+    // Note: backends not supporting concurrency do not echo the correlation id
+    if let Some(reply_id) = res.as_object_mut().and_then(|o| o.remove("correlation_id")) {
+        if reply_id != correlation_id {
+            error!("Backend replied with a mismatching correlation id: {reply_id}");
+            return Err("Correlation id mismatch".into());
+        }
+    }
+    let res: BackendResult = serde_json::from_value(res).map_err(|e| {
+        // Backend returned an invalid reply
+        error!("Invalid reply received from backend: {e}");
+        e
+    })?;
+    Ok(res)
+}
+
+/// Turns the backend produced symbols into valid symbols (uppercase alphanumeric)
+pub fn sanitize_backend_symbols(symbols: &mut [String]) {
+    for sym in symbols.iter_mut() {
+        let sanitized_sym = sym
+            .to_ascii_uppercase()
+            .replace(|c: char| !(c.is_ascii_alphanumeric() || c == '_'), "_");
+        if sanitized_sym != *sym {
+            warn!("Symbol \"{}\" sanitized to \"{}\"", sym, sanitized_sym);
+            *sym = sanitized_sym;
+        }
+    }
+}
+
+/// Merges the parent and clamd symbols into a successful backend result
+///
+/// The backend symbols are expected to be already sanitized (see
+/// [`sanitize_backend_symbols()`]); `clamd_res` is [`None`] if clamd is not used
+///
+/// The clamd scan time is added to `perf_meta`, which is then stored in the object
+/// metadata
+pub fn merge_result(
+    res: &mut BackendResultOk,
+    parent_symbols: &[String],
+    clamd_res: Option<crate::clamd::ScanResult>,
+    mut perf_meta: object::Metadata,
+) {
+    debug!("Merged {} exising symbols", parent_symbols.len());
+    res.symbols.extend(parent_symbols.iter().cloned());
+    match clamd_res {
+        None => {}
+        Some(Err(e)) => {
+            warn!("Clamd failed: {e}");
+            res.symbols.push("AV_SCAN_INCOMPLETE".to_string());
+        }
+        Some(Ok((clamd_symbols, scan_time))) => {
+            if !clamd_symbols.is_empty() {
+                debug!("Merged {} symbols from clamd", clamd_symbols.len());
+                let mut infected = false;
+                for sym in clamd_symbols {
+                    if sym.starts_with("ContexQL.") {
+                        res.symbols.push(sym);
+                    } else {
+                        infected = true;
+                        res.symbols.push(format!("INFECTED-CLAM-{}", sym));
+                    }
+                }
+                if infected {
+                    res.symbols.push("INFECTED".to_string());
+                }
+            }
+            perf_meta.insert("time_clamd".into(), serde_json::Value::from(scan_time));
+        }
+    }
+    res.object_metadata
+        .insert(PERF_META_KEY.into(), perf_meta.into());
+    object::sanitize_meta_keys(&mut res.object_metadata);
+    res.symbols.sort_unstable();
+    res.symbols.dedup();
+}
+
+/// A child job: an object along with the symbols and relation metadata it
+/// inherits from its parent
+#[derive(Debug)]
+pub struct ChildJob {
+    pub info: object::Info,
+    pub symbols: Vec<String>,
+    pub relation_metadata: object::Metadata,
+}
+
+impl ChildJob {
+    /// Returns true unless the child needs no processing (i.e. it's skipped or empty)
+    pub fn needs_processing(&self) -> bool {
+        !(self.info.is_skipped() || self.info.is_empty())
+    }
+
+    /// Returns the result of a child which needs no processing
+    pub fn into_result(self) -> crate::amqp::JobResult {
+        crate::amqp::JobResult {
+            info: self.info,
+            relation_metadata: self.relation_metadata,
+            result: crate::amqp::JobResultKind::ok(crate::amqp::JobResultOk {
+                symbols: self.symbols,
+                object_metadata: object::Metadata::new(),
+                children: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Creates the additional interpretations of a polyglot object
+///
+/// Each interpretation is the same object, typed as one of its secondary types
+pub fn interpretation_children(
+    info: &object::Info,
+    symbols: &[String],
+    relation_metadata: &object::Metadata,
+    interpretations: &[typedet::Candidate],
+) -> Vec<ChildJob> {
+    let primary_type = match &info.object_subtype {
+        Some(subtype) => format!("{}/{}", info.object_type, subtype),
+        None => info.object_type.clone(),
+    };
+    interpretations
+        .iter()
+        .map(|candidate| {
+            let mut info = info.clone();
+            info.set_type(&candidate.object_type);
+            let mut relation_metadata = relation_metadata.clone();
+            relation_metadata.insert(
+                crate::META_KEY_INTERPRETATION_OF.to_string(),
+                primary_type.clone().into(),
+            );
+            ChildJob {
+                info,
+                symbols: symbols.to_vec(),
+                relation_metadata,
+            }
+        })
+        .collect()
+}
+
+/// Creates the additional interpretations of a polyglot entry object
+///
+/// Their types are determined on submission and listed in the entry relation metadata
+pub fn entry_interpretations(entry: &object::Descriptor) -> Vec<ChildJob> {
+    let interpretations: Vec<typedet::Candidate> = match entry
+        .relation_metadata
+        .get(crate::META_KEY_INTERPRETATIONS)
+        .map(|v| serde_json::from_value(v.clone()))
+    {
+        Some(Ok(interpretations)) => interpretations,
+        Some(Err(e)) => {
+            warn!("Invalid entry interpretations: {e}");
+            return Vec::new();
+        }
+        None => return Vec::new(),
+    };
+    let mut info = entry.info.clone();
+    info.recursion_level += 1;
+    let mut relation_metadata = object::Metadata::new();
+    if let Some(global_meta) = entry.relation_metadata.get(crate::META_KEY_GLOBAL) {
+        relation_metadata.insert(crate::META_KEY_GLOBAL.to_string(), global_meta.clone());
+    }
+    interpretation_children(&info, &[], &relation_metadata, &interpretations)
+}
+
+/// Turns a backend child into an object via atomic copy+move and removes its tempfile
+///
+/// Children which the backend failed to extract become failed objects
+pub async fn child_to_object(
+    child: &BackendResultChild,
+    org: &str,
+    objects_path: &str,
+    recursion_level: u32,
+    ctime: f64,
+) -> Result<object::Info, Box<dyn std::error::Error>> {
+    let path = match child.path {
+        Some(ref path) => path,
+        None => return Ok(object::Info::new_failed(org, recursion_level, ctime)),
+    };
+    let res = object::Info::new_from_file(org, path, objects_path, recursion_level, ctime).await;
+    tokio::fs::remove_file(path).await.ok();
+    let mut object = res?;
+    if let Some(forced) = &child.force_type {
+        object.set_type(forced);
+    }
+    Ok(object)
+}
+
+/// Creates the child jobs for a backend child object
+///
+/// These are the object itself and, for polyglots, its other interpretations
+pub fn child_jobs(
+    object: object::Info,
+    interpretations: &[typedet::Candidate],
+    mut child: BackendResultChild,
+    parent: &object::Descriptor,
+) -> Vec<ChildJob> {
+    // Merge global relation_metadata into child relation_metadata
+    if let Some(global_meta) = parent.relation_metadata.get(crate::META_KEY_GLOBAL) {
+        child
+            .relation_metadata
+            .insert(crate::META_KEY_GLOBAL.to_string(), global_meta.clone());
+    }
+    sanitize_backend_symbols(&mut child.symbols);
+    object::sanitize_meta_keys(&mut child.relation_metadata);
+    // Polyglots are also processed as their other types, as siblings
+    let siblings = interpretation_children(
+        &object,
+        &child.symbols,
+        &child.relation_metadata,
+        interpretations,
+    );
+    if !siblings.is_empty() {
+        child.symbols.push(POLYGLOT_SYMBOL.to_string());
+        child.relation_metadata.insert(
+            crate::META_KEY_INTERPRETATIONS.to_string(),
+            serde_json::to_value(interpretations).unwrap(),
+        );
+    }
+    std::iter::once(ChildJob {
+        info: object,
+        symbols: child.symbols,
+        relation_metadata: child.relation_metadata,
+    })
+    .chain(siblings)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ok_result(symbols: &[&str]) -> BackendResultOk {
+        BackendResultOk {
+            symbols: symbols.iter().map(|s| s.to_string()).collect(),
+            object_metadata: object::Metadata::new(),
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let res = parse_reply(
+            br#"{"ok":{"symbols":["A"],"object_metadata":{},"children":[]},"correlation_id":"id"}"#,
+            "id",
+        )
+        .unwrap();
+        assert!(matches!(res.result, BackendResultKind::ok(ref ok) if ok.symbols == ["A"]));
+        // Ambiguous replies are errors
+        let res = parse_reply(
+            br#"{"ok":{"symbols":[],"object_metadata":{},"children":[]},"error":"failed"}"#,
+            "id",
+        )
+        .unwrap();
+        assert!(matches!(res.result, BackendResultKind::error(ref e) if e == "failed"));
+        assert!(parse_reply(br#"{"error":"failed","correlation_id":"other"}"#, "id").is_err());
+        assert!(parse_reply(b"not json", "id").is_err());
+    }
+
+    #[test]
+    fn test_merge_result() {
+        let mut symbols = vec!["lower-case".to_string(), "OK".to_string()];
+        sanitize_backend_symbols(&mut symbols);
+        assert_eq!(symbols, ["LOWER_CASE", "OK"]);
+
+        let mut res = ok_result(&["B", "A"]);
+        let clamd = Ok((
+            vec!["ContexQL.Match".to_string(), "Eicar-Test".to_string()],
+            0.5,
+        ));
+        merge_result(
+            &mut res,
+            &["A".to_string(), "C".to_string()],
+            Some(clamd),
+            object::Metadata::new(),
+        );
+        assert_eq!(
+            res.symbols,
+            [
+                "A",
+                "B",
+                "C",
+                "ContexQL.Match",
+                "INFECTED",
+                "INFECTED-CLAM-Eicar-Test"
+            ]
+        );
+        assert_eq!(res.object_metadata[PERF_META_KEY]["time_clamd"], 0.5);
+
+        let mut res = ok_result(&[]);
+        merge_result(
+            &mut res,
+            &[],
+            Some(Err("unreachable".into())),
+            object::Metadata::new(),
+        );
+        assert_eq!(res.symbols, ["AV_SCAN_INCOMPLETE"]);
+
+        let mut res = ok_result(&[]);
+        merge_result(&mut res, &[], None, object::Metadata::new());
+        assert!(res.symbols.is_empty());
+    }
+}
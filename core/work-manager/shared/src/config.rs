@@ -26,6 +26,34 @@ pub struct ClamdServiceConfig {
     pub objects_path: String,
 }
 
+#[derive(Deserialize, Default)]
+/// Object type detection configuration
+pub struct TypedetConfig {
+    /// Additional signature database files
+    pub signatures: Option<Vec<String>>,
+    /// The confidence below which clamd is consulted
+    min_confidence: Option<u8>,
+    /// The confidence above which additional types are processed
+    polyglot_min_confidence: Option<u8>,
+    /// The optional clamd fallback
+    #[serde(flatten)]
+    pub clamd: Option<ClamdServiceConfig>,
+}
+
+impl TypedetConfig {
+    /// The confidence below which clamd is consulted (default 60)
+    pub fn get_min_confidence(&self) -> u8 {
+        self.min_confidence.unwrap_or(60).min(100)
+    }
+
+    /// The confidence at which secondary types are also processed (default 60)
+    ///
+    /// Values above 100 disable polyglot processing
+    pub fn get_polyglot_min_confidence(&self) -> u8 {
+        self.polyglot_min_confidence.unwrap_or(60)
+    }
+}
+
 #[derive(Deserialize)]
 /// GraphDB configuration
 pub struct DBConfig {
@@ -0,0 +1,606 @@
+//! Fuzzy (similarity preserving) hashes
+//!
+//! Provides streaming implementations of the ssdeep (context triggered
+//! piecewise hash) and TLSH (trend micro locality sensitive hash) algorithms,
+//! as well as their comparison functions
+//!
+//! Note: the same comparisons are provided to ContexQL by the database
+//! functions `ssdeep_compare` and `tlsh_distance`
+
+const SPAMSUM_LENGTH: usize = 64;
+const NUM_BLOCKHASHES: usize = 31;
+const MIN_BLOCKSIZE: u32 = 3;
+const ROLLING_WINDOW: usize = 7;
+const HASH_INIT: u8 = 0x27;
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn ssdeep_bs(index: usize) -> u32 {
+    MIN_BLOCKSIZE << index
+}
+
+/// The (FNV based) piecewise hash, reduced to its 6 lowest bits
+fn sum_hash(c: u8, h: u8) -> u8 {
+    (h.wrapping_mul(0x93) ^ c) & 0x3f
+}
+
+/// The ssdeep rolling hash
+#[derive(Default)]
+struct Roll {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl Roll {
+    fn hash(&mut self, c: u8) {
+        let cc = u32::from(c);
+        self.h2 = self
+            .h2
+            .wrapping_sub(self.h1)
+            .wrapping_add(ROLLING_WINDOW as u32 * cc);
+        self.h1 = self
+            .h1
+            .wrapping_add(cc)
+            .wrapping_sub(u32::from(self.window[self.n]));
+        self.window[self.n] = c;
+        self.n = (self.n + 1) % ROLLING_WINDOW;
+        self.h3 = (self.h3 << 5) ^ cc;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BlockHash {
+    h: u8,
+    halfh: u8,
+    digest: [u8; SPAMSUM_LENGTH],
+    halfdigest: u8,
+    dlen: usize,
+}
+
+impl Default for BlockHash {
+    fn default() -> Self {
+        Self {
+            h: HASH_INIT,
+            halfh: HASH_INIT,
+            digest: [0; SPAMSUM_LENGTH],
+            halfdigest: 0,
+            dlen: 0,
+        }
+    }
+}
+
+/// A streaming ssdeep hasher
+pub struct Ssdeep {
+    bh: [BlockHash; NUM_BLOCKHASHES],
+    bhstart: usize,
+    bhend: usize,
+    total_size: u64,
+    roll: Roll,
+    lasth: Option<u8>,
+}
+
+impl Ssdeep {
+    pub fn new() -> Self {
+        Self {
+            bh: [BlockHash::default(); NUM_BLOCKHASHES],
+            bhstart: 0,
+            bhend: 1,
+            total_size: 0,
+            roll: Roll::default(),
+            lasth: None,
+        }
+    }
+
+    fn try_fork_blockhash(&mut self) {
+        if self.bhend < NUM_BLOCKHASHES {
+            let last = self.bh[self.bhend - 1];
+            self.bh[self.bhend] = BlockHash {
+                h: last.h,
+                halfh: last.halfh,
+                ..Default::default()
+            };
+            self.bhend += 1;
+        } else if self.lasth.is_none() {
+            self.lasth = Some(self.bh[self.bhend - 1].h);
+        }
+    }
+
+    fn try_reduce_blockhash(&mut self) {
+        if self.bhend - self.bhstart < 2
+            || u64::from(ssdeep_bs(self.bhstart)) * SPAMSUM_LENGTH as u64 >= self.total_size
+            || self.bh[self.bhstart + 1].dlen < SPAMSUM_LENGTH / 2
+        {
+            return;
+        }
+        self.bhstart += 1;
+    }
+
+    fn step(&mut self, c: u8) {
+        self.roll.hash(c);
+        let h = self.roll.sum();
+        for bh in self.bh[self.bhstart..self.bhend].iter_mut() {
+            bh.h = sum_hash(c, bh.h);
+            bh.halfh = sum_hash(c, bh.halfh);
+        }
+        if let Some(lasth) = self.lasth.as_mut() {
+            *lasth = sum_hash(c, *lasth);
+        }
+        let mut i = self.bhstart;
+        while i < self.bhend {
+            let bs = ssdeep_bs(i);
+            if h % bs != bs - 1 {
+                // Note: if this fails for one block size, it fails for all the larger ones
+                break;
+            }
+            if self.bh[i].dlen == 0 {
+                self.try_fork_blockhash();
+            }
+            let bh = &mut self.bh[i];
+            bh.digest[bh.dlen] = B64[usize::from(bh.h)];
+            bh.halfdigest = B64[usize::from(bh.halfh)];
+            if bh.dlen < SPAMSUM_LENGTH - 1 {
+                bh.dlen += 1;
+                bh.digest[bh.dlen] = 0;
+                bh.h = HASH_INIT;
+                if bh.dlen < SPAMSUM_LENGTH / 2 {
+                    bh.halfh = HASH_INIT;
+                    bh.halfdigest = 0;
+                }
+            } else {
+                self.try_reduce_blockhash();
+            }
+            i += 1;
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.total_size = self.total_size.saturating_add(buf.len() as u64);
+        for c in buf {
+            self.step(*c);
+        }
+    }
+
+    /// Returns the hash in the canonical `blocksize:hash:hash` form
+    ///
+    /// Returns `None` if the data is too large to be hashed
+    pub fn digest(&self) -> Option<String> {
+        let mut bi = self.bhstart;
+        let h = self.roll.sum();
+        while u64::from(ssdeep_bs(bi)) * (SPAMSUM_LENGTH as u64) < self.total_size {
+            bi += 1;
+            if bi >= NUM_BLOCKHASHES {
+                return None;
+            }
+        }
+        bi = bi.min(self.bhend - 1);
+        while bi > self.bhstart && self.bh[bi].dlen < SPAMSUM_LENGTH / 2 {
+            bi -= 1;
+        }
+        let mut res = format!("{}:", ssdeep_bs(bi)).into_bytes();
+        let bh = &self.bh[bi];
+        res.extend_from_slice(&bh.digest[..bh.dlen]);
+        if h != 0 {
+            res.push(B64[usize::from(bh.h)]);
+        } else if bh.digest[bh.dlen] != 0 {
+            res.push(bh.digest[bh.dlen]);
+        }
+        res.push(b':');
+        if bi < self.bhend - 1 {
+            let bh = &self.bh[bi + 1];
+            let len = bh.dlen.min(SPAMSUM_LENGTH / 2 - 1);
+            res.extend_from_slice(&bh.digest[..len]);
+            if h != 0 {
+                res.push(B64[usize::from(bh.halfh)]);
+            } else if bh.halfdigest != 0 {
+                res.push(bh.halfdigest);
+            }
+        } else if h != 0 {
+            if bi == 0 {
+                res.push(B64[usize::from(self.bh[bi].h)]);
+            } else if let Some(lasth) = self.lasth {
+                res.push(B64[usize::from(lasth)]);
+            }
+        }
+        // Safe because only ASCII is pushed
+        Some(String::from_utf8(res).unwrap())
+    }
+}
+
+impl Default for Ssdeep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reduces sequences of more than 3 identical characters to 3
+fn eliminate_sequences(s: &str) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::with_capacity(s.len());
+    for &c in s.as_bytes() {
+        if res.len() >= 3 && res[res.len() - 3..].iter().all(|p| *p == c) {
+            continue;
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Weighted edit distance (insertions and removals cost 1, replacements cost 2)
+fn edit_distance(s1: &[u8], s2: &[u8]) -> usize {
+    let mut prev: Vec<usize> = (0..=s2.len()).collect();
+    let mut cur = vec![0usize; s2.len() + 1];
+    for (i, c1) in s1.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, c2) in s2.iter().enumerate() {
+            let replace = prev[j] + if c1 == c2 { 0 } else { 2 };
+            cur[j + 1] = replace.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[s2.len()]
+}
+
+fn score_strings(s1: &[u8], s2: &[u8], block_size: u64) -> u32 {
+    if s1.len() > SPAMSUM_LENGTH || s2.len() > SPAMSUM_LENGTH {
+        return 0;
+    }
+    let common = s1
+        .windows(ROLLING_WINDOW)
+        .any(|w| s2.windows(ROLLING_WINDOW).any(|w2| w == w2));
+    if !common {
+        return 0;
+    }
+    let score = edit_distance(s1, s2) * SPAMSUM_LENGTH / (s1.len() + s2.len());
+    let score = 100 * score / SPAMSUM_LENGTH;
+    if score >= 100 {
+        return 0;
+    }
+    let score = (100 - score) as u64;
+    let cap_threshold = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE as u64;
+    if block_size >= cap_threshold {
+        return score as u32;
+    }
+    let cap = block_size / u64::from(MIN_BLOCKSIZE) * s1.len().min(s2.len()) as u64;
+    score.min(cap) as u32
+}
+
+fn parse_ssdeep(hash: &str) -> Option<(u64, &str, &str)> {
+    let mut parts = hash.splitn(3, ':');
+    let bs: u32 = parts.next()?.parse().ok()?;
+    let b1 = parts.next()?;
+    let b2 = parts.next()?;
+    let b2 = b2.split_once(',').map(|(b2, _)| b2).unwrap_or(b2);
+    Some((u64::from(bs), b1, b2))
+}
+
+/// Compares two ssdeep hashes
+///
+/// Returns the match score, from 0 (no similarity) to 100 (identical), or
+/// `None` if any hash is invalid
+pub fn ssdeep_compare(hash1: &str, hash2: &str) -> Option<u32> {
+    let (bs1, h1b1, h1b2) = parse_ssdeep(hash1)?;
+    let (bs2, h2b1, h2b2) = parse_ssdeep(hash2)?;
+    if bs1 != bs2 && bs1 * 2 != bs2 && bs2 * 2 != bs1 {
+        return Some(0);
+    }
+    let (h1b1, h1b2) = (eliminate_sequences(h1b1), eliminate_sequences(h1b2));
+    let (h2b1, h2b2) = (eliminate_sequences(h2b1), eliminate_sequences(h2b2));
+    if bs1 == bs2 && h1b1 == h2b1 && h1b2 == h2b2 {
+        return Some(100);
+    }
+    Some(if bs1 == bs2 {
+        score_strings(&h1b1, &h2b1, bs1).max(score_strings(&h1b2, &h2b2, bs1 * 2))
+    } else if bs1 * 2 == bs2 {
+        score_strings(&h1b2, &h2b1, bs2)
+    } else {
+        score_strings(&h1b1, &h2b2, bs1)
+    })
+}
+
+/// The Pearson hash table used by TLSH
+#[rustfmt::skip]
+const V_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163,
+    14, 197, 213, 181, 161, 85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200,
+    110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222,
+    25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235,
+    97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248,
+    174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243,
+    132, 56, 148, 75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219,
+    119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32, 136, 114, 52, 10,
+    138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152,
+    170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131,
+    125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123,
+    118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229,
+    27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39, 203,
+    233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76,
+    140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120,
+    51, 65, 28, 144, 254, 221, 93, 189, 194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+const TLSH_WINDOW: usize = 5;
+const TLSH_BUCKETS: usize = 128;
+const TLSH_CODE_SIZE: usize = TLSH_BUCKETS / 4;
+const TLSH_MIN_DATA_LENGTH: u64 = 50;
+
+fn b_mapping(salt: u8, i: u8, j: u8, k: u8) -> u8 {
+    let mut h = V_TABLE[usize::from(salt)];
+    h = V_TABLE[usize::from(h ^ i)];
+    h = V_TABLE[usize::from(h ^ j)];
+    V_TABLE[usize::from(h ^ k)]
+}
+
+/// The TLSH length encoding
+fn l_capturing(len: u64) -> u8 {
+    let log = (len as f64).ln();
+    let l = if len <= 656 {
+        (log / 0.4054651).floor()
+    } else if len <= 3199 {
+        (log / 0.26236426 - 8.72777).floor()
+    } else {
+        (log / 0.09531018 - 62.5472).floor()
+    };
+    (l as i64 & 0xff) as u8
+}
+
+fn swap_nibbles(b: u8) -> u8 {
+    b.rotate_left(4)
+}
+
+/// A streaming TLSH hasher
+pub struct Tlsh {
+    window: [u8; TLSH_WINDOW],
+    buckets: [u32; 256],
+    checksum: u8,
+    data_len: u64,
+}
+
+impl Tlsh {
+    pub fn new() -> Self {
+        Self {
+            window: [0; TLSH_WINDOW],
+            buckets: [0; 256],
+            checksum: 0,
+            data_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let mut j = (self.data_len % TLSH_WINDOW as u64) as usize;
+        for &c in buf {
+            self.window[j] = c;
+            if self.data_len >= 4 {
+                let w = |back: usize| self.window[(j + TLSH_WINDOW - back) % TLSH_WINDOW];
+                let (c0, c1, c2, c3, c4) = (c, w(1), w(2), w(3), w(4));
+                self.checksum = b_mapping(0, c0, c1, self.checksum);
+                for r in [
+                    b_mapping(2, c0, c1, c2),
+                    b_mapping(3, c0, c1, c3),
+                    b_mapping(5, c0, c2, c3),
+                    b_mapping(7, c0, c2, c4),
+                    b_mapping(11, c0, c1, c4),
+                    b_mapping(13, c0, c3, c4),
+                ] {
+                    self.buckets[usize::from(r)] = self.buckets[usize::from(r)].wrapping_add(1);
+                }
+            }
+            self.data_len += 1;
+            j = (j + 1) % TLSH_WINDOW;
+        }
+    }
+
+    /// Returns the hash in the `T1` hex form
+    ///
+    /// Returns `None` if the data is too short or not varied enough
+    pub fn digest(&self) -> Option<String> {
+        if self.data_len < TLSH_MIN_DATA_LENGTH {
+            return None;
+        }
+        let buckets = &self.buckets[..TLSH_BUCKETS];
+        let mut sorted = buckets.to_vec();
+        sorted.sort_unstable();
+        let q1 = sorted[TLSH_BUCKETS / 4 - 1];
+        let q2 = sorted[TLSH_BUCKETS / 2 - 1];
+        let q3 = sorted[TLSH_BUCKETS - TLSH_BUCKETS / 4 - 1];
+        if q3 == 0 || buckets.iter().filter(|b| **b > 0).count() <= TLSH_BUCKETS / 2 {
+            return None;
+        }
+        let mut code = [0u8; TLSH_CODE_SIZE];
+        for (i, h) in code.iter_mut().enumerate() {
+            for j in 0..4 {
+                let k = buckets[4 * i + j];
+                if q3 < k {
+                    *h += 3 << (j * 2);
+                } else if q2 < k {
+                    *h += 2 << (j * 2);
+                } else if q1 < k {
+                    *h += 1 << (j * 2);
+                }
+            }
+        }
+        // Note: computed in floating point, like the reference implementation
+        let q1ratio = ((q1 as f32 * 100.0 / q3 as f32) as u32 % 16) as u8;
+        let q2ratio = ((q2 as f32 * 100.0 / q3 as f32) as u32 % 16) as u8;
+        let mut res = String::from("T1");
+        for b in [
+            swap_nibbles(self.checksum),
+            swap_nibbles(l_capturing(self.data_len)),
+            (q1ratio << 4) | q2ratio,
+        ]
+        .into_iter()
+        .chain(code.into_iter().rev())
+        {
+            res.push_str(&format!("{b:02X}"));
+        }
+        Some(res)
+    }
+}
+
+impl Default for Tlsh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_tlsh(hash: &str) -> Option<Vec<u8>> {
+    let hex = hash.strip_prefix("T1")?;
+    if hex.len() != 2 * (3 + TLSH_CODE_SIZE) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn mod_diff(x: u8, y: u8, range: u32) -> u32 {
+    let dl = u32::from(x.abs_diff(y));
+    dl.min(range - dl)
+}
+
+/// Computes the distance between two TLSH hashes
+///
+/// Returns the distance, from 0 (identical) upwards, or `None` if any hash
+/// is invalid
+pub fn tlsh_distance(hash1: &str, hash2: &str) -> Option<u32> {
+    let (h1, h2) = (parse_tlsh(hash1)?, parse_tlsh(hash2)?);
+    let mut diff = match mod_diff(swap_nibbles(h1[1]), swap_nibbles(h2[1]), 256) {
+        d @ (0 | 1) => d,
+        d => d * 12,
+    };
+    for (q1, q2) in [(h1[2] >> 4, h2[2] >> 4), (h1[2] & 0xf, h2[2] & 0xf)] {
+        diff += match mod_diff(q1, q2, 16) {
+            d @ (0 | 1) => d,
+            d => (d - 1) * 12,
+        };
+    }
+    if h1[0] != h2[0] {
+        diff += 1;
+    }
+    for (b1, b2) in h1[3..].iter().zip(h2[3..].iter()) {
+        for shift in [0, 2, 4, 6] {
+            diff += match ((b1 >> shift) & 3).abs_diff((b2 >> shift) & 3) {
+                3 => 6,
+                d => u32::from(d),
+            };
+        }
+    }
+    Some(diff)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn hashes(data: &[u8]) -> (String, Option<String>) {
+        let (mut ssdeep, mut tlsh) = (Ssdeep::new(), Tlsh::new());
+        // Note: chunking must not affect the result
+        for chunk in data.chunks(1000) {
+            ssdeep.update(chunk);
+            tlsh.update(chunk);
+        }
+        let (mut ssdeep1, mut tlsh1) = (Ssdeep::new(), Tlsh::new());
+        ssdeep1.update(data);
+        tlsh1.update(data);
+        assert_eq!(ssdeep.digest(), ssdeep1.digest());
+        assert_eq!(tlsh.digest(), tlsh1.digest());
+        (ssdeep.digest().unwrap(), tlsh.digest())
+    }
+
+    #[test]
+    fn test_ssdeep() {
+        assert_eq!(Ssdeep::new().digest().unwrap(), "3::");
+        let data = sample(100_000, 1);
+        let (hash, _) = hashes(&data);
+        let (bs, b1, b2) = parse_ssdeep(&hash).unwrap();
+        assert!(bs >= 3 && !b1.is_empty() && !b2.is_empty());
+        assert_eq!(ssdeep_compare(&hash, &hash), Some(100));
+
+        let mut similar = data.clone();
+        similar[50_000..50_010].copy_from_slice(b"0123456789");
+        let (similar, _) = hashes(&similar);
+        let score = ssdeep_compare(&hash, &similar).unwrap();
+        assert!(score > 50 && score < 100, "{score}");
+
+        let (different, _) = hashes(&sample(100_000, 2));
+        assert_eq!(ssdeep_compare(&hash, &different), Some(0));
+        assert_eq!(ssdeep_compare(&hash, "garbage"), None);
+    }
+
+    #[test]
+    fn test_ssdeep_reference() {
+        // Note: digests and score produced by libfuzzy (as published in the
+        // python-ssdeep documentation)
+        let digest = |s: &str| {
+            let mut ssdeep = Ssdeep::new();
+            ssdeep.update(s.as_bytes());
+            ssdeep.digest().unwrap()
+        };
+        let hash1 = digest("Also called fuzzy hashes, Ctph can match inputs that have homologies.");
+        let hash2 = digest("Also called fuzzy hashes, CTPH can match inputs that have homologies.");
+        assert_eq!(hash1, "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C");
+        assert_eq!(hash2, "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C");
+        assert_eq!(ssdeep_compare(&hash1, &hash2), Some(22));
+    }
+
+    #[test]
+    fn test_tlsh() {
+        assert_eq!(hashes(b"too short").1, None);
+        assert_eq!(hashes(&[0u8; 1000]).1, None);
+        let data = sample(100_000, 1);
+        let hash = hashes(&data).1.unwrap();
+        assert_eq!(hash.len(), 72);
+        assert_eq!(tlsh_distance(&hash, &hash), Some(0));
+
+        let mut similar = data.clone();
+        similar[50_000..50_010].copy_from_slice(b"0123456789");
+        let similar = hashes(&similar).1.unwrap();
+        let distance = tlsh_distance(&hash, &similar).unwrap();
+        assert!(distance < 50, "{distance}");
+
+        let different =
+            hashes(b"The quick brown fox jumps over the lazy dog, then naps in the sun").1;
+        let distance = tlsh_distance(&hash, &different.unwrap()).unwrap();
+        assert!(distance > 100, "{distance}");
+        assert_eq!(tlsh_distance(&hash, "T1XYZ"), None);
+    }
+
+    #[test]
+    fn test_tlsh_large_quartiles() {
+        // Note: bucket counts of this magnitude take inputs of several GB
+        let mut tlsh = Tlsh::new();
+        tlsh.data_len = 1 << 33;
+        for (i, b) in tlsh.buckets[..TLSH_BUCKETS].iter_mut().enumerate() {
+            *b = 50_000_000 + i as u32 * 1_000_000;
+        }
+        let hash = tlsh.digest().unwrap();
+        let (q1, q2, q3) = (81_000_000f32, 113_000_000f32, 145_000_000f32);
+        let q1ratio = (q1 * 100.0 / q3) as u32 % 16;
+        let q2ratio = (q2 * 100.0 / q3) as u32 % 16;
+        assert_eq!(hash[6..8], format!("{:X}{:X}", q1ratio, q2ratio));
+    }
+
+    #[test]
+    fn test_v_table() {
+        let mut seen = [false; 256];
+        for v in V_TABLE {
+            assert!(!seen[usize::from(v)]);
+            seen[usize::from(v)] = true;
+        }
+    }
+}
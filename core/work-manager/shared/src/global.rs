@@ -0,0 +1,248 @@
+//! Global scenario query evaluation
+//!
+//! The global (context) query of a scenario is tested against the works
+//! submitted around the time of the work being evaluated (its neighbors)
+
+use pgrules::Interval;
+use tokio_postgres::{GenericClient, Statement, Transaction};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+/// The statements used to look up the neighbors of a work
+pub struct Neighbors {
+    get_before: Statement,
+    get_before_count: Statement,
+    get_after: Statement,
+    get_after_count: Statement,
+}
+
+impl Neighbors {
+    /// Prepares the neighbor lookup statements
+    pub async fn prepare<C: GenericClient + Sync>(
+        client: &C,
+    ) -> Result<Self, tokio_postgres::Error> {
+        let qtypes = &[
+            tokio_postgres::types::Type::TEXT,
+            tokio_postgres::types::Type::INTERVAL,
+        ];
+        let get_before = client
+            .prepare_typed(
+                "
+            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
+            SELECT work_id, ref.t - objects.t dt
+            FROM objects, ref
+            WHERE
+                objects.t >= ref.t - $2 AND
+                objects.t <= ref.t AND
+                work_id != $1 AND
+                is_entry
+            ORDER BY objects.t DESC",
+                qtypes,
+            )
+            .await?;
+        let get_before_count = client
+            .prepare_typed(
+                "
+            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
+            SELECT COUNT(*)
+            FROM objects, ref
+            WHERE
+                objects.t >= ref.t - $2 AND
+                objects.t <= ref.t AND
+                work_id != $1 AND
+                is_entry",
+                qtypes,
+            )
+            .await?;
+        let get_after = client
+            .prepare_typed(
+                "
+            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
+            SELECT work_id, objects.t - ref.t AS dt
+            FROM objects, ref
+            WHERE
+                objects.t > ref.t AND
+                objects.t <= ref.t + $2 AND
+                is_entry
+            ORDER BY objects.t ASC",
+                qtypes,
+            )
+            .await?;
+        let get_after_count = client
+            .prepare_typed(
+                "
+            WITH ref AS (SELECT t FROM objects WHERE is_entry AND work_id = $1 LIMIT 1)
+            SELECT COUNT(*)
+            FROM objects, ref
+            WHERE
+                objects.t > ref.t AND
+                objects.t <= ref.t + $2 AND
+                is_entry",
+                qtypes,
+            )
+            .await?;
+        Ok(Self {
+            get_before,
+            get_before_count,
+            get_after,
+            get_after_count,
+        })
+    }
+}
+
+async fn portal_next(
+    txn: &Transaction<'_>,
+    portal: &tokio_postgres::Portal,
+) -> Result<Option<tokio_postgres::row::Row>, tokio_postgres::error::Error> {
+    txn.query_portal(portal, 1).await.map(|mut rows| rows.pop())
+}
+
+/// A compiled global query
+pub struct GlobalQuery {
+    query: String,
+    has_with_clause: bool,
+    settings: pgrules::GlobalquerySettings,
+}
+
+impl GlobalQuery {
+    /// Compiles a global query
+    pub fn compile(global_query: &str) -> Result<Self, String> {
+        let global_parsed = pgrules::parse_to_sql(global_query, pgrules::QueryType::ScenarioGlobal)
+            .map_err(|e| e.to_string())?;
+        let Some(settings) = global_parsed.global_query_settings else {
+            return Err("missing global query settings".to_string());
+        };
+        let has_with_clause = global_parsed.with_clause.is_some();
+        let query = format!(
+            "{} SELECT EXISTS (SELECT 1 {})",
+            global_parsed.with_clause.unwrap_or_default(),
+            global_parsed.query
+        );
+        Ok(Self {
+            query,
+            has_with_clause,
+            settings,
+        })
+    }
+
+    /// Tests the global query against the neighbors of a work
+    ///
+    /// The transaction is expected to be read-only and repeatable-read
+    pub async fn matches(
+        &self,
+        txn: &Transaction<'_>,
+        neighbors: &Neighbors,
+        work_id: &str,
+        scenario: &str,
+    ) -> Result<bool, tokio_postgres::Error> {
+        let time_window = &self.settings.time_window;
+        let required_matches = &self.settings.matches;
+        let global_stmt = txn.prepare(&self.query).await?;
+        let params: &[&(dyn tokio_postgres::types::ToSql + Sync)] = &[&work_id, time_window];
+        let before_it = txn.bind(&neighbors.get_before, params).await?;
+        let after_it = txn.bind(&neighbors.get_after, params).await?;
+        let avail_before: i64 = txn
+            .query_one(&neighbors.get_before_count, params)
+            .await?
+            .get(0);
+        let avail_after: i64 = txn
+            .query_one(&neighbors.get_after_count, params)
+            .await?
+            .get(0);
+        let avail_neighbors: u32 = avail_before
+            .saturating_add(avail_after)
+            .try_into()
+            .unwrap_or(u32::MAX);
+        let max_neighbors = self.settings.max_neighbors.unwrap_or(avail_neighbors);
+        let total_neigbors = max_neighbors.min(avail_neighbors);
+        let mut before = portal_next(txn, &before_it).await?;
+        debug!("Before: {:?}", before);
+        let mut after = portal_next(txn, &after_it).await?;
+        debug!("After: {:?}", after);
+        let mut nmatches = 0u32;
+        let target_matches = match required_matches {
+            pgrules::Matches::MoreThan(req) => *req,
+            pgrules::Matches::MoreThanPercent(req) => {
+                (f64::from(*req) / 100.0 * f64::from(total_neigbors)) as u32
+            }
+            pgrules::Matches::LessThan(req) => (*req).saturating_sub(1),
+            pgrules::Matches::LessThanPercent(req) => {
+                ((f64::from(*req) / 100.0 * f64::from(total_neigbors)) as u32).saturating_sub(1)
+            }
+            pgrules::Matches::None => 0,
+        };
+        let mut attempts = 0u32;
+        for _ in 0..max_neighbors {
+            attempts += 1;
+            if nmatches > target_matches {
+                break;
+            }
+            let neighbour_work_id = if let Some(before_row) = &before {
+                if let Some(after_row) = &after {
+                    let before_t: Interval = before_row.try_get("dt")?;
+                    let after_t: Interval = after_row.try_get("dt")?;
+                    if before_t <= after_t {
+                        let work_id: String = before_row.try_get("work_id")?;
+                        before = portal_next(txn, &before_it).await?;
+                        work_id
+                    } else {
+                        let work_id: String = after_row.try_get("work_id")?;
+                        after = portal_next(txn, &after_it).await?;
+                        work_id
+                    }
+                } else {
+                    let work_id: String = before_row.try_get("work_id")?;
+                    before = portal_next(txn, &before_it).await?;
+                    work_id
+                }
+            } else if let Some(after_row) = &after {
+                let work_id: String = after_row.try_get("work_id")?;
+                after = portal_next(txn, &after_it).await?;
+                work_id
+            } else {
+                // not reached
+                break;
+            };
+            let row = if self.has_with_clause {
+                txn.query_one(&global_stmt, &[&neighbour_work_id, &work_id])
+                    .await?
+            } else {
+                txn.query_one(&global_stmt, &[&neighbour_work_id]).await?
+            };
+
+            let global_match: bool = row.try_get(0)?;
+            if global_match {
+                nmatches += 1;
+            }
+            debug!(
+                "Scenario {}: {}global match on {} ({} matches so far) - lookup {}/{}",
+                scenario,
+                if global_match { "" } else { "no " },
+                neighbour_work_id,
+                nmatches,
+                attempts,
+                max_neighbors
+            );
+        }
+        let matched = match required_matches {
+            pgrules::Matches::MoreThan(_) | pgrules::Matches::MoreThanPercent(_) => {
+                nmatches > target_matches
+            }
+            pgrules::Matches::LessThan(_) | pgrules::Matches::LessThanPercent(_) => {
+                nmatches <= target_matches
+            }
+            pgrules::Matches::None => nmatches == 0,
+        };
+        debug!(
+            "Scenario {}: condition ({}) {}met (matches: {}, target: {}, max_neighbors: {}, attempts: {})",
+            scenario,
+            required_matches,
+            if matched { "" } else { "not "},
+            nmatches,
+            target_matches,
+            max_neighbors,
+            attempts,
+        );
+        Ok(matched)
+    }
+}
@@ -1,13 +1,19 @@
 //! Shared library with common structs and routines
 
 pub mod amqp;
+pub mod api;
+pub mod backend;
 pub mod clamd;
 pub mod config;
+pub mod fuzzy;
+#[cfg(feature = "postgres")]
+pub mod global;
 pub mod object;
 pub mod scene;
+pub mod typedet;
 pub mod utils;
 
-use serde::Serializer;
+use serde::{Deserialize, Deserializer, Serializer};
 
 /// The maximum time a work can take (from the entry to the result)
 ///
@@ -18,6 +24,7 @@ use serde::Serializer;
 /// The actual TTL is specified per work request. This value acts both as a
 /// default (if not specified) and as an absolute maximum (larger TTLs are
 /// silently capped)
+#[allow(clippy::identity_op)]
 pub const MAX_WORK_TTL: std::time::Duration = std::time::Duration::from_secs(1 * 60 * 60);
 
 /// The maximum recursion level a work can reach
@@ -35,18 +42,30 @@ pub const MAX_WORK_DEPTH: u32 = 24;
 pub const RESULTS_QUEUE_NAME: &str = "CTX-JobRes";
 /// The name of the global director queue
 pub const DIRECTOR_QUEUE_NAME: &str = "CTX-Director";
+/// The name of the global work status queue
+pub const STATUS_QUEUE_NAME: &str = "CTX-Status";
 /// The name of the global scenario reload exchange
 pub const SC_RELOAD_EXCHANGE_NAME: &str = "ctx.screload";
+/// The name of the global backend result cache invalidation exchange
+pub const CACHE_INVALIDATE_EXCHANGE_NAME: &str = "ctx.cacheinval";
+/// The name of the global backend registry exchange
+pub const BACKENDS_EXCHANGE_NAME: &str = "ctx.backends";
 /// The `content-type` to use in all the messages
 pub const MSG_CONTENT_TYPE: &str = "application/json";
 /// The `message-type` to use in job requests
 pub const REQUEST_TYPE: &str = "job.request";
 /// The `message-type` to use in job results
 pub const RESULT_TYPE: &str = "job.result";
+/// The `message-type` to use in work progress updates
+pub const PROGRESS_TYPE: &str = "work.progress";
 /// The `message-type` to use in scenario application requests
 pub const SC_PROCESS_TYPE: &str = "scenarios.process";
 /// The `message-type` to use in scenario reload requests
 pub const SC_RELOAD_TYPE: &str = "scenarios.reload";
+/// The `message-type` to use in backend result cache invalidation requests
+pub const CACHE_INVALIDATE_TYPE: &str = "cache.invalidate";
+/// The `message-type` to use in backend announcements
+pub const BACKEND_ANNOUNCE_TYPE: &str = "backend.announce";
 /// The length of the `correlation_id` to use in messages
 pub const MSG_CORRID_LEN: usize = 24;
 /// The relation metadata key holding the work global data (bubbled)
@@ -55,9 +74,19 @@ pub const META_KEY_GLOBAL: &str = "_global";
 pub const META_KEY_ORIGIN: &str = "_origin";
 /// The relation metatadata origin key controlling work reprocession
 pub const META_KEY_REPROCESSABLE: &str = "_can_reprocess";
+/// The relation metadata key linking a reprocessed work to the work it supersedes
+pub const META_KEY_REPROCESS_OF: &str = "_reprocess_of";
+/// The relation metadata key listing the additional types of a polyglot object
+pub const META_KEY_INTERPRETATIONS: &str = "_interpretations";
+/// The relation metadata key marking an additional interpretation of a polyglot object
+///
+/// The value is the primary type of the object
+pub const META_KEY_INTERPRETATION_OF: &str = "_interpretation_of";
+/// The object metadata key holding the version of the backend which produced it
+pub const META_KEY_BACKEND_VERSION: &str = "_backend_version";
 
 /// The expected database version
-pub const DB_SCHEMA_VERSION: i32 = 4;
+pub const DB_SCHEMA_VERSION: i32 = 13;
 
 pub fn time_to_f64<S: Serializer>(
     time: &std::time::SystemTime,
@@ -69,3 +98,30 @@ pub fn time_to_f64<S: Serializer>(
             .as_secs_f64(),
     )
 }
+
+pub fn f64_to_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<std::time::SystemTime, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    std::time::Duration::try_from_secs_f64(secs)
+        .map(|d| std::time::UNIX_EPOCH + d)
+        .map_err(serde::de::Error::custom)
+}
+
+pub fn time_to_f64_opt<S: Serializer>(
+    time: &Option<std::time::SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(t) => time_to_f64(t, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn f64_to_time_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<std::time::SystemTime>, D::Error> {
+    #[derive(Deserialize)]
+    struct Wrap(#[serde(deserialize_with = "f64_to_time")] std::time::SystemTime);
+    Ok(Option::<Wrap>::deserialize(deserializer)?.map(|w| w.0))
+}
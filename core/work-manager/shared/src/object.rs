@@ -1,5 +1,6 @@
 //! Object definitions
-use crate::utils::{get_queue_for, mktemp};
+use crate::fuzzy::{Ssdeep, Tlsh};
+use crate::utils::{get_lane_for, mktemp};
 use digest::{Digest, DynDigest};
 use md5::Md5;
 use serde::{ser::SerializeMap, ser::SerializeSeq, Deserialize, Serialize, Serializer};
@@ -7,7 +8,6 @@ use sha1::Sha1;
 use sha2::{Sha256, Sha512};
 use std::collections::HashMap;
 use std::fmt::Write;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 #[allow(unused_imports)]
 use tracing::{debug, error, info, warn};
 
@@ -52,6 +52,22 @@ pub fn sanitize_meta_keys(meta: &mut Metadata) {
     }
 }
 
+/// The work priority
+///
+/// Each priority has its own request queue (lane) for each object type; workers
+/// drain the lanes with weighted fairness so that interactive works are not
+/// stuck behind bulk submissions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// User triggered analyses (default)
+    #[default]
+    Interactive,
+    /// Mass submissions and reprocessing
+    Bulk,
+}
+
 /// The full object descriptor (as received in job requests)
 #[derive(Deserialize, Debug)]
 pub struct Descriptor {
@@ -59,6 +75,14 @@ pub struct Descriptor {
     pub symbols: Vec<String>,
     pub relation_metadata: Metadata,
     pub max_recursion: u32,
+    /// The id of the work this object belongs to
+    ///
+    /// Not present in work requests where the work id is the `correlation_id`
+    #[serde(default)]
+    pub work_id: Option<String>,
+    /// The work priority (inherited by children)
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 /// Same as [`Descriptor`] but using references (used in publishing job requests)
@@ -69,6 +93,9 @@ pub struct DescriptorRef<'a> {
     #[serde(serialize_with = "serialize_meta")]
     pub relation_metadata: &'a Metadata,
     pub max_recursion: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_id: Option<&'a str>,
+    pub priority: Priority,
 }
 
 impl<'a> From<&'a Descriptor> for DescriptorRef<'a> {
@@ -78,15 +105,25 @@ impl<'a> From<&'a Descriptor> for DescriptorRef<'a> {
             symbols: &d.symbols,
             relation_metadata: &d.relation_metadata,
             max_recursion: d.max_recursion,
+            work_id: d.work_id.as_deref(),
+            priority: d.priority,
         }
     }
 }
 
+impl DescriptorRef<'_> {
+    /// Convenience fn to retrieve the request queue (lane) for the object priority
+    pub fn request_queue(&self) -> String {
+        get_lane_for(&self.info.object_type, self.priority)
+    }
+}
+
 /// The hash type to use as the object id
 pub const OBJECT_ID_HASH_TYPE: &str = "sha256";
 
 /// The JSON representing the object to perform work upon
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Info {
     /// The object origin
     pub org: String,
@@ -163,55 +200,71 @@ impl Info {
         }
     }
 
+    /// Copies `inf` to `outf` computing its size, entropy and hashes
+    ///
+    /// Note: hashing is CPU heavy, therefore this runs on the blocking thread pool
     async fn hash_and_copy(
         src_file: &std::path::Path,
-        mut inf: tokio::fs::File,
+        inf: tokio::fs::File,
         dst_file: &std::path::Path,
-        mut outf: tokio::fs::File,
+        outf: tokio::fs::File,
     ) -> Result<(u64, f64, HashMap<String, String>), Box<dyn std::error::Error>> {
-        let mut hasher = Hasher::new();
-        let mut buf = [0u8; 4096];
-        let mut size = 0u64;
-        let mut ent = ShannonEntropy::new();
-        loop {
-            match inf.read(&mut buf[..]).await {
-                Ok(0) => break,
-                Ok(len) => {
-                    size += u64::try_from(len).unwrap();
-                    hasher.update(&buf[0..len]);
-                    ent.update(&buf[0..len]);
-                    outf.write_all(&buf[0..len]).await.map_err(|e| {
-                        error!(
-                            "Failed to write to temp object \"{}\": {}",
-                            dst_file.display(),
+        let mut inf = inf.into_std().await;
+        let mut outf = outf.into_std().await;
+        let src_file = src_file.to_path_buf();
+        let dst_file = dst_file.to_path_buf();
+        let res = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Write as _};
+            let mut hasher = Hasher::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            let mut size = 0u64;
+            let mut ent = ShannonEntropy::new();
+            loop {
+                match inf.read(&mut buf[..]) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        size += u64::try_from(len).unwrap();
+                        hasher.update(&buf[0..len]);
+                        ent.update(&buf[0..len]);
+                        outf.write_all(&buf[0..len]).map_err(|e| {
+                            error!(
+                                "Failed to write to temp object \"{}\": {}",
+                                dst_file.display(),
+                                e
+                            );
                             e
-                        );
-                        e
-                    })?;
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(e) => {
-                    error!("Failed to read from \"{}\": {}", src_file.display(), e);
-                    return Err(e.into());
+                        })?;
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to read from \"{}\": {}", src_file.display(), e);
+                        return Err(e);
+                    }
                 }
             }
-        }
-        outf.flush().await.map_err(|e| {
-            error!(
-                "Failed to flush temp object \"{}\": {}",
-                dst_file.display(),
+            outf.flush().map_err(|e| {
+                error!(
+                    "Failed to flush temp object \"{}\": {}",
+                    dst_file.display(),
+                    e
+                );
                 e
-            );
+            })?;
+            // FIXME: sync or don't?
+            //outf.sync_all().map_err(|e| {
+            //    error!("Failed to sync temp object \"{}\": {}", dst_file, e);
+            //    e
+            //})?;
+            Ok((size, ent.entropy(), hasher.into_map()))
+        })
+        .await
+        .map_err(|e| {
+            error!("Hashing task failed: {}", e);
             e
         })?;
-        // FIXME: sync or don't?
-        //outf.sync_all().await.map_err(|e| {
-        //    error!("Failed to sync temp object \"{}\": {}", dst_file, e);
-        //    e
-        //})?;
-        Ok((size, ent.entropy(), hasher.into_map()))
+        Ok(res?)
     }
 
     /// Returns a failed child
@@ -258,11 +311,6 @@ impl Info {
         self.size == 0
     }
 
-    /// Convenience fn to retrieve the proper request queue for an object
-    pub fn request_queue(&self) -> String {
-        get_queue_for(&self.object_type)
-    }
-
     /// Returns the work creation time of this object as [`SystemTime`](std::time::SystemTime)
     pub fn work_creation_time(&self) -> std::time::SystemTime {
         std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs_f64(self.ctime)
@@ -279,26 +327,42 @@ impl Info {
     }
 }
 
-pub struct Hasher(Vec<(&'static str, Box<dyn DynDigest>)>);
+/// Computes all the object hashes, including the fuzzy ones, in a single pass
+pub struct Hasher {
+    digests: Vec<(&'static str, Box<dyn DynDigest>)>,
+    ssdeep: Ssdeep,
+    tlsh: Tlsh,
+}
 
 impl Hasher {
     pub fn new() -> Self {
-        Self(vec![
-            ("md5", Box::new(Md5::new())),
-            ("sha1", Box::new(Sha1::new())),
-            ("sha256", Box::new(Sha256::new())),
-            ("sha512", Box::new(Sha512::new())),
-        ])
+        Self {
+            digests: vec![
+                ("md5", Box::new(Md5::new())),
+                ("sha1", Box::new(Sha1::new())),
+                ("sha256", Box::new(Sha256::new())),
+                ("sha512", Box::new(Sha512::new())),
+            ],
+            ssdeep: Ssdeep::new(),
+            tlsh: Tlsh::new(),
+        }
     }
 
     pub fn update(&mut self, buf: &[u8]) {
-        for (_, h) in self.0.iter_mut() {
+        for (_, h) in self.digests.iter_mut() {
             h.update(buf);
         }
+        self.ssdeep.update(buf);
+        self.tlsh.update(buf);
     }
 
+    /// Returns the hashes by type
+    ///
+    /// Note: the fuzzy hashes are omitted when they cannot be computed (e.g. a
+    /// TLSH of a short or uniform object)
     pub fn into_map(self) -> HashMap<String, String> {
-        self.0
+        let mut res: HashMap<String, String> = self
+            .digests
             .into_iter()
             .map(|h| {
                 (
@@ -309,7 +373,14 @@ impl Hasher {
                     }),
                 )
             })
-            .collect()
+            .collect();
+        if let Some(h) = self.ssdeep.digest() {
+            res.insert("ssdeep".to_string(), h);
+        }
+        if let Some(h) = self.tlsh.digest() {
+            res.insert("tlsh".to_string(), h);
+        }
+        res
     }
 }
 
@@ -481,4 +552,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn priority_lanes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut desc: Descriptor = serde_json::from_value(serde_json::json!({
+            "info": Info::new_failed("ctx", 1, 0.0),
+            "symbols": [],
+            "relation_metadata": {},
+            "max_recursion": 5,
+        }))?;
+        desc.info.object_type = "Zip".to_string();
+        assert_eq!(desc.priority, Priority::Interactive);
+        assert_eq!(DescriptorRef::from(&desc).request_queue(), "CTX-JobReq-ZIP");
+
+        desc.priority = Priority::Bulk;
+        let desc_ref = DescriptorRef::from(&desc);
+        assert_eq!(desc_ref.request_queue(), "CTX-JobReq-ZIP-Bulk");
+        let json = serde_json::to_value(&desc_ref)?;
+        assert_eq!(json["priority"], "bulk");
+        let desc: Descriptor = serde_json::from_value(json)?;
+        assert_eq!(desc.priority, Priority::Bulk);
+        Ok(())
+    }
 }
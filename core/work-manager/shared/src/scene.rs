@@ -4,17 +4,49 @@ use serde::{Deserialize, Serialize};
 const SCN_MIN_VER: &str = ">=1.3.0";
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Scenario {
     pub name: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>))]
     pub compatible_with: Option<semver::VersionReq>,
     pub creator: String,
     pub description: String,
     pub local_query: String,
     pub context: Option<Contextual>,
-    pub action: String,
+    pub action: Action,
+    /// Whether the scenario is evaluated at all
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Whether matches are acted upon or just recorded
+    #[serde(default)]
+    pub mode: ScenarioMode,
+    /// The orgs whose works the scenario applies to (empty means all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub orgs: Vec<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// The scenario evaluation mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioMode {
+    /// Matches trigger the scenario action
+    #[default]
+    Live,
+    /// Matches are recorded but not acted upon (staging)
+    Monitor,
 }
 
 impl Scenario {
+    /// Returns true if the scenario applies to works of the given org
+    pub fn applies_to(&self, org: &str) -> bool {
+        self.orgs.is_empty() || self.orgs.iter().any(|o| o == org)
+    }
+
     pub fn is_compatible(&self) -> bool {
         self.compatible_with
             .as_ref()
@@ -31,6 +63,7 @@ impl Scenario {
 }
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Contextual {
     pub global_query: String,
 }
@@ -40,17 +73,293 @@ pub struct DirectorRequest {
     pub work_id: String,
 }
 
+/// The verdict of an action, from the least to the most decisive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Verdict {
+    Allow,
+    Alert,
+    Quarantine,
+    Block,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Allow => "ALLOW",
+            Self::Alert => "ALERT",
+            Self::Quarantine => "QUARANTINE",
+            Self::Block => "BLOCK",
+        })
+    }
+}
+
+impl std::str::FromStr for Verdict {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "ALLOW" => Ok(Self::Allow),
+            "ALERT" => Ok(Self::Alert),
+            "QUARANTINE" => Ok(Self::Quarantine),
+            "BLOCK" => Ok(Self::Block),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The severity of an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+/// The action triggered by a matching scenario
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(from = "ActionDef")]
+pub struct Action {
+    /// The action name
+    pub name: String,
+    /// The action verdict
+    pub verdict: Verdict,
+    /// The action severity
+    pub severity: Severity,
+    /// The MITRE ATT&CK technique ids (e.g. `T1566.001`)
+    pub mitre_techniques: Vec<String>,
+    /// The priority used to resolve conflicting verdicts (higher wins)
+    pub priority: i32,
+    /// Arbitrary action parameters
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub params: serde_json::Map<String, serde_json::Value>,
+    /// The names of the executors (as configured in the director) the
+    /// action is dispatched to
+    pub executors: Vec<String>,
+}
+
+/// The accepted representations of an [`Action`]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActionDef {
+    /// Legacy free-form action string
+    Legacy(String),
+    Structured {
+        name: String,
+        verdict: Verdict,
+        #[serde(default)]
+        severity: Severity,
+        #[serde(default)]
+        mitre_techniques: Vec<String>,
+        #[serde(default)]
+        priority: i32,
+        #[serde(default)]
+        params: serde_json::Map<String, serde_json::Value>,
+        #[serde(default)]
+        executors: Vec<String>,
+    },
+}
+
+impl From<ActionDef> for Action {
+    fn from(def: ActionDef) -> Self {
+        match def {
+            // Note: legacy actions named after a verdict carry that verdict,
+            // all other legacy actions are alerts
+            ActionDef::Legacy(name) => Self {
+                verdict: name.parse().unwrap_or(Verdict::Alert),
+                name,
+                severity: Severity::default(),
+                mitre_techniques: Vec::new(),
+                priority: 0,
+                params: serde_json::Map::new(),
+                executors: Vec::new(),
+            },
+            ActionDef::Structured {
+                name,
+                verdict,
+                severity,
+                mitre_techniques,
+                priority,
+                params,
+                executors,
+            } => Self {
+                name,
+                verdict,
+                severity,
+                mitre_techniques,
+                priority,
+                params,
+                executors,
+            },
+        }
+    }
+}
+
+impl Action {
+    /// Checks the action for validity
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.name.is_empty() {
+            return Err("Invalid action");
+        }
+        if !self.mitre_techniques.iter().all(|t| is_technique_id(t)) {
+            return Err("Invalid MITRE ATT&CK technique id");
+        }
+        if self.executors.iter().any(|e| e.is_empty()) {
+            return Err("Invalid executor name");
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `id` has the form `Tnnnn` or `Tnnnn.nnn`
+fn is_technique_id(id: &str) -> bool {
+    let Some(id) = id.strip_prefix('T') else {
+        return false;
+    };
+    let (technique, sub) = match id.split_once('.') {
+        Some((technique, sub)) => (technique, Some(sub)),
+        None => (id, None),
+    };
+    technique.len() == 4
+        && technique.bytes().all(|b| b.is_ascii_digit())
+        && sub
+            .map(|s| s.len() == 3 && s.bytes().all(|b| b.is_ascii_digit()))
+            .unwrap_or(true)
+}
+
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct WorkAction {
     pub scenario: String,
     pub ctime: f64,
-    pub action: String,
+    pub action: Action,
+    /// Set if the scenario is in monitor mode (the action is not enforced)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub monitor: bool,
+}
+
+/// Resolves the actions triggered on a work into a single verdict
+///
+/// The verdict of the highest priority action wins; among actions of the
+/// same priority the most decisive verdict wins. Works which triggered no
+/// actions are allowed. Actions of monitored scenarios are ignored
+pub fn final_verdict(actions: &[WorkAction]) -> Verdict {
+    actions
+        .iter()
+        .filter(|a| !a.monitor)
+        .map(|a| (a.action.priority, a.action.verdict))
+        .max()
+        .map(|(_, verdict)| verdict)
+        .unwrap_or(Verdict::Allow)
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct WorkActions {
     pub work_id: String,
-    #[serde(serialize_with = "crate::time_to_f64")]
+    #[serde(
+        serialize_with = "crate::time_to_f64",
+        deserialize_with = "crate::f64_to_time"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
     pub t: std::time::SystemTime,
+    /// The final verdict resolved from the actions
+    pub verdict: Verdict,
     pub actions: Vec<WorkAction>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_action() {
+        let legacy: Action = serde_json::from_str(r#""block""#).unwrap();
+        assert_eq!(legacy.name, "block");
+        assert_eq!(legacy.verdict, Verdict::Block);
+        let legacy: Action = serde_json::from_str(r#""notify SOC""#).unwrap();
+        assert_eq!(legacy.verdict, Verdict::Alert);
+        assert!(legacy.validate().is_ok());
+
+        let action: Action = serde_json::from_value(serde_json::json!({
+            "name": "phishing",
+            "verdict": "QUARANTINE",
+            "severity": "high",
+            "mitre_techniques": ["T1566", "T1566.001"],
+            "priority": 10,
+            "params": { "folder": "junk" }
+        }))
+        .unwrap();
+        assert_eq!(action.verdict, Verdict::Quarantine);
+        assert_eq!(action.severity, Severity::High);
+        assert!(action.validate().is_ok());
+        let roundtrip: Action =
+            serde_json::from_value(serde_json::to_value(&action).unwrap()).unwrap();
+        assert_eq!(roundtrip, action);
+
+        let invalid = Action {
+            mitre_techniques: vec!["T15".to_string()],
+            ..action
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_scenario_scope() {
+        let mut scenario: Scenario = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "compatible_with": ">=1.3.0",
+            "creator": "me",
+            "description": "",
+            "local_query": "true",
+            "context": null,
+            "action": "ALERT",
+        }))
+        .unwrap();
+        assert!(scenario.enabled);
+        assert_eq!(scenario.mode, ScenarioMode::Live);
+        assert!(scenario.applies_to("ctx"));
+        scenario.orgs = vec!["ctx".to_string()];
+        assert!(scenario.applies_to("ctx"));
+        assert!(!scenario.applies_to("other"));
+    }
+
+    #[test]
+    fn test_final_verdict() {
+        let action = |verdict: &str, priority: i32| WorkAction {
+            scenario: String::new(),
+            ctime: 0.0,
+            action: serde_json::from_value(serde_json::json!({
+                "name": verdict,
+                "verdict": verdict,
+                "priority": priority,
+            }))
+            .unwrap(),
+            monitor: false,
+        };
+        assert_eq!(final_verdict(&[]), Verdict::Allow);
+        assert_eq!(
+            final_verdict(&[action("ALERT", 0), action("BLOCK", 0)]),
+            Verdict::Block
+        );
+        assert_eq!(
+            final_verdict(&[action("BLOCK", 0), action("ALLOW", 100)]),
+            Verdict::Allow
+        );
+        let monitored = WorkAction {
+            monitor: true,
+            ..action("BLOCK", 100)
+        };
+        assert_eq!(
+            final_verdict(&[action("ALERT", 0), monitored]),
+            Verdict::Alert
+        );
+    }
+}
@@ -0,0 +1,846 @@
+//! Object type detection
+//!
+//! Object types are determined in process by matching the object content
+//! against a database of magic signatures (see `typedet.db` for the format),
+//! with a text heuristic for objects matching no signature
+//!
+//! Clamd can optionally be consulted when the native detection is not
+//! conclusive
+use crate::clamd::Clamd;
+use crate::config::TypedetConfig;
+use crate::object;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const TYPEDET_COUNT: &str = "typedet_total";
+const TYPEDET_TIME: &str = "typedet_time_seconds";
+const TYPEDET_FALLBACK_COUNT: &str = "typedet_fallback_total";
+
+/// The builtin signature database
+const BUILTIN_SIGNATURES: &str = include_str!("typedet.db");
+
+/// The size of the object start and end windows signatures are matched against
+pub const WINDOW_SIZE: usize = 64 * 1024;
+
+/// The type assigned to objects which cannot be identified
+pub const UNKNOWN_TYPE: &str = "UNKNOWN";
+
+/// The maximum number of additional interpretations of a polyglot object
+pub const MAX_INTERPRETATIONS: usize = 3;
+
+/// The confidence assigned to objects identified by the text heuristic
+const TEXT_CONFIDENCE: u8 = 50;
+
+/// A single pattern element
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Elem {
+    /// An exact byte
+    Byte(u8),
+    /// An ASCII case insensitive byte (stored in lowercase)
+    Nocase(u8),
+    /// Any byte
+    Any,
+}
+
+impl Elem {
+    fn matches(&self, b: u8) -> bool {
+        match self {
+            Self::Byte(v) => *v == b,
+            Self::Nocase(v) => *v == b.to_ascii_lowercase(),
+            Self::Any => true,
+        }
+    }
+}
+
+/// Where a pattern is located
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Offset {
+    /// At the exact offset
+    At(usize),
+    /// Starting anywhere within the (inclusive) range
+    Range(usize, usize),
+    /// Anywhere within the start or the end window
+    Anywhere,
+    /// At the exact offset from the end of the object
+    FromEnd(usize),
+}
+
+/// A signature condition
+#[derive(Debug, Clone)]
+struct Condition {
+    offset: Offset,
+    pattern: Vec<Elem>,
+}
+
+fn pattern_at(pattern: &[Elem], data: &[u8], pos: usize) -> bool {
+    data.get(pos..pos + pattern.len()).is_some_and(|window| {
+        pattern
+            .iter()
+            .zip(window.iter())
+            .all(|(elem, b)| elem.matches(*b))
+    })
+}
+
+fn pattern_in(pattern: &[Elem], data: &[u8], start: usize, end: usize) -> bool {
+    let end = end.min(data.len().saturating_sub(pattern.len()));
+    (start..=end).any(|pos| pattern_at(pattern, data, pos))
+}
+
+impl Condition {
+    fn matches(&self, sample: &Sample) -> bool {
+        match self.offset {
+            Offset::At(pos) => pattern_at(&self.pattern, sample.head, pos),
+            Offset::Range(start, end) => pattern_in(&self.pattern, sample.head, start, end),
+            Offset::Anywhere => {
+                pattern_in(&self.pattern, sample.head, 0, usize::MAX)
+                    || pattern_in(&self.pattern, sample.tail, 0, usize::MAX)
+            }
+            Offset::FromEnd(pos) => sample
+                .tail
+                .len()
+                .checked_sub(pos)
+                .is_some_and(|pos| pattern_at(&self.pattern, sample.tail, pos)),
+        }
+    }
+}
+
+/// A type signature
+#[derive(Debug, Clone)]
+struct Signature {
+    object_type: String,
+    confidence: u8,
+    conditions: Vec<Condition>,
+}
+
+/// An error in a signature database
+#[derive(Debug)]
+pub struct SignatureError {
+    source: String,
+    line: usize,
+    message: String,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid signature at {}:{}: {}",
+            self.source, self.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Splits a signature line into tokens, keeping quoted strings together
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut token = String::from(c);
+        let mut quoted = c == '"';
+        if c == 'i' && chars.peek() == Some(&'"') {
+            token.push('"');
+            chars.next();
+            quoted = true;
+        }
+        if quoted {
+            loop {
+                match chars.next() {
+                    Some('\\') => {
+                        token.push('\\');
+                        token.push(chars.next().ok_or("unterminated escape")?);
+                    }
+                    Some('"') => {
+                        token.push('"');
+                        break;
+                    }
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_offset(s: &str) -> Result<Offset, String> {
+    let num = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("invalid offset \"{s}\""))
+    };
+    if s == "*" {
+        Ok(Offset::Anywhere)
+    } else if let Some(pos) = s.strip_prefix("EOF-") {
+        Ok(Offset::FromEnd(num(pos)?))
+    } else if let Some((start, end)) = s.split_once('-') {
+        let (start, end) = (num(start)?, num(end)?);
+        if start > end {
+            return Err(format!("invalid offset range \"{s}\""));
+        }
+        Ok(Offset::Range(start, end))
+    } else {
+        Ok(Offset::At(num(s)?))
+    }
+}
+
+fn parse_string(s: &str, nocase: bool, pattern: &mut Vec<Elem>) -> Result<(), String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(
+                    u8::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("invalid escape \"\\x{hex}\""))?,
+                );
+            }
+            Some(c) => return Err(format!("invalid escape \"\\{c}\"")),
+            None => return Err("unterminated escape".to_string()),
+        }
+    }
+    pattern.extend(bytes.into_iter().map(|b| {
+        if nocase {
+            Elem::Nocase(b.to_ascii_lowercase())
+        } else {
+            Elem::Byte(b)
+        }
+    }));
+    Ok(())
+}
+
+fn parse_hex(s: &str, pattern: &mut Vec<Elem>) -> Result<(), String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("invalid hex pattern \"{s}\""));
+    }
+    for i in (0..s.len()).step_by(2) {
+        let pair = &s[i..i + 2];
+        if pair == "??" {
+            pattern.push(Elem::Any);
+        } else {
+            pattern.push(Elem::Byte(
+                u8::from_str_radix(pair, 16).map_err(|_| format!("invalid hex pattern \"{s}\""))?,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_condition(tokens: &[String]) -> Result<Condition, String> {
+    let (offset, parts) = tokens.split_first().ok_or("empty condition")?;
+    let offset = parse_offset(offset)?;
+    let mut pattern = Vec::new();
+    for part in parts {
+        if let Some(s) = part.strip_prefix("i\"") {
+            parse_string(&s[..s.len() - 1], true, &mut pattern)?;
+        } else if let Some(s) = part.strip_prefix('"') {
+            parse_string(&s[..s.len() - 1], false, &mut pattern)?;
+        } else {
+            parse_hex(part, &mut pattern)?;
+        }
+    }
+    if pattern.is_empty() {
+        return Err("empty pattern".to_string());
+    }
+    let reach = match offset {
+        Offset::At(pos) | Offset::Range(_, pos) => pos + pattern.len(),
+        Offset::FromEnd(pos) => pos.max(pattern.len()),
+        Offset::Anywhere => pattern.len(),
+    };
+    if reach > WINDOW_SIZE {
+        return Err("condition exceeds the detection window".to_string());
+    }
+    Ok(Condition { offset, pattern })
+}
+
+fn parse_signature(line: &str) -> Result<Signature, String> {
+    let tokens = tokenize(line)?;
+    let mut tokens = tokens.split(|t| t == "&");
+    let first = tokens.next().unwrap_or_default();
+    if first.len() < 3 {
+        return Err("missing type, confidence or condition".to_string());
+    }
+    let object_type = first[0].clone();
+    let confidence: u8 = first[1]
+        .parse()
+        .ok()
+        .filter(|c| *c <= 100)
+        .ok_or_else(|| format!("invalid confidence \"{}\"", first[1]))?;
+    let mut conditions = vec![parse_condition(&first[2..])?];
+    for tokens in tokens {
+        conditions.push(parse_condition(tokens)?);
+    }
+    Ok(Signature {
+        object_type,
+        confidence,
+        conditions,
+    })
+}
+
+/// The portions of an object which signatures are matched against
+pub struct Sample<'a> {
+    /// The start of the object (up to `WINDOW_SIZE` bytes)
+    pub head: &'a [u8],
+    /// The end of the object (up to `WINDOW_SIZE` bytes)
+    pub tail: &'a [u8],
+}
+
+/// A type candidate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    /// The object type (and subtype)
+    pub object_type: String,
+    /// The detection confidence (0-100)
+    pub confidence: u8,
+}
+
+/// A signature database
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    signatures: Vec<Signature>,
+    /// Pairs of types where the former also matches the latter by design
+    supersedes: Vec<(String, String)>,
+}
+
+impl Signatures {
+    /// Parses a signature database
+    ///
+    /// The source is only used in error messages
+    pub fn parse(source: &str, text: &str) -> Result<Self, SignatureError> {
+        let mut db = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |message| SignatureError {
+                source: source.to_string(),
+                line: i + 1,
+                message,
+            };
+            if let Some(types) = line.strip_prefix("%supersedes ") {
+                let mut types = types.split_whitespace();
+                match (types.next(), types.next(), types.next()) {
+                    (Some(a), Some(b), None) => {
+                        db.supersedes.push((a.to_string(), b.to_string()));
+                    }
+                    _ => return Err(err("invalid supersedes directive".to_string())),
+                }
+                continue;
+            }
+            db.signatures.push(parse_signature(line).map_err(err)?);
+        }
+        Ok(db)
+    }
+
+    /// Returns the builtin signature database
+    pub fn builtin() -> Self {
+        Self::parse("builtin", BUILTIN_SIGNATURES).expect("Invalid builtin signatures")
+    }
+
+    /// Appends the signatures of another database
+    pub fn extend(&mut self, other: Self) {
+        self.signatures.extend(other.signatures);
+        self.supersedes.extend(other.supersedes);
+    }
+
+    /// Returns the types matching the sample, most confident first
+    ///
+    /// Each type is reported once, with the confidence of its best signature;
+    /// types superseded by another matching type are not reported
+    pub fn detect(&self, sample: &Sample) -> Vec<Candidate> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for sig in self
+            .signatures
+            .iter()
+            .filter(|sig| sig.conditions.iter().all(|c| c.matches(sample)))
+        {
+            match candidates
+                .iter_mut()
+                .find(|c| c.object_type == sig.object_type)
+            {
+                Some(c) => c.confidence = c.confidence.max(sig.confidence),
+                None => candidates.push(Candidate {
+                    object_type: sig.object_type.clone(),
+                    confidence: sig.confidence,
+                }),
+            }
+        }
+        let superseded: Vec<&str> = self
+            .supersedes
+            .iter()
+            .filter(|(a, _)| candidates.iter().any(|c| &c.object_type == a))
+            .map(|(_, b)| b.as_str())
+            .collect();
+        candidates.retain(|c| !superseded.contains(&c.object_type.as_str()));
+        // Note: the sort is stable so earlier signatures win ties
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.confidence));
+        candidates
+    }
+}
+
+/// The outcome of a type detection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Detection {
+    /// The detected object type (and subtype)
+    pub object_type: String,
+    /// The detection confidence (0-100)
+    pub confidence: u8,
+    /// The other plausible types, most confident first
+    pub candidates: Vec<Candidate>,
+}
+
+impl Detection {
+    fn unknown() -> Self {
+        Self {
+            object_type: UNKNOWN_TYPE.to_string(),
+            confidence: 0,
+            candidates: Vec::new(),
+        }
+    }
+}
+
+/// Determines whether the data looks like text
+fn is_text(buf: &[u8], object_id: &str) -> bool {
+    if buf.starts_with(b"\xff\xfe") {
+        debug!("Text type confirmed for object \"{object_id}\" (UTF-16LE bom)");
+        return true;
+    }
+    if buf.starts_with(b"\xfe\xff") {
+        debug!("Text type confirmed for object \"{object_id}\" (UTF-16BE bom)");
+        return true;
+    }
+    if buf.starts_with(b"\xef\xbb\xbf") {
+        debug!("Text type confirmed for object \"{object_id}\" (UTF-8 bom)");
+        return true;
+    }
+    #[rustfmt::skip]
+    const UTF8_LUT: [i8; 256] = [
+        /* 0x00 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x10 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x20 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x30 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x40 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x50 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x60 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x70 */ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        /* 0x80 */ -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2,
+        /* 0x90 */ -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2,
+        /* 0xa0 */ -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2,
+        /* 0xb0 */ -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2, -2,
+        /* 0xc0 */ -1, -1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        /* 0xd0 */ 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        /* 0xe0 */ 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+        /* 0xf0 */ 3, 3, 3, 3, 3, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+    ];
+    let mut char_counts = [0u32; 256];
+    let mut continuations = 0i8;
+    let mut non_zero_chars = 0u32;
+    let mut bad_utf8_chars = 0u32;
+    for &b in buf.iter() {
+        char_counts[usize::from(b)] += 1;
+        let code = UTF8_LUT[usize::from(b)];
+        if continuations > 0 {
+            if code != -2 {
+                bad_utf8_chars += 1;
+            }
+            continuations -= 1;
+        } else if b == 0 {
+            continue;
+        } else if code < 0 {
+            bad_utf8_chars += 1;
+        } else {
+            continuations = code;
+        }
+        non_zero_chars += 1;
+    }
+    let invalid_utf8_ratio = f64::from(bad_utf8_chars) / f64::from(non_zero_chars);
+    if non_zero_chars > 0 && invalid_utf8_ratio < 0.05 {
+        // Parses as UTF-8 (possibly with statistically insignificant errors)
+        // U+0000 is ignored as although it's valid unicode as it doesn't convey any
+        // significance to text and it's also vastly reppresented in binary files
+        debug!(
+            "Text type confirmed for object \"{}\" ({:.2}% invalid UTF-8)",
+            object_id,
+            invalid_utf8_ratio * 100.0
+        );
+        return true;
+    }
+    let wsp_chars = char_counts[0x0a] + char_counts[32] + char_counts[0x09];
+    let ctrl_chars: u32 = char_counts
+        .into_iter()
+        .take(32)
+        .enumerate()
+        .filter_map(|(i, c)| {
+            if ![0, 9, 10, 11, 12, 13].contains(&i) {
+                // sum up counts for non printable chars in low ascii
+                Some(c)
+            } else {
+                None
+            }
+        })
+        .sum();
+    let size = buf.len() as f64;
+    let wsp_ratio = f64::from(wsp_chars) / size;
+    let ctrl_ratio = f64::from(ctrl_chars) / size;
+    if wsp_ratio > 0.02 && ctrl_ratio < 0.001 {
+        debug!(
+            "Text type confirmed for object \"{}\" ({:.2}% whitespace, {:.2} control chars)",
+            object_id,
+            wsp_ratio * 100.0,
+            ctrl_ratio * 100.0,
+        );
+        return true;
+    }
+    debug!(
+        "Text type not confirmed for object \"{}\" ({:.2}% invalid UTF-8, {:.2}% whitespace, {:.2} control chars)",
+        object_id,
+        invalid_utf8_ratio * 100.0,
+        wsp_ratio * 100.0,
+        ctrl_ratio * 100.0,
+    );
+    false
+}
+
+/// Reads the samples used for text detection
+///
+/// Small objects are read in full, larger objects are sampled in a few chunks
+/// spread across the whole file
+async fn read_text_sample(f: &mut tokio::fs::File, size: u64) -> Result<Vec<u8>, std::io::Error> {
+    const CHUNK_SIZE: u64 = 512;
+    const CHUNK_SIZE_BUF: usize = CHUNK_SIZE as usize; // safe
+    const NCHUNKS: u64 = 4;
+    let buf_size = usize::try_from(size.min(CHUNK_SIZE * NCHUNKS)).unwrap(); // safe bc min
+    let mut buf = vec![0u8; buf_size];
+    if size <= CHUNK_SIZE * NCHUNKS {
+        f.seek(std::io::SeekFrom::Start(0)).await?;
+        f.read_exact(&mut buf).await?;
+    } else {
+        let chunk_size = size / NCHUNKS;
+        let mut cur_buf = buf.as_mut_slice();
+        for i in 0u64..NCHUNKS {
+            f.seek(std::io::SeekFrom::Start(chunk_size * i)).await?;
+            f.read_exact(&mut cur_buf[0..CHUNK_SIZE_BUF]).await?;
+            cur_buf = &mut cur_buf[CHUNK_SIZE_BUF..];
+        }
+    }
+    Ok(buf)
+}
+
+/// The object type detector
+#[derive(Clone)]
+pub struct Typedet {
+    signatures: Arc<Signatures>,
+    min_confidence: u8,
+    polyglot_min_confidence: u8,
+    clamd: Option<Clamd>,
+}
+
+impl Typedet {
+    /// Creates a new type detector
+    ///
+    /// The builtin signatures are extended with the configured signature files
+    pub fn new(config: &TypedetConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        metrics::describe_counter!(TYPEDET_COUNT, "Total number of type detections");
+        metrics::describe_histogram!(
+            TYPEDET_TIME,
+            metrics::Unit::Seconds,
+            "Time to detect the type of an object"
+        );
+        metrics::describe_counter!(
+            TYPEDET_FALLBACK_COUNT,
+            "Number of type detections deferred to clamd"
+        );
+        // Note: custom signatures are listed first so that they win ties
+        let mut signatures = Signatures::default();
+        for path in config.signatures.iter().flatten() {
+            let text = std::fs::read_to_string(path).inspect_err(|e| {
+                error!("Failed to read signature file \"{path}\": {e}");
+            })?;
+            signatures.extend(Signatures::parse(path, &text)?);
+        }
+        signatures.extend(Signatures::builtin());
+        Ok(Self {
+            signatures: Arc::new(signatures),
+            min_confidence: config.get_min_confidence(),
+            polyglot_min_confidence: config.get_polyglot_min_confidence(),
+            clamd: config.clamd.as_ref().map(Clamd::new),
+        })
+    }
+
+    /// Detects the type of an object file using the signature database
+    pub async fn detect(&self, path: &str, object_id: &str) -> Result<Detection, std::io::Error> {
+        let mut f = tokio::fs::File::open(path)
+            .await
+            .inspect_err(|e| error!("Failed to open \"{path}\" for type detection: {e}"))?;
+        let size = f
+            .metadata()
+            .await
+            .inspect_err(|e| error!("Failed to read metadata from \"{path}\": {e}"))?
+            .len();
+        if size == 0 {
+            debug!("Object \"{object_id}\" is empty");
+            return Ok(Detection::unknown());
+        }
+        let window = size.min(WINDOW_SIZE as u64);
+        let mut head = vec![0u8; window as usize]; // safe bc min
+        f.read_exact(&mut head)
+            .await
+            .inspect_err(|e| error!("Failed to read from \"{path}\" for type detection: {e}"))?;
+        let tail = if size > window {
+            let mut tail = vec![0u8; window as usize]; // safe bc min
+            f.seek(std::io::SeekFrom::End(-(window as i64)))
+                .await
+                .inspect_err(|e| error!("Failed to seek \"{path}\" for type detection: {e}"))?;
+            f.read_exact(&mut tail).await.inspect_err(|e| {
+                error!("Failed to read from \"{path}\" for type detection: {e}")
+            })?;
+            Some(tail)
+        } else {
+            None
+        };
+        let sample = Sample {
+            head: &head,
+            tail: tail.as_deref().unwrap_or(&head),
+        };
+        let mut candidates = self.signatures.detect(&sample).into_iter();
+        if let Some(best) = candidates.next() {
+            return Ok(Detection {
+                object_type: best.object_type,
+                confidence: best.confidence,
+                candidates: candidates.collect(),
+            });
+        }
+        let buf = read_text_sample(&mut f, size)
+            .await
+            .inspect_err(|e| error!("Failed to read \"{path}\" for text detection: {e}"))?;
+        if is_text(&buf, object_id) {
+            Ok(Detection {
+                object_type: "Text".to_string(),
+                confidence: TEXT_CONFIDENCE,
+                candidates: Vec::new(),
+            })
+        } else {
+            Ok(Detection::unknown())
+        }
+    }
+
+    /// Returns the additional types an object should be processed as
+    ///
+    /// These are the most confident secondary candidates, provided their
+    /// confidence reaches the configured polyglot threshold
+    pub fn interpretations(&self, detection: &Detection) -> Vec<Candidate> {
+        detection
+            .candidates
+            .iter()
+            .filter(|c| {
+                c.confidence >= self.polyglot_min_confidence
+                    && c.object_type != detection.object_type
+            })
+            .take(MAX_INTERPRETATIONS)
+            .cloned()
+            .collect()
+    }
+
+    /// Asks clamd for the type of an object
+    ///
+    /// Returns `None` if clamd is unavailable or cannot identify the object
+    async fn clamd_type(&self, clamd: &Clamd, path: &str, object_id: &str) -> Option<String> {
+        metrics::counter!(TYPEDET_FALLBACK_COUNT).increment(1);
+        let ftype = match clamd.get_symbol(object_id).await {
+            Ok(Some(ftype)) => ftype,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("Clamd failed to determine ftype for \"{object_id}\": {e}");
+                return None;
+            }
+        };
+        debug!("Clamd type for object \"{object_id}\": {ftype:?}");
+        if ftype != "Text" {
+            return Some(ftype);
+        }
+        // Clamd is quite liberal with the text type
+        let mut f = tokio::fs::File::open(path).await.ok()?;
+        let size = f.metadata().await.ok()?.len();
+        let buf = read_text_sample(&mut f, size).await.ok()?;
+        is_text(&buf, object_id).then_some(ftype)
+    }
+
+    /// Updates the object type based on the detection results
+    ///
+    /// If the detection is not conclusive and clamd is configured, the object
+    /// type is determined by clamd instead
+    pub async fn set_ftype(
+        &self,
+        object: &mut object::Info,
+        objects_path: &str,
+    ) -> Result<Detection, std::io::Error> {
+        let start = std::time::Instant::now();
+        let path = format!("{}/{}", objects_path, object.object_id);
+        let mut detection = self.detect(&path, &object.object_id).await?;
+        if let (true, Some(clamd)) = (detection.confidence < self.min_confidence, &self.clamd) {
+            if let Some(ftype) = self.clamd_type(clamd, &path, &object.object_id).await {
+                if ftype != detection.object_type {
+                    let native = Candidate {
+                        object_type: std::mem::replace(&mut detection.object_type, ftype),
+                        confidence: detection.confidence,
+                    };
+                    if native.confidence > 0 {
+                        detection.candidates.insert(0, native);
+                    }
+                }
+                detection.confidence = detection.confidence.max(self.min_confidence);
+            }
+        }
+        debug!(
+            "Typedet for object \"{}\": {:?}",
+            object.object_id, detection
+        );
+        object.set_type(&detection.object_type);
+        metrics::counter!(TYPEDET_COUNT).increment(1);
+        metrics::histogram!(TYPEDET_TIME).record(start.elapsed().as_secs_f64());
+        Ok(detection)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn detect(data: &[u8]) -> Vec<Candidate> {
+        Signatures::builtin().detect(&Sample {
+            head: data,
+            tail: data,
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let sigs = Signatures::parse(
+            "test",
+            "# comment\n\
+             \n\
+             Foo/bar 42 0 \"FOO\" ?? 00 & 16-32 i\"b a\\x72\" & * 0102 & EOF-4 \"END\"\n",
+        )
+        .unwrap();
+        assert_eq!(sigs.signatures.len(), 1);
+        let sig = &sigs.signatures[0];
+        assert_eq!(sig.object_type, "Foo/bar");
+        assert_eq!(sig.confidence, 42);
+        assert_eq!(sig.conditions.len(), 4);
+        assert_eq!(sig.conditions[0].offset, Offset::At(0));
+        assert_eq!(
+            sig.conditions[0].pattern,
+            vec![
+                Elem::Byte(b'F'),
+                Elem::Byte(b'O'),
+                Elem::Byte(b'O'),
+                Elem::Any,
+                Elem::Byte(0)
+            ]
+        );
+        assert_eq!(sig.conditions[1].offset, Offset::Range(16, 32));
+        assert_eq!(
+            sig.conditions[1].pattern,
+            vec![
+                Elem::Nocase(b'b'),
+                Elem::Nocase(b' '),
+                Elem::Nocase(b'a'),
+                Elem::Nocase(b'r')
+            ]
+        );
+        assert_eq!(sig.conditions[2].offset, Offset::Anywhere);
+        assert_eq!(sig.conditions[3].offset, Offset::FromEnd(4));
+
+        for bad in [
+            "Foo",
+            "Foo 101 0 00",
+            "Foo 10 0",
+            "Foo 10 x 00",
+            "Foo 10 0 0",
+            "Foo 10 0 \"abc",
+            "Foo 10 8-4 00",
+            "Foo 10 70000 00",
+            "%supersedes Foo",
+        ] {
+            assert!(Signatures::parse("test", bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert!(detect(b"").is_empty());
+        assert!(detect(b"hello world").is_empty());
+        assert_eq!(detect(b"%PDF-1.7\n")[0].object_type, "PDF");
+        assert_eq!(detect(b"\n\n  <!DOCTYPE HTML>")[0].object_type, "HTML");
+
+        let mut pe = vec![0u8; 512];
+        pe[0..2].copy_from_slice(b"MZ");
+        assert_eq!(
+            detect(&pe),
+            vec![Candidate {
+                object_type: "PE".to_string(),
+                confidence: 70
+            }]
+        );
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        assert_eq!(detect(&pe)[0].confidence, 90);
+
+        let mut odf = b"PK\x03\x04".to_vec();
+        odf.resize(30, 0);
+        odf.extend_from_slice(b"mimetypeapplication/vnd.oasis.opendocument.text");
+        let candidates = detect(&odf);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_type, "ODF");
+
+        let mut polyglot = b"%PDF-1.4\n".to_vec();
+        polyglot.resize(4096, b' ');
+        polyglot.extend_from_slice(b"PK\x05\x06");
+        polyglot.resize(4096 + 22, 0);
+        let candidates = detect(&polyglot);
+        assert_eq!(candidates[0].object_type, "PDF");
+        assert_eq!(
+            candidates[1],
+            Candidate {
+                object_type: "ZIP".to_string(),
+                confidence: 60
+            }
+        );
+
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(detect(&tar)[0].object_type, "Tar");
+    }
+
+    #[test]
+    fn test_is_text() {
+        assert!(is_text(b"Just some text\nwith a newline", "test"));
+        assert!(is_text(b"\xff\xfeU\0T\0F\0", "test"));
+        assert!(!is_text(
+            b"\x01\x02\x03\x04\x05\x06\x07\x08\xf8\xf9",
+            "test"
+        ));
+    }
+}
@@ -34,6 +34,16 @@ pub fn get_queue_for(object_type: &str) -> String {
     format!("CTX-JobReq-{}", object_type.to_uppercase())
 }
 
+/// Utility fn to retrieve the request queue for a given object type and priority
+///
+/// Interactive requests use the plain request queue, bulk requests a separate lane
+pub fn get_lane_for(object_type: &str, priority: crate::object::Priority) -> String {
+    match priority {
+        crate::object::Priority::Interactive => get_queue_for(object_type),
+        crate::object::Priority::Bulk => format!("{}-Bulk", get_queue_for(object_type)),
+    }
+}
+
 // Create a tempfile in the indicated path
 // Note: leaking tempfiles is possible if we get killed; a proper approach
 // would use O_TMPFILE + re-linking via /proc but it has too strong requirements
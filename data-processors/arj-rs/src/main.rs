@@ -172,6 +172,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["ARJ".to_string()],
+        symbols: ["TOOBIG", "CORRUPTED", "LIMITS_REACHED"].map(String::from).to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         process_request(request, &config)
     })?;
@@ -14,6 +14,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["Bzip2".to_string()],
+        symbols: ["LIMITS_REACHED", "TOOBIG"].map(String::from).to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         process_request(request, &config)
     })?;
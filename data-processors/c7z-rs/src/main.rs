@@ -301,6 +301,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["7z".to_string()],
+        symbols: ["ENCRYPTED", "DECRYPTED", "TOOBIG", "LIMITS_REACHED"].map(String::from).to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         process_request(request, &config)
     })?;
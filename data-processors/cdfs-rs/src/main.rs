@@ -13,6 +13,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["CDFS".to_string()],
+        symbols: ["UDF", "ISO9660", "TOOBIG", "CORRUPTED", "TRUNCATED", "LIMITS_REACHED"]
+            .map(String::from)
+            .to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         process_request(request, &config)
     })?;
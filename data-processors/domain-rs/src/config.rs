@@ -18,6 +18,8 @@ pub struct Config {
     pub objects_path: String,
     /// Whois server query timeout in seconds
     pub query_timeout_secs: Option<u32>,
+    /// The number of requests processed at the same time
+    pub concurrency: Option<usize>,
 }
 
 impl Config {
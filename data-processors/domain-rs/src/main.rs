@@ -124,12 +124,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let config = config::Config::new()?;
-    let dq = RefCell::new(DomainQuery::new());
     let runtime = Runtime::new()?;
+    thread_local! {
+        // Each worker thread queries through its own client
+        static DQ: RefCell<DomainQuery> = RefCell::new(DomainQuery::new());
+    }
 
-    backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
-        runtime.block_on(async { process_request(request, &config, &dq).await })
-    })?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["Domain".to_string()],
+        symbols: vec!["NO_DATA".to_string()],
+        ..backend_utils::capabilities!()
+    });
+    backend_utils::work_loop_concurrent!(
+        config.host.as_deref(),
+        config.port,
+        config.concurrency.unwrap_or(8),
+        |request, _| {
+            DQ.with(|dq| runtime.block_on(async { process_request(request, &config, dq).await }))
+        }
+    )?;
 
     Ok(())
 }
@@ -12,6 +12,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["Gzip".to_string()],
+        symbols: ["GZIP_MULTI_MEMBER", "GZIP_TRAILING_GARBAGE", "LIMITS_REACHED", "TOOBIG"]
+            .map(String::from)
+            .to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         worker::process_request(request, &config)
     })?;
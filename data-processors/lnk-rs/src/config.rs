@@ -15,6 +15,8 @@ pub struct Config {
     pub port: Option<u16>,
     /// The path to the objects store
     pub objects_path: String,
+    /// The number of requests processed at the same time
+    pub concurrency: Option<usize>,
 }
 
 impl Config {
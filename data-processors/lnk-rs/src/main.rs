@@ -18,9 +18,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
-    backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
-        process_request(request, &config)
-    })?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["LNK".to_string()],
+        ..backend_utils::capabilities!()
+    });
+    backend_utils::work_loop_concurrent!(
+        config.host.as_deref(),
+        config.port,
+        config.concurrency.unwrap_or(4),
+        |request, _| process_request(request, &config)
+    )?;
     unreachable!()
 }
 
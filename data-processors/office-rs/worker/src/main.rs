@@ -48,6 +48,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["Office".to_string()],
+        symbols: [
+            "DOC",
+            "DOCX",
+            "XLS",
+            "XLSX",
+            "XLSB",
+            "OLE",
+            "ENCRYPTED",
+            "DECRYPTED",
+            "VBA",
+            "DECOMPILED",
+            "CORRUPTED_VBA",
+            "HAS_FORMS",
+            "HAS_MACRO_SHEET",
+            "CORRUPTED",
+            "NOT_FOUND",
+            "LIMITS_REACHED",
+            "TOOBIG",
+        ]
+        .map(String::from)
+        .to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(config.host.as_deref(), config.port, |request| {
         process_request(request, &config)
     })?;
@@ -46,6 +46,32 @@ fn main() -> Result<(), PdfBackendError> {
 
     let config = Config::new()?;
 
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["PDF".to_string()],
+        symbols: [
+            "ENCRYPTED",
+            "DECRYPTED",
+            "NOTEXT",
+            "ISSUES",
+            "JAVASCRIPT",
+            "OCR",
+            "FALLBACK_TO_RAW_IMAGE",
+            "MAX_PAGES_REACHED",
+            "MAX_ANNOTATIONS_REACHED",
+            "MAX_ATTACHMENTS_REACHED",
+            "MAX_BOOKMARKS_REACHED",
+            "MAX_FONTS_PER_PAGE_REACHED",
+            "MAX_LINKS_REACHED",
+            "MAX_OBJECTS_REACHED",
+            "MAX_OBJECT_DEPTH_REACHED",
+            "MAX_SIGNATURES_REACHED",
+            "LIMITS_REACHED",
+            "TOOBIG",
+        ]
+        .map(String::from)
+        .to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(None, None, |request| { process_request(request, &config) })?;
 
     Ok(())
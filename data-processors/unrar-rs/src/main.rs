@@ -27,6 +27,29 @@ fn main() -> Result<(), RarBackendError> {
 
     let config = Config::new()?;
 
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["RAR".to_string()],
+        symbols: [
+            "ENCRYPTED",
+            "DECRYPTED",
+            "INVALID_PASSWORD",
+            "SOLID",
+            "PARTIAL_DATA",
+            "CHECKSUM_MISMATCH",
+            "HAS_CHECKSUM_INCONSISTENCY",
+            "COMMENT_TRUNCATED",
+            "COMMENT_EXTRACTION_FAILED",
+            "INVALID_FILETIME",
+            "INVALID_MTIME",
+            "INVALID_ATIME",
+            "INVALID_CTIME",
+            "LIMITS_REACHED",
+            "TOOBIG",
+        ]
+        .map(String::from)
+        .to_vec(),
+        ..backend_utils::capabilities!()
+    });
     backend_utils::work_loop!(None, None, |request| { process_request(request, &config) })?;
 
     Ok(())
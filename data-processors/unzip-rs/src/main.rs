@@ -1,5 +1,5 @@
 //! Unzip backend
-use backend_utils::objects::*;
+use backend_utils::{ChildStream, objects::*};
 use ctxunzip::{FileType, Zip};
 use scopeguard::ScopeGuard;
 use serde::Serialize;
@@ -16,9 +16,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
     let config = config::Config::new()?;
-    backend_utils::work_loop!(Some(&config.host), Some(config.port), |request| {
-        process_request(request, &config)
-    })?;
+    backend_utils::advertise(backend_utils::objects::Capabilities {
+        object_types: vec!["ZIP".to_string()],
+        symbols: [
+            "ZIP_BAD",
+            "ZIP_UNSUP",
+            "ZIP_DSIZE_MISMATCH",
+            "ZIP_CSIZE_MISMATCH",
+            "ENCRYPTED",
+            "DECRYPTED",
+            "CORRUPTED",
+            "LIMITS_REACHED",
+            "TOOBIG",
+        ]
+        .map(String::from)
+        .to_vec(),
+        ..backend_utils::capabilities!()
+    });
+    backend_utils::work_loop_streaming!(
+        Some(&config.host),
+        Some(config.port),
+        |request, children| { process_request(request, &config, children) }
+    )?;
     unreachable!()
 }
 
@@ -82,6 +101,8 @@ struct ZipEntry {
 
 /// Parse a Zip object and its entries.
 ///
+/// Entries are pushed to `children` as soon as they are extracted.
+///
 /// # Returns #
 /// * Err for transient retriable errors (I/O, memory allocation, etc)
 /// * Ok(BackendResultKind::ok) for success
@@ -90,6 +111,7 @@ struct ZipEntry {
 fn process_request(
     request: &BackendRequest,
     config: &config::Config,
+    children: &mut ChildStream,
 ) -> Result<BackendResultKind, Box<dyn std::error::Error>> {
     let mut possible_passwords: Vec<&str> = Vec::new();
     if let Some(serde_json::Value::Object(glob)) = request.relation_metadata.get("_global") {
@@ -131,7 +153,6 @@ fn process_request(
     let mut remaining_processed_size = config.max_processed_size;
     let mut has_encrypted_entries = false;
 
-    let mut children: Vec<BackendResultChild> = Vec::new();
     let mut idx = 0u32;
     for entry in zip.into_iter() {
         idx += 1;
@@ -316,7 +337,8 @@ fn process_request(
                 }
             },
         };
-        children.push(BackendResultChild {
+        let has_file = path.is_some();
+        let handed_over = children.push(BackendResultChild {
             path,
             force_type: None,
             symbols: entry_symbols,
@@ -327,6 +349,10 @@ fn process_request(
                 _ => unreachable!(),
             },
         });
+        if handed_over && has_file {
+            // The file is now owned by the frontend
+            files_for_cleanup.pop();
+        }
     }
 
     let metadata = ZipMetadata {
@@ -350,7 +376,7 @@ fn process_request(
             serde_json::Value::Object(v) => v,
             _ => unreachable!(),
         },
-        children,
+        children: Vec::new(),
     });
     ScopeGuard::into_inner(files_for_cleanup); // disarm garbage collection
 
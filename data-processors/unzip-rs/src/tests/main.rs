@@ -1,5 +1,5 @@
 use crate::{config, process_request};
-use backend_utils::objects::*;
+use backend_utils::{ChildStream, objects::*};
 use std::collections::HashMap;
 use tempfile::TempDir;
 
@@ -28,12 +28,22 @@ fn mock_env_for_file(file: &str) -> (config::Config, BackendRequest, TempDir) {
     (config, request, temp_dir)
 }
 
+/// Processes the request collecting all the children into the result
+fn process(
+    request: &BackendRequest,
+    config: &config::Config,
+) -> Result<BackendResultKind, Box<dyn std::error::Error>> {
+    let mut children = ChildStream::new();
+    let result = process_request(request, config, &mut children)?;
+    Ok(children.finish(result))
+}
+
 #[test]
 fn longer_uncompressed_stream_size() {
     let (config, request, _temp_dir) = mock_env_for_file("stream_longer_than_claimed.zip");
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::Ok is expected")
     };
@@ -51,7 +61,7 @@ fn shorter_uncompressed_stream_size() {
     let (config, request, _temp_dir) = mock_env_for_file("stream_shorter_than_claimed.zip");
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::Ok is expected")
     };
@@ -69,7 +79,7 @@ fn valid_uncompressed_stream_sizes() {
     let (config, request, _temp_dir) = mock_env_for_file("alice.zip");
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::Ok variant is expected")
     };
@@ -87,7 +97,7 @@ fn much_longer_compressed_stream_size() {
     let (config, request, _temp_dir) = mock_env_for_file("compressed_size_longer_than_claimed.zip");
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::Ok is expected")
     };
@@ -105,7 +115,7 @@ fn encryption() {
     let (config, mut request, _temp_dir) = mock_env_for_file("encryption.zip");
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::Ok is expected")
     };
@@ -129,7 +139,7 @@ fn encryption() {
         .insert("_global".into(), global.into());
 
     let BackendResultKind::ok(backend_result) =
-        process_request(&request, &config).expect("BackendResultKind is expected")
+        process(&request, &config).expect("BackendResultKind is expected")
     else {
         panic!("BackendResultKind::ok is expected")
     };
@@ -20,13 +20,66 @@
 //!     unreachable!()
 //! }
 //! ```
+//!
+//! Backends which extract many children (e.g. archives) should rather use
+//! [`work_loop_streaming!`] and push each child to the provided [`ChildStream`]
+//! as soon as it's extracted, so that the frontend can process it while the
+//! backend is still at work
+//!
+//! Backends which spend most of their time waiting on I/O can serve several
+//! requests at the same time via [`work_loop_concurrent!`]; the worker function
+//! is then invoked from multiple threads
+//!
+//! Backends should also [`advertise()`] their capabilities before entering the
+//! work loop, e.g.:
+//! ```rust,ignore
+//! backend_utils::advertise(backend_utils::objects::Capabilities {
+//!     object_types: vec!["LNK".to_string()],
+//!     symbols: vec!["LNK_REMOTE".to_string()],
+//!     ..backend_utils::capabilities!()
+//! });
+//! ```
 
 pub mod objects;
 pub mod tcpserver;
 
 use std::fmt::{Debug, Display};
+use std::sync::OnceLock;
 use tracing::{debug, warn};
 
+/// The advertised backend capabilities
+static CAPABILITIES: OnceLock<objects::Capabilities> = OnceLock::new();
+
+/// Sets the capabilities advertised to the frontend
+///
+/// Only the first call is effective; returns `false` if the capabilities were
+/// already set
+pub fn advertise(capabilities: objects::Capabilities) -> bool {
+    CAPABILITIES.set(capabilities).is_ok()
+}
+
+/// Retrieves the advertised capabilities, filling in the backend version if missing
+fn advertised(backend_version: &str) -> objects::Capabilities {
+    let mut capabilities = CAPABILITIES.get().cloned().unwrap_or_default();
+    if capabilities.version.is_empty() {
+        capabilities.version = backend_version.to_string();
+    }
+    capabilities
+}
+
+/// A convenience macro which creates [`Capabilities`](objects::Capabilities) with the
+/// backend crate name and version (extracted from environment variables at compile time)
+#[macro_export]
+macro_rules! capabilities {
+    () => {
+        $crate::objects::Capabilities {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        }
+    };
+}
+
 /// A convenience macro which accepts optional `host`, optional `port` and `Fn`, adds backend crate
 /// version (extracted from environment variable at compile time) and passes it all to
 /// [`work_loop_with_backend_version`].
@@ -40,10 +93,93 @@ macro_rules! work_loop {
         // time. And this could only happen if something other than Cargo (e.g. direct invocation
         // of `rustc`) is used as a build tool.
         let backend_version = env!("CARGO_PKG_VERSION");
+        // Advertise the crate name and version (unless already advertised)
+        $crate::advertise($crate::capabilities!());
         $crate::work_loop_with_backend_version($host, $port, backend_version, $fn)
     }};
 }
 
+/// A convenience macro which accepts optional `host`, optional `port` and `Fn`, adds backend crate
+/// version (extracted from environment variable at compile time) and passes it all to
+/// [`work_loop_streaming_with_backend_version`].
+#[macro_export]
+macro_rules! work_loop_streaming {
+    ($host:expr, $port:expr, $fn:expr) => {{
+        // See work_loop!
+        let backend_version = env!("CARGO_PKG_VERSION");
+        $crate::advertise($crate::capabilities!());
+        $crate::work_loop_streaming_with_backend_version($host, $port, backend_version, $fn)
+    }};
+}
+
+/// A convenience macro which accepts optional `host`, optional `port`, the concurrency level
+/// and `Fn`, adds backend crate version (extracted from environment variable at compile time)
+/// and passes it all to [`work_loop_concurrent_with_backend_version`].
+#[macro_export]
+macro_rules! work_loop_concurrent {
+    ($host:expr, $port:expr, $concurrency:expr, $fn:expr) => {{
+        // See work_loop!
+        let backend_version = env!("CARGO_PKG_VERSION");
+        $crate::advertise($crate::capabilities!());
+        $crate::work_loop_concurrent_with_backend_version(
+            $host,
+            $port,
+            backend_version,
+            $concurrency,
+            $fn,
+        )
+    }};
+}
+
+/// The child objects extracted by a streaming backend
+///
+/// Children are handed over to the frontend as soon as they are pushed; if the
+/// frontend does not accept streamed children, or when not connected to a frontend
+/// at all (e.g. in tests), they are collected and later added to the job result
+/// (see [`finish()`](Self::finish))
+#[derive(Debug, Default)]
+pub struct ChildStream<'a> {
+    server: Option<&'a mut tcpserver::TcpServer>,
+    children: Vec<objects::BackendResultChild>,
+}
+
+impl ChildStream<'_> {
+    /// Creates a new, unconnected, child stream which collects all the children
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pushes an extracted child object
+    ///
+    /// Returns `true` if the child was handed over to the frontend: from this point
+    /// on the frontend owns the child file, which must not be altered or removed
+    pub fn push(&mut self, child: objects::BackendResultChild) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            match server.send_child(&child) {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("Failed to send child to frontend: {e}");
+                    self.server = None;
+                }
+            }
+        }
+        self.children.push(child);
+        false
+    }
+
+    /// Adds the collected (not handed over) children to the provided result
+    ///
+    /// The collected children are placed before any child already present in the
+    /// result, matching the order in which the frontend receives streamed children
+    pub fn finish(self, mut result: objects::BackendResultKind) -> objects::BackendResultKind {
+        if let objects::BackendResultKind::ok(ref mut v) = result {
+            v.children.splice(0..0, self.children);
+        }
+        result
+    }
+}
+
 /// The main backend<->frontend communication handler
 ///
 /// Convenience function that:
@@ -77,15 +213,84 @@ pub fn work_loop_with_backend_version<W, E>(
 where
     W: Fn(&objects::BackendRequest) -> Result<objects::BackendResultKind, E>,
     E: Display + Debug,
+{
+    work_loop_streaming_with_backend_version(host, port, backend_version, |request, _| {
+        work_fn(request)
+    })
+}
+
+/// Same as [`work_loop_with_backend_version`] but for a streaming worker function
+///
+/// The worker function additionally receives a [`ChildStream`] to which the
+/// extracted children are pushed
+pub fn work_loop_streaming_with_backend_version<W, E>(
+    host: Option<&str>,
+    port: Option<u16>,
+    backend_version: &str,
+    work_fn: W,
+) -> Result<(), std::io::Error>
+where
+    W: Fn(&objects::BackendRequest, &mut ChildStream) -> Result<objects::BackendResultKind, E>,
+    E: Display + Debug,
 {
     let host = host.unwrap_or("127.0.0.1");
     let port = port.unwrap_or(44203);
     let mut server = tcpserver::TcpServer::new(host, port)?;
+    server.set_capabilities(advertised(backend_version));
+    serve(&mut server, backend_version, &work_fn)
+}
+
+/// Same as [`work_loop_streaming_with_backend_version`] but serving up to
+/// `concurrency` requests at the same time
+///
+/// Each request is processed in its own thread, therefore the worker function
+/// must be [`Sync`]
+pub fn work_loop_concurrent_with_backend_version<W, E>(
+    host: Option<&str>,
+    port: Option<u16>,
+    backend_version: &str,
+    concurrency: usize,
+    work_fn: W,
+) -> Result<(), std::io::Error>
+where
+    W: Fn(&objects::BackendRequest, &mut ChildStream) -> Result<objects::BackendResultKind, E>
+        + Sync,
+    E: Display + Debug,
+{
+    let host = host.unwrap_or("127.0.0.1");
+    let port = port.unwrap_or(44203);
+    let mut server = tcpserver::TcpServer::new(host, port)?;
+    server.set_concurrency(concurrency);
+    server.set_capabilities(advertised(backend_version));
+    let servers = (1..concurrency)
+        .map(|_| server.try_clone())
+        .collect::<Result<Vec<_>, _>>()?;
+    std::thread::scope(|scope| -> Result<(), std::io::Error> {
+        for mut server in servers {
+            let work_fn = &work_fn;
+            scope.spawn(move || {
+                serve(&mut server, backend_version, work_fn);
+            });
+        }
+        serve(&mut server, backend_version, &work_fn)
+    })
+}
+
+/// Serves job requests forever
+fn serve<W, E>(server: &mut tcpserver::TcpServer, backend_version: &str, work_fn: &W) -> !
+where
+    W: Fn(&objects::BackendRequest, &mut ChildStream) -> Result<objects::BackendResultKind, E>,
+    E: Display + Debug,
+{
     loop {
         let request = server.get_job_request();
         debug!("Job request received: {request:?}");
 
-        let result = work_fn(&request);
+        let mut children = ChildStream {
+            server: Some(&mut *server),
+            children: Vec::new(),
+        };
+        let result = work_fn(&request, &mut children).map(|result| children.finish(result));
         debug!("Job result generated: {result:?}");
 
         match result {
@@ -73,3 +73,22 @@ pub struct BackendResultChild {
     /// The relation metadata linking the main object to this child
     pub relation_metadata: Metadata,
 }
+
+/// The backend capabilities, advertised to the frontend on startup
+///
+/// See [`advertise()`](crate::advertise) and [`capabilities!`](crate::capabilities)
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Capabilities {
+    /// The backend name
+    pub name: String,
+    /// The backend version
+    pub version: String,
+    /// The object types the backend can process
+    pub object_types: Vec<String>,
+    /// The limits enforced by the backend (e.g. the maximum number of children)
+    pub limits: Metadata,
+    /// The symbols the backend can emit
+    pub symbols: Vec<String>,
+    /// The object metadata keys the backend can emit
+    pub metadata_keys: Vec<String>,
+}
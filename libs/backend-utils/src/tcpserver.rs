@@ -1,14 +1,61 @@
 //! Backend TCP server
-use crate::objects::{BackendRequest, BackendResultKind};
-use std::io::Read;
+//!
+//! Each job request is received over its own connection; the job result is sent
+//! back over the same connection, which is then closed
+//!
+//! Before sending any job, the frontend may query the number of requests the
+//! backend can process at the same time, along with the backend capabilities,
+//! via a `{"hello":{}}` request, to which the backend replies with
+//! `{"concurrency":N, "name":..., "version":..., ...}`; the same request is
+//! periodically repeated as a health check
+use crate::objects::{BackendRequest, BackendResultChild, BackendResultKind, Capabilities};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
 #[allow(unused_imports)]
 use tracing::{debug, error, info, instrument, warn};
 
+/// The protocol options requested by the frontend along with the job
+#[derive(Deserialize)]
+struct RequestOptions {
+    /// Whether the frontend accepts children ahead of the job result
+    #[serde(default)]
+    stream_children: bool,
+    /// The request id, echoed back in the job result
+    #[serde(default)]
+    correlation_id: Option<String>,
+}
+
+/// The frontend hello request
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HelloRequest {
+    #[allow(dead_code)]
+    hello: serde_json::Value,
+}
+
+/// The reply to the frontend hello request
+#[derive(Serialize)]
+struct HelloReply<'a> {
+    concurrency: usize,
+    #[serde(flatten)]
+    capabilities: &'a Capabilities,
+}
+
+/// A child object sent ahead of the job result (one JSON object per line)
+#[derive(Serialize)]
+struct StreamedChild<'a> {
+    child: &'a BackendResultChild,
+}
+
 /// A listening TCP socket
 #[derive(Debug)]
 pub struct TcpServer {
     listener: std::net::TcpListener,
     stream: Option<std::net::TcpStream>,
+    stream_children: bool,
+    correlation_id: Option<String>,
+    concurrency: usize,
+    capabilities: Capabilities,
 }
 
 impl TcpServer {
@@ -24,6 +71,37 @@ impl TcpServer {
         Ok(Self {
             listener,
             stream: None,
+            stream_children: false,
+            correlation_id: None,
+            concurrency: 1,
+            capabilities: Capabilities::default(),
+        })
+    }
+
+    /// Sets the capabilities advertised to the frontend
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Sets the number of requests the backend can process at the same time
+    ///
+    /// This value is only advertised to the frontend: it is up to the caller to
+    /// actually serve that many requests (see [`try_clone()`](Self::try_clone))
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Creates a new server sharing the same listening socket
+    ///
+    /// Each server independently accepts and serves job requests
+    pub fn try_clone(&self) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            listener: self.listener.try_clone()?,
+            stream: None,
+            stream_children: false,
+            correlation_id: None,
+            concurrency: self.concurrency,
+            capabilities: self.capabilities.clone(),
         })
     }
 
@@ -47,9 +125,24 @@ impl TcpServer {
                     continue;
                 }
             }
+            if serde_json::from_slice::<HelloRequest>(&breq).is_ok() {
+                let reply = HelloReply {
+                    concurrency: self.concurrency,
+                    capabilities: &self.capabilities,
+                };
+                if let Err(e) = serde_json::to_writer(stream, &reply) {
+                    warn!("Failed to reply to hello from {}: {}", remote_address, e);
+                }
+                continue;
+            }
             match serde_json::from_slice::<BackendRequest>(&breq) {
                 Ok(request) => {
                     self.stream = Some(stream);
+                    (self.stream_children, self.correlation_id) =
+                        match serde_json::from_slice::<RequestOptions>(&breq) {
+                            Ok(o) => (o.stream_children, o.correlation_id),
+                            Err(_) => (false, None),
+                        };
                     return request;
                 }
                 Err(e) => warn!("Invalid request from {}: {}", remote_address, e),
@@ -57,14 +150,46 @@ impl TcpServer {
         }
     }
 
+    /// Sends a child object to the frontend ahead of the job result
+    ///
+    /// Returns `Ok(false)` if the frontend does not accept streamed children: in
+    /// this case the child must be included in the job result instead
+    pub fn send_child(
+        &mut self,
+        child: &BackendResultChild,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if !self.stream_children {
+            return Ok(false);
+        }
+        match self.stream.as_mut() {
+            Some(stream) => {
+                let mut line = serde_json::to_vec(&StreamedChild { child })?;
+                line.push(b'\n');
+                stream.write_all(&line)?;
+                Ok(true)
+            }
+            None => Err("Internal error: called out of context".into()),
+        }
+    }
+
     /// Sends back the job result
     pub fn send_job_result(
         &mut self,
         result: &BackendResultKind,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        match self.stream.take() {
-            Some(stream) => Ok(serde_json::to_writer(stream, result)?),
-            None => Err("Internal error: called out of context".into()),
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return Err("Internal error: called out of context".into()),
+        };
+        match self.correlation_id.take() {
+            Some(correlation_id) => {
+                let mut result = serde_json::to_value(result)?;
+                if let Some(result) = result.as_object_mut() {
+                    result.insert("correlation_id".into(), correlation_id.into());
+                }
+                Ok(serde_json::to_writer(stream, &result)?)
+            }
+            None => Ok(serde_json::to_writer(stream, result)?),
         }
     }
 
@@ -72,6 +197,7 @@ impl TcpServer {
     ///
     /// In fact it just closes the connection (the client will retry the job)
     pub fn send_job_failure(&mut self) {
+        self.correlation_id.take();
         self.stream.take();
     }
 }